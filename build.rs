@@ -0,0 +1,5 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/control.proto").expect("Failed to compile control.proto");
+}
@@ -0,0 +1,61 @@
+use crate::settings::AcmeSettings;
+use anyhow::{anyhow, Result};
+use futures_util::StreamExt;
+use log::{error, info};
+use rustls::ServerConfig;
+use rustls_acme::caches::DirCache;
+use rustls_acme::{AcmeConfig, ResolvesServerCertAcme, ACME_TLS_ALPN_NAME};
+use std::sync::Arc;
+use url::Url;
+
+/// Certificate of the [public_url] host, provisioned and renewed using ACME (TLS-ALPN-01)
+pub struct AcmeCerts {
+    resolver: Arc<ResolvesServerCertAcme>,
+}
+
+impl AcmeCerts {
+    /// Start provisioning the certificate, the https server must be reachable on port 443 of
+    /// the [public_url] host for validation to succeed
+    pub fn new(public_url: &str, settings: &AcmeSettings) -> Result<Self> {
+        let url: Url = public_url.parse()?;
+        let domain = url
+            .host_str()
+            .ok_or(anyhow!("public_url has no host"))?
+            .to_string();
+
+        let mut config = AcmeConfig::new([domain.clone()])
+            .cache(DirCache::new(settings.cache_dir.clone()))
+            .directory_lets_encrypt(settings.production);
+        if let Some(email) = &settings.email {
+            config = config.contact_push(format!("mailto:{}", email));
+        }
+        let mut state = config.state();
+        let resolver = state.resolver();
+
+        info!("Starting ACME for {}", domain);
+        tokio::spawn(async move {
+            while let Some(e) = state.next().await {
+                match e {
+                    Ok(e) => info!("ACME: {:?}", e),
+                    Err(e) => error!("ACME: {:?}", e),
+                }
+            }
+        });
+        Ok(Self { resolver })
+    }
+
+    /// Create a rustls server config serving the certificate with the given ALPN protocols
+    ///
+    /// The https and HTTP/3 servers share the certificate, challenges are only answered by
+    /// the https server (TCP)
+    pub fn server_config(&self, alpn: &[&[u8]]) -> Result<Arc<ServerConfig>> {
+        let mut config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()?
+                .with_no_client_auth()
+                .with_cert_resolver(self.resolver.clone());
+        config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+        config.alpn_protocols.push(ACME_TLS_ALPN_NAME.to_vec());
+        Ok(Arc::new(config))
+    }
+}
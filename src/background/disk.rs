@@ -0,0 +1,114 @@
+use anyhow::{bail, Result};
+use log::{info, warn};
+#[cfg(unix)]
+use std::ffi::CString;
+#[cfg(unix)]
+use std::mem::MaybeUninit;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set while free space in the output dir is below the configured minimum
+static DISK_LOW: AtomicBool = AtomicBool::new(false);
+
+/// True while the output dir is almost full, muxers keep fewer segments and
+/// recordings are paused
+pub fn disk_low() -> bool {
+    DISK_LOW.load(Ordering::Relaxed)
+}
+
+/// Watches free space in the output dir
+pub struct DiskWatchdog {
+    dir: PathBuf,
+    /// Min free bytes before [disk_low] is set
+    min_free: u64,
+}
+
+impl DiskWatchdog {
+    pub fn new(dir: &str, min_free: u64) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+            min_free,
+        }
+    }
+
+    pub fn check(&self) -> Result<()> {
+        let free = free_space(&self.dir)?;
+        let was_low = disk_low();
+        // clear only with some headroom so the state doesn't flap on every check
+        let low = if was_low {
+            free < self.min_free + self.min_free / 2
+        } else {
+            free < self.min_free
+        };
+        if low != was_low {
+            if low {
+                warn!(
+                    "Free space in {} is {}MB, shrinking DVR windows and pausing recordings",
+                    self.dir.display(),
+                    free / 1024 / 1024
+                );
+            } else {
+                info!(
+                    "Free space in {} is {}MB, resuming normal operation",
+                    self.dir.display(),
+                    free / 1024 / 1024
+                );
+            }
+            DISK_LOW.store(low, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+/// Bytes available to unprivileged users on the filesystem containing [path]
+#[cfg(unix)]
+fn free_space(path: &Path) -> Result<u64> {
+    let c_path = CString::new(path.to_string_lossy().as_bytes())?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        bail!(
+            "Failed to get free space of {}: {}",
+            path.display(),
+            std::io::Error::last_os_error()
+        );
+    }
+    let stat = unsafe { stat.assume_init() };
+    // field sizes differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Bytes available to the current user on the volume containing [path]
+#[cfg(windows)]
+fn free_space(path: &Path) -> Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetDiskFreeSpaceExW(
+            directory: *const u16,
+            free_bytes_available: *mut u64,
+            total_bytes: *mut u64,
+            total_free_bytes: *mut u64,
+        ) -> i32;
+    }
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+    let mut free = 0u64;
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            wide.as_ptr(),
+            &mut free,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        bail!(
+            "Failed to get free space of {}: {}",
+            path.display(),
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(free)
+}
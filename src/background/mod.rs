@@ -1,2 +1,5 @@
+mod disk;
 mod monitor;
+
+pub use disk::*;
 pub use monitor::*;
@@ -1,18 +1,62 @@
+use crate::background::DiskWatchdog;
+use crate::ingress::{file, spawn_pipeline};
 use crate::overseer::Overseer;
 use anyhow::Result;
+use log::error;
 use std::sync::Arc;
+use tokio::runtime::Handle;
 
 /// Monitor stream status, perform any necessary cleanup
 pub struct BackgroundMonitor {
     overseer: Arc<dyn Overseer>,
+    disk: Option<DiskWatchdog>,
+    /// Output dir of pipelines started for [Overseer::due_inputs] and [Overseer::due_pulls]
+    out_dir: Option<String>,
 }
 
 impl BackgroundMonitor {
     pub fn new(overseer: Arc<dyn Overseer>) -> Self {
-        Self { overseer }
+        Self {
+            overseer,
+            disk: None,
+            out_dir: None,
+        }
+    }
+
+    /// Watch free space in the output dir
+    pub fn with_disk_watchdog(mut self, disk: DiskWatchdog) -> Self {
+        self.disk = Some(disk);
+        self
+    }
+
+    /// Stream files the overseer schedules and ingests it pulls, pipelines write to [out_dir]
+    pub fn with_file_inputs(mut self, out_dir: &str) -> Self {
+        self.out_dir = Some(out_dir.to_string());
+        self
     }
 
     pub async fn check(&mut self) -> Result<()> {
-        self.overseer.check_streams().await
+        if let Some(disk) = &self.disk {
+            disk.check()?;
+        }
+        self.overseer.check_streams().await?;
+        if let Some(out_dir) = &self.out_dir {
+            for input in self.overseer.due_inputs().await? {
+                let path = input.path.clone();
+                if let Err(e) = file::play(out_dir.clone(), input, self.overseer.clone()) {
+                    error!("Failed to play {}: {}", path.display(), e);
+                }
+            }
+            for input in self.overseer.due_pulls().await? {
+                spawn_pipeline(
+                    Handle::current(),
+                    input.connection,
+                    out_dir.clone(),
+                    self.overseer.clone(),
+                    input.reader,
+                );
+            }
+        }
+        Ok(())
     }
 }
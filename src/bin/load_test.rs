@@ -0,0 +1,170 @@
+use anyhow::Result;
+use clap::Parser;
+use config::Config;
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::{av_log_set_callback, av_version_info};
+use ffmpeg_rs_raw::rstr;
+use log::{info, warn};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::time::sleep;
+use zap_stream_core::ingress::test::TestPatternSrc;
+use zap_stream_core::ingress::{spawn_pipeline, ConnectionInfo};
+use zap_stream_core::overseer::Overseer;
+use zap_stream_core::pipeline::log::av_log_pipeline;
+use zap_stream_core::settings::Settings;
+
+/// Spawn synthetic test-pattern pipelines against the configured overseer
+/// to find the number of concurrent streams this machine can handle
+#[derive(Parser, Debug)]
+struct Args {
+    /// Max number of concurrent pipelines to start
+    #[arg(short, long, default_value_t = 4)]
+    count: usize,
+
+    /// Width of the test pattern source
+    #[arg(long, default_value_t = 1280)]
+    width: u16,
+
+    /// Height of the test pattern source
+    #[arg(long, default_value_t = 720)]
+    height: u16,
+
+    /// Framerate of the test pattern source
+    #[arg(long, default_value_t = 30.0)]
+    fps: f32,
+
+    /// Seconds to wait between starting each pipeline
+    #[arg(long, default_value_t = 10)]
+    ramp: u64,
+
+    /// Seconds to keep all pipelines running once ramp up is complete
+    #[arg(long, default_value_t = 60)]
+    duration: u64,
+
+    /// Speed (x realtime) below which the machine is considered saturated
+    #[arg(long, default_value_t = 0.95)]
+    threshold: f32,
+}
+
+/// A running synthetic pipeline
+struct LoadTestPipeline {
+    /// Frames produced by the test pattern source
+    frames: Arc<AtomicU64>,
+    /// Frame count at the last sample
+    last_frames: u64,
+    /// Time of the last sample
+    last_sample: Instant,
+}
+
+impl LoadTestPipeline {
+    /// Speed (x realtime) since the last sample, anything below 1.0 means
+    /// the pipeline is not keeping up with its source
+    fn sample(&mut self, fps: f32) -> f32 {
+        let frames = self.frames.load(Ordering::Relaxed);
+        let elapsed = self.last_sample.elapsed().as_secs_f32();
+        let speed = (frames - self.last_frames) as f32 / (elapsed * fps);
+        self.last_frames = frames;
+        self.last_sample = Instant::now();
+        speed
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    pretty_env_logger::init();
+
+    let args = Args::parse();
+
+    unsafe {
+        av_log_set_callback(Some(av_log_pipeline));
+        info!("FFMPEG version={}", rstr!(av_version_info()));
+    }
+
+    let builder = Config::builder()
+        .add_source(config::File::with_name("config.yaml"))
+        .add_source(config::Environment::with_prefix("APP"))
+        .build()?;
+
+    let mut settings = Settings::from_config(builder)?;
+    settings.resolve_secrets()?;
+    settings.validate()?;
+    let overseer = settings.get_overseer().await?;
+
+    info!(
+        "Starting load test: {} pipelines {}x{}@{}fps",
+        args.count, args.width, args.height, args.fps
+    );
+    let mut pipelines = Vec::new();
+    let mut saturated_at = None;
+    for n in 0..args.count {
+        pipelines.push(start_pipeline(n, &args, &settings, &overseer)?);
+        sleep(Duration::from_secs(args.ramp)).await;
+
+        let (min, avg) = sample_speed(&mut pipelines, args.fps);
+        info!(
+            "{} pipelines: min={:.2}x avg={:.2}x",
+            pipelines.len(),
+            min,
+            avg
+        );
+        if min < args.threshold && saturated_at.is_none() {
+            warn!("Saturated at {} concurrent pipelines", pipelines.len());
+            saturated_at = Some(pipelines.len());
+        }
+    }
+
+    sleep(Duration::from_secs(args.duration)).await;
+    let (min, avg) = sample_speed(&mut pipelines, args.fps);
+    info!(
+        "Sustained {} pipelines for {}s: min={:.2}x avg={:.2}x",
+        pipelines.len(),
+        args.duration,
+        min,
+        avg
+    );
+    match saturated_at {
+        Some(n) => info!("Max concurrent pipelines: {}", n - 1),
+        None if min < args.threshold => info!("Max concurrent pipelines: {}", args.count - 1),
+        None => info!("No saturation detected up to {} pipelines", args.count),
+    }
+    Ok(())
+}
+
+fn start_pipeline(
+    n: usize,
+    args: &Args,
+    settings: &Settings,
+    overseer: &Arc<dyn Overseer>,
+) -> Result<LoadTestPipeline> {
+    let info = ConnectionInfo {
+        endpoint: "load-test".to_string(),
+        ip_addr: format!("load-test-{}", n),
+        app_name: "".to_string(),
+        key: "test".to_string(),
+        ..Default::default()
+    };
+    let src = TestPatternSrc::new(args.width, args.height, args.fps)?;
+    let frames = src.frame_counter();
+    spawn_pipeline(
+        Handle::current(),
+        info,
+        settings.output_dir.clone(),
+        overseer.clone(),
+        Box::new(src),
+    );
+    Ok(LoadTestPipeline {
+        frames,
+        last_frames: 0,
+        last_sample: Instant::now(),
+    })
+}
+
+/// Sample all pipelines and return the (min, avg) speed
+fn sample_speed(pipelines: &mut [LoadTestPipeline], fps: f32) -> (f32, f32) {
+    let speeds: Vec<f32> = pipelines.iter_mut().map(|p| p.sample(fps)).collect();
+    let min = speeds.iter().cloned().fold(f32::MAX, f32::min);
+    let avg = speeds.iter().sum::<f32>() / speeds.len() as f32;
+    (min, avg)
+}
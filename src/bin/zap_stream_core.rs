@@ -2,42 +2,69 @@ use anyhow::{bail, Result};
 use clap::Parser;
 use config::Config;
 use ffmpeg_rs_raw::ffmpeg_sys_the_third::{av_log_set_callback, av_version_info};
-use ffmpeg_rs_raw::{av_log_redirect, rstr};
-use hyper::server::conn::http1;
-use hyper_util::rt::TokioIo;
+use ffmpeg_rs_raw::rstr;
 use log::{error, info};
-use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::task::JoinHandle;
 use tokio::time::sleep;
+use tokio_rustls::TlsAcceptor;
 use url::Url;
-use zap_stream_core::background::BackgroundMonitor;
-use zap_stream_core::http::HttpServer;
+#[cfg(feature = "acme")]
+use zap_stream_core::acme;
+use zap_stream_core::background::{BackgroundMonitor, DiskWatchdog};
+#[cfg(feature = "grpc")]
+use zap_stream_core::control::{self, ControlOverseer};
+use zap_stream_core::http::{HttpServer, IpRange};
+#[cfg(feature = "http3")]
+use zap_stream_core::http3;
+#[cfg(feature = "rist")]
+use zap_stream_core::ingress::rist;
 #[cfg(feature = "rtmp")]
 use zap_stream_core::ingress::rtmp;
 #[cfg(feature = "srt")]
 use zap_stream_core::ingress::srt;
 #[cfg(feature = "test-pattern")]
 use zap_stream_core::ingress::test;
+#[cfg(unix)]
+use zap_stream_core::ingress::unix;
+#[cfg(feature = "stt")]
+use zap_stream_core::pipeline::stt;
 
-use zap_stream_core::ingress::{file, tcp};
+use zap_stream_core::ingress::{file, listen_addr, pipe, resolve_addr, tcp};
 use zap_stream_core::overseer::Overseer;
+use zap_stream_core::pipeline::log::av_log_pipeline;
 use zap_stream_core::settings::Settings;
+use zap_stream_core::tls::CertStore;
+
+/// Default min free space (MB) in the output dir
+const DEFAULT_MIN_FREE_SPACE: u64 = 1024;
 
 #[derive(Parser, Debug)]
-struct Args {}
+struct Args {
+    /// Publish MPEG-TS read from stdin, i.e. `ffmpeg ... -f mpegts - | zap-stream-core --stdin`
+    #[arg(long)]
+    stdin: bool,
+
+    /// Publish MPEG-TS read from a named pipe (FIFO)
+    #[arg(long, conflicts_with = "stdin")]
+    fifo: Option<PathBuf>,
+
+    /// Stream key piped input is published with
+    #[arg(long, default_value = "test")]
+    key: String,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     pretty_env_logger::init();
 
-    let _args = Args::parse();
+    let args = Args::parse();
 
     unsafe {
-        av_log_set_callback(Some(av_log_redirect));
+        av_log_set_callback(Some(av_log_pipeline));
         info!("FFMPEG version={}", rstr!(av_version_info()));
     }
 
@@ -46,42 +73,128 @@ async fn main() -> Result<()> {
         .add_source(config::Environment::with_prefix("APP"))
         .build()?;
 
-    let settings: Settings = builder.try_deserialize()?;
+    let mut settings = Settings::from_config(builder)?;
+    settings.resolve_secrets()?;
+    settings.validate()?;
+    #[cfg(not(feature = "acme"))]
+    if settings.acme.is_some() {
+        bail!("acme requires the acme feature");
+    }
+    #[cfg(feature = "stt")]
+    if let Some(s) = &settings.speech_to_text {
+        stt::configure(s.clone());
+    }
     let overseer = settings.get_overseer().await?;
 
     let mut tasks = vec![];
+    #[cfg(feature = "grpc")]
+    let overseer: Arc<dyn Overseer> = match &settings.listen_grpc {
+        Some(addr) => {
+            let control = Arc::new(ControlOverseer::new(overseer));
+            tasks.push(tokio::spawn(control::listen(
+                resolve_addr(addr).await?,
+                control.clone(),
+            )));
+            control
+        }
+        None => overseer,
+    };
+    let mut listeners = vec![];
     for e in &settings.endpoints {
-        match try_create_listener(e, &settings.output_dir, &overseer) {
-            Ok(l) => tasks.push(l),
+        match try_create_listener(e, &settings, &overseer) {
+            Ok(l) => {
+                listeners.push((e.clone(), l.abort_handle()));
+                tasks.push(l);
+            }
             Err(e) => error!("{}", e),
         }
     }
 
-    let http_addr: SocketAddr = settings.listen_http.parse()?;
+    if args.stdin || args.fifo.is_some() {
+        tasks.push(tokio::spawn(pipe::listen(
+            settings.output_dir.clone(),
+            args.fifo.clone(),
+            args.key.clone(),
+            overseer.clone(),
+        )));
+    }
+
+    let http_addr = resolve_addr(&settings.listen_http).await?;
     let index_html = include_str!("../index.html").replace("%%PUBLIC_URL%%", &settings.public_url);
 
-    let server = HttpServer::new(
+    let mut server = HttpServer::new(
         index_html,
-        PathBuf::from(settings.output_dir),
+        PathBuf::from(&settings.output_dir),
         overseer.clone(),
-    );
-    tasks.push(tokio::spawn(async move {
-        let listener = TcpListener::bind(&http_addr).await?;
-
-        loop {
-            let (socket, _) = listener.accept().await?;
-            let io = TokioIo::new(socket);
-            let server = server.clone();
-            tokio::spawn(async move {
-                if let Err(e) = http1::Builder::new().serve_connection(io, server).await {
-                    error!("Failed to handle request: {}", e);
-                }
-            });
+    )
+    .with_listeners(listeners);
+    if let Some(r) = &settings.replication {
+        server = server.with_replica_token(&r.token);
+    }
+    if settings.verify_segments.unwrap_or(false) {
+        server = server.with_segment_verification();
+    }
+    if let Some(rules) = &settings.playlist_rules {
+        server = server.with_playlist_rules(rules.clone());
+    }
+    if let Some(proxies) = &settings.trusted_proxies {
+        let proxies = proxies
+            .iter()
+            .map(|p| p.parse())
+            .collect::<Result<Vec<IpRange>>>()?;
+        server = server.with_trusted_proxies(proxies);
+    }
+    let certs = match &settings.tls {
+        Some(t) => Some(Arc::new(CertStore::new(t)?)),
+        None => None,
+    };
+    #[cfg(feature = "acme")]
+    let acme_certs = match &settings.acme {
+        Some(a) => Some(acme::AcmeCerts::new(&settings.public_url, a)?),
+        None => None,
+    };
+    // the ACME certificate is used instead of [tls] by the https and http3 servers
+    let server_config = |alpn: &[&[u8]]| -> Result<Arc<rustls::ServerConfig>> {
+        #[cfg(feature = "acme")]
+        if let Some(acme) = &acme_certs {
+            return acme.server_config(alpn);
         }
-    }));
+        match &certs {
+            Some(certs) => certs.clone().server_config(alpn),
+            None => bail!("listen_https and listen_http3 require tls or acme settings"),
+        }
+    };
+    #[cfg(feature = "http3")]
+    let server = match &settings.listen_http3 {
+        Some(addr) => {
+            let addr = resolve_addr(addr).await?;
+            let server = server.with_http3(addr.port());
+            tasks.push(tokio::spawn(http3::listen(
+                addr,
+                server.clone(),
+                server_config(&[b"h3"])?,
+            )));
+            server
+        }
+        None => server,
+    };
+    if let Some(addr) = &settings.listen_https {
+        let tls = TlsAcceptor::from(server_config(&[b"h2", b"http/1.1"])?);
+        let listener = TcpListener::bind(addr).await?;
+        info!("HTTPS listening on: {}", addr);
+        tasks.push(tokio::spawn(server.clone().listen(listener, Some(tls))));
+    }
+    let listener = TcpListener::bind(&http_addr).await?;
+    info!("HTTP listening on: {}", http_addr);
+    tasks.push(tokio::spawn(server.listen(listener, None)));
 
     // spawn background job
-    let mut bg = BackgroundMonitor::new(overseer.clone());
+    let mut bg = BackgroundMonitor::new(overseer.clone())
+        .with_disk_watchdog(DiskWatchdog::new(
+            &settings.output_dir,
+            settings.min_free_space.unwrap_or(DEFAULT_MIN_FREE_SPACE) * 1024 * 1024,
+        ))
+        .with_file_inputs(&settings.output_dir);
     tasks.push(tokio::spawn(async move {
         loop {
             if let Err(e) = bg.check().await {
@@ -91,37 +204,94 @@ async fn main() -> Result<()> {
         }
     }));
 
-    for handle in tasks {
-        if let Err(e) = handle.await? {
-            error!("{e}");
+    let run = async {
+        for handle in tasks {
+            if let Err(e) = handle.await? {
+                error!("{e}");
+            }
+        }
+        anyhow::Ok(())
+    };
+    tokio::select! {
+        r = run => r?,
+        r = shutdown_signal() => {
+            r?;
+            // pipelines are not stopped, ending them would end their streams
+            info!("Shutting down");
+            if let Err(e) = overseer.on_shutdown().await {
+                error!("Failed to save state on shutdown: {}", e);
+            }
         }
     }
     info!("Server closed");
     Ok(())
 }
 
+/// Wait for ctrl-c, or SIGTERM on unix
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut term = signal(SignalKind::terminate())?;
+        tokio::select! {
+            r = tokio::signal::ctrl_c() => r?,
+            _ = term.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
 fn try_create_listener(
     u: &str,
-    out_dir: &str,
+    settings: &Settings,
     overseer: &Arc<dyn Overseer>,
 ) -> Result<JoinHandle<Result<()>>> {
     let url: Url = u.parse()?;
+    let out_dir = &settings.output_dir;
     match url.scheme() {
         #[cfg(feature = "srt")]
         "srt" => Ok(tokio::spawn(srt::listen(
             out_dir.to_string(),
-            format!("{}:{}", url.host().unwrap(), url.port().unwrap()),
+            listen_addr(&url)?,
             overseer.clone(),
         ))),
-        #[cfg(feature = "srt")]
+        #[cfg(feature = "rist")]
+        "rist" => Ok(tokio::spawn(rist::listen(
+            out_dir.to_string(),
+            url.clone(),
+            overseer.clone(),
+        ))),
+        #[cfg(feature = "rtmp")]
         "rtmp" => Ok(tokio::spawn(rtmp::listen(
             out_dir.to_string(),
-            format!("{}:{}", url.host().unwrap(), url.port().unwrap()),
+            listen_addr(&url)?,
             overseer.clone(),
+            None,
         ))),
+        #[cfg(feature = "rtmp")]
+        "rtmps" => {
+            let tls = match &settings.tls {
+                Some(t) => Arc::new(CertStore::new(t)?).acceptor(&[])?,
+                None => bail!("rtmps endpoint {u} requires tls settings"),
+            };
+            Ok(tokio::spawn(rtmp::listen(
+                out_dir.to_string(),
+                listen_addr(&url)?,
+                overseer.clone(),
+                Some(tls),
+            )))
+        }
         "tcp" => Ok(tokio::spawn(tcp::listen(
             out_dir.to_string(),
-            format!("{}:{}", url.host().unwrap(), url.port().unwrap()),
+            listen_addr(&url)?,
+            overseer.clone(),
+        ))),
+        #[cfg(unix)]
+        "unix" => Ok(tokio::spawn(unix::listen(
+            out_dir.to_string(),
+            url.clone(),
             overseer.clone(),
         ))),
         "file" => Ok(tokio::spawn(file::listen(
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use base64::Engine;
 use nostr_sdk::{EventBuilder, JsonUtil, Keys, Kind, Tag, Timestamp};
 use serde::{Deserialize, Serialize};
@@ -91,4 +91,21 @@ impl Blossom {
 
         Ok(rsp)
     }
+
+    /// Download the blob with [sha256], failing if the content doesn't match the hash
+    pub async fn download(&self, sha256: &str) -> Result<Vec<u8>> {
+        let rsp = self
+            .client
+            .get(self.url.join(&format!("/{}", sha256))?)
+            .send()
+            .await?;
+        if !rsp.status().is_success() {
+            bail!("Blob {} not found: {}", sha256, rsp.status());
+        }
+        let data = rsp.bytes().await?;
+        if hex::encode(Sha256::digest(&data)) != sha256 {
+            bail!("Blob {} hash mismatch", sha256);
+        }
+        Ok(data.to_vec())
+    }
 }
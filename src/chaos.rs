@@ -0,0 +1,63 @@
+use anyhow::{bail, Result};
+use log::warn;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// Fault injection settings, all disabled by default
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChaosConfig {
+    /// Percentage (0-100) of relay publishes to drop
+    pub relay_drop_percent: u8,
+    /// Delay added to every LND call in milliseconds
+    pub lnd_delay_ms: u64,
+    /// Percentage (0-100) of blossom uploads to fail
+    pub blossom_fail_percent: u8,
+}
+
+/// Fault injection layer for testing retry/queue behaviour against real infrastructure
+///
+/// Only compiled with the `chaos` feature, controlled at runtime via the admin API
+#[derive(Default)]
+pub struct Chaos {
+    config: RwLock<ChaosConfig>,
+}
+
+impl Chaos {
+    pub async fn get(&self) -> ChaosConfig {
+        self.config.read().await.clone()
+    }
+
+    pub async fn set(&self, config: ChaosConfig) {
+        warn!("Chaos config updated: {:?}", config);
+        *self.config.write().await = config;
+    }
+
+    /// Returns true if this relay publish should be dropped
+    pub async fn drop_relay_publish(&self) -> bool {
+        let pct = self.config.read().await.relay_drop_percent;
+        Self::roll(pct)
+    }
+
+    /// Sleep for the configured LND delay
+    pub async fn delay_lnd(&self) {
+        let delay = self.config.read().await.lnd_delay_ms;
+        if delay > 0 {
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+        }
+    }
+
+    /// Returns an error if this blossom upload should fail
+    pub async fn fail_blossom_upload(&self) -> Result<()> {
+        let pct = self.config.read().await.blossom_fail_percent;
+        if Self::roll(pct) {
+            bail!("Chaos: blossom upload failed");
+        }
+        Ok(())
+    }
+
+    fn roll(pct: u8) -> bool {
+        pct > 0 && rand::thread_rng().gen_range(0..100) < pct
+    }
+}
@@ -0,0 +1,399 @@
+use crate::egress::recorder::RecordingFile;
+use crate::ingress::file::FileInput;
+use crate::ingress::{ConnectionInfo, EndpointStats, PulledInput};
+use crate::overseer::{IngressInfo, Overseer};
+use crate::pipeline::timing::SegmentTiming;
+use crate::pipeline::{AdBreak, PipelineConfig, PipelineControl};
+use crate::status::ServerStatus;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::Utc;
+use http_body_util::combinators::BoxBody;
+use hyper::body::Incoming;
+use hyper::{Request, Response};
+use log::info;
+use proto::control_server::{Control, ControlServer};
+use proto::pipeline_command::Command;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tonic::Status;
+use uuid::Uuid;
+
+pub mod proto {
+    tonic::include_proto!("zap_stream.control.v1");
+}
+
+/// Serve the [proto::control_server::Control] service for the pipelines of [overseer]
+pub async fn listen(addr: SocketAddr, overseer: Arc<ControlOverseer>) -> Result<()> {
+    info!("gRPC listening on: {}", addr);
+    tonic::transport::Server::builder()
+        .add_service(ControlServer::from_arc(overseer))
+        .serve(addr)
+        .await?;
+    Ok(())
+}
+
+/// Overseer which tracks the running pipelines for the control API and applies its
+/// commands on top of the [PipelineControl] of the wrapped overseer
+///
+/// Everything else is passed through, so any overseer (or an embedding application's own)
+/// can be controlled without the zap-stream business logic
+pub struct ControlOverseer {
+    inner: Arc<dyn Overseer>,
+    pipelines: RwLock<HashMap<Uuid, PipelineState>>,
+}
+
+struct PipelineState {
+    connection: ConnectionInfo,
+    started: i64,
+    variants: Vec<String>,
+    egress: Vec<String>,
+    segments: u64,
+    /// Seconds written by each variant
+    durations: HashMap<Uuid, f32>,
+    endpoint_stats: Option<EndpointStats>,
+    /// Last control of the wrapped overseer with the overrides applied
+    control: PipelineControl,
+    overrides: ControlOverrides,
+    stop: bool,
+}
+
+/// Changes requested over the control API, unset fields follow the wrapped overseer
+#[derive(Default)]
+struct ControlOverrides {
+    slate: Option<(bool, Option<PathBuf>, Option<PathBuf>)>,
+    recording: Option<bool>,
+    playlist_window: Option<Option<usize>>,
+    ad_break: Option<Option<AdBreak>>,
+}
+
+impl ControlOverrides {
+    fn is_empty(&self) -> bool {
+        self.slate.is_none()
+            && self.recording.is_none()
+            && self.playlist_window.is_none()
+            && self.ad_break.is_none()
+    }
+
+    fn apply(&self, mut control: PipelineControl) -> PipelineControl {
+        if let Some((slate, image, audio)) = &self.slate {
+            control.slate = *slate;
+            control.slate_image = image.clone();
+            control.slate_audio = audio.clone();
+        }
+        if let Some(r) = self.recording {
+            control.recording = r;
+        }
+        if let Some(w) = self.playlist_window {
+            control.playlist_window = w;
+        }
+        if let Some(a) = &self.ad_break {
+            control.ad_break = a.clone();
+        }
+        control
+    }
+}
+
+impl ControlOverseer {
+    pub fn new(inner: Arc<dyn Overseer>) -> Self {
+        Self {
+            inner,
+            pipelines: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Change the overrides of a running pipeline, applied after its next segment
+    async fn update(
+        &self,
+        id: &str,
+        f: impl FnOnce(&mut PipelineState) -> Result<(), Status>,
+    ) -> Result<(), Status> {
+        let id = parse_id(id)?;
+        let mut pipelines = self.pipelines.write().await;
+        let Some(p) = pipelines.get_mut(&id) else {
+            return Err(Status::not_found(format!("Pipeline {} is not running", id)));
+        };
+        f(p)
+    }
+}
+
+fn parse_id(id: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(id).map_err(|_| Status::invalid_argument(format!("Invalid id '{}'", id)))
+}
+
+#[async_trait]
+impl Control for ControlOverseer {
+    async fn list_pipelines(
+        &self,
+        _request: tonic::Request<proto::ListPipelinesRequest>,
+    ) -> Result<tonic::Response<proto::ListPipelinesResponse>, Status> {
+        let pipelines = self
+            .pipelines
+            .read()
+            .await
+            .iter()
+            .map(|(id, p)| proto::Pipeline {
+                id: id.to_string(),
+                endpoint: p.connection.endpoint.clone(),
+                ip_addr: p.connection.ip_addr.clone(),
+                app_name: p.connection.app_name.clone(),
+                started: p.started,
+                variants: p.variants.clone(),
+                egress: p.egress.clone(),
+            })
+            .collect();
+        Ok(tonic::Response::new(proto::ListPipelinesResponse {
+            pipelines,
+        }))
+    }
+
+    async fn get_stats(
+        &self,
+        request: tonic::Request<proto::PipelineRequest>,
+    ) -> Result<tonic::Response<proto::PipelineStats>, Status> {
+        let id = parse_id(&request.get_ref().id)?;
+        let pipelines = self.pipelines.read().await;
+        let Some(p) = pipelines.get(&id) else {
+            return Err(Status::not_found(format!("Pipeline {} is not running", id)));
+        };
+        Ok(tonic::Response::new(proto::PipelineStats {
+            id: id.to_string(),
+            segments: p.segments,
+            duration: p.durations.values().fold(0.0f32, |a, d| a.max(*d)) as f64,
+            endpoint: p.endpoint_stats.as_ref().map(|s| proto::EndpointStats {
+                rtt_ms: s.rtt_ms,
+                latency_ms: s.latency_ms,
+                received_packets: s.received_packets,
+                lost_packets: s.lost_packets,
+                retransmitted_packets: s.retransmitted_packets,
+                dropped_packets: s.dropped_packets,
+            }),
+            slate: p.control.slate,
+            recording: p.control.recording,
+            playlist_window: p.control.playlist_window.map(|w| w as u32),
+            ad_break: p.control.ad_break.as_ref().map(|a| proto::AdBreak {
+                id: a.id,
+                duration: a.duration,
+            }),
+        }))
+    }
+
+    async fn send_command(
+        &self,
+        request: tonic::Request<proto::PipelineCommand>,
+    ) -> Result<tonic::Response<proto::CommandResponse>, Status> {
+        let cmd = request.into_inner();
+        self.update(&cmd.id, |p| {
+            match cmd.command {
+                Some(Command::Slate(s)) => {
+                    p.overrides.slate = Some((
+                        s.enabled,
+                        s.image.map(PathBuf::from),
+                        s.audio.map(PathBuf::from),
+                    ))
+                }
+                Some(Command::StartAdBreak(a)) => {
+                    if a.duration == 0 {
+                        return Err(Status::invalid_argument("Ad break duration must be set"));
+                    }
+                    p.overrides.ad_break = Some(Some(AdBreak {
+                        id: a.id,
+                        duration: a.duration,
+                    }))
+                }
+                Some(Command::EndAdBreak(_)) => p.overrides.ad_break = Some(None),
+                Some(Command::Stop(_)) => p.stop = true,
+                None => return Err(Status::invalid_argument("No command")),
+            }
+            Ok(())
+        })
+        .await?;
+        Ok(tonic::Response::new(proto::CommandResponse {}))
+    }
+
+    async fn reconfigure_egress(
+        &self,
+        request: tonic::Request<proto::ReconfigureEgressRequest>,
+    ) -> Result<tonic::Response<proto::CommandResponse>, Status> {
+        let req = request.into_inner();
+        self.update(&req.id, |p| {
+            if let Some(r) = req.recording {
+                p.overrides.recording = Some(r);
+            }
+            if let Some(w) = req.playlist_window {
+                p.overrides.playlist_window = Some(if w == 0 { None } else { Some(w as usize) });
+            }
+            Ok(())
+        })
+        .await?;
+        Ok(tonic::Response::new(proto::CommandResponse {}))
+    }
+}
+
+#[async_trait]
+impl Overseer for ControlOverseer {
+    async fn api(&self, req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, anyhow::Error>>> {
+        self.inner.api(req).await
+    }
+
+    async fn check_streams(&self) -> Result<()> {
+        self.inner.check_streams().await
+    }
+
+    async fn due_inputs(&self) -> Result<Vec<FileInput>> {
+        self.inner.due_inputs().await
+    }
+
+    async fn due_pulls(&self) -> Result<Vec<PulledInput>> {
+        self.inner.due_pulls().await
+    }
+
+    async fn status(&self) -> Result<ServerStatus> {
+        self.inner.status().await
+    }
+
+    async fn check_connection(&self, connection: &ConnectionInfo) -> Result<()> {
+        self.inner.check_connection(connection).await
+    }
+
+    async fn start_stream(
+        &self,
+        connection: &ConnectionInfo,
+        stream_info: &IngressInfo,
+    ) -> Result<PipelineConfig> {
+        let cfg = self.inner.start_stream(connection, stream_info).await?;
+        self.pipelines.write().await.insert(
+            cfg.id,
+            PipelineState {
+                connection: connection.clone(),
+                started: Utc::now().timestamp(),
+                variants: cfg.variants.iter().map(|v| v.to_string()).collect(),
+                egress: cfg.egress.iter().map(|e| e.to_string()).collect(),
+                segments: 0,
+                durations: HashMap::new(),
+                endpoint_stats: None,
+                control: PipelineControl::default(),
+                overrides: ControlOverrides::default(),
+                stop: false,
+            },
+        );
+        Ok(cfg)
+    }
+
+    async fn on_segment(
+        &self,
+        pipeline_id: &Uuid,
+        variant_id: &Uuid,
+        index: u64,
+        duration: f32,
+        path: &PathBuf,
+    ) -> Result<()> {
+        if let Some(p) = self.pipelines.write().await.get_mut(pipeline_id) {
+            if p.stop {
+                bail!("Pipeline stopped by the control API");
+            }
+            p.segments += 1;
+            *p.durations.entry(*variant_id).or_default() += duration;
+        }
+        self.inner
+            .on_segment(pipeline_id, variant_id, index, duration, path)
+            .await
+    }
+
+    async fn on_thumbnail(
+        &self,
+        pipeline_id: &Uuid,
+        width: usize,
+        height: usize,
+        path: &PathBuf,
+    ) -> Result<()> {
+        self.inner
+            .on_thumbnail(pipeline_id, width, height, path)
+            .await
+    }
+
+    async fn on_end(&self, pipeline_id: &Uuid) -> Result<()> {
+        self.pipelines.write().await.remove(pipeline_id);
+        self.inner.on_end(pipeline_id).await
+    }
+
+    async fn on_shutdown(&self) -> Result<()> {
+        self.inner.on_shutdown().await
+    }
+
+    async fn pipeline_control(&self, pipeline_id: &Uuid) -> Result<Option<PipelineControl>> {
+        let control = self.inner.pipeline_control(pipeline_id).await?;
+        let mut pipelines = self.pipelines.write().await;
+        let Some(p) = pipelines.get_mut(pipeline_id) else {
+            return Ok(control);
+        };
+        if control.is_none() && p.overrides.is_empty() {
+            return Ok(None);
+        }
+        p.control = p
+            .overrides
+            .apply(control.unwrap_or_else(|| p.control.clone()));
+        Ok(Some(p.control.clone()))
+    }
+
+    async fn on_endpoint_stats(&self, pipeline_id: &Uuid, stats: &EndpointStats) -> Result<()> {
+        if let Some(p) = self.pipelines.write().await.get_mut(pipeline_id) {
+            p.endpoint_stats = Some(stats.clone());
+        }
+        self.inner.on_endpoint_stats(pipeline_id, stats).await
+    }
+
+    async fn on_segment_timing(&self, pipeline_id: &Uuid, timing: &SegmentTiming) -> Result<()> {
+        self.inner.on_segment_timing(pipeline_id, timing).await
+    }
+
+    async fn check_playback(
+        &self,
+        stream_id: &Uuid,
+        file: &str,
+        token: Option<&str>,
+    ) -> Result<bool> {
+        self.inner.check_playback(stream_id, file, token).await
+    }
+
+    async fn on_view(&self, stream_id: &Uuid, viewer: &str) -> Result<()> {
+        self.inner.on_view(stream_id, viewer).await
+    }
+
+    async fn on_egress(&self, stream_id: &Uuid, bytes: u64) -> Result<()> {
+        self.inner.on_egress(stream_id, bytes).await
+    }
+
+    async fn on_pipeline_crash(
+        &self,
+        pipeline_id: &Uuid,
+        error: &str,
+        restarting: bool,
+    ) -> Result<()> {
+        self.inner
+            .on_pipeline_crash(pipeline_id, error, restarting)
+            .await
+    }
+
+    async fn on_recording_paused(&self, pipeline_id: &Uuid, paused: bool) -> Result<()> {
+        self.inner.on_recording_paused(pipeline_id, paused).await
+    }
+
+    async fn on_recording_complete(
+        &self,
+        pipeline_id: &Uuid,
+        recordings: &[RecordingFile],
+    ) -> Result<()> {
+        self.inner
+            .on_recording_complete(pipeline_id, recordings)
+            .await
+    }
+
+    async fn repair_segment(&self, path: &Path, sha256: &str) -> Result<bool> {
+        self.inner.repair_segment(path, sha256).await
+    }
+}
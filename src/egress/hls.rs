@@ -2,8 +2,9 @@ use anyhow::Result;
 use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVPacket;
 use uuid::Uuid;
 
-use crate::egress::{Egress, EgressResult};
-use crate::mux::HlsMuxer;
+use crate::egress::{Egress, EgressResult, WallClock};
+use crate::mux::{Cue, HlsMuxer, DEFAULT_PLAYLIST_WINDOW};
+use crate::pipeline::AdBreak;
 
 /// Alias the muxer directly
 pub type HlsEgress = HlsMuxer;
@@ -27,4 +28,26 @@ impl Egress for HlsMuxer {
         }
         Ok(())
     }
+
+    fn set_playlist_window(&mut self, segments: Option<usize>) {
+        for var in &mut self.variants {
+            var.playlist_window = segments.unwrap_or(DEFAULT_PLAYLIST_WINDOW);
+        }
+    }
+
+    fn set_ad_break(&mut self, ad_break: Option<&AdBreak>) {
+        for var in &mut self.variants {
+            var.set_ad_break(ad_break);
+        }
+    }
+
+    fn set_wall_clock(&mut self, clock: WallClock) {
+        for var in &mut self.variants {
+            var.wall_clock = Some(clock);
+        }
+    }
+
+    fn add_cues(&mut self, cues: &[Cue]) {
+        HlsMuxer::add_cues(self, cues);
+    }
 }
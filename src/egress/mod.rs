@@ -1,4 +1,7 @@
+use crate::mux::Cue;
+use crate::pipeline::AdBreak;
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVPacket;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
@@ -19,6 +22,18 @@ pub trait Egress {
     unsafe fn process_pkt(&mut self, packet: *mut AVPacket, variant: &Uuid)
         -> Result<EgressResult>;
     unsafe fn reset(&mut self) -> Result<()>;
+
+    /// Number of segments kept in live playlists, [None] for the default window
+    fn set_playlist_window(&mut self, _segments: Option<usize>) {}
+
+    /// Start or end (with [None]) an ad break at the next segment
+    fn set_ad_break(&mut self, _ad_break: Option<&AdBreak>) {}
+
+    /// Wall clock of the stream timeline, segments are dated from it
+    fn set_wall_clock(&mut self, _clock: WallClock) {}
+
+    /// Captions decoded from the input, see [crate::pipeline::captions]
+    fn add_cues(&mut self, _cues: &[Cue]) {}
 }
 
 #[derive(Debug, Clone)]
@@ -29,6 +44,23 @@ pub enum EgressResult {
     NewSegment(NewSegment),
 }
 
+/// Wall clock time of a point on the stream timeline, taken when the input packet was
+/// received
+#[derive(Debug, Clone, Copy)]
+pub struct WallClock {
+    /// Stream time in seconds
+    pub pts: f64,
+    /// Time the packet at [pts] was received
+    pub received: DateTime<Utc>,
+}
+
+impl WallClock {
+    /// Wall clock time of stream time [pts]
+    pub fn at(&self, pts: f64) -> DateTime<Utc> {
+        self.received + chrono::Duration::microseconds(((pts - self.pts) * 1e6) as i64)
+    }
+}
+
 /// Basic details of new segment created by a muxer
 #[derive(Debug, Clone)]
 pub struct NewSegment {
@@ -38,6 +70,8 @@ pub struct NewSegment {
     pub idx: u64,
     /// Duration in seconds
     pub duration: f32,
+    /// Presentation time of the first packet in seconds
+    pub pts_start: f32,
     /// Path on disk to the segment file
     pub path: PathBuf,
 }
@@ -7,6 +7,7 @@ use std::path::PathBuf;
 use uuid::Uuid;
 
 use crate::egress::{Egress, EgressResult};
+use crate::mux::remux_recording;
 use crate::variant::{StreamMapping, VariantStream};
 
 pub struct RecorderEgress {
@@ -16,20 +17,74 @@ pub struct RecorderEgress {
     muxer: Muxer,
     /// Mapping from Variant ID to stream index
     var_map: HashMap<Uuid, i32>,
+    /// File the muxer writes to
+    out_file: PathBuf,
+    /// Streams of each video variant with the audio of its group, remuxed into one MP4
+    /// each once the recording is finished, if enabled
+    mp4_outputs: Option<Vec<(Uuid, Vec<i32>)>>,
+}
+
+/// Recording of a video variant remuxed into a faststart MP4
+#[derive(Debug, Clone)]
+pub struct RecordingFile {
+    /// Video variant in the file
+    pub variant: Uuid,
+    pub path: PathBuf,
+    /// Duration in seconds
+    pub duration: f32,
+}
+
+/// Remux of a finished recording into one MP4 per video variant, see [RecorderEgress::remux]
+pub struct RecordingRemux {
+    src: PathBuf,
+    outputs: Vec<(Uuid, Vec<i32>)>,
+}
+
+impl RecordingRemux {
+    /// Write the MP4s next to the recording, this reads the whole recording so it should
+    /// not run on the pipeline thread
+    pub fn run(&self) -> Result<Vec<RecordingFile>> {
+        let stem = self
+            .src
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut ret = vec![];
+        for (variant, streams) in &self.outputs {
+            let path = self.src.with_file_name(format!("{}-{}.mp4", stem, variant));
+            let duration = remux_recording(&self.src, &path, streams)?;
+            ret.push(RecordingFile {
+                variant: *variant,
+                path,
+                duration,
+            });
+        }
+        Ok(ret)
+    }
 }
 
 impl RecorderEgress {
+    /// Record [variants] of the pipeline [id], with [mp4] the recording is also remuxed
+    /// into a faststart MP4 per video variant when it's finished (see [Self::remux])
     pub fn new<'a>(
         id: &Uuid,
         out_dir: &str,
         variants: impl Iterator<Item = (&'a VariantStream, &'a Encoder)>,
+        mp4: bool,
     ) -> Result<Self> {
         let base = PathBuf::from(out_dir).join(id.to_string());
 
-        let out_file = base.join("recording.ts");
+        // recording can be restarted while live, don't overwrite earlier parts
+        let mut out_file = base.join("recording.ts");
+        let mut part = 1;
+        while out_file.exists() {
+            out_file = base.join(format!("recording-{}.ts", part));
+            part += 1;
+        }
         fs::create_dir_all(&base)?;
 
         let mut var_map = HashMap::new();
+        let mut streams = vec![];
         let muxer = unsafe {
             let mut m = Muxer::builder()
                 .with_output_path(out_file.to_str().unwrap(), None)?
@@ -37,14 +92,46 @@ impl RecorderEgress {
             for (var, enc) in variants {
                 let stream = m.add_stream_encoder(enc)?;
                 var_map.insert(var.id(), (*stream).index);
+                streams.push((var.clone(), (*stream).index));
             }
             m.open(None)?;
             m
         };
+        let mp4_outputs = mp4.then(|| Self::mp4_outputs(&streams));
         Ok(Self {
             id: *id,
             muxer,
             var_map,
+            out_file,
+            mp4_outputs,
+        })
+    }
+
+    /// Output streams of each video variant, with the audio of its group or the first
+    /// audio variant if the group has none
+    fn mp4_outputs(streams: &[(VariantStream, i32)]) -> Vec<(Uuid, Vec<i32>)> {
+        let is_audio =
+            |v: &VariantStream| matches!(v, VariantStream::Audio(_) | VariantStream::CopyAudio(_));
+        streams
+            .iter()
+            .filter(|(v, _)| matches!(v, VariantStream::Video(_) | VariantStream::CopyVideo(_)))
+            .map(|(v, idx)| {
+                let audio = streams
+                    .iter()
+                    .find(|(a, _)| is_audio(a) && a.group_id() == v.group_id())
+                    .or_else(|| streams.iter().find(|(a, _)| is_audio(a)));
+                let mut out = vec![*idx];
+                out.extend(audio.map(|(_, i)| *i));
+                (v.id(), out)
+            })
+            .collect()
+    }
+
+    /// MP4 remux of the recording, call after [Egress::reset] closed the recording
+    pub fn remux(&self) -> Option<RecordingRemux> {
+        Some(RecordingRemux {
+            src: self.out_file.clone(),
+            outputs: self.mp4_outputs.clone()?,
         })
     }
 }
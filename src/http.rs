@@ -1,23 +1,242 @@
+use crate::mux::checksum_path;
 use crate::overseer::Overseer;
+use crate::pipeline::log::PIPELINE_LOG;
+use crate::settings::PlaylistRule;
+use crate::status::{ComponentStatus, ServerStatus};
+use anyhow::{bail, Result};
 use bytes::Bytes;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
 use futures_util::TryStreamExt;
 use http_body_util::combinators::BoxBody;
 use http_body_util::{BodyExt, Full, StreamBody};
-use hyper::body::{Frame, Incoming};
+use hyper::body::{Body, Frame, Incoming};
+use hyper::header::HeaderMap;
 use hyper::service::Service;
-use hyper::{Method, Request, Response};
+use hyper::{Method, Request, Response, StatusCode, Uri};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use log::{error, info, warn};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
 use std::future::Future;
-use std::path::PathBuf;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Component, Path, PathBuf};
 use std::pin::Pin;
-use std::sync::Arc;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "zap-stream")]
+use subtle::ConstantTimeEq;
 use tokio::fs::File;
+use tokio::net::TcpListener;
+use tokio::task::AbortHandle;
+use tokio_rustls::TlsAcceptor;
 use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+/// IP of the client which made a request, stored in request extensions
+///
+/// This is the address of the connection, unless it comes from a trusted proxy, see
+/// [resolve_client_ip]
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+/// IP address range in CIDR notation (`10.0.0.0/8`), a plain address only matches itself
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for IpRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((a, p)) => (a.parse::<IpAddr>()?, Some(p.parse::<u8>()?)),
+            None => (s.parse::<IpAddr>()?, None),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        if prefix > max {
+            bail!("Prefix length {} is too long for {}", prefix, addr);
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // dual stack sockets report IPv4 peers as IPv4-mapped IPv6 addresses
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            ip => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(range) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(range) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// IP of the client of a request received from [peer]
+///
+/// `x-forwarded-for` is only used when [peer] is one of the [trusted] proxies, it is read from
+/// the right and the first address which isn't a trusted proxy is the client. Anyone else could
+/// put any address in the header.
+pub fn resolve_client_ip(peer: IpAddr, headers: &HeaderMap, trusted: &[IpRange]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|r| r.contains(ip));
+    if !is_trusted(peer) {
+        return peer;
+    }
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+    let mut client = peer;
+    for hop in forwarded.iter().rev() {
+        match hop.trim().parse::<IpAddr>() {
+            Ok(ip) if is_trusted(ip) => client = ip,
+            Ok(ip) => return ip,
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+/// API error caused by the request, returned with a 400 status instead of 500
+#[derive(Debug)]
+pub struct BadRequest(pub String);
+
+impl Display for BadRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for BadRequest {}
+
+/// API error for requests without valid credentials, returned with a 401 status
+#[derive(Debug)]
+pub struct Unauthorized(pub String);
+
+impl Display for Unauthorized {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+/// API error for authenticated requests which may not access a resource, returned with a 403
+/// status
+#[derive(Debug)]
+pub struct Forbidden;
+
+impl Display for Forbidden {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Access denied")
+    }
+}
+
+impl std::error::Error for Forbidden {}
+
+/// API error for requests which can't be served right now, returned with a 503 status
+#[derive(Debug)]
+pub struct Unavailable(pub String);
+
+impl Display for Unavailable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Unavailable {}
+
+/// Status and body of a failed API request
+///
+/// Typed errors and `bail!` messages raised by handlers are returned as they are, anything else
+/// (database, IO etc.) may leak internals and gets a generic message
+fn api_error(e: &anyhow::Error) -> (StatusCode, String) {
+    if let Some(e) = e.downcast_ref::<BadRequest>() {
+        return (StatusCode::BAD_REQUEST, e.to_string());
+    }
+    if let Some(e) = e.downcast_ref::<Unauthorized>() {
+        return (StatusCode::UNAUTHORIZED, e.to_string());
+    }
+    if let Some(e) = e.downcast_ref::<Forbidden>() {
+        return (StatusCode::FORBIDDEN, e.to_string());
+    }
+    if let Some(e) = e.downcast_ref::<Unavailable>() {
+        return (StatusCode::SERVICE_UNAVAILABLE, e.to_string());
+    }
+    if e.is::<serde_json::Error>() {
+        return (StatusCode::BAD_REQUEST, "Invalid request body".to_string());
+    }
+    if e.is::<uuid::Error>() || e.is::<std::num::ParseIntError>() {
+        return (StatusCode::BAD_REQUEST, "Invalid id".to_string());
+    }
+    #[cfg(feature = "zap-stream")]
+    if matches!(
+        e.downcast_ref::<zap_stream_db::sqlx::Error>(),
+        Some(zap_stream_db::sqlx::Error::RowNotFound)
+    ) {
+        return (StatusCode::NOT_FOUND, "Not found".to_string());
+    }
+    if let Some(msg) = e.downcast_ref::<&str>() {
+        return (StatusCode::BAD_REQUEST, msg.to_string());
+    }
+    if let Some(msg) = e.downcast_ref::<String>() {
+        return (StatusCode::BAD_REQUEST, msg.clone());
+    }
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal server error".to_string(),
+    )
+}
 
 #[derive(Clone)]
 pub struct HttpServer {
     index: String,
     files_dir: PathBuf,
     overseer: Arc<dyn Overseer>,
+    /// `alt-svc` header value advertising HTTP/3
+    alt_svc: Option<String>,
+    /// Token origin nodes must present to push replicated files
+    replica_token: Option<String>,
+    /// Remote address of the connection this instance is serving
+    client_addr: Option<SocketAddr>,
+    /// Reverse proxies whose `x-forwarded-for` header is used for the client IP
+    trusted_proxies: Arc<Vec<IpRange>>,
+    /// Check segments against their checksum before serving them
+    verify_segments: bool,
+    /// Segments which are currently being repaired
+    repairing: Arc<Mutex<HashSet<PathBuf>>>,
+    /// Ingest listener tasks (endpoint, task), reported on the status page
+    listeners: Arc<Vec<(String, AbortHandle)>>,
+    /// Master playlist filtering by viewer device
+    playlist_rules: Arc<Vec<PlaylistRule>>,
+}
+
+/// Result of verifying a segment before serving it
+pub enum SegmentCheck {
+    /// Verification is disabled or the file has no checksum
+    Unchecked,
+    /// Contents of the file, matching its checksum
+    Valid(Vec<u8>),
+    /// The file doesn't match its checksum, a repair has been started
+    Corrupt,
 }
 
 impl HttpServer {
@@ -26,6 +245,223 @@ impl HttpServer {
             index,
             files_dir,
             overseer,
+            alt_svc: None,
+            replica_token: None,
+            client_addr: None,
+            trusted_proxies: Arc::new(Vec::new()),
+            verify_segments: false,
+            repairing: Arc::new(Mutex::new(HashSet::new())),
+            listeners: Arc::new(Vec::new()),
+            playlist_rules: Arc::new(Vec::new()),
+        }
+    }
+
+    /// Hide master playlist variants from the viewer devices matched by [rules]
+    pub fn with_playlist_rules(mut self, rules: Vec<PlaylistRule>) -> Self {
+        self.playlist_rules = Arc::new(rules);
+        self
+    }
+
+    /// Playlist rules which apply to the viewer device of [req]
+    pub fn playlist_rules<B>(&self, req: &Request<B>) -> Vec<&PlaylistRule> {
+        match req
+            .headers()
+            .get("user-agent")
+            .and_then(|v| v.to_str().ok())
+        {
+            Some(ua) => self
+                .playlist_rules
+                .iter()
+                .filter(|r| r.matches(ua))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Use the `x-forwarded-for` header for the client IP of requests from [proxies]
+    pub fn with_trusted_proxies(mut self, proxies: Vec<IpRange>) -> Self {
+        self.trusted_proxies = Arc::new(proxies);
+        self
+    }
+
+    /// Report the ingest listener tasks (endpoint, task) on the status page
+    pub fn with_listeners(mut self, listeners: Vec<(String, AbortHandle)>) -> Self {
+        self.listeners = Arc::new(listeners);
+        self
+    }
+
+    /// Status of the overseer components and ingest listeners
+    pub async fn status(&self) -> Result<ServerStatus> {
+        let listeners = self
+            .listeners
+            .iter()
+            .map(|(endpoint, task)| {
+                let result = if task.is_finished() {
+                    Err("Listener stopped".to_string())
+                } else {
+                    Ok(())
+                };
+                ComponentStatus::new(&format!("Ingest {}", endpoint), result)
+            })
+            .collect();
+        Ok(self.overseer.status().await?.with_components(listeners))
+    }
+
+    /// Verify segment checksums when serving segments, corrupt segments are answered with
+    /// 503 while they are restored from a backup
+    pub fn with_segment_verification(mut self) -> Self {
+        self.verify_segments = true;
+        self
+    }
+
+    /// Check the segment at [path] against the checksum written by the muxer
+    pub async fn check_segment(&self, path: &Path) -> Result<SegmentCheck> {
+        if !self.verify_segments {
+            return Ok(SegmentCheck::Unchecked);
+        }
+        let expected = match tokio::fs::read_to_string(checksum_path(path)).await {
+            Ok(s) => s.trim().to_string(),
+            Err(_) => return Ok(SegmentCheck::Unchecked),
+        };
+        let data = tokio::fs::read(path).await?;
+        if hex::encode(Sha256::digest(&data)) == expected {
+            return Ok(SegmentCheck::Valid(data));
+        }
+
+        warn!("Segment {} failed checksum verification", path.display());
+        if self.repairing.lock().unwrap().insert(path.to_path_buf()) {
+            let overseer = self.overseer.clone();
+            let repairing = self.repairing.clone();
+            let path = path.to_path_buf();
+            tokio::spawn(async move {
+                match overseer.repair_segment(&path, &expected).await {
+                    Ok(true) => info!("Repaired segment {}", path.display()),
+                    Ok(false) => error!("No backup available for segment {}", path.display()),
+                    Err(e) => error!("Failed to repair segment {}: {}", path.display(), e),
+                }
+                repairing.lock().unwrap().remove(&path);
+            });
+        }
+        Ok(SegmentCheck::Corrupt)
+    }
+
+    /// Accept HLS files pushed from an origin node using [token]
+    pub fn with_replica_token(mut self, token: &str) -> Self {
+        self.replica_token = Some(token.to_string());
+        self
+    }
+
+    /// Advertise HTTP/3 on [port] to clients
+    pub fn with_http3(mut self, port: u16) -> Self {
+        self.alt_svc = Some(format!("h3=\":{}\"; ma=86400", port));
+        self
+    }
+
+    /// Path of the file in [files_dir] which maps to this request path, if it exists
+    pub fn file_path(&self, path: &str) -> Option<PathBuf> {
+        let dst_path = served_path(&self.files_dir, path)?;
+        if dst_path.is_file() {
+            Some(dst_path)
+        } else {
+            None
+        }
+    }
+
+    /// Ask the overseer if the stream file at [uri] can be played, files outside stream
+    /// directories are always allowed
+    pub async fn check_playback(&self, uri: &Uri) -> Result<bool> {
+        let mut parts = uri.path().trim_start_matches('/').splitn(2, '/');
+        match (parts.next().map(Uuid::parse_str), parts.next()) {
+            (Some(Ok(id)), Some(file)) => {
+                self.overseer
+                    .check_playback(&id, file, share_token(uri).as_deref())
+                    .await
+            }
+            _ => Ok(true),
+        }
+    }
+
+    /// Report a playlist request of the stream at [uri] to the overseer
+    ///
+    /// Viewers are identified by a hash of their address and user agent, viewers sharing
+    /// an address (NAT) are told apart by their share token
+    pub async fn record_view<B>(&self, req: &Request<B>) {
+        let id = req
+            .uri()
+            .path()
+            .trim_start_matches('/')
+            .split('/')
+            .next()
+            .and_then(|id| Uuid::parse_str(id).ok());
+        let id = match id {
+            Some(id) => id,
+            None => return,
+        };
+        let mut hash = Sha256::new();
+        // behind a reverse proxy every viewer would share the proxy address
+        if let Some(ip) = req.extensions().get::<ClientIp>() {
+            hash.update(ip.0.to_string().as_bytes());
+        }
+        hash.update(b"\n");
+        if let Some(ua) = req.headers().get("user-agent") {
+            hash.update(ua.as_bytes());
+        }
+        hash.update(b"\n");
+        if let Some(token) = share_token(req.uri()) {
+            hash.update(token.as_bytes());
+        }
+        let viewer = hex::encode(&hash.finalize()[..16]);
+        if let Err(e) = self.overseer.on_view(&id, &viewer).await {
+            warn!("Failed to record view of stream {}: {}", id, e);
+        }
+    }
+
+    /// Report [bytes] served for the stream file at [uri] to the overseer
+    pub async fn record_egress(&self, uri: &Uri, bytes: u64) {
+        let id = uri
+            .path()
+            .trim_start_matches('/')
+            .split('/')
+            .next()
+            .and_then(|id| Uuid::parse_str(id).ok());
+        if let Some(id) = id {
+            if let Err(e) = self.overseer.on_egress(&id, bytes).await {
+                warn!("Failed to record egress of stream {}: {}", id, e);
+            }
+        }
+    }
+
+    /// Serve HTTP/1.1 and HTTP/2 connections on [listener], over TLS if [tls] is set
+    ///
+    /// HTTP/2 is negotiated with ALPN on TLS connections, plaintext connections
+    /// accept HTTP/2 with prior knowledge (h2c)
+    pub async fn listen(self, listener: TcpListener, tls: Option<TlsAcceptor>) -> Result<()> {
+        loop {
+            let (socket, addr) = listener.accept().await?;
+            let mut server = self.clone();
+            server.client_addr = Some(addr);
+            let tls = tls.clone();
+            tokio::spawn(async move {
+                let builder = auto::Builder::new(TokioExecutor::new());
+                let res = match tls {
+                    Some(tls) => match tls.accept(socket).await {
+                        Ok(s) => {
+                            builder
+                                .serve_connection_with_upgrades(TokioIo::new(s), server)
+                                .await
+                        }
+                        Err(e) => Err(e.into()),
+                    },
+                    None => {
+                        builder
+                            .serve_connection_with_upgrades(TokioIo::new(socket), server)
+                            .await
+                    }
+                };
+                if let Err(e) = res {
+                    error!("Failed to handle request: {}", e);
+                }
+            });
         }
     }
 }
@@ -35,7 +471,12 @@ impl Service<Request<Incoming>> for HttpServer {
     type Error = anyhow::Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
-    fn call(&self, req: Request<Incoming>) -> Self::Future {
+    fn call(&self, mut req: Request<Incoming>) -> Self::Future {
+        if let Some(addr) = self.client_addr {
+            let ip = resolve_client_ip(addr.ip(), req.headers(), &self.trusted_proxies);
+            req.extensions_mut().insert(ClientIp(ip));
+        }
+
         // check is index.html
         if req.method() == Method::GET && req.uri().path() == "/"
             || req.uri().path() == "/index.html"
@@ -53,20 +494,130 @@ impl Service<Request<Incoming>> for HttpServer {
             });
         }
 
+        if req.method() == Method::GET
+            && (req.uri().path() == "/status" || req.uri().path() == "/status.json")
+        {
+            let server = self.clone();
+            return Box::pin(async move {
+                let status = server.status().await?;
+                let rsp = Response::builder()
+                    .header("server", "zap-stream-core")
+                    .header("access-control-allow-origin", "*")
+                    .status(if status.healthy { 200 } else { 503 });
+                let (content_type, body) = if req.uri().path() == "/status.json" {
+                    ("application/json", serde_json::to_vec(&status)?)
+                } else {
+                    ("text/html", status.to_html().into_bytes())
+                };
+                Ok(rsp
+                    .header("content-type", content_type)
+                    .body(Full::from(body).map_err(|e| match e {}).boxed())?)
+            });
+        }
+
+        let encoding = req
+            .headers()
+            .get("accept-encoding")
+            .and_then(|v| v.to_str().ok())
+            .and_then(ContentEncoding::negotiate);
+
+        // replicated files pushed from origin node
+        #[cfg(feature = "zap-stream")]
+        if req.method() == Method::PUT && req.uri().path().starts_with("/api/v1/replica/") {
+            let token = self.replica_token.clone();
+            let files_dir = self.files_dir.clone();
+            return Box::pin(async move {
+                let auth = req
+                    .headers()
+                    .get("authorization")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.strip_prefix("Bearer "));
+                let rsp = Response::builder().header("server", "zap-stream-core");
+                // constant time so the token can't be guessed byte by byte from response times
+                let valid = match (auth, &token) {
+                    (Some(auth), Some(token)) => {
+                        bool::from(auth.as_bytes().ct_eq(token.as_bytes()))
+                    }
+                    _ => false,
+                };
+                if !valid {
+                    return Ok(rsp.status(401).body(BoxBody::default())?);
+                }
+                let path = req.uri().path()["/api/v1/replica/".len()..].to_string();
+                let data = req.into_body().collect().await?.to_bytes();
+                crate::replication::accept_replica(&files_dir, &path, &data).await?;
+                Ok(rsp.status(204).body(BoxBody::default())?)
+            });
+        }
+
         // check if mapped to file
-        let mut dst_path = self.files_dir.join(req.uri().path()[1..].to_string());
-        if dst_path.exists() {
+        if let Some(dst_path) = self.file_path(req.uri().path()) {
+            let server = self.clone();
             return Box::pin(async move {
                 let mut rsp = Response::builder()
                     .header("server", "zap-stream-core")
                     .header("access-control-allow-origin", "*")
                     .header("access-control-allow-headers", "*")
                     .header("access-control-allow-methods", "HEAD, GET");
+                if let Some(alt_svc) = &server.alt_svc {
+                    rsp = rsp.header("alt-svc", alt_svc);
+                }
 
+                if !server.check_playback(req.uri()).await? {
+                    return Ok(rsp.status(403).body(BoxBody::default())?);
+                }
                 if req.method() == Method::HEAD {
                     return Ok(rsp.body(BoxBody::default())?);
                 }
+                let is_playlist = dst_path.extension().and_then(|e| e.to_str()) == Some("m3u8");
+                if is_playlist {
+                    server.record_view(&req).await;
+                    if !server.playlist_rules.is_empty() {
+                        // caches must not serve a playlist filtered for another device
+                        rsp = rsp.header("vary", "user-agent");
+                    }
+                }
+                // playlists are small and polled constantly, segments are already compressed
+                let token = share_token(req.uri());
+                let rules = server.playlist_rules(&req);
+                if is_playlist && (encoding.is_some() || token.is_some() || !rules.is_empty()) {
+                    let mut data = tokio::fs::read(&dst_path).await?;
+                    if !rules.is_empty() {
+                        data = filter_master_playlist(data, &rules);
+                    }
+                    if let Some(token) = &token {
+                        data = playlist_with_token(&data, token);
+                    }
+                    return match encoding {
+                        Some(enc) => {
+                            let rsp = enc.compress_response(rsp, &data)?;
+                            let len = rsp.body().size_hint().exact().unwrap_or(0);
+                            server.record_egress(req.uri(), len).await;
+                            Ok(rsp)
+                        }
+                        None => {
+                            server.record_egress(req.uri(), data.len() as u64).await;
+                            Ok(rsp.body(Full::from(data).map_err(|e| match e {}).boxed())?)
+                        }
+                    };
+                }
+                match server.check_segment(&dst_path).await? {
+                    SegmentCheck::Unchecked => {}
+                    SegmentCheck::Valid(data) => {
+                        server.record_egress(req.uri(), data.len() as u64).await;
+                        return Ok(rsp.body(Full::from(data).map_err(|e| match e {}).boxed())?);
+                    }
+                    SegmentCheck::Corrupt => {
+                        return Ok(rsp
+                            .status(503)
+                            .header("retry-after", "1")
+                            .body(BoxBody::default())?);
+                    }
+                }
                 let f = File::open(&dst_path).await?;
+                server
+                    .record_egress(req.uri(), f.metadata().await?.len())
+                    .await;
                 let f_stream = ReaderStream::new(f);
                 let body = StreamBody::new(
                     f_stream
@@ -82,9 +633,473 @@ impl Service<Request<Incoming>> for HttpServer {
         let overseer = self.overseer.clone();
         Box::pin(async move {
             match overseer.api(req).await {
-                Ok(res) => Ok(res),
-                Err(e) => Err(e),
+                Ok(res) => match encoding {
+                    Some(enc) => enc.compress_api_response(res).await,
+                    None => Ok(res),
+                },
+                Err(e) => {
+                    let (status, msg) = api_error(&e);
+                    if status == StatusCode::INTERNAL_SERVER_ERROR {
+                        error!("API request failed: {:#}", e);
+                    } else {
+                        warn!("API request failed: {}", e);
+                    }
+                    Ok(Response::builder()
+                        .status(status)
+                        .header("server", "zap-stream-core")
+                        .header("access-control-allow-origin", "*")
+                        .body(Full::new(Bytes::from(msg)).map_err(|e| match e {}).boxed())?)
+                }
+            }
+        })
+    }
+}
+
+/// Share token of an unlisted stream from the `token` query param
+pub fn share_token(uri: &Uri) -> Option<String> {
+    url::form_urlencoded::parse(uri.query()?.as_bytes())
+        .find(|(k, _)| k == "token")
+        .map(|(_, v)| v.into_owned())
+}
+
+/// Append the share [token] to every URI in an HLS playlist, players do not forward
+/// query params when resolving relative URIs
+pub fn playlist_with_token(playlist: &[u8], token: &str) -> Vec<u8> {
+    let query: String = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("token", token)
+        .finish();
+    let with_query = |uri: &str| {
+        let sep = if uri.contains('?') { '&' } else { '?' };
+        format!("{}{}{}", uri, sep, query)
+    };
+    let mut out = String::with_capacity(playlist.len());
+    for line in String::from_utf8_lossy(playlist).lines() {
+        if !line.is_empty() && !line.starts_with('#') {
+            out.push_str(&with_query(line));
+        } else if let Some((tag, rest)) = line.split_once("URI=\"") {
+            // e.g. #EXT-X-MAP:URI="init.mp4"
+            match rest.split_once('"') {
+                Some((uri, attrs)) => {
+                    out.push_str(&format!("{}URI=\"{}\"{}", tag, with_query(uri), attrs))
+                }
+                None => out.push_str(line),
             }
+        } else {
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    out.into_bytes()
+}
+
+/// Remove the variants hidden by [rules] from [playlist], media playlists are returned
+/// unchanged
+pub fn filter_master_playlist(playlist: Vec<u8>, rules: &[&PlaylistRule]) -> Vec<u8> {
+    let mut master = match m3u8_rs::parse_playlist_res(&playlist) {
+        Ok(m3u8_rs::Playlist::MasterPlaylist(m)) => m,
+        _ => return playlist,
+    };
+    let hidden = |v: &m3u8_rs::VariantStream| {
+        rules.iter().any(|r| {
+            r.max_height
+                .is_some_and(|h| v.resolution.as_ref().is_some_and(|res| res.height > h))
+                || r.max_bandwidth.is_some_and(|b| v.bandwidth > b)
+                || v.codecs.as_ref().is_some_and(|c| {
+                    c.split(',').any(|c| {
+                        r.exclude_codecs
+                            .iter()
+                            .any(|x| !x.is_empty() && c.trim().starts_with(x.as_str()))
+                    })
+                })
         })
+    };
+    let lowest = master
+        .variants
+        .iter()
+        .filter(|v| !v.is_i_frame)
+        .min_by_key(|v| v.bandwidth)
+        .cloned();
+    master.variants.retain(|v| !hidden(v));
+    if !master.variants.iter().any(|v| !v.is_i_frame) {
+        // better a stream the device may struggle with than none
+        master.variants.extend(lowest);
+    }
+    let mut out = Vec::with_capacity(playlist.len());
+    match master.write_to(&mut out) {
+        Ok(()) => out,
+        Err(e) => {
+            warn!("Failed to write filtered master playlist: {}", e);
+            playlist
+        }
+    }
+}
+
+/// Path in [files_dir] of the request [path], [None] for paths which could point outside
+/// of it (`..`, drive prefixes and `\` separators on Windows) or files which are not served
+fn served_path(files_dir: &Path, path: &str) -> Option<PathBuf> {
+    let rel = Path::new(path.trim_start_matches('/'));
+    if path.contains('\\') || rel.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    // pipeline logs can contain stream keys and ingest addresses
+    if rel.file_name().is_some_and(|f| f == PIPELINE_LOG) {
+        return None;
+    }
+    Some(files_dir.join(rel))
+}
+
+/// Bodies smaller than this are not worth compressing
+const MIN_COMPRESS_SIZE: usize = 512;
+
+/// Response compression supported by the HTTP server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ContentEncoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl ContentEncoding {
+    /// Pick the encoding with the highest q-value in an `accept-encoding` header, `*` covers
+    /// the encodings not listed and ties go to the best compression
+    fn negotiate(accept: &str) -> Option<Self> {
+        // (coding, q) of each entry, entries with an invalid q-value are ignored
+        let listed: Vec<(&str, f32)> = accept
+            .split(',')
+            .filter_map(|e| {
+                let mut parts = e.trim().split(';');
+                let name = parts.next()?.trim();
+                let q = match parts.find_map(|p| p.trim().strip_prefix("q=")) {
+                    Some(q) => q.trim().parse::<f32>().ok()?,
+                    None => 1.0,
+                };
+                Some((name, q))
+            })
+            .collect();
+        let q = |name: &str| {
+            listed
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .or(listed.iter().find(|(n, _)| *n == "*"))
+                .map_or(0.0, |(_, q)| *q)
+        };
+        let mut best: Option<(Self, f32)> = None;
+        for e in [Self::Brotli, Self::Gzip, Self::Deflate] {
+            let q = q(e.name());
+            if q > 0.0 && !best.is_some_and(|(_, b)| q <= b) {
+                best = Some((e, q));
+            }
+        }
+        best.map(|(e, _)| e)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ContentEncoding::Brotli => "br",
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+        }
+    }
+
+    fn compress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(data.len() / 2);
+        match self {
+            ContentEncoding::Brotli => {
+                let mut w = brotli::CompressorWriter::new(&mut out, 4096, 5, 22);
+                w.write_all(data)?;
+                w.into_inner();
+            }
+            ContentEncoding::Gzip => {
+                let mut w = GzEncoder::new(&mut out, Compression::default());
+                w.write_all(data)?;
+                w.finish()?;
+            }
+            ContentEncoding::Deflate => {
+                let mut w = ZlibEncoder::new(&mut out, Compression::default());
+                w.write_all(data)?;
+                w.finish()?;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Build a response from [data], compressing it if large enough
+    fn compress_response(
+        &self,
+        mut rsp: hyper::http::response::Builder,
+        data: &[u8],
+    ) -> Result<Response<BoxBody<Bytes, anyhow::Error>>> {
+        rsp = rsp.header("vary", "accept-encoding");
+        let body = if data.len() >= MIN_COMPRESS_SIZE {
+            rsp = rsp.header("content-encoding", self.name());
+            Bytes::from(self.compress(data)?)
+        } else {
+            Bytes::copy_from_slice(data)
+        };
+        Ok(rsp.body(Full::new(body).map_err(|e| match e {}).boxed())?)
+    }
+
+    /// Compress JSON/CSV API responses
+    async fn compress_api_response(
+        &self,
+        rsp: Response<BoxBody<Bytes, anyhow::Error>>,
+    ) -> Result<Response<BoxBody<Bytes, anyhow::Error>>> {
+        let compressible = rsp
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|t| t.starts_with("application/json") || t.starts_with("text/csv"));
+        if !compressible || rsp.headers().contains_key("content-encoding") {
+            return Ok(rsp);
+        }
+        let (parts, body) = rsp.into_parts();
+        let data = body.collect().await?.to_bytes();
+        let mut builder = Response::builder()
+            .status(parts.status)
+            .version(parts.version);
+        for (k, v) in parts.headers.iter() {
+            if k != "content-length" {
+                builder = builder.header(k, v);
+            }
+        }
+        self.compress_response(builder, &data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn served_path_stays_in_files_dir() {
+        let dir = Path::new("/srv/out");
+        assert_eq!(
+            served_path(dir, "/abc/stream_0/live.m3u8"),
+            Some(dir.join("abc/stream_0/live.m3u8"))
+        );
+        assert_eq!(served_path(dir, "//abc/1.ts"), Some(dir.join("abc/1.ts")));
+        assert_eq!(served_path(dir, "/../etc/passwd"), None);
+        assert_eq!(served_path(dir, "/abc/../../etc/passwd"), None);
+        assert_eq!(served_path(dir, "/./abc/1.ts"), None);
+        assert_eq!(served_path(dir, "/..\\..\\etc\\passwd"), None);
+        assert_eq!(served_path(dir, "/C:\\Windows\\win.ini"), None);
+        assert_eq!(served_path(dir, "/abc/pipeline.log"), None);
+    }
+
+    #[test]
+    fn ip_range_contains() {
+        let range: IpRange = "10.0.0.0/8".parse().unwrap();
+        assert!(range.contains("10.1.2.3".parse().unwrap()));
+        assert!(range.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!range.contains("11.0.0.1".parse().unwrap()));
+        let single: IpRange = "192.168.1.1".parse().unwrap();
+        assert!(single.contains("192.168.1.1".parse().unwrap()));
+        assert!(!single.contains("192.168.1.2".parse().unwrap()));
+        let v6: IpRange = "fd00::/8".parse().unwrap();
+        assert!(v6.contains("fd12::1".parse().unwrap()));
+        assert!(!v6.contains("10.0.0.1".parse().unwrap()));
+        let any: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains("1.2.3.4".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("proxy.local".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn client_ip_only_forwarded_by_trusted_proxies() {
+        let trusted = vec!["10.0.0.0/8".parse().unwrap()];
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            "1.1.1.1, 2.2.2.2, 10.0.0.2".parse().unwrap(),
+        );
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        // spoofed header of a direct client
+        assert_eq!(
+            resolve_client_ip(ip("3.3.3.3"), &headers, &trusted),
+            ip("3.3.3.3")
+        );
+        // rightmost address which isn't a trusted proxy, the client can prepend anything
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &headers, &trusted),
+            ip("2.2.2.2")
+        );
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &HeaderMap::new(), &trusted),
+            ip("10.0.0.1")
+        );
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &headers, &[]),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn api_error_hides_internal_errors() {
+        let status = |e: anyhow::Error| api_error(&e);
+        assert_eq!(
+            status(Forbidden.into()),
+            (StatusCode::FORBIDDEN, "Access denied".to_string())
+        );
+        assert_eq!(
+            status(Unauthorized("Missing authorization header".to_string()).into()),
+            (
+                StatusCode::UNAUTHORIZED,
+                "Missing authorization header".to_string()
+            )
+        );
+        assert_eq!(
+            status(anyhow::anyhow!("Stream not found")).0,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(anyhow::anyhow!("Invalid {}", "fps")),
+            (StatusCode::BAD_REQUEST, "Invalid fps".to_string())
+        );
+        assert_eq!(
+            status(Uuid::parse_str("nope").unwrap_err().into()).0,
+            StatusCode::BAD_REQUEST
+        );
+        let io = std::io::Error::other("/secret/path");
+        assert_eq!(
+            status(io.into()),
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error".to_string()
+            )
+        );
+    }
+
+    const MASTER: &str = "#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=5000000,RESOLUTION=1920x1080,CODECS=\"avc1.640028,mp4a.40.2\"
+1080/live.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=3000000,RESOLUTION=1280x720,CODECS=\"hvc1.1.6.L93.B0,mp4a.40.2\"
+720/live.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=1000000,RESOLUTION=854x480,CODECS=\"avc1.64001f,mp4a.40.2\"
+480/live.m3u8
+";
+
+    /// Variant URIs of the master playlist served to [user_agent]
+    fn variants(rules: &[PlaylistRule], user_agent: &str) -> Vec<String> {
+        let rules: Vec<&PlaylistRule> = rules.iter().filter(|r| r.matches(user_agent)).collect();
+        let out = filter_master_playlist(MASTER.as_bytes().to_vec(), &rules);
+        match m3u8_rs::parse_playlist_res(&out) {
+            Ok(m3u8_rs::Playlist::MasterPlaylist(m)) => {
+                m.variants.into_iter().map(|v| v.uri).collect()
+            }
+            _ => panic!("not a master playlist"),
+        }
+    }
+
+    fn rule(user_agent: &str) -> PlaylistRule {
+        PlaylistRule {
+            user_agent: vec![user_agent.to_string()],
+            max_height: None,
+            max_bandwidth: None,
+            exclude_codecs: vec![],
+        }
+    }
+
+    #[test]
+    fn filter_master_playlist_by_device() {
+        let rules = vec![
+            PlaylistRule {
+                max_height: Some(720),
+                ..rule("SmartTV")
+            },
+            PlaylistRule {
+                exclude_codecs: vec!["hvc1".to_string()],
+                ..rule("firefox")
+            },
+        ];
+        assert_eq!(
+            variants(&rules, "Mozilla/5.0 (SMART-TV; Linux; Tizen 6.0) SmartTV"),
+            vec!["720/live.m3u8", "480/live.m3u8"]
+        );
+        assert_eq!(
+            variants(
+                &rules,
+                "Mozilla/5.0 (X11; Linux x86_64; rv:133.0) Firefox/133.0"
+            ),
+            vec!["1080/live.m3u8", "480/live.m3u8"]
+        );
+        // both rules apply
+        assert_eq!(variants(&rules, "SmartTV Firefox"), vec!["480/live.m3u8"]);
+        // other devices get every variant
+        assert_eq!(variants(&rules, "VLC/3.0.20").len(), 3);
+        // media playlists are not touched
+        let media = b"#EXTM3U\n#EXTINF:2.0,\n1.ts\n".to_vec();
+        assert_eq!(
+            filter_master_playlist(media.clone(), &rules.iter().collect::<Vec<_>>()),
+            media
+        );
+    }
+
+    #[test]
+    fn filter_master_playlist_keeps_lowest_variant() {
+        let rules = vec![PlaylistRule {
+            max_bandwidth: Some(500_000),
+            ..rule("watch")
+        }];
+        assert_eq!(variants(&rules, "Watch/1.0"), vec!["480/live.m3u8"]);
+    }
+
+    #[test]
+    fn playlist_rules_ignore_empty_patterns() {
+        assert!(!rule("").matches("any device"));
+        assert!(rule("iphone").matches("Mozilla/5.0 (iPhone; CPU iPhone OS 17_0)"));
+    }
+
+    #[test]
+    fn negotiate_content_encoding() {
+        use ContentEncoding::*;
+        let negotiate = ContentEncoding::negotiate;
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Brotli));
+        assert_eq!(negotiate("deflate, gzip"), Some(Gzip));
+        assert_eq!(negotiate("GZIP"), Some(Gzip));
+        // highest q-value wins
+        assert_eq!(negotiate("br;q=0.5, gzip;q=1.0"), Some(Gzip));
+        assert_eq!(negotiate("br;q=0.8, gzip;q=0.9, deflate"), Some(Deflate));
+        assert_eq!(negotiate("gzip ; q=0.5, deflate;q=0.4"), Some(Gzip));
+        // q=0 disables an encoding
+        assert_eq!(negotiate("br;q=0, gzip"), Some(Gzip));
+        assert_eq!(negotiate("gzip;q=0.0"), None);
+        // * covers the encodings not listed
+        assert_eq!(negotiate("*"), Some(Brotli));
+        assert_eq!(negotiate("*;q=0.5, gzip;q=0.8"), Some(Gzip));
+        assert_eq!(negotiate("*, br;q=0"), Some(Gzip));
+        assert_eq!(negotiate("*;q=0"), None);
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate(""), None);
+        // entries with an invalid q-value are ignored
+        assert_eq!(negotiate("br;q=high, gzip;q=0.1"), Some(Gzip));
+    }
+
+    #[test]
+    fn playlist_with_token_on_every_uri() {
+        let playlist = "#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-MAP:URI=\"init.mp4\",BYTERANGE=\"720@0\"
+#EXTINF:2.000,
+1.m4s
+#EXTINF:2.000,
+2.m4s?v=1
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"en\",URI=\"audio/live.m3u8\"
+#EXT-X-ENDLIST
+";
+        let out = playlist_with_token(playlist.as_bytes(), "a b&c");
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-MAP:URI=\"init.mp4?token=a+b%26c\",BYTERANGE=\"720@0\"
+#EXTINF:2.000,
+1.m4s?token=a+b%26c
+#EXTINF:2.000,
+2.m4s?v=1&token=a+b%26c
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"en\",URI=\"audio/live.m3u8?token=a+b%26c\"
+#EXT-X-ENDLIST
+"
+        );
     }
 }
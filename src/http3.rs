@@ -0,0 +1,151 @@
+use crate::http::{
+    filter_master_playlist, playlist_with_token, share_token, HttpServer, SegmentCheck,
+};
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use h3::server::RequestStream;
+use hyper::{Method, Request, Response, StatusCode};
+use log::{error, info};
+use quinn::crypto::rustls::QuicServerConfig;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+/// Serve playlists and segments over HTTP/3
+///
+/// Only static files are served, API requests should use HTTP/1.1 or HTTP/2
+pub async fn listen(
+    addr: SocketAddr,
+    server: HttpServer,
+    tls: Arc<rustls::ServerConfig>,
+) -> Result<()> {
+    let quic = QuicServerConfig::try_from(tls)?;
+    let endpoint = quinn::Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(quic)), addr)?;
+
+    info!("HTTP/3 listening on: {}", addr);
+    while let Some(incoming) = endpoint.accept().await {
+        let server = server.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(incoming, server).await {
+                error!("HTTP/3 connection failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn handle_connection(incoming: quinn::Incoming, server: HttpServer) -> Result<()> {
+    let conn = incoming.await?;
+    let mut conn = h3::server::Connection::new(h3_quinn::Connection::new(conn)).await?;
+    while let Some(resolver) = conn.accept().await? {
+        let server = server.clone();
+        tokio::spawn(async move {
+            match resolver.resolve_request().await {
+                Ok((req, stream)) => {
+                    if let Err(e) = handle_request(req, stream, &server).await {
+                        error!("HTTP/3 request failed: {}", e);
+                    }
+                }
+                Err(e) => error!("HTTP/3 request failed: {}", e),
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn handle_request(
+    req: Request<()>,
+    mut stream: RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>,
+    server: &HttpServer,
+) -> Result<()> {
+    let path = match (req.method(), server.file_path(req.uri().path())) {
+        (&Method::GET | &Method::HEAD, Some(p)) => p,
+        _ => {
+            let rsp = Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .header("server", "zap-stream-core")
+                .body(())?;
+            stream.send_response(rsp).await?;
+            stream.finish().await?;
+            return Ok(());
+        }
+    };
+
+    let rsp = Response::builder()
+        .header("server", "zap-stream-core")
+        .header("access-control-allow-origin", "*")
+        .header("access-control-allow-headers", "*")
+        .header("access-control-allow-methods", "HEAD, GET");
+    if !server.check_playback(req.uri()).await? {
+        stream
+            .send_response(rsp.status(StatusCode::FORBIDDEN).body(())?)
+            .await?;
+        stream.finish().await?;
+        return Ok(());
+    }
+
+    // filter master playlists by device and forward the share token of unlisted streams
+    // to playlist URIs
+    let token = share_token(req.uri());
+    let rules = server.playlist_rules(&req);
+    let is_playlist = path.extension().and_then(|e| e.to_str()) == Some("m3u8");
+    if is_playlist && (token.is_some() || !rules.is_empty()) {
+        let mut data = tokio::fs::read(&path).await?;
+        if !rules.is_empty() {
+            data = filter_master_playlist(data, &rules);
+        }
+        if let Some(token) = &token {
+            data = playlist_with_token(&data, token);
+        }
+        stream
+            .send_response(rsp.header("content-length", data.len()).body(())?)
+            .await?;
+        if req.method() == Method::GET {
+            server.record_egress(req.uri(), data.len() as u64).await;
+            stream.send_data(Bytes::from(data)).await?;
+        }
+        stream.finish().await?;
+        return Ok(());
+    }
+
+    match server.check_segment(&path).await? {
+        SegmentCheck::Unchecked => {}
+        SegmentCheck::Valid(data) => {
+            stream
+                .send_response(rsp.header("content-length", data.len()).body(())?)
+                .await?;
+            if req.method() == Method::GET {
+                server.record_egress(req.uri(), data.len() as u64).await;
+                stream.send_data(Bytes::from(data)).await?;
+            }
+            stream.finish().await?;
+            return Ok(());
+        }
+        SegmentCheck::Corrupt => {
+            let rsp = rsp
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .header("retry-after", "1")
+                .body(())?;
+            stream.send_response(rsp).await?;
+            stream.finish().await?;
+            return Ok(());
+        }
+    }
+
+    let mut f = File::open(&path).await?;
+    let len = f.metadata().await?.len();
+    let rsp = rsp.header("content-length", len).body(())?;
+    stream.send_response(rsp).await?;
+
+    if req.method() == Method::GET {
+        server.record_egress(req.uri(), len).await;
+        let mut buf = BytesMut::with_capacity(64 * 1024);
+        while f.read_buf(&mut buf).await? > 0 {
+            stream.send_data(buf.split().freeze()).await?;
+            buf.reserve(64 * 1024);
+        }
+    }
+    stream.finish().await?;
+    Ok(())
+}
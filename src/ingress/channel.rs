@@ -0,0 +1,46 @@
+use crate::ingress::IngressReader;
+use std::io::Read;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+/// Chunks buffered between the application and the pipeline before sends wait
+const CHANNEL_CAPACITY: usize = 64;
+
+/// Ingest fed by the application instead of a listener, the input ends once all
+/// [Sender]s are dropped
+pub struct ChannelReader {
+    rx: Receiver<Vec<u8>>,
+    /// Rest of the last chunk which did not fit the read buffer
+    pending: Vec<u8>,
+}
+
+impl ChannelReader {
+    /// Reader for the pipeline and the sender to push the input (i.e. MPEG-TS) into it,
+    /// use [Sender::blocking_send] outside the async runtime
+    pub fn new() -> (Sender<Vec<u8>>, Self) {
+        let (tx, rx) = channel(CHANNEL_CAPACITY);
+        (
+            tx,
+            Self {
+                rx,
+                pending: Vec::new(),
+            },
+        )
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pending.is_empty() {
+            match self.rx.blocking_recv() {
+                Some(data) => self.pending = data,
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+impl IngressReader for ChannelReader {}
@@ -0,0 +1,119 @@
+use crate::ingress::{ConnectionInfo, IngressReader};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+/// Seconds a live stream waits for a backup ingest after its source dropped
+pub const DEFAULT_FAILOVER_WINDOW: u64 = 10;
+
+/// Stream key query param marking a backup ingest (`<key>?backup=1`)
+const PARAM_BACKUP: &str = "backup";
+
+/// Backup connections waiting per stream
+const MAX_STANDBY: usize = 2;
+
+type Source = (ConnectionInfo, Box<dyn IngressReader>);
+
+/// Live stream accepting sources for its stream key
+struct Slot {
+    tx: SyncSender<Source>,
+    /// The current source dropped, any connection with the key takes over
+    waiting: Arc<AtomicBool>,
+}
+
+/// Live streams by stream key, see [Failover]
+static SLOTS: LazyLock<Mutex<HashMap<String, Slot>>> = LazyLock::new(Default::default);
+
+/// Stream key and backup flag of a raw stream key, the backup flag is only a suffix of the
+/// key so encoders can set it anywhere they can set a stream key
+pub fn parse_key(raw: &str) -> (String, bool) {
+    match raw.split_once('?') {
+        Some((key, query)) => {
+            let backup = url::form_urlencoded::parse(query.as_bytes())
+                .any(|(k, v)| k == PARAM_BACKUP && v != "0" && v != "false");
+            (key.to_string(), backup)
+        }
+        None => (raw.to_string(), false),
+    }
+}
+
+/// Hand [reader] to the live stream of its key if it's a backup ingest or the stream is
+/// waiting for a new source, returns the reader when it should start its own pipeline
+pub fn offer(
+    info: &ConnectionInfo,
+    reader: Box<dyn IngressReader>,
+) -> Option<Box<dyn IngressReader>> {
+    let slots = SLOTS.lock().unwrap_or_else(|e| e.into_inner());
+    let slot = match slots.get(&info.key) {
+        Some(s) if info.backup || s.waiting.load(Ordering::Relaxed) => s,
+        _ => return Some(reader),
+    };
+    match slot.tx.try_send((info.clone(), reader)) {
+        Ok(()) => {
+            info!("Ingest from {} is on standby for its stream", info.ip_addr);
+            None
+        }
+        Err(_) => {
+            warn!("No standby slot for ingest from {}", info.ip_addr);
+            None
+        }
+    }
+}
+
+/// Backup sources of a live stream, the pipeline keeps the stream and switches to one of
+/// them when its source drops instead of ending the stream
+pub struct Failover {
+    key: String,
+    window: Duration,
+    rx: Receiver<Source>,
+    waiting: Arc<AtomicBool>,
+}
+
+impl Failover {
+    /// Accept sources for [key] until dropped, [window] is how long to wait for one after
+    /// the current source dropped
+    pub fn new(key: &str, window: Duration) -> Self {
+        let (tx, rx) = sync_channel(MAX_STANDBY);
+        let waiting = Arc::new(AtomicBool::new(false));
+        SLOTS.lock().unwrap_or_else(|e| e.into_inner()).insert(
+            key.to_string(),
+            Slot {
+                tx,
+                waiting: waiting.clone(),
+            },
+        );
+        Self {
+            key: key.to_string(),
+            window,
+            rx,
+            waiting,
+        }
+    }
+
+    /// Source replacing the one which dropped, a waiting backup or a connection made within
+    /// the failover window
+    pub fn next_source(&self) -> Option<Box<dyn IngressReader>> {
+        self.waiting.store(true, Ordering::Relaxed);
+        let next = self.rx.recv_timeout(self.window).ok();
+        self.waiting.store(false, Ordering::Relaxed);
+        let (info, reader) = next?;
+        info!("Stream source failed over to {}", info.ip_addr);
+        Some(reader)
+    }
+}
+
+impl Drop for Failover {
+    fn drop(&mut self) {
+        let mut slots = SLOTS.lock().unwrap_or_else(|e| e.into_inner());
+        // a new pipeline for the key may have registered already
+        if slots
+            .get(&self.key)
+            .is_some_and(|s| Arc::ptr_eq(&s.waiting, &self.waiting))
+        {
+            slots.remove(&self.key);
+        }
+    }
+}
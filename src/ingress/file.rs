@@ -6,6 +6,14 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::runtime::Handle;
 
+/// File the overseer wants streamed, i.e. a scheduled premiere of a recording
+pub struct FileInput {
+    pub path: PathBuf,
+    /// Connection the pipeline is started with, set [crate::ingress::PARAM_REALTIME] to
+    /// play the file at realtime speed
+    pub connection: ConnectionInfo,
+}
+
 pub async fn listen(out_dir: String, path: PathBuf, overseer: Arc<dyn Overseer>) -> Result<()> {
     info!("Sending file: {}", path.display());
 
@@ -14,6 +22,7 @@ pub async fn listen(out_dir: String, path: PathBuf, overseer: Arc<dyn Overseer>)
         endpoint: "file-input".to_owned(),
         app_name: "".to_string(),
         key: "test".to_string(),
+        ..Default::default()
     };
     let file = std::fs::File::open(path)?;
     spawn_pipeline(
@@ -26,3 +35,17 @@ pub async fn listen(out_dir: String, path: PathBuf, overseer: Arc<dyn Overseer>)
 
     Ok(())
 }
+
+/// Start a pipeline streaming [input]
+pub fn play(out_dir: String, input: FileInput, overseer: Arc<dyn Overseer>) -> Result<()> {
+    info!("Playing file: {}", input.path.display());
+    let file = std::fs::File::open(&input.path)?;
+    spawn_pipeline(
+        Handle::current(),
+        input.connection,
+        out_dir,
+        overseer,
+        Box::new(file),
+    );
+    Ok(())
+}
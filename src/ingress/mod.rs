@@ -1,12 +1,27 @@
+use crate::ingress::failover::Failover;
 use crate::overseer::Overseer;
-use crate::pipeline::runner::PipelineRunner;
-use log::{error, info};
+use crate::pipeline::builder::PipelineBuilder;
+use anyhow::{bail, Result};
+use log::error;
 use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::io::Read;
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
 use tokio::runtime::Handle;
+use url::Url;
 
+pub mod channel;
+pub mod failover;
 pub mod file;
+pub mod pipe;
+#[cfg(feature = "rist")]
+pub mod rist;
 #[cfg(feature = "rtmp")]
 pub mod rtmp;
 #[cfg(feature = "srt")]
@@ -14,13 +29,23 @@ pub mod srt;
 pub mod tcp;
 #[cfg(feature = "test-pattern")]
 pub mod test;
+#[cfg(unix)]
+pub mod unix;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// [ConnectionInfo::params] key of inputs which are read faster than realtime (files),
+/// the pipeline paces them by their timestamps
+pub const PARAM_REALTIME: &str = "realtime";
+
+/// Backlog of pending connections of TCP listeners
+const LISTEN_BACKLOG: i32 = 1024;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct ConnectionInfo {
     /// Endpoint of the ingress
     pub endpoint: String,
 
-    /// IP address of the connection
+    /// Remote address of the connection, IPv4 clients of dual stack listeners are
+    /// recorded as IPv4 (see [remote_addr])
     pub ip_addr: String,
 
     /// App name, empty unless RTMP ingress
@@ -28,42 +53,249 @@ pub struct ConnectionInfo {
 
     /// Stream key
     pub key: String,
+
+    /// Backup ingest of the stream key (`<key>?backup=1`), it takes over when the source of
+    /// the live stream drops, see [failover]
+    pub backup: bool,
+
+    /// Encoder software reported by the client, if any
+    pub user_agent: Option<String>,
+
+    /// Protocol specific connection parameters (negotiated latency, metadata etc.)
+    pub params: HashMap<String, String>,
+}
+
+/// Why the overseer refused a stream, shown to the streamer by protocols which can report it
+#[derive(Debug, Clone)]
+pub enum Rejection {
+    /// The stream key is not valid
+    BadKey,
+    /// Refused for any other reason (blocked account, balance, ban, input limits)
+    Denied(String),
 }
 
+impl Rejection {
+    /// Rejection reported by an overseer error, errors which are not a [Rejection] are
+    /// reported with their message
+    pub fn from_error(e: &anyhow::Error) -> Self {
+        match e.downcast_ref::<Rejection>() {
+            Some(r) => r.clone(),
+            None => Rejection::Denied(e.to_string()),
+        }
+    }
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::BadKey => write!(f, "Invalid stream key"),
+            Rejection::Denied(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+/// Link statistics of an ingest connection, reported by protocols which track them (SRT)
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EndpointStats {
+    /// Round trip time to the encoder in ms
+    pub rtt_ms: Option<f32>,
+    /// Latency negotiated with the encoder in ms
+    pub latency_ms: Option<u64>,
+    /// Data packets received
+    pub received_packets: u64,
+    /// Data packets which were lost
+    pub lost_packets: u64,
+    /// Data packets the encoder had to send again
+    pub retransmitted_packets: u64,
+    /// Data packets dropped because they arrived too late to be played
+    pub dropped_packets: u64,
+}
+
+impl EndpointStats {
+    /// Share of packets lost, 0-1
+    pub fn packet_loss(&self) -> f32 {
+        let total = self.received_packets + self.lost_packets;
+        if total == 0 {
+            0.0
+        } else {
+            self.lost_packets as f32 / total as f32
+        }
+    }
+}
+
+/// Connection of an ingest client read by the pipeline
+pub trait IngressReader: Read + Send {
+    /// The overseer refused to start the stream, tell the client why if the protocol has
+    /// a way to do so
+    fn reject(&mut self, _rejection: &Rejection) {}
+
+    /// Current link statistics of the connection
+    fn stats(&mut self) -> Option<EndpointStats> {
+        None
+    }
+
+    /// The input can be demuxed from any point, i.e. MPEG-TS which syncs on the next
+    /// packet, so a new pipeline can take over the connection from a crashed one
+    fn resumable(&self) -> bool {
+        false
+    }
+}
+
+impl IngressReader for std::net::TcpStream {}
+
+impl IngressReader for std::fs::File {}
+
+impl IngressReader for std::io::Stdin {}
+
+/// Ingest the overseer connected itself, i.e. a stream pulled from another server, see
+/// [Overseer::due_pulls]
+pub struct PulledInput {
+    pub connection: ConnectionInfo,
+    pub reader: Box<dyn IngressReader>,
+}
+
+/// Ingest reader shared between pipeline restarts, the connection outlives a crashed pipeline
+///
+/// With [Self::enable_failover] a dropped source is replaced by a backup ingest of the
+/// stream, the demuxer keeps reading as if the source never changed
+#[derive(Clone)]
+pub struct SharedReader {
+    reader: Arc<Mutex<Box<dyn IngressReader>>>,
+    failover: Arc<Mutex<Option<Failover>>>,
+    /// Bytes handed to the demuxer
+    read: Arc<AtomicU64>,
+}
+
+impl SharedReader {
+    pub fn new(reader: Arc<Mutex<Box<dyn IngressReader>>>) -> Self {
+        Self {
+            reader,
+            failover: Default::default(),
+            read: Default::default(),
+        }
+    }
+
+    /// Accept backup sources for the stream key [key], waiting up to [window] for one when
+    /// the current source drops
+    pub fn enable_failover(&self, key: &str, window: Duration) {
+        *self.failover.lock().unwrap_or_else(|e| e.into_inner()) = Some(Failover::new(key, window));
+    }
+
+    /// See [IngressReader::stats]
+    pub fn stats(&self) -> Option<EndpointStats> {
+        self.reader
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .stats()
+    }
+
+    /// A new demuxer can start on the connection, nothing was read from it yet or the input
+    /// is [IngressReader::resumable], otherwise it stopped somewhere inside a packet
+    pub fn can_restart(&self) -> bool {
+        self.read.load(Ordering::Relaxed) == 0
+            || self
+                .reader
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .resumable()
+    }
+}
+
+impl Read for SharedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let res = self
+                .reader
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .read(buf);
+            if let Ok(n @ 1..) = res {
+                self.read.fetch_add(n as u64, Ordering::Relaxed);
+                return res;
+            }
+            let next = match &*self.failover.lock().unwrap_or_else(|e| e.into_inner()) {
+                Some(f) => f.next_source(),
+                None => None,
+            };
+            match next {
+                Some(r) => *self.reader.lock().unwrap_or_else(|e| e.into_inner()) = r,
+                None => return res,
+            }
+        }
+    }
+}
+
+/// [ConnectionInfo::endpoint] of connections to an endpoint URL, the socket path of unix
+/// socket endpoints and the [listen_addr] of others
+pub fn endpoint_name(url: &Url) -> Result<String> {
+    match url.scheme() {
+        "unix" => Ok(url.path().to_string()),
+        _ => listen_addr(url),
+    }
+}
+
+/// Listen address (`host:port`) of an endpoint URL, IPv6 hosts keep their brackets
+/// (`rtmp://[::]:1935` listens on `[::]:1935`)
+pub fn listen_addr(url: &Url) -> Result<String> {
+    match (url.host(), url.port()) {
+        (Some(host), Some(port)) => Ok(format!("{}:{}", host, port)),
+        _ => bail!("Endpoint {} must include a host and port", url),
+    }
+}
+
+/// Resolve a listen address, host names bind to their first address
+pub async fn resolve_addr(addr: &str) -> Result<SocketAddr> {
+    match tokio::net::lookup_host(addr).await?.next() {
+        Some(a) => Ok(a),
+        None => bail!("Cannot resolve listen address {}", addr),
+    }
+}
+
+/// Bind a TCP listener to [addr], the unspecified IPv6 address (`[::]`) is dual stack so
+/// it also accepts IPv4 clients regardless of the OS default
+pub async fn bind_tcp(addr: &str) -> Result<TcpListener> {
+    let addr = resolve_addr(addr).await?;
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+/// Remote address of a connection as recorded in [ConnectionInfo::ip_addr], IPv4 clients
+/// of dual stack listeners are reported as IPv4-mapped IPv6 (`[::ffff:1.2.3.4]`) which
+/// are converted back so bans and logs see the real IPv4 address
+pub fn remote_addr(addr: SocketAddr) -> String {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port()).to_string()
+}
+
+/// Run a pipeline for the ingest connection [info] on its own thread, see
+/// [PipelineBuilder::spawn]
+///
+/// Backup ingests of a live stream are handed to its pipeline instead, see [failover]
 pub fn spawn_pipeline(
     handle: Handle,
     info: ConnectionInfo,
     out_dir: String,
     seer: Arc<dyn Overseer>,
-    reader: Box<dyn Read + Send>,
+    reader: Box<dyn IngressReader>,
 ) {
-    info!("New client connected: {}", &info.ip_addr);
-    let seer = seer.clone();
-    let out_dir = out_dir.to_string();
-    std::thread::spawn(move || unsafe {
-        match PipelineRunner::new(handle, out_dir, seer, info, reader) {
-            Ok(mut pl) => loop {
-                match pl.run() {
-                    Ok(c) => {
-                        if !c {
-                            if let Err(e) = pl.flush() {
-                                error!("Pipeline flush failed: {}", e);
-                            }
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        if let Err(e) = pl.flush() {
-                            error!("Pipeline flush failed: {}", e);
-                        }
-                        error!("Pipeline run failed: {}", e);
-                        break;
-                    }
-                }
-            },
-            Err(e) => {
-                error!("Failed to create PipelineRunner: {}", e);
-            }
-        }
-    });
+    let reader = match failover::offer(&info, reader) {
+        Some(r) => r,
+        None => return,
+    };
+    let spawned = PipelineBuilder::new(seer)
+        .with_handle(handle)
+        .with_out_dir(out_dir)
+        .with_connection(info)
+        .spawn(reader);
+    if let Err(e) = spawned {
+        error!("Failed to start pipeline: {}", e);
+    }
 }
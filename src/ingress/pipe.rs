@@ -0,0 +1,39 @@
+use crate::ingress::{spawn_pipeline, ConnectionInfo, IngressReader};
+use crate::overseer::Overseer;
+use anyhow::Result;
+use log::info;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+
+/// Endpoint name of piped input
+const PIPE_ENDPOINT: &str = "pipe";
+
+/// Publish MPEG-TS read from stdin, or from the named pipe [fifo], as one stream with the
+/// stream key [key]
+pub async fn listen(
+    out_dir: String,
+    fifo: Option<PathBuf>,
+    key: String,
+    overseer: Arc<dyn Overseer>,
+) -> Result<()> {
+    let reader: Box<dyn IngressReader> = match fifo {
+        Some(path) => {
+            info!("Waiting for a writer on {}", path.display());
+            // opening a FIFO blocks until the other end is opened
+            Box::new(tokio::task::spawn_blocking(move || std::fs::File::open(path)).await??)
+        }
+        None => {
+            info!("Reading stream from stdin");
+            Box::new(std::io::stdin())
+        }
+    };
+    let info = ConnectionInfo {
+        endpoint: PIPE_ENDPOINT.to_string(),
+        ip_addr: PIPE_ENDPOINT.to_string(),
+        key,
+        ..Default::default()
+    };
+    spawn_pipeline(Handle::current(), info, out_dir, overseer, reader);
+    Ok(())
+}
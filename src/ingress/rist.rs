@@ -0,0 +1,166 @@
+use crate::ingress::{listen_addr, spawn_pipeline, ConnectionInfo, IngressReader};
+use crate::overseer::Overseer;
+use anyhow::{bail, Result};
+use ffmpeg_rs_raw::cstr;
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::{
+    av_dict_free, av_dict_set, avio_closep, avio_open2, avio_read, AVDictionary, AVIOContext,
+    AVERROR, AVERROR_EOF, AVIO_FLAG_READ,
+};
+use log::{info, warn};
+use std::ffi::CString;
+use std::io::Read;
+use std::ptr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+use url::Url;
+
+/// Reads without data for this long end the stream, RIST has no disconnect so this is how
+/// a stopped sender is noticed
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Endpoint URL query param with the stream key of the listener, other params are passed
+/// to librist (i.e. `secret`, `aes-type`, `buffer`)
+const PARAM_KEY: &str = "key";
+
+/// Listen for a RIST sender on [endpoint] (`rist://[::]:5004?key=<stream-key>`)
+///
+/// RIST has no stream id so every listener publishes to the stream key configured on its
+/// endpoint, one sender at a time. Reading uses the librist protocol of ffmpeg, which must
+/// be built with `--enable-librist`
+pub async fn listen(out_dir: String, endpoint: Url, overseer: Arc<dyn Overseer>) -> Result<()> {
+    let addr = listen_addr(&endpoint)?;
+    let key = match endpoint.query_pairs().find(|(k, _)| k == PARAM_KEY) {
+        Some((_, v)) => v.to_string(),
+        None => bail!(
+            "RIST endpoint {} requires a ?{}= stream key",
+            addr,
+            PARAM_KEY
+        ),
+    };
+    let mut url = Url::parse(&format!("rist://@{}", addr))?;
+    for (k, v) in endpoint.query_pairs().filter(|(k, _)| k != PARAM_KEY) {
+        url.query_pairs_mut().append_pair(&k, &v);
+    }
+
+    info!("RIST listening on: {}", &addr);
+    loop {
+        let (done, ended) = oneshot::channel();
+        let u = url.to_string();
+        let reader = tokio::task::spawn_blocking(move || -> Result<RistReader> {
+            let mut r = RistReader::open(&u, done)?;
+            r.wait_for_sender()?;
+            Ok(r)
+        })
+        .await??;
+        let info = ConnectionInfo {
+            endpoint: addr.clone(),
+            ip_addr: "rist".to_string(),
+            key: key.clone(),
+            ..Default::default()
+        };
+        spawn_pipeline(
+            Handle::current(),
+            info,
+            out_dir.clone(),
+            overseer.clone(),
+            Box::new(reader),
+        );
+        // the port is held by the session until its pipeline dropped the reader
+        let _ = ended.await;
+        info!("RIST session on {} ended", addr);
+    }
+}
+
+/// RIST receiver session read through ffmpeg's avio
+struct RistReader {
+    ctx: *mut AVIOContext,
+    /// Data read while waiting for the sender, returned by the first reads
+    pending: Vec<u8>,
+    /// Dropped with the reader to tell the listener the session ended
+    _done: oneshot::Sender<()>,
+}
+
+unsafe impl Send for RistReader {}
+
+impl RistReader {
+    fn open(url: &str, done: oneshot::Sender<()>) -> Result<Self> {
+        let c_url = CString::new(url)?;
+        let timeout = CString::new(READ_TIMEOUT.as_micros().to_string())?;
+        unsafe {
+            let mut opts: *mut AVDictionary = ptr::null_mut();
+            av_dict_set(&mut opts, cstr!("rw_timeout"), timeout.as_ptr(), 0);
+            let mut ctx: *mut AVIOContext = ptr::null_mut();
+            let ret = avio_open2(
+                &mut ctx,
+                c_url.as_ptr(),
+                AVIO_FLAG_READ as _,
+                ptr::null(),
+                &mut opts,
+            );
+            av_dict_free(&mut opts);
+            if ret < 0 {
+                bail!("Failed to open {}: {}", url, ret);
+            }
+            Ok(Self {
+                ctx,
+                pending: Vec::new(),
+                _done: done,
+            })
+        }
+    }
+
+    /// Block until a sender delivers the first data
+    fn wait_for_sender(&mut self) -> Result<()> {
+        let mut buf = vec![0u8; 1316 * 7];
+        loop {
+            let ret = unsafe { avio_read(self.ctx, buf.as_mut_ptr(), buf.len() as _) };
+            if ret > 0 {
+                self.pending.extend_from_slice(&buf[..ret as usize]);
+                return Ok(());
+            }
+            // nobody is sending yet
+            if ret == AVERROR(libc::ETIMEDOUT) || ret == AVERROR(libc::EAGAIN) {
+                continue;
+            }
+            bail!("RIST read failed: {}", ret);
+        }
+    }
+}
+
+impl Read for RistReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if !self.pending.is_empty() {
+            let n = buf.len().min(self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            return Ok(n);
+        }
+        let ret = unsafe { avio_read(self.ctx, buf.as_mut_ptr(), buf.len() as _) };
+        if ret == AVERROR_EOF || ret == AVERROR(libc::ETIMEDOUT) {
+            // the sender stopped
+            Ok(0)
+        } else if ret < 0 {
+            warn!("RIST read failed: {}", ret);
+            Err(std::io::Error::other(format!("RIST read failed: {}", ret)))
+        } else {
+            Ok(ret as usize)
+        }
+    }
+}
+
+impl IngressReader for RistReader {
+    fn resumable(&self) -> bool {
+        // RIST carries MPEG-TS
+        true
+    }
+}
+
+impl Drop for RistReader {
+    fn drop(&mut self) {
+        unsafe {
+            avio_closep(&mut self.ctx);
+        }
+    }
+}
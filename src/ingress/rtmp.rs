@@ -1,33 +1,112 @@
-use crate::ingress::{spawn_pipeline, ConnectionInfo};
+use crate::ingress::{
+    bind_tcp, failover, remote_addr, spawn_pipeline, ConnectionInfo, IngressReader, Rejection,
+};
 use crate::overseer::Overseer;
 use anyhow::{bail, Result};
-use log::{error, info};
+use log::{error, info, warn};
+use rml_amf0::Amf0Value;
+use rml_rtmp::chunk_io::ChunkSerializer;
 use rml_rtmp::handshake::{Handshake, HandshakeProcessResult, PeerType};
+use rml_rtmp::messages::RtmpMessage;
 use rml_rtmp::sessions::{
-    ServerSession, ServerSessionConfig, ServerSessionEvent, ServerSessionResult,
+    ClientSession, ClientSessionConfig, ClientSessionEvent, ClientSessionResult, ServerSession,
+    ServerSessionConfig, ServerSessionEvent, ServerSessionResult, StreamMetadata,
 };
-use std::collections::VecDeque;
+use rml_rtmp::time::RtmpTimestamp;
+use rustls::StreamOwned;
+use std::collections::{HashMap, VecDeque};
 use std::io::{ErrorKind, Read, Write};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
 use tokio::runtime::Handle;
 use tokio::time::Instant;
+use tokio_rustls::TlsAcceptor;
+use url::Url;
+
+/// How long to wait for stream metadata after the publish request
+const METADATA_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long to wait before reading from a client again when it had no data
+const WOULD_BLOCK_WAIT: Duration = Duration::from_millis(5);
+
+/// Max time for a client to complete the TLS and RTMP handshakes
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for another server to connect and start playback of a pulled stream
+const PULL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A pulled stream which sends nothing for this long is ended
+const PULL_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Message stream id of the published stream, encoders publish on the first stream they
+/// create which [ServerSession] numbers from 1
+const PUBLISH_STREAM_ID: u32 = 1;
+
 #[derive(PartialEq, Eq, Clone, Hash)]
 struct RtmpPublishedStream(String, String);
 
+/// Blocking socket the RTMP session is read from (plain TCP or TLS)
+trait RtmpSocket: Read + Write + Send {}
+
+impl<T: Read + Write + Send> RtmpSocket for T {}
+
 struct RtmpClient {
-    socket: std::net::TcpStream,
+    socket: Box<dyn RtmpSocket>,
     media_buf: Vec<u8>,
     session: ServerSession,
     msg_queue: VecDeque<ServerSessionResult>,
     reader_buf: [u8; 4096],
     pub published_stream: Option<RtmpPublishedStream>,
+    /// Publish request waiting for the overseer to check the stream key
+    publish_request: Option<u32>,
+    /// Stream metadata sent by the encoder (@setDataFrame)
+    pub metadata: Option<StreamMetadata>,
+    /// Chunk size negotiated by the client
+    pub chunk_size: Option<u32>,
 }
 
 impl RtmpClient {
-    async fn start(mut socket: TcpStream) -> Result<Self> {
+    async fn start(socket: TcpStream, tls: Option<TlsAcceptor>) -> Result<Self> {
+        match tls {
+            Some(acceptor) => {
+                let mut stream = acceptor.accept(socket).await?;
+                let (session, res) = Self::handshake(&mut stream).await?;
+                let (socket, conn) = stream.into_inner();
+                let socket = StreamOwned::new(conn, socket.into_std()?);
+                Ok(Self::new(Box::new(socket), session, res))
+            }
+            None => {
+                let mut socket = socket;
+                let (session, res) = Self::handshake(&mut socket).await?;
+                Ok(Self::new(Box::new(socket.into_std()?), session, res))
+            }
+        }
+    }
+
+    fn new(
+        socket: Box<dyn RtmpSocket>,
+        session: ServerSession,
+        res: Vec<ServerSessionResult>,
+    ) -> Self {
+        Self {
+            socket,
+            media_buf: vec![],
+            session,
+            msg_queue: VecDeque::from(res),
+            reader_buf: [0; 4096],
+            published_stream: None,
+            publish_request: None,
+            metadata: None,
+            chunk_size: None,
+        }
+    }
+
+    async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+        socket: &mut S,
+    ) -> Result<(ServerSession, Vec<ServerSessionResult>)> {
         let mut hs = Handshake::new(PeerType::Server);
 
         let exchange = hs.generate_outbound_p0_and_p1()?;
@@ -55,22 +134,14 @@ impl RtmpClient {
                     let q = ses.handle_input(&remaining_bytes)?;
                     res.extend(q);
 
-                    let ret = Self {
-                        socket: socket.into_std()?,
-                        media_buf: vec![],
-                        session: ses,
-                        msg_queue: VecDeque::from(res),
-                        reader_buf: [0; 4096],
-                        published_stream: None,
-                    };
-
-                    return Ok(ret);
+                    return Ok((ses, res));
                 }
             }
         }
     }
 
-    /// Read data until we get the publish request
+    /// Read data until we get the publish request, the request is answered by
+    /// [Self::accept_publish] or [IngressReader::reject]
     pub fn read_until_publish_request(&mut self, timeout: Duration) -> Result<()> {
         let start = Instant::now();
         while self.published_stream.is_none() {
@@ -82,12 +153,95 @@ impl RtmpClient {
         Ok(())
     }
 
+    /// Accept the publish request and wait for the stream metadata
+    pub fn accept_publish(&mut self) -> Result<()> {
+        if let Some(request_id) = self.publish_request.take() {
+            let mx = self.session.accept_request(request_id)?;
+            self.msg_queue.extend(mx);
+            self.process_msg_queue()?;
+        }
+
+        // encoders send metadata right after publish, wait a moment so we can capture it
+        let start = Instant::now();
+        while self.metadata.is_none() && (Instant::now() - start) < METADATA_TIMEOUT {
+            self.read_data()?;
+        }
+        Ok(())
+    }
+
+    /// Connection parameters reported by the client
+    pub fn connection_params(&self) -> HashMap<String, String> {
+        let mut params = HashMap::new();
+        if let Some(cs) = self.chunk_size {
+            params.insert("chunk_size".to_string(), cs.to_string());
+        }
+        if let Some(m) = &self.metadata {
+            if let (Some(w), Some(h)) = (m.video_width, m.video_height) {
+                params.insert("resolution".to_string(), format!("{}x{}", w, h));
+            }
+            if let Some(fps) = m.video_frame_rate {
+                params.insert("fps".to_string(), fps.to_string());
+            }
+            if let Some(codec) = &m.video_codec {
+                params.insert("video_codec".to_string(), codec.clone());
+            }
+            if let Some(br) = m.video_bitrate_kbps {
+                params.insert("video_bitrate_kbps".to_string(), br.to_string());
+            }
+            if let Some(codec) = &m.audio_codec {
+                params.insert("audio_codec".to_string(), codec.clone());
+            }
+            if let Some(br) = m.audio_bitrate_kbps {
+                params.insert("audio_bitrate_kbps".to_string(), br.to_string());
+            }
+        }
+        params
+    }
+
+    /// Send an error `onStatus` for the published stream, encoders like OBS show the
+    /// description to the streamer
+    fn send_error_status(&mut self, code: &str, description: &str) -> Result<()> {
+        let props = HashMap::from([
+            (
+                "level".to_string(),
+                Amf0Value::Utf8String("error".to_string()),
+            ),
+            ("code".to_string(), Amf0Value::Utf8String(code.to_string())),
+            (
+                "description".to_string(),
+                Amf0Value::Utf8String(description.to_string()),
+            ),
+        ]);
+        let msg = RtmpMessage::Amf0Command {
+            command_name: "onStatus".to_string(),
+            transaction_id: 0.0,
+            command_object: Amf0Value::Null,
+            additional_arguments: vec![Amf0Value::Object(props)],
+        };
+        let payload = msg.into_message_payload(RtmpTimestamp::new(0), PUBLISH_STREAM_ID)?;
+
+        // the session serializer is private, use the chunk size it announced to the client
+        let mut serializer = ChunkSerializer::new();
+        let chunk_size = ServerSessionConfig::new().chunk_size;
+        let packet = serializer.set_max_chunk_size(chunk_size, RtmpTimestamp::new(0))?;
+        self.socket.write_all(&packet.bytes)?;
+        let packet = serializer.serialize(&payload, true, false)?;
+        self.socket.write_all(&packet.bytes)?;
+        self.socket.flush()?;
+        Ok(())
+    }
+
     fn read_data(&mut self) -> Result<()> {
         let r = match self.socket.read(&mut self.reader_buf) {
             Ok(r) => r,
             Err(e) => {
                 return match e.kind() {
-                    ErrorKind::WouldBlock => Ok(()),
+                    // the socket is non-blocking, wait a moment so callers polling for
+                    // data don't spin
+                    ErrorKind::WouldBlock => {
+                        std::thread::sleep(WOULD_BLOCK_WAIT);
+                        Ok(())
+                    }
                     ErrorKind::Interrupted => Ok(()),
                     _ => Err(anyhow::Error::new(e)),
                 };
@@ -125,6 +279,7 @@ impl RtmpClient {
         match event {
             ServerSessionEvent::ClientChunkSizeChanged { new_chunk_size } => {
                 info!("New client chunk size: {}", new_chunk_size);
+                self.chunk_size = Some(new_chunk_size);
             }
             ServerSessionEvent::ConnectionRequested { request_id, .. } => {
                 let mx = self.session.accept_request(request_id)?;
@@ -143,12 +298,11 @@ impl RtmpClient {
                             .reject_request(request_id, "0", "stream already published")?;
                     self.msg_queue.extend(mx);
                 } else {
-                    let mx = self.session.accept_request(request_id)?;
-                    self.msg_queue.extend(mx);
                     info!(
                         "Published stream request: {app_name}/{stream_key} [{:?}]",
                         mode
                     );
+                    self.publish_request = Some(request_id);
                     self.published_stream = Some(RtmpPublishedStream(app_name, stream_key));
                 }
             }
@@ -162,6 +316,7 @@ impl RtmpClient {
                     "Metadata configured: {}/{} {:?}",
                     app_name, stream_key, metadata
                 );
+                self.metadata = Some(metadata);
             }
             ServerSessionEvent::AudioDataReceived { data, .. } => {
                 self.media_buf.extend(data);
@@ -184,6 +339,18 @@ impl RtmpClient {
     }
 }
 
+impl IngressReader for RtmpClient {
+    fn reject(&mut self, rejection: &Rejection) {
+        let code = match rejection {
+            Rejection::BadKey => "NetStream.Publish.BadName",
+            Rejection::Denied(_) => "NetStream.Publish.Rejected",
+        };
+        if let Err(e) = self.send_error_status(code, &rejection.to_string()) {
+            warn!("Failed to send rejection to RTMP client: {}", e);
+        }
+    }
+}
+
 impl Read for RtmpClient {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         // block this thread until something comes into [media_buf]
@@ -201,38 +368,225 @@ impl Read for RtmpClient {
     }
 }
 
-pub async fn listen(out_dir: String, addr: String, overseer: Arc<dyn Overseer>) -> Result<()> {
-    let listener = TcpListener::bind(&addr).await?;
+/// Listen for RTMP connections, if [tls] is set the listener accepts RTMPS only
+pub async fn listen(
+    out_dir: String,
+    addr: String,
+    overseer: Arc<dyn Overseer>,
+    tls: Option<TlsAcceptor>,
+) -> Result<()> {
+    let listener = bind_tcp(&addr).await?;
 
-    info!("RTMP listening on: {}", &addr);
+    info!(
+        "{} listening on: {}",
+        if tls.is_some() { "RTMPS" } else { "RTMP" },
+        &addr
+    );
     while let Ok((socket, ip)) = listener.accept().await {
-        let mut cc = RtmpClient::start(socket).await?;
         let addr = addr.clone();
         let overseer = overseer.clone();
         let out_dir = out_dir.clone();
-        let handle = Handle::current();
-        std::thread::Builder::new()
-            .name("rtmp-client".to_string())
-            .spawn(move || {
-                if let Err(e) = cc.read_until_publish_request(Duration::from_secs(10)) {
-                    error!("{}", e);
-                } else {
-                    let pr = cc.published_stream.as_ref().unwrap();
-                    let info = ConnectionInfo {
-                        ip_addr: ip.to_string(),
-                        endpoint: addr.clone(),
-                        app_name: pr.0.clone(),
-                        key: pr.1.clone(),
-                    };
-                    spawn_pipeline(
-                        handle,
-                        info,
-                        out_dir.clone(),
-                        overseer.clone(),
-                        Box::new(cc),
-                    );
+        let tls = tls.clone();
+        // a client stalling the TLS or RTMP handshake must not hold up the accept loop
+        tokio::spawn(async move {
+            let start = RtmpClient::start(socket, tls);
+            let mut cc = match tokio::time::timeout(HANDSHAKE_TIMEOUT, start).await {
+                Ok(Ok(cc)) => cc,
+                Ok(Err(e)) => {
+                    warn!("Failed to accept RTMP client {}: {}", ip, e);
+                    return;
+                }
+                Err(_) => {
+                    warn!("RTMP client {} did not complete the handshake in time", ip);
+                    return;
                 }
-            })?;
+            };
+            let handle = Handle::current();
+            let spawned = std::thread::Builder::new()
+                .name("rtmp-client".to_string())
+                .spawn(move || {
+                    if let Err(e) = cc.read_until_publish_request(Duration::from_secs(10)) {
+                        error!("{}", e);
+                    } else {
+                        let pr = cc.published_stream.as_ref().unwrap();
+                        let (key, backup) = failover::parse_key(&pr.1);
+                        let mut info = ConnectionInfo {
+                            ip_addr: remote_addr(ip),
+                            endpoint: addr.clone(),
+                            app_name: pr.0.clone(),
+                            key,
+                            backup,
+                            user_agent: None,
+                            params: HashMap::new(),
+                        };
+                        // refuse before accepting so the encoder shows why instead of a disconnect
+                        if let Err(e) = handle.block_on(overseer.check_connection(&info)) {
+                            let rejection = Rejection::from_error(&e);
+                            warn!("RTMP stream from {} rejected: {}", ip, rejection);
+                            cc.reject(&rejection);
+                            return;
+                        }
+                        if let Err(e) = cc.accept_publish() {
+                            error!("{}", e);
+                            return;
+                        }
+                        info.user_agent = cc.metadata.as_ref().and_then(|m| m.encoder.clone());
+                        info.params = cc.connection_params();
+                        spawn_pipeline(
+                            handle,
+                            info,
+                            out_dir.clone(),
+                            overseer.clone(),
+                            Box::new(cc),
+                        );
+                    }
+                });
+            if let Err(e) = spawned {
+                error!("Failed to start RTMP client thread: {}", e);
+            }
+        });
     }
     Ok(())
 }
+
+/// Stream played from another RTMP server, see [pull]
+struct RtmpPull {
+    socket: std::net::TcpStream,
+    session: ClientSession,
+    media_buf: Vec<u8>,
+    reader_buf: [u8; 4096],
+    stream_key: String,
+    playing: bool,
+}
+
+impl RtmpPull {
+    fn handle_results(&mut self, results: Vec<ClientSessionResult>) -> Result<()> {
+        let mut queue = VecDeque::from(results);
+        while let Some(r) = queue.pop_front() {
+            match r {
+                ClientSessionResult::OutboundResponse(data) => {
+                    self.socket.write_all(&data.bytes)?
+                }
+                ClientSessionResult::RaisedEvent(ev) => match ev {
+                    ClientSessionEvent::ConnectionRequestAccepted => {
+                        let r = self.session.request_playback(self.stream_key.clone())?;
+                        queue.push_back(r);
+                    }
+                    ClientSessionEvent::ConnectionRequestRejected { description } => {
+                        bail!("RTMP server rejected the connection: {}", description);
+                    }
+                    ClientSessionEvent::PlaybackRequestAccepted => self.playing = true,
+                    ClientSessionEvent::AudioDataReceived { data, .. }
+                    | ClientSessionEvent::VideoDataReceived { data, .. } => {
+                        self.media_buf.extend(data);
+                    }
+                    _ => {}
+                },
+                ClientSessionResult::UnhandleableMessageReceived(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn read_data(&mut self) -> Result<()> {
+        let r = self.socket.read(&mut self.reader_buf)?;
+        if r == 0 {
+            bail!("EOF");
+        }
+        let results = self.session.handle_input(&self.reader_buf[..r])?;
+        self.handle_results(results)
+    }
+}
+
+impl IngressReader for RtmpPull {}
+
+impl Read for RtmpPull {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.media_buf.is_empty() {
+            if let Err(e) = self.read_data() {
+                warn!("Pulled RTMP stream ended: {}", e);
+                return Ok(0);
+            };
+        }
+
+        let to_read = buf.len().min(self.media_buf.len());
+        let drain = self.media_buf.drain(..to_read);
+        buf[..to_read].copy_from_slice(drain.as_slice());
+        Ok(to_read)
+    }
+}
+
+/// Play the stream of [url] (`rtmp://host/app/key`) from another RTMP server connecting
+/// to [addr], the stream is read like one published by an encoder
+pub async fn pull(url: &Url, addr: SocketAddr) -> Result<Box<dyn IngressReader>> {
+    let (app, key) = match url.path().trim_start_matches('/').split_once('/') {
+        Some((app, key)) if !app.is_empty() && !key.is_empty() => {
+            (app.to_string(), key.to_string())
+        }
+        _ => bail!("RTMP URL must be rtmp://host/app/key"),
+    };
+    let mut tc_url = url.clone();
+    tc_url.set_path(&app);
+    tc_url.set_query(None);
+
+    let mut socket = tokio::time::timeout(PULL_TIMEOUT, TcpStream::connect(addr)).await??;
+    let remaining = tokio::time::timeout(PULL_TIMEOUT, client_handshake(&mut socket)).await??;
+    let socket = socket.into_std()?;
+    socket.set_nonblocking(false)?;
+    socket.set_read_timeout(Some(PULL_READ_TIMEOUT))?;
+
+    let mut config = ClientSessionConfig::new();
+    config.tc_url = Some(tc_url.to_string());
+    let (session, results) = ClientSession::new(config)?;
+    let mut pull = RtmpPull {
+        socket,
+        session,
+        media_buf: vec![],
+        reader_buf: [0; 4096],
+        stream_key: key,
+        playing: false,
+    };
+    tokio::task::spawn_blocking(move || {
+        pull.handle_results(results)?;
+        let r = pull.session.request_connection(app)?;
+        pull.handle_results(vec![r])?;
+        let r = pull.session.handle_input(&remaining)?;
+        pull.handle_results(r)?;
+        let start = Instant::now();
+        while !pull.playing {
+            if start.elapsed() > PULL_TIMEOUT {
+                bail!("Timed out waiting for playback of {}", tc_url);
+            }
+            pull.read_data()?;
+        }
+        info!("Pulling RTMP stream from {}", tc_url);
+        Ok(Box::new(pull) as Box<dyn IngressReader>)
+    })
+    .await?
+}
+
+/// Handshake as the client, returns the bytes the server sent after it
+async fn client_handshake(socket: &mut TcpStream) -> Result<Vec<u8>> {
+    let mut hs = Handshake::new(PeerType::Client);
+    socket.write_all(&hs.generate_outbound_p0_and_p1()?).await?;
+
+    let mut buf = [0; 4096];
+    loop {
+        let r = socket.read(&mut buf).await?;
+        if r == 0 {
+            bail!("EOF reached while reading");
+        }
+        match hs.process_bytes(&buf[..r])? {
+            HandshakeProcessResult::InProgress { response_bytes } => {
+                socket.write_all(&response_bytes).await?;
+            }
+            HandshakeProcessResult::Completed {
+                response_bytes,
+                remaining_bytes,
+            } => {
+                socket.write_all(&response_bytes).await?;
+                return Ok(remaining_bytes);
+            }
+        }
+    }
+}
@@ -1,31 +1,36 @@
-use crate::ingress::{spawn_pipeline, ConnectionInfo};
+use crate::ingress::{
+    failover, remote_addr, resolve_addr, spawn_pipeline, ConnectionInfo, EndpointStats,
+    IngressReader,
+};
 use crate::overseer::Overseer;
 use anyhow::Result;
 use futures_util::stream::FusedStream;
-use futures_util::StreamExt;
+use futures_util::{FutureExt, StreamExt};
 use log::info;
 use srt_tokio::{SrtListener, SrtSocket};
+use std::collections::HashMap;
 use std::io::Read;
-use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::runtime::Handle;
 
 pub async fn listen(out_dir: String, addr: String, overseer: Arc<dyn Overseer>) -> Result<()> {
-    let binder: SocketAddr = addr.parse()?;
+    let binder = resolve_addr(&addr).await?;
     let (_binding, mut packets) = SrtListener::builder().bind(binder).await?;
 
     info!("SRT listening on: {}", &addr);
     while let Some(request) = packets.incoming().next().await {
         let socket = request.accept(None).await?;
+        let latency = socket.settings().recv_tsbpd_latency;
+        let (key, backup) =
+            failover::parse_key(socket.settings().stream_id.as_deref().unwrap_or_default());
         let info = ConnectionInfo {
             endpoint: addr.clone(),
-            ip_addr: socket.settings().remote.to_string(),
+            ip_addr: remote_addr(socket.settings().remote),
             app_name: "".to_string(),
-            key: socket
-                .settings()
-                .stream_id
-                .as_ref()
-                .map_or(String::new(), |s| s.to_string()),
+            key,
+            backup,
+            user_agent: None,
+            params: HashMap::from([("latency_ms".to_string(), latency.as_millis().to_string())]),
         };
         spawn_pipeline(
             Handle::current(),
@@ -36,6 +41,10 @@ pub async fn listen(out_dir: String, addr: String, overseer: Arc<dyn Overseer>)
                 handle: Handle::current(),
                 socket,
                 buf: Vec::with_capacity(4096),
+                stats: EndpointStats {
+                    latency_ms: Some(latency.as_millis() as u64),
+                    ..Default::default()
+                },
             }),
         );
     }
@@ -46,6 +55,28 @@ struct SrtReader {
     pub handle: Handle,
     pub socket: SrtSocket,
     pub buf: Vec<u8>,
+    /// Latest statistics reported by the socket
+    pub stats: EndpointStats,
+}
+
+/// SRT can only send a reject reason during the handshake, which has already completed
+/// when the overseer checks the stream, rejected clients are disconnected
+impl IngressReader for SrtReader {
+    fn stats(&mut self) -> Option<EndpointStats> {
+        // the socket reports statistics at an interval, keep the last report until the next
+        if let Some(Some(s)) = self.socket.statistics().next().now_or_never() {
+            self.stats.received_packets = s.rx_data;
+            self.stats.lost_packets = s.rx_loss_data;
+            self.stats.retransmitted_packets = s.rx_retransmit_data;
+            self.stats.dropped_packets = s.rx_dropped_data;
+        }
+        Some(self.stats.clone())
+    }
+
+    fn resumable(&self) -> bool {
+        // SRT carries MPEG-TS
+        true
+    }
 }
 
 impl Read for SrtReader {
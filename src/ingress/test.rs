@@ -1,4 +1,4 @@
-use crate::ingress::{spawn_pipeline, ConnectionInfo};
+use crate::ingress::{spawn_pipeline, ConnectionInfo, IngressReader};
 use crate::overseer::Overseer;
 use anyhow::Result;
 use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVColorSpace::AVCOL_SPC_RGB;
@@ -14,6 +14,7 @@ use log::info;
 use ringbuf::traits::{Observer, Split};
 use ringbuf::{HeapCons, HeapRb};
 use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tiny_skia::Pixmap;
@@ -27,8 +28,9 @@ pub async fn listen(out_dir: String, overseer: Arc<dyn Overseer>) -> Result<()>
         ip_addr: "test-pattern".to_string(),
         app_name: "".to_string(),
         key: "test".to_string(),
+        ..Default::default()
     };
-    let src = TestPatternSrc::new()?;
+    let src = TestPatternSrc::new(1280, 720, 30.0)?;
     spawn_pipeline(
         Handle::current(),
         info,
@@ -39,13 +41,18 @@ pub async fn listen(out_dir: String, overseer: Arc<dyn Overseer>) -> Result<()>
     Ok(())
 }
 
-struct TestPatternSrc {
+pub struct TestPatternSrc {
     encoder: Encoder,
     scaler: Scaler,
     muxer: Muxer,
     background: Pixmap,
     font: [Font; 1],
+    width: u16,
+    height: u16,
+    fps: f32,
     frame_no: u64,
+    /// Number of frames produced, shared with observers (load testing)
+    frames: Arc<AtomicU64>,
     start: Instant,
     reader: HeapCons<u8>,
 }
@@ -53,16 +60,16 @@ struct TestPatternSrc {
 unsafe impl Send for TestPatternSrc {}
 
 impl TestPatternSrc {
-    pub fn new() -> Result<Self> {
+    pub fn new(width: u16, height: u16, fps: f32) -> Result<Self> {
         let scaler = Scaler::new();
         let encoder = unsafe {
             Encoder::new_with_name("libx264")?
                 .with_stream_index(0)
-                .with_framerate(30.0)?
+                .with_framerate(fps)?
                 .with_bitrate(1_000_000)
                 .with_pix_fmt(AV_PIX_FMT_YUV420P)
-                .with_width(1280)
-                .with_height(720)
+                .with_width(width as _)
+                .with_height(height as _)
                 .with_level(51)
                 .with_profile(AV_PROFILE_H264_MAIN)
                 .open(None)?
@@ -70,7 +77,7 @@ impl TestPatternSrc {
 
         let svg_data = include_bytes!("../../test.svg");
         let tree = usvg::Tree::from_data(svg_data, &Default::default())?;
-        let mut pixmap = Pixmap::new(1280, 720).unwrap();
+        let mut pixmap = Pixmap::new(width as _, height as _).unwrap();
         let render_ts = tiny_skia::Transform::from_scale(
             pixmap.width() as f32 / tree.size().width(),
             pixmap.height() as f32 / tree.size().height(),
@@ -98,14 +105,23 @@ impl TestPatternSrc {
             muxer,
             background: pixmap,
             font: [font],
+            width,
+            height,
+            fps,
             frame_no: 0,
+            frames: Arc::new(AtomicU64::new(0)),
             start: Instant::now(),
             reader,
         })
     }
 
+    /// Counter of frames produced by this source, used to measure realtime speed
+    pub fn frame_counter(&self) -> Arc<AtomicU64> {
+        self.frames.clone()
+    }
+
     pub unsafe fn next_pkt(&mut self) -> Result<()> {
-        let stream_time = Duration::from_secs_f64(self.frame_no as f64 / 30.0);
+        let stream_time = Duration::from_secs_f64(self.frame_no as f64 / self.fps as f64);
         let real_time = Instant::now().duration_since(self.start);
         let wait_time = if stream_time > real_time {
             stream_time - real_time
@@ -117,12 +133,13 @@ impl TestPatternSrc {
         }
 
         self.frame_no += 1;
+        self.frames.store(self.frame_no, Ordering::Relaxed);
 
         let mut src_frame = unsafe {
             let src_frame = av_frame_alloc();
 
-            (*src_frame).width = 1280;
-            (*src_frame).height = 720;
+            (*src_frame).width = self.width as _;
+            (*src_frame).height = self.height as _;
             (*src_frame).pict_type = AV_PICTURE_TYPE_NONE;
             (*src_frame).key_frame = 1;
             (*src_frame).colorspace = AVCOL_SPC_RGB;
@@ -134,7 +151,10 @@ impl TestPatternSrc {
             self.background
                 .data()
                 .as_ptr()
-                .copy_to((*src_frame).data[0] as *mut _, 1280 * 720 * 4);
+                .copy_to(
+                    (*src_frame).data[0] as *mut _,
+                    self.width as usize * self.height as usize * 4,
+                );
             src_frame
         };
         let mut layout = Layout::new(CoordinateSystem::PositiveYDown);
@@ -164,7 +184,7 @@ impl TestPatternSrc {
         // scale/encode
         let mut frame = self
             .scaler
-            .process_frame(src_frame, 1280, 720, AV_PIX_FMT_YUV420P)?;
+            .process_frame(src_frame, self.width, self.height, AV_PIX_FMT_YUV420P)?;
         for mut pkt in self.encoder.encode_frame(frame)? {
             self.muxer.write_packet(pkt)?;
             av_packet_free(&mut pkt);
@@ -175,6 +195,8 @@ impl TestPatternSrc {
     }
 }
 
+impl IngressReader for TestPatternSrc {}
+
 impl Read for TestPatternSrc {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         unsafe {
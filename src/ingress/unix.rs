@@ -0,0 +1,58 @@
+use crate::ingress::{spawn_pipeline, ConnectionInfo, IngressReader};
+use crate::overseer::Overseer;
+use anyhow::{bail, Result};
+use log::info;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::net::UnixListener;
+use tokio::runtime::Handle;
+use url::Url;
+
+/// Endpoint URL query param with the stream key of the listener
+const PARAM_KEY: &str = "key";
+
+impl IngressReader for UnixStream {}
+
+/// Listen for MPEG-TS on the unix socket of [endpoint] (`unix:///run/zap-stream/ingest.sock?key=<stream-key>`)
+///
+/// For encoders running on the same host, there is no handshake carrying a stream key so
+/// every connection publishes to the key configured on the endpoint. A socket file left by
+/// a previous run is replaced
+pub async fn listen(out_dir: String, endpoint: Url, overseer: Arc<dyn Overseer>) -> Result<()> {
+    let path = PathBuf::from(endpoint.path());
+    let key = match endpoint.query_pairs().find(|(k, _)| k == PARAM_KEY) {
+        Some((_, v)) => v.to_string(),
+        None => bail!(
+            "Unix socket endpoint {} requires a ?{}= stream key",
+            path.display(),
+            PARAM_KEY
+        ),
+    };
+    if path.exists() {
+        std::fs::remove_file(&path)?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    let name = path.to_string_lossy().to_string();
+
+    info!("Unix socket listening on: {}", name);
+    while let Ok((socket, _)) = listener.accept().await {
+        let info = ConnectionInfo {
+            endpoint: name.clone(),
+            ip_addr: "unix".to_string(),
+            key: key.clone(),
+            ..Default::default()
+        };
+        // the pipeline reads on its own thread
+        let socket = socket.into_std()?;
+        socket.set_nonblocking(false)?;
+        spawn_pipeline(
+            Handle::current(),
+            info,
+            out_dir.clone(),
+            overseer.clone(),
+            Box::new(socket),
+        );
+    }
+    Ok(())
+}
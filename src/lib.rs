@@ -1,11 +1,25 @@
+#[cfg(feature = "acme")]
+pub mod acme;
 pub mod background;
 #[cfg(feature = "zap-stream")]
 pub mod blossom;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+#[cfg(feature = "grpc")]
+pub mod control;
 pub mod egress;
 pub mod http;
+#[cfg(feature = "http3")]
+pub mod http3;
 pub mod ingress;
 pub mod mux;
+#[cfg(feature = "zap-stream")]
+pub mod nip98;
 pub mod overseer;
 pub mod pipeline;
+#[cfg(feature = "zap-stream")]
+pub mod replication;
 pub mod settings;
+pub mod status;
+pub mod tls;
 pub mod variant;
@@ -1,24 +1,45 @@
-use crate::egress::NewSegment;
+use crate::background::disk_low;
+use crate::egress::{NewSegment, WallClock};
+use crate::mux::{Cue, WebVttRendition, SUBTITLES_GROUP, SUBTITLES_NAME};
+use crate::pipeline::AdBreak;
 use crate::variant::{StreamMapping, VariantStream};
 use anyhow::{bail, Result};
-use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVCodecID::AV_CODEC_ID_H264;
+use chrono::{DateTime, SecondsFormat, Utc};
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVCodecID::{
+    AV_CODEC_ID_AAC, AV_CODEC_ID_AV1, AV_CODEC_ID_H264, AV_CODEC_ID_HEVC, AV_CODEC_ID_OPUS,
+};
 use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVMediaType::AVMEDIA_TYPE_VIDEO;
 use ffmpeg_rs_raw::ffmpeg_sys_the_third::{
-    av_free, av_opt_set, av_q2d, av_write_frame, avio_flush, avio_open, AVPacket, AVStream,
-    AVIO_FLAG_WRITE, AV_PKT_FLAG_KEY,
+    av_free, av_opt_set, av_q2d, av_write_frame, avio_flush, avio_open, AVCodecParameters,
+    AVPacket, AVStream, AVIO_FLAG_WRITE, AV_PKT_FLAG_KEY,
 };
 use ffmpeg_rs_raw::{cstr, Encoder, Muxer};
 use itertools::Itertools;
 use log::{info, warn};
-use m3u8_rs::MediaSegment;
+use m3u8_rs::{ExtTag, Map, MediaSegment};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::fmt::Display;
 use std::fs::File;
-use std::path::PathBuf;
-use std::ptr;
+use std::path::{Path, PathBuf};
+use std::{ptr, slice};
 use uuid::Uuid;
 
-#[derive(Clone, Copy)]
+/// Segments kept in live playlists unless the overseer asks for a larger window
+pub const DEFAULT_PLAYLIST_WINDOW: usize = 10;
+
+/// Target duration of live segments in seconds
+pub const SEGMENT_LENGTH: f32 = 2.0;
+
+/// Change of the measured bandwidth of a variant after which the master playlist is
+/// written again
+const BANDWIDTH_UPDATE: f64 = 0.1;
+
+/// CLASS of the `EXT-X-DATERANGE` of ad breaks
+const AD_BREAK_CLASS: &str = "stream.zap.ad-break";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SegmentType {
     MPEGTS,
     FMP4,
@@ -70,10 +91,65 @@ impl Display for HlsVariantStream {
     }
 }
 
+/// Path of the sha256 checksum file written next to each finished segment
+pub fn checksum_path(segment: &Path) -> PathBuf {
+    let mut path = segment.as_os_str().to_owned();
+    path.push(".sha256");
+    PathBuf::from(path)
+}
+
+/// Init segment of fMP4 variants, referenced by every segment with `EXT-X-MAP`
+pub const INIT_SEGMENT: &str = "init.mp4";
+
+/// Move the header boxes (ftyp, moov) at the start of the first fMP4 segment into
+/// [init] so the segment only contains fragments like the ones after it
+///
+/// The muxer delays the moov until the first fragment is flushed because codec extradata
+/// is only known after the first packets were encoded
+fn split_init_segment(segment: &Path, init: &Path) -> Result<()> {
+    let data = std::fs::read(segment)?;
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let size = u32::from_be_bytes(data[pos..pos + 4].try_into()?) as usize;
+        let size = match size {
+            1 if pos + 16 <= data.len() => {
+                u64::from_be_bytes(data[pos + 8..pos + 16].try_into()?) as usize
+            }
+            s if s >= 8 => s,
+            _ => bail!("Invalid box size in {}", segment.display()),
+        };
+        let end = pos.saturating_add(size);
+        if data[pos + 4..pos + 8] == *b"moov" && end <= data.len() {
+            std::fs::write(init, &data[..end])?;
+            let tmp = segment.with_extension("tmp");
+            std::fs::write(&tmp, &data[end..])?;
+            std::fs::rename(tmp, segment)?;
+            return Ok(());
+        }
+        pos = end;
+    }
+    bail!("No moov found in {}", segment.display())
+}
+
+/// Store the checksum of a finished segment so corruption can be detected when serving it
+/// Segment tag m3u8-rs has no field for, written as `#EXT<tag>:<value>`
+fn ext_tag(tag: &str, value: String) -> ExtTag {
+    ExtTag {
+        tag: tag.to_string(),
+        rest: Some(value),
+    }
+}
+
+fn write_checksum(segment: &Path) -> Result<()> {
+    let hash = Sha256::digest(std::fs::read(segment)?);
+    std::fs::write(checksum_path(segment), hex::encode(hash))?;
+    Ok(())
+}
+
 pub struct HlsVariant {
     /// Name of this variant (720p)
     pub name: String,
-    /// MPEG-TS muxer for this variant
+    /// MPEG-TS / fMP4 muxer for this variant
     pub mux: Muxer,
     /// List of streams ids in this variant
     pub streams: Vec<HlsVariantStream>,
@@ -89,9 +165,58 @@ pub struct HlsVariant {
     pub segments: Vec<SegmentInfo>,
     /// Type of segments to create
     pub segment_type: SegmentType,
+    /// Number of segments kept in the playlist
+    pub playlist_window: usize,
+    /// The init segment still has to be split from the first fMP4 segment
+    init_pending: bool,
+    /// Bitrate measured from the finished segments
+    bandwidth: SegmentBandwidth,
+    /// Peak bandwidth listed in the master playlist
+    listed_bandwidth: u64,
+    /// Ad break signalled in the playlist
+    ad_cue: AdCue,
+    /// Wall clock of the stream timeline, segments are tagged with
+    /// `EXT-X-PROGRAM-DATE-TIME` once it's set
+    pub wall_clock: Option<WallClock>,
+}
+
+/// Ad break state of a variant playlist, breaks start and end on segment boundaries
+#[derive(Default)]
+struct AdCue {
+    /// Break starting with the next segment
+    pending: Option<AdBreak>,
+    /// Break in progress with its start time (stream seconds, wall clock)
+    active: Option<(AdBreak, f32, DateTime<Utc>)>,
+    /// End the active break with the next segment
+    end: bool,
+}
+
+/// Bitrate of the segments of a variant, BANDWIDTH must be the peak segment bitrate
+/// (Apple HLS authoring spec 2.14) which is only known once segments are written
+#[derive(Default)]
+struct SegmentBandwidth {
+    peak: u64,
+    bytes: u64,
+    duration: f64,
+}
+
+impl SegmentBandwidth {
+    fn add(&mut self, bytes: u64, duration: f32) {
+        if duration <= 0.0 {
+            return;
+        }
+        self.peak = self.peak.max((bytes as f64 * 8.0 / duration as f64) as u64);
+        self.bytes += bytes;
+        self.duration += duration as f64;
+    }
+
+    fn average(&self) -> Option<u64> {
+        (self.duration > 0.0).then(|| (self.bytes as f64 * 8.0 / self.duration) as u64)
+    }
 }
 
-struct SegmentInfo(u64, f32, SegmentType);
+/// (index, duration, type, discontinuity, tags)
+struct SegmentInfo(u64, f32, SegmentType, bool, Vec<ExtTag>);
 
 impl SegmentInfo {
     fn to_media_segment(&self) -> MediaSegment {
@@ -99,6 +224,15 @@ impl SegmentInfo {
             uri: self.filename(),
             duration: self.1,
             title: None,
+            discontinuity: self.3,
+            map: match self.2 {
+                SegmentType::MPEGTS => None,
+                SegmentType::FMP4 => Some(Map {
+                    uri: INIT_SEGMENT.to_string(),
+                    ..Default::default()
+                }),
+            },
+            unknown_tags: self.4.clone(),
             ..MediaSegment::default()
         }
     }
@@ -117,7 +251,17 @@ impl HlsVariant {
         segment_type: SegmentType,
     ) -> Result<Self> {
         let name = format!("stream_{}", group);
-        let first_seg = Self::map_segment_path(out_dir, &name, 1, segment_type);
+        // continue the existing playlist when a stream is resumed after a restart
+        let mut segments = Self::load_segments(out_dir, &name, segment_type);
+        let start_idx = segments.last().map(|s| s.0 + 1).unwrap_or(1);
+        segments.push(SegmentInfo(
+            start_idx,
+            segment_length,
+            segment_type,
+            start_idx > 1,
+            vec![],
+        ));
+        let first_seg = Self::map_segment_path(out_dir, &name, start_idx, segment_type);
         std::fs::create_dir_all(PathBuf::from(&first_seg).parent().unwrap())?;
 
         let mut opts = HashMap::new();
@@ -144,6 +288,11 @@ impl HlsVariant {
             match var {
                 VariantStream::Video(v) => unsafe {
                     let stream = mux.add_stream_encoder(enc)?;
+                    if v.is_hevc() {
+                        // Apple players only play `hvc1` (parameter sets in the sample
+                        // entry), the mp4 muxer writes `hev1` by default
+                        (*(*stream).codecpar).codec_tag = u32::from_le_bytes(*b"hvc1");
+                    }
                     streams.push(HlsVariantStream::Video {
                         group,
                         index: (*stream).index as usize,
@@ -177,14 +326,55 @@ impl HlsVariant {
             segment_length,
             mux,
             streams,
-            idx: 1,
+            idx: start_idx,
             pkt_start: 0.0,
-            segments: Vec::from([SegmentInfo(1, segment_length, segment_type)]),
+            segments,
             out_dir: out_dir.to_string(),
             segment_type,
+            playlist_window: DEFAULT_PLAYLIST_WINDOW,
+            init_pending: matches!(segment_type, SegmentType::FMP4),
+            bandwidth: SegmentBandwidth::default(),
+            listed_bandwidth: 0,
+            ad_cue: AdCue::default(),
+            wall_clock: None,
         })
     }
 
+    /// Load the segments from an existing variant playlist
+    fn load_segments(out_dir: &str, name: &str, segment_type: SegmentType) -> Vec<SegmentInfo> {
+        let path = PathBuf::from(out_dir).join(name).join("live.m3u8");
+        let data = match std::fs::read(&path) {
+            Ok(d) => d,
+            Err(_) => return vec![],
+        };
+        match m3u8_rs::parse_media_playlist_res(&data) {
+            Ok(pl) => {
+                info!("Resuming playlist {}", path.display());
+                pl.segments
+                    .iter()
+                    .filter_map(|s| {
+                        let idx = s.uri.split('.').next()?.parse().ok()?;
+                        Some(SegmentInfo(
+                            idx,
+                            s.duration,
+                            segment_type,
+                            s.discontinuity,
+                            vec![],
+                        ))
+                    })
+                    .collect()
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to parse existing playlist {}: {}",
+                    path.display(),
+                    e
+                );
+                vec![]
+            }
+        }
+    }
+
     pub fn segment_name(t: SegmentType, idx: u64) -> String {
         match t {
             SegmentType::MPEGTS => format!("{}.ts", idx),
@@ -216,8 +406,10 @@ impl HlsVariant {
         let pkt_stream = *(*self.mux.context())
             .streams
             .add((*pkt).stream_index as usize);
+        // audio-only variants split on any audio packet
         let can_split = (*pkt).flags & AV_PKT_FLAG_KEY == AV_PKT_FLAG_KEY
-            && (*(*pkt_stream).codecpar).codec_type == AVMEDIA_TYPE_VIDEO;
+            && ((*(*pkt_stream).codecpar).codec_type == AVMEDIA_TYPE_VIDEO
+                || self.video_stream().is_none());
         if pkt_seg != self.idx && can_split {
             result = Some(self.split_next_seg(pkt_time)?);
         }
@@ -236,6 +428,16 @@ impl HlsVariant {
         let ctx = self.mux.context();
         av_write_frame(ctx, ptr::null_mut());
         avio_flush((*ctx).pb);
+        if self.init_pending {
+            let prev_seg = PathBuf::from(Self::map_segment_path(
+                &self.out_dir,
+                &self.name,
+                self.idx - 1,
+                self.segment_type,
+            ));
+            split_init_segment(&prev_seg, &self.out_dir().join(INIT_SEGMENT))?;
+            self.init_pending = false;
+        }
         av_free((*ctx).url as *mut _);
 
         let next_seg_url =
@@ -257,7 +459,8 @@ impl HlsVariant {
 
         let duration = pkt_time - self.pkt_start;
         info!("Writing segment {} [{}s]", &next_seg_url, duration);
-        if let Err(e) = self.add_segment(self.idx, duration) {
+        let tags = self.segment_tags(pkt_time);
+        if let Err(e) = self.add_segment(self.idx, duration, tags) {
             warn!("Failed to update playlist: {}", e);
         }
 
@@ -268,39 +471,129 @@ impl HlsVariant {
 
         // emit result of the previously completed segment,
         let prev_seg = self.idx - 1;
+        let prev_path = PathBuf::from(Self::map_segment_path(
+            &self.out_dir,
+            &self.name,
+            prev_seg,
+            self.segment_type,
+        ));
+        match std::fs::metadata(&prev_path) {
+            Ok(m) => self.bandwidth.add(m.len(), duration),
+            Err(e) => warn!("Failed to read size of {}: {}", prev_path.display(), e),
+        }
+        if let Err(e) = write_checksum(&prev_path) {
+            warn!(
+                "Failed to write checksum for {}: {}",
+                prev_path.display(),
+                e
+            );
+        }
         let ret = NewSegment {
             variant: *video_var.id(),
             idx: prev_seg,
             duration,
-            path: PathBuf::from(Self::map_segment_path(
-                &self.out_dir,
-                &self.name,
-                prev_seg,
-                self.segment_type,
-            )),
+            pts_start: self.pkt_start,
+            path: prev_path,
         };
         self.pkt_start = pkt_time;
         Ok(ret)
     }
 
+    /// Start or end (with [None]) an ad break at the next segment
+    pub fn set_ad_break(&mut self, ad_break: Option<&AdBreak>) {
+        let active = self.ad_cue.active.as_ref().map(|(b, _, _)| b);
+        match ad_break {
+            Some(b) if active == Some(b) => {}
+            Some(b) => {
+                self.ad_cue.end = active.is_some();
+                self.ad_cue.pending = Some(b.clone());
+            }
+            None => {
+                self.ad_cue.end = active.is_some();
+                self.ad_cue.pending = None;
+            }
+        }
+    }
+
+    /// Tags of a segment starting at [pkt_time]: its program date time and the ad break
+    /// state, breaks past their planned duration are ended
+    fn segment_tags(&mut self, pkt_time: f32) -> Vec<ExtTag> {
+        let date = self.wall_clock.map(|c| c.at(pkt_time as f64));
+        let now = date.unwrap_or_else(Utc::now);
+        let mut tags = vec![];
+        if let Some((b, start, start_date)) = &self.ad_cue.active {
+            if self.ad_cue.end || pkt_time - start >= b.duration as f32 {
+                tags.push(ext_tag(
+                    "-X-DATERANGE",
+                    format!(
+                        "ID=\"ad-{}\",CLASS=\"{}\",START-DATE=\"{}\",END-DATE=\"{}\",DURATION={:.3}",
+                        b.id,
+                        AD_BREAK_CLASS,
+                        start_date.to_rfc3339_opts(SecondsFormat::Millis, true),
+                        now.to_rfc3339_opts(SecondsFormat::Millis, true),
+                        pkt_time - start
+                    ),
+                ));
+                tags.push(ExtTag {
+                    tag: "-X-CUE-IN".to_string(),
+                    rest: None,
+                });
+                self.ad_cue.active = None;
+                self.ad_cue.end = false;
+            }
+        }
+        if let Some(b) = self.ad_cue.pending.take() {
+            tags.push(ext_tag(
+                "-X-DATERANGE",
+                format!(
+                    "ID=\"ad-{}\",CLASS=\"{}\",START-DATE=\"{}\",PLANNED-DURATION={}",
+                    b.id,
+                    AD_BREAK_CLASS,
+                    now.to_rfc3339_opts(SecondsFormat::Millis, true),
+                    b.duration
+                ),
+            ));
+            tags.push(ext_tag("-X-CUE-OUT", format!("DURATION={}", b.duration)));
+            self.ad_cue.active = Some((b, pkt_time, now));
+        }
+        // date ranges need a program date time in the playlist
+        if date.is_some() || !tags.is_empty() {
+            tags.insert(
+                0,
+                ext_tag(
+                    "-X-PROGRAM-DATE-TIME",
+                    now.to_rfc3339_opts(SecondsFormat::Millis, true),
+                ),
+            );
+        }
+        tags
+    }
+
     fn video_stream(&self) -> Option<&HlsVariantStream> {
         self.streams
             .iter()
             .find(|a| matches!(*a, HlsVariantStream::Video { .. }))
     }
 
-    fn add_segment(&mut self, idx: u64, duration: f32) -> Result<()> {
+    fn add_segment(&mut self, idx: u64, duration: f32, tags: Vec<ExtTag>) -> Result<()> {
         self.segments
-            .push(SegmentInfo(idx, duration, self.segment_type));
+            .push(SegmentInfo(idx, duration, self.segment_type, false, tags));
 
-        const MAX_SEGMENTS: usize = 10;
+        // keep just enough segments for players to continue when the disk is almost full
+        const LOW_DISK_SEGMENTS: usize = 3;
 
-        if self.segments.len() > MAX_SEGMENTS {
-            let n_drain = self.segments.len() - MAX_SEGMENTS;
+        let max_segments = if disk_low() {
+            LOW_DISK_SEGMENTS
+        } else {
+            self.playlist_window
+        };
+        if self.segments.len() > max_segments {
+            let n_drain = self.segments.len() - max_segments;
             let seg_dir = self.out_dir();
             for seg in self.segments.drain(..n_drain) {
                 // delete file
                 let seg_path = seg_dir.join(seg.filename());
+                let _ = std::fs::remove_file(checksum_path(&seg_path));
                 std::fs::remove_file(seg_path)?;
             }
         }
@@ -311,17 +604,30 @@ impl HlsVariant {
         let mut pl = m3u8_rs::MediaPlaylist::default();
         pl.target_duration = self.segment_length as u64;
         pl.segments = self.segments.iter().map(|s| s.to_media_segment()).collect();
-        pl.version = Some(3);
+        pl.version = Some(match self.segment_type {
+            SegmentType::MPEGTS => 3,
+            // EXT-X-MAP
+            SegmentType::FMP4 => 7,
+        });
         pl.media_sequence = self.segments.first().map(|s| s.0).unwrap_or(0);
 
-        let mut f_out = File::create(self.out_dir().join("live.m3u8"))?;
+        // write a copy first so a full disk can't leave a truncated playlist
+        let path = self.out_dir().join("live.m3u8");
+        let tmp = self.out_dir().join("live.m3u8.tmp");
+        let mut f_out = File::create(&tmp)?;
         pl.write_to(&mut f_out)?;
+        std::fs::rename(tmp, path)?;
         Ok(())
     }
 
     /// https://git.ffmpeg.org/gitweb/ffmpeg.git/blob/HEAD:/libavformat/hlsenc.c#l351
     unsafe fn to_codec_attr(&self, stream: *mut AVStream) -> Option<String> {
         let p = (*stream).codecpar;
+        match (*p).codec_id {
+            AV_CODEC_ID_AAC => return Some("mp4a.40.2".to_string()),
+            AV_CODEC_ID_OPUS => return Some("opus".to_string()),
+            _ => {}
+        }
         if (*p).codec_id == AV_CODEC_ID_H264 {
             let data = (*p).extradata;
             if !data.is_null() {
@@ -343,25 +649,154 @@ impl HlsVariant {
                 ));
             }
         }
+        if (*p).codec_id == AV_CODEC_ID_AV1 {
+            return Self::to_av1_codec_attr(p);
+        }
+        if (*p).codec_id == AV_CODEC_ID_HEVC {
+            return Self::to_hevc_codec_attr(p);
+        }
         None
     }
 
+    /// `hvc1.P.C.TL.B` from the HEVCDecoderConfigurationRecord, or the codec parameters if
+    /// the encoder has no global header
+    ///
+    /// ISO/IEC 14496-15 Annex E.3
+    unsafe fn to_hevc_codec_attr(p: *mut AVCodecParameters) -> Option<String> {
+        let data = (*p).extradata;
+        if !data.is_null() && (*p).extradata_size >= 13 && *data == 1 {
+            let b = slice::from_raw_parts(data, 13);
+            let space = match b[1] >> 6 {
+                1 => "A",
+                2 => "B",
+                3 => "C",
+                _ => "",
+            };
+            // compatibility flags in reverse bit order
+            let compat = u32::from_be_bytes([b[2], b[3], b[4], b[5]]).reverse_bits();
+            // constraint flags without trailing zero bytes
+            let constraints = match b[6..12].iter().rposition(|c| *c != 0) {
+                Some(n) => b[6..=6 + n]
+                    .iter()
+                    .map(|c| format!(".{:X}", c))
+                    .collect::<String>(),
+                None => String::new(),
+            };
+            return Some(format!(
+                "hvc1.{}{}.{:X}.{}{}{}",
+                space,
+                b[1] & 0x1f,
+                compat,
+                if (b[1] >> 5) & 1 == 1 { 'H' } else { 'L' },
+                b[12],
+                constraints
+            ));
+        }
+        if (*p).profile > 0 && (*p).level > 0 {
+            // main is compatible with main 10
+            let compat = match (*p).profile {
+                1 => 6,
+                p => 1u32 << p,
+            };
+            return Some(format!(
+                "hvc1.{}.{:X}.L{}.B0",
+                (*p).profile,
+                compat,
+                (*p).level
+            ));
+        }
+        None
+    }
+
+    /// `av01.P.LLT.DD` from the AV1CodecConfigurationRecord, or the codec parameters if the
+    /// encoder has no global header
+    ///
+    /// https://aomediacodec.github.io/av1-isobmff/#codecsparam
+    unsafe fn to_av1_codec_attr(p: *mut AVCodecParameters) -> Option<String> {
+        let data = (*p).extradata;
+        if !data.is_null() && (*p).extradata_size >= 4 && *data & 0x7f == 1 {
+            let (b1, b2) = (*data.add(1), *data.add(2));
+            let depth = match ((b2 >> 6) & 1, (b2 >> 5) & 1) {
+                (1, 1) => 12,
+                (1, 0) => 10,
+                _ => 8,
+            };
+            return Some(format!(
+                "av01.{}.{:02}{}.{:02}",
+                b1 >> 5,
+                b1 & 0x1f,
+                if b2 >> 7 == 1 { 'H' } else { 'M' },
+                depth
+            ));
+        }
+        if (*p).profile >= 0 && (*p).level >= 0 {
+            return Some(format!("av01.{}.{:02}M.08", (*p).profile, (*p).level));
+        }
+        None
+    }
+
+    /// CODECS attribute of all streams in this variant
+    unsafe fn to_codecs_attr(&self) -> Option<String> {
+        let ctx = self.mux.context();
+        let codecs: Vec<String> = self
+            .streams
+            .iter()
+            .filter_map(|s| self.to_codec_attr(*(*ctx).streams.add(*s.index())))
+            .collect();
+        if codecs.is_empty() {
+            None
+        } else {
+            Some(codecs.join(","))
+        }
+    }
+
+    /// Bitrate of all streams as configured on the encoders, copied streams may not have one
+    unsafe fn configured_bandwidth(&self) -> u64 {
+        let ctx = self.mux.context();
+        self.streams
+            .iter()
+            .map(|s| {
+                let stream = *(*ctx).streams.add(*s.index());
+                (*(*stream).codecpar).bit_rate.max(0) as u64
+            })
+            .sum()
+    }
+
+    /// Peak bandwidth to list in the master playlist, measured once segments were written
+    /// and estimated from the configured bitrates until then
+    fn peak_bandwidth(&self) -> u64 {
+        match self.bandwidth.peak {
+            0 => unsafe { self.configured_bandwidth() * 11 / 10 },
+            p => p,
+        }
+    }
+
     pub fn to_playlist_variant(&self) -> m3u8_rs::VariantStream {
         unsafe {
             let pes = self.video_stream().unwrap_or(self.streams.first().unwrap());
             let av_stream = *(*self.mux.context()).streams.add(*pes.index());
             let codec_par = (*av_stream).codecpar;
+            let codecs = self.to_codecs_attr();
+            if codecs.is_none() {
+                warn!("No CODECS for {}, some players will skip it", self.name);
+            }
+            let average = self
+                .bandwidth
+                .average()
+                .unwrap_or_else(|| self.configured_bandwidth());
+            let fps = av_q2d((*codec_par).framerate);
             m3u8_rs::VariantStream {
                 is_i_frame: false,
                 uri: format!("{}/live.m3u8", self.name),
-                bandwidth: 0,
-                average_bandwidth: Some((*codec_par).bit_rate as u64),
-                codecs: self.to_codec_attr(av_stream),
-                resolution: Some(m3u8_rs::Resolution {
+                bandwidth: self.peak_bandwidth(),
+                average_bandwidth: (average > 0).then_some(average),
+                codecs,
+                resolution: ((*codec_par).width > 0).then(|| m3u8_rs::Resolution {
                     width: (*codec_par).width as _,
                     height: (*codec_par).height as _,
                 }),
-                frame_rate: Some(av_q2d((*codec_par).framerate)),
+                // at most 3 decimals
+                frame_rate: (fps.is_finite() && fps > 0.0).then(|| (fps * 1000.0).round() / 1000.0),
                 hdcp_level: None,
                 audio: None,
                 video: None,
@@ -376,6 +811,8 @@ impl HlsVariant {
 pub struct HlsMuxer {
     pub out_dir: PathBuf,
     pub variants: Vec<HlsVariant>,
+    /// Captions rendition, segmented along with the first variant
+    pub subtitles: Option<WebVttRendition>,
 }
 
 impl HlsMuxer {
@@ -403,39 +840,106 @@ impl HlsMuxer {
             vars.push(var);
         }
 
-        let ret = Self {
+        let mut ret = Self {
             out_dir: base,
             variants: vars,
+            subtitles: None,
         };
         ret.write_master_playlist()?;
         Ok(ret)
     }
 
-    fn write_master_playlist(&self) -> Result<()> {
+    fn write_master_playlist(&mut self) -> Result<()> {
         let mut pl = m3u8_rs::MasterPlaylist::default();
         pl.version = Some(3);
+        // every segment starts with a keyframe
+        pl.independent_segments = true;
+        let subtitles = self.subtitles.as_ref().filter(|s| s.has_cues);
+        if let Some(s) = subtitles {
+            pl.alternatives.push(m3u8_rs::AlternativeMedia {
+                media_type: m3u8_rs::AlternativeMediaType::Subtitles,
+                uri: Some(format!("{}/live.m3u8", SUBTITLES_NAME)),
+                group_id: SUBTITLES_GROUP.to_string(),
+                language: s.language.clone(),
+                name: s.language.clone().unwrap_or_else(|| "Captions".to_string()),
+                default: true,
+                autoselect: true,
+                ..Default::default()
+            });
+        }
         pl.variants = self
             .variants
             .iter()
-            .map(|v| v.to_playlist_variant())
+            .map(|v| m3u8_rs::VariantStream {
+                subtitles: subtitles.map(|_| SUBTITLES_GROUP.to_string()),
+                ..v.to_playlist_variant()
+            })
             .collect();
+        for v in self.variants.iter_mut() {
+            v.listed_bandwidth = v.peak_bandwidth();
+        }
 
-        let mut f_out = File::create(self.out_dir.join("live.m3u8"))?;
+        let path = self.out_dir.join("live.m3u8");
+        let tmp = self.out_dir.join("live.m3u8.tmp");
+        let mut f_out = File::create(&tmp)?;
         pl.write_to(&mut f_out)?;
+        std::fs::rename(tmp, path)?;
         Ok(())
     }
 
+    /// The measured bandwidth of a variant differs from the one in the master playlist
+    fn bandwidth_changed(&self) -> bool {
+        self.variants.iter().any(|v| {
+            let listed = v.listed_bandwidth.max(1) as f64;
+            (v.peak_bandwidth() as f64 - listed).abs() / listed > BANDWIDTH_UPDATE
+        })
+    }
+
+    /// Add a captions rendition, listed in the master playlist once it has cues
+    ///
+    /// Subtitle segments are written [lag] segments behind the video when cues arrive
+    /// late
+    pub fn add_subtitles(&mut self, language: Option<String>, lag: usize) -> Result<()> {
+        self.subtitles = Some(WebVttRendition::new(&self.out_dir, language, lag)?);
+        Ok(())
+    }
+
+    /// Add decoded captions to the captions rendition
+    pub fn add_cues(&mut self, cues: &[Cue]) {
+        let first = match &mut self.subtitles {
+            Some(s) => s.add_cues(cues),
+            None => return,
+        };
+        if first {
+            if let Err(e) = self.write_master_playlist() {
+                warn!("Failed to update master playlist: {}", e);
+            }
+        }
+    }
+
     /// Mux an encoded packet from [Encoder]
     pub unsafe fn mux_packet(
         &mut self,
         pkt: *mut AVPacket,
         variant: &Uuid,
     ) -> Result<Option<NewSegment>> {
-        for var in self.variants.iter_mut() {
+        for (i, var) in self.variants.iter_mut().enumerate() {
             if let Some(vs) = var.streams.iter().find(|s| s.id() == variant) {
                 // very important for muxer to know which stream this pkt belongs to
                 (*pkt).stream_index = *vs.index() as _;
-                return var.mux_packet(pkt);
+                let seg = var.mux_packet(pkt)?;
+                if let (0, Some(s), Some(subs)) = (i, &seg, &mut self.subtitles) {
+                    let end = var.pkt_start;
+                    if let Err(e) = subs.split(s.idx, end - s.duration, end, var.playlist_window) {
+                        warn!("Failed to write subtitle segment: {}", e);
+                    }
+                }
+                if seg.is_some() && self.bandwidth_changed() {
+                    if let Err(e) = self.write_master_playlist() {
+                        warn!("Failed to update master playlist: {}", e);
+                    }
+                }
+                return Ok(seg);
             }
         }
         bail!("Packet doesnt match any variants");
@@ -1,2 +1,9 @@
 mod hls;
+mod thumb;
+mod trim;
+mod webvtt;
+
 pub use hls::*;
+pub use thumb::*;
+pub use trim::*;
+pub use webvtt::*;
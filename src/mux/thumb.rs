@@ -0,0 +1,34 @@
+use crate::pipeline::slate::decode_first_frame;
+use anyhow::{anyhow, Result};
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::av_frame_free;
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVCodecID::AV_CODEC_ID_WEBP;
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVMediaType::AVMEDIA_TYPE_VIDEO;
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVPixelFormat::AV_PIX_FMT_YUV420P;
+use ffmpeg_rs_raw::{Encoder, Scaler};
+use std::mem::transmute;
+use std::path::Path;
+
+/// Save the first video frame of the media file [src] as a webp thumbnail at [dst]
+pub fn save_thumbnail(src: &Path, dst: &Path) -> Result<()> {
+    let dst = dst.to_str().ok_or(anyhow!("Invalid thumbnail path"))?;
+    unsafe {
+        let mut frame = decode_first_frame(src, AVMEDIA_TYPE_VIDEO)?;
+        let mut sw = Scaler::new();
+        let scaled = sw.process_frame(
+            frame,
+            (*frame).width as _,
+            (*frame).height as _,
+            AV_PIX_FMT_YUV420P,
+        );
+        av_frame_free(&mut frame);
+        let mut scaled = scaled?;
+        Encoder::new(AV_CODEC_ID_WEBP)?
+            .with_height((*scaled).height)
+            .with_width((*scaled).width)
+            .with_pix_fmt(transmute((*scaled).format))
+            .open(None)?
+            .save_picture(scaled, dst)?;
+        av_frame_free(&mut scaled);
+    }
+    Ok(())
+}
@@ -0,0 +1,220 @@
+use anyhow::{bail, Result};
+use ffmpeg_rs_raw::cstr;
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVMediaType::{AVMEDIA_TYPE_AUDIO, AVMEDIA_TYPE_VIDEO};
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::{
+    av_dict_free, av_dict_set, av_interleaved_write_frame, av_packet_alloc, av_packet_free,
+    av_packet_rescale_ts, av_packet_unref, av_q2d, av_read_frame, av_write_trailer,
+    avcodec_parameters_copy, avformat_alloc_output_context2, avformat_close_input,
+    avformat_find_stream_info, avformat_free_context, avformat_new_stream, avformat_open_input,
+    avformat_write_header, avio_closep, avio_open, AVDictionary, AVFormatContext, AVPacket,
+    AVIO_FLAG_WRITE, AV_NOPTS_VALUE, AV_PKT_FLAG_KEY,
+};
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use uuid::Uuid;
+
+/// Cut [start, end) seconds out of a recording into a new MP4 without re-encoding
+///
+/// The output starts at the first video keyframe at or after [start] so it can be decoded
+/// without the preceding frames. Returns the duration of the output in seconds
+pub fn trim_recording(src: &Path, dst: &Path, start: f32, end: Option<f32>) -> Result<f32> {
+    copy_recording(src, dst, start, end, None)
+}
+
+/// Copy the [streams] (by index) of a recording into a new MP4 without re-encoding,
+/// returns the duration of the output in seconds
+pub fn remux_recording(src: &Path, dst: &Path, streams: &[i32]) -> Result<f32> {
+    copy_recording(src, dst, 0.0, None, Some(streams))
+}
+
+/// The output is written to a unique temp file next to [dst] and renamed over it once
+/// complete, so concurrent copies don't interleave and readers never see a partial file
+fn copy_recording(
+    src: &Path,
+    dst: &Path,
+    start: f32,
+    end: Option<f32>,
+    streams: Option<&[i32]>,
+) -> Result<f32> {
+    let tmp = temp_path(dst);
+    match copy_to(src, &tmp, start, end, streams) {
+        Ok(duration) => {
+            std::fs::rename(&tmp, dst)?;
+            Ok(duration)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+/// Unique hidden file in the directory of [dst]
+fn temp_path(dst: &Path) -> PathBuf {
+    let name = dst
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    dst.with_file_name(format!(".{}.{}.tmp", name, Uuid::new_v4().simple()))
+}
+
+fn copy_to(
+    src: &Path,
+    dst: &Path,
+    start: f32,
+    end: Option<f32>,
+    streams: Option<&[i32]>,
+) -> Result<f32> {
+    let src = CString::new(src.to_string_lossy().as_bytes())?;
+    let dst = CString::new(dst.to_string_lossy().as_bytes())?;
+    unsafe {
+        let mut ictx: *mut AVFormatContext = ptr::null_mut();
+        let ret = avformat_open_input(&mut ictx, src.as_ptr(), ptr::null(), ptr::null_mut());
+        if ret < 0 {
+            bail!("Failed to open recording: {}", ret);
+        }
+        let mut octx: *mut AVFormatContext = ptr::null_mut();
+        let mut pkt = av_packet_alloc();
+        let res = remux(ictx, &mut octx, pkt, &dst, start, end, streams);
+
+        av_packet_free(&mut pkt);
+        if !octx.is_null() {
+            avio_closep(&mut (*octx).pb);
+            avformat_free_context(octx);
+        }
+        avformat_close_input(&mut ictx);
+        res
+    }
+}
+
+unsafe fn remux(
+    ictx: *mut AVFormatContext,
+    octx: &mut *mut AVFormatContext,
+    pkt: *mut AVPacket,
+    dst: &CString,
+    start: f32,
+    end: Option<f32>,
+    streams: Option<&[i32]>,
+) -> Result<f32> {
+    let ret = avformat_find_stream_info(ictx, ptr::null_mut());
+    if ret < 0 {
+        bail!("Failed to probe recording: {}", ret);
+    }
+    // the format can't be guessed from the temp file name
+    let ret = avformat_alloc_output_context2(octx, ptr::null(), cstr!("mp4"), dst.as_ptr());
+    if ret < 0 {
+        bail!("Failed to create output: {}", ret);
+    }
+
+    // input stream index -> output stream index, only audio/video is kept
+    let mut stream_map = vec![None; (*ictx).nb_streams as usize];
+    let mut has_video = false;
+    for (i, map) in stream_map.iter_mut().enumerate() {
+        let ist = *(*ictx).streams.add(i);
+        let codec_type = (*(*ist).codecpar).codec_type;
+        if codec_type != AVMEDIA_TYPE_VIDEO && codec_type != AVMEDIA_TYPE_AUDIO {
+            continue;
+        }
+        if streams.is_some_and(|s| !s.contains(&(i as i32))) {
+            continue;
+        }
+        has_video |= codec_type == AVMEDIA_TYPE_VIDEO;
+        let ost = avformat_new_stream(*octx, ptr::null());
+        if ost.is_null() {
+            bail!("Failed to create output stream");
+        }
+        if avcodec_parameters_copy((*ost).codecpar, (*ist).codecpar) < 0 {
+            bail!("Failed to copy stream parameters");
+        }
+        (*(*ost).codecpar).codec_tag = 0;
+        (*ost).time_base = (*ist).time_base;
+        *map = Some((*ost).index);
+    }
+
+    let ret = avio_open(&mut (**octx).pb, dst.as_ptr(), AVIO_FLAG_WRITE as _);
+    if ret < 0 {
+        bail!("Failed to open output: {}", ret);
+    }
+    let mut opts: *mut AVDictionary = ptr::null_mut();
+    av_dict_set(&mut opts, cstr!("movflags"), cstr!("+faststart"), 0);
+    let ret = avformat_write_header(*octx, &mut opts);
+    av_dict_free(&mut opts);
+    if ret < 0 {
+        bail!("Failed to write header: {}", ret);
+    }
+
+    // timestamps in the recording don't start at 0
+    let mut origin: Option<f64> = None;
+    // recording time of the first packet written
+    let mut cut: Option<f64> = None;
+    let mut last = 0f64;
+    while av_read_frame(ictx, pkt) >= 0 {
+        let out_idx = match stream_map.get((*pkt).stream_index as usize) {
+            Some(Some(i)) => *i,
+            _ => {
+                av_packet_unref(pkt);
+                continue;
+            }
+        };
+        let ist = *(*ictx).streams.add((*pkt).stream_index as usize);
+        let ts = if (*pkt).pts != AV_NOPTS_VALUE {
+            (*pkt).pts
+        } else {
+            (*pkt).dts
+        };
+        if ts == AV_NOPTS_VALUE {
+            av_packet_unref(pkt);
+            continue;
+        }
+        let tb = av_q2d((*ist).time_base);
+        let abs = ts as f64 * tb;
+        let t = abs - *origin.get_or_insert(abs);
+
+        if cut.is_none() {
+            let is_video = (*(*ist).codecpar).codec_type == AVMEDIA_TYPE_VIDEO;
+            let is_key = (*pkt).flags & AV_PKT_FLAG_KEY as i32 != 0;
+            if t >= start as f64 && (!has_video || (is_video && is_key)) {
+                cut = Some(abs);
+            } else {
+                av_packet_unref(pkt);
+                continue;
+            }
+        }
+        if end.is_some_and(|e| t >= e as f64) {
+            av_packet_unref(pkt);
+            break;
+        }
+
+        // shift to start at 0, dropping audio which started before the keyframe
+        let offset = (cut.unwrap_or(abs) / tb).round() as i64;
+        if (*pkt).pts != AV_NOPTS_VALUE {
+            (*pkt).pts -= offset;
+        }
+        if (*pkt).dts != AV_NOPTS_VALUE {
+            (*pkt).dts -= offset;
+        }
+        if (*pkt).dts < 0 && (*pkt).dts != AV_NOPTS_VALUE {
+            av_packet_unref(pkt);
+            continue;
+        }
+        last = last.max(abs - cut.unwrap_or(abs));
+
+        let ost = *(**octx).streams.add(out_idx as usize);
+        av_packet_rescale_ts(pkt, (*ist).time_base, (*ost).time_base);
+        (*pkt).stream_index = out_idx;
+        (*pkt).pos = -1;
+        let ret = av_interleaved_write_frame(*octx, pkt);
+        if ret < 0 {
+            bail!("Failed to write packet: {}", ret);
+        }
+    }
+    if cut.is_none() {
+        bail!("Trim start is after the end of the recording");
+    }
+    let ret = av_write_trailer(*octx);
+    if ret < 0 {
+        bail!("Failed to write trailer: {}", ret);
+    }
+    Ok(last as f32)
+}
@@ -0,0 +1,166 @@
+use anyhow::Result;
+use log::warn;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Directory of the subtitles rendition next to the `stream_{group}` variants
+pub const SUBTITLES_NAME: &str = "stream_subs";
+
+/// GROUP-ID of the subtitles rendition in the master playlist
+pub const SUBTITLES_GROUP: &str = "subs";
+
+/// Caption shown from [start] until [end] (seconds on the timeline of the encoded streams)
+#[derive(Clone, Debug)]
+pub struct Cue {
+    pub start: f64,
+    pub end: f64,
+    /// Empty text clears the captions on screen at [start]
+    pub text: String,
+}
+
+/// WebVTT subtitles rendition segmented along with a variant of the HLS muxer
+pub struct WebVttRendition {
+    dir: PathBuf,
+    /// BCP-47 / ISO 639 language of the captions
+    pub language: Option<String>,
+    /// Cues which may still be shown in the next segments
+    cues: Vec<Cue>,
+    /// (index, duration) of the segments in the playlist
+    segments: Vec<(u64, f32)>,
+    /// Segments written after this many more segments, so cues which arrive late
+    /// (generated captions) are still in them
+    lag: usize,
+    /// (index, start, end) of the segments waiting for [Self::lag]
+    pending: VecDeque<(u64, f32, f32)>,
+    /// Cues were received, the rendition is only listed in the master playlist once
+    /// there are captions to show
+    pub has_cues: bool,
+}
+
+impl WebVttRendition {
+    pub fn new(out_dir: &Path, language: Option<String>, lag: usize) -> Result<Self> {
+        let dir = out_dir.join(SUBTITLES_NAME);
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            language,
+            cues: Vec::new(),
+            segments: Vec::new(),
+            lag,
+            pending: VecDeque::new(),
+            has_cues: false,
+        })
+    }
+
+    /// Add decoded cues, a new cue replaces the captions on screen
+    ///
+    /// Returns true for the first cues, when the rendition should be added to the master
+    /// playlist
+    pub fn add_cues(&mut self, cues: &[Cue]) -> bool {
+        for cue in cues {
+            for c in self.cues.iter_mut() {
+                c.end = c.end.min(cue.start);
+            }
+            self.cues.retain(|c| c.end > c.start);
+            if !cue.text.is_empty() && cue.end > cue.start {
+                self.cues.push(cue.clone());
+            }
+        }
+        let first = !self.has_cues && !self.cues.is_empty();
+        self.has_cues |= first;
+        first
+    }
+
+    /// Add the segment [idx] covering [start]..[end] and keep the last [window]
+    /// segments in the playlist
+    pub fn split(&mut self, idx: u64, start: f32, end: f32, window: usize) -> Result<()> {
+        self.pending.push_back((idx, start, end));
+        while self.pending.len() > self.lag {
+            if let Some((idx, start, end)) = self.pending.pop_front() {
+                self.write_segment(idx, start, end, window)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_segment(&mut self, idx: u64, start: f32, end: f32, window: usize) -> Result<()> {
+        let (start, end) = (start as f64, end as f64);
+        // MPEG-TS timestamps are the timeline of the cues
+        let mut vtt = String::from("WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:0,LOCAL:00:00:00.000\n");
+        for c in self.cues.iter().filter(|c| c.start < end && c.end > start) {
+            write!(
+                vtt,
+                "\n{} --> {}\n{}\n",
+                vtt_time(c.start),
+                vtt_time(c.end),
+                vtt_escape(&c.text)
+            )?;
+        }
+        std::fs::write(self.dir.join(format!("{}.vtt", idx)), vtt)?;
+        // cues spanning the boundary are repeated in the next segment
+        self.cues.retain(|c| c.end > end);
+
+        self.segments.push((idx, (end - start) as f32));
+        if self.segments.len() > window {
+            let n_drain = self.segments.len() - window;
+            for (idx, _) in self.segments.drain(..n_drain) {
+                if let Err(e) = std::fs::remove_file(self.dir.join(format!("{}.vtt", idx))) {
+                    warn!("Failed to remove subtitle segment {}: {}", idx, e);
+                }
+            }
+        }
+        self.write_playlist()
+    }
+
+    fn write_playlist(&self) -> Result<()> {
+        let mut pl = m3u8_rs::MediaPlaylist::default();
+        pl.version = Some(3);
+        pl.target_duration = self
+            .segments
+            .iter()
+            .map(|(_, d)| d.ceil() as u64)
+            .max()
+            .unwrap_or(1);
+        pl.media_sequence = self.segments.first().map(|s| s.0).unwrap_or(0);
+        pl.segments = self
+            .segments
+            .iter()
+            .map(|(idx, duration)| m3u8_rs::MediaSegment {
+                uri: format!("{}.vtt", idx),
+                duration: *duration,
+                ..Default::default()
+            })
+            .collect();
+
+        let path = self.dir.join("live.m3u8");
+        let tmp = self.dir.join("live.m3u8.tmp");
+        let mut f_out = File::create(&tmp)?;
+        pl.write_to(&mut f_out)?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+fn vtt_time(secs: f64) -> String {
+    let ms = (secs.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Cue text can't contain markup characters or blank lines, which end the cue
+fn vtt_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,96 @@
+use anyhow::{bail, Result};
+use base64::Engine;
+use hyper::Request;
+use nostr_sdk::{Event, JsonUtil, Kind, PublicKey, Timestamp};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+/// Max age in seconds of a NIP-98 auth event
+const MAX_AUTH_AGE: u64 = 60;
+
+/// Validate a NIP-98 `Authorization` header and return the pubkey which signed it
+///
+/// [public_url] is used to build the absolute URL of the request for the `u` tag check
+pub fn check_nip98_auth<T>(req: &Request<T>, public_url: &str) -> Result<PublicKey> {
+    let auth = match req.headers().get("authorization") {
+        Some(a) => a.to_str()?,
+        None => bail!("Missing authorization header"),
+    };
+    let event = match auth_event(auth)? {
+        Some(e) => e,
+        None => bail!("Invalid authorization scheme"),
+    };
+
+    if event.kind != Kind::HttpAuth {
+        bail!("Invalid auth event kind");
+    }
+    if event.created_at.as_u64().abs_diff(Timestamp::now().as_u64()) > MAX_AUTH_AGE {
+        bail!("Auth event has expired");
+    }
+
+    let url = Url::parse(public_url)?.join(
+        req.uri()
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or("/"),
+    )?;
+    let mut url_match = false;
+    let mut method_match = false;
+    for tag in event.tags.iter() {
+        match tag.as_slice() {
+            [k, v, ..] if k == "u" => {
+                url_match = Url::parse(v).map(|u| u == url).unwrap_or(false);
+            }
+            [k, v, ..] if k == "method" => {
+                method_match = v.eq_ignore_ascii_case(req.method().as_str());
+            }
+            _ => {}
+        }
+    }
+    if !url_match {
+        bail!("Auth event url does not match request");
+    }
+    if !method_match {
+        bail!("Auth event method does not match request");
+    }
+    Ok(event.pubkey)
+}
+
+/// Check the `payload` tag of the NIP-98 auth event of [req] is the SHA-256 of [body]
+///
+/// Requests with an empty body or which are not NIP-98 signed (e.g. controller tokens) are not
+/// checked, call [check_nip98_auth] first
+pub fn check_nip98_payload<T>(req: &Request<T>, body: &[u8]) -> Result<()> {
+    if body.is_empty() {
+        return Ok(());
+    }
+    let auth = match req.headers().get("authorization") {
+        Some(a) => a.to_str()?,
+        None => return Ok(()),
+    };
+    let event = match auth_event(auth)? {
+        Some(e) => e,
+        None => return Ok(()),
+    };
+    let hash = hex::encode(Sha256::digest(body));
+    let payload_match = event.tags.iter().any(|tag| match tag.as_slice() {
+        [k, v, ..] if k == "payload" => v.eq_ignore_ascii_case(&hash),
+        _ => false,
+    });
+    if !payload_match {
+        bail!("Auth event payload does not match request body");
+    }
+    Ok(())
+}
+
+/// Signed auth event of a `Nostr` authorization header, [None] for other schemes
+fn auth_event(auth: &str) -> Result<Option<Event>> {
+    let token = match auth.strip_prefix("Nostr ") {
+        Some(t) => t,
+        None => return Ok(None),
+    };
+    let json = base64::engine::general_purpose::STANDARD.decode(token)?;
+    let event = Event::from_json(json)?;
+    event.verify()?;
+    Ok(Some(event))
+}
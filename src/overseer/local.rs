@@ -2,6 +2,7 @@ use crate::egress::EgressConfig;
 use crate::ingress::ConnectionInfo;
 use crate::overseer::{get_default_variants, IngressInfo, Overseer};
 use crate::pipeline::{EgressType, PipelineConfig};
+use crate::variant::video::VideoEncoder;
 use crate::variant::StreamMapping;
 use anyhow::Result;
 use async_trait::async_trait;
@@ -10,11 +11,30 @@ use uuid::Uuid;
 
 /// Simple static file output without any access controls
 /// Useful for testing or self-hosting
-pub struct LocalOverseer;
+pub struct LocalOverseer {
+    /// See [PipelineConfig::settle_window]
+    settle_window: f32,
+    /// See [PipelineConfig::failover_window]
+    failover_window: u64,
+    /// See [PipelineConfig::program_date_time_anchor]
+    program_date_time_anchor: u64,
+    /// Encoder of the transcoded video variants
+    video_encoder: VideoEncoder,
+}
 
 impl LocalOverseer {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(
+        settle_window: f32,
+        failover_window: u64,
+        program_date_time_anchor: u64,
+        video_encoder: VideoEncoder,
+    ) -> Self {
+        Self {
+            settle_window,
+            failover_window,
+            program_date_time_anchor,
+            video_encoder,
+        }
     }
 }
 
@@ -25,7 +45,8 @@ impl Overseer for LocalOverseer {
         _connection: &ConnectionInfo,
         stream_info: &IngressInfo,
     ) -> Result<PipelineConfig> {
-        let vars = get_default_variants(stream_info)?;
+        let mut vars = get_default_variants(stream_info)?;
+        self.video_encoder.apply(&mut vars);
         let var_ids = vars.iter().map(|v| v.id()).collect();
         Ok(PipelineConfig {
             id: Uuid::new_v4(),
@@ -34,6 +55,13 @@ impl Overseer for LocalOverseer {
                 name: "HLS".to_owned(),
                 variants: var_ids,
             })],
+            settle_window: self.settle_window,
+            recording_mp4: false,
+            failover_window: self.failover_window,
+            program_date_time_anchor: self.program_date_time_anchor,
+            loudnorm: None,
+            segment_type: None,
+            watermark: None,
         })
     }
 
@@ -1,4 +1,7 @@
-use crate::ingress::ConnectionInfo;
+use crate::egress::recorder::RecordingFile;
+use crate::ingress::failover::DEFAULT_FAILOVER_WINDOW;
+use crate::ingress::file::FileInput;
+use crate::ingress::{ConnectionInfo, EndpointStats, PulledInput};
 
 #[cfg(feature = "local-overseer")]
 use crate::overseer::local::LocalOverseer;
@@ -6,7 +9,10 @@ use crate::overseer::local::LocalOverseer;
 use crate::overseer::webhook::WebhookOverseer;
 #[cfg(feature = "zap-stream")]
 use crate::overseer::zap_stream::ZapStreamOverseer;
-use crate::pipeline::PipelineConfig;
+use crate::pipeline::captions::{is_text_subtitle, speech_to_text_enabled};
+use crate::pipeline::settle::DEFAULT_SETTLE_WINDOW;
+use crate::pipeline::timing::SegmentTiming;
+use crate::pipeline::{PipelineConfig, PipelineControl};
 #[cfg(any(
     feature = "local-overseer",
     feature = "webhook-overseer",
@@ -14,9 +20,10 @@ use crate::pipeline::PipelineConfig;
 ))]
 use crate::settings::OverseerConfig;
 use crate::settings::Settings;
+use crate::status::ServerStatus;
 use crate::variant::audio::AudioVariant;
 use crate::variant::mapping::VariantMapping;
-use crate::variant::video::VideoVariant;
+use crate::variant::video::{VideoEncoder, VideoVariant};
 use crate::variant::VariantStream;
 use anyhow::Result;
 use async_trait::async_trait;
@@ -27,7 +34,7 @@ use http_body_util::Full;
 use hyper::body::Incoming;
 use hyper::{Request, Response};
 use std::cmp::PartialEq;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use uuid::Uuid;
 
@@ -77,6 +84,29 @@ pub trait Overseer: Send + Sync {
     /// Check all streams
     async fn check_streams(&self) -> Result<()>;
 
+    /// Files which should start streaming now, polled with [Overseer::check_streams]
+    async fn due_inputs(&self) -> Result<Vec<FileInput>> {
+        Ok(vec![])
+    }
+
+    /// Ingests which should start streaming now, polled with [Overseer::check_streams]
+    async fn due_pulls(&self) -> Result<Vec<PulledInput>> {
+        Ok(vec![])
+    }
+
+    /// Health of the components managed by the overseer, shown on the public status page
+    async fn status(&self) -> Result<ServerStatus> {
+        Ok(ServerStatus::default())
+    }
+
+    /// Check an ingest connection before any media is read, protocols which negotiate the
+    /// stream (RTMP) use this to refuse it early with a [crate::ingress::Rejection]
+    ///
+    /// [start_stream] must still check the connection, not every ingress calls this
+    async fn check_connection(&self, _connection: &ConnectionInfo) -> Result<()> {
+        Ok(())
+    }
+
     /// Set up a new streaming pipeline
     async fn start_stream(
         &self,
@@ -108,36 +138,123 @@ pub trait Overseer: Send + Sync {
 
     /// Stream is finished
     async fn on_end(&self, pipeline_id: &Uuid) -> Result<()>;
+
+    /// The server is shutting down, save any state which is only kept in memory
+    ///
+    /// Running streams must not be ended, encoders reconnecting after the restart resume them
+    async fn on_shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Changes requested for a running pipeline, polled after each new segment
+    ///
+    /// Returns [None] when the pipeline should keep running as configured
+    async fn pipeline_control(&self, _pipeline_id: &Uuid) -> Result<Option<PipelineControl>> {
+        Ok(None)
+    }
+
+    /// Latest link statistics of the ingest connection of a pipeline, reported after each
+    /// new segment by protocols which track them
+    async fn on_endpoint_stats(&self, _pipeline_id: &Uuid, _stats: &EndpointStats) -> Result<()> {
+        Ok(())
+    }
+
+    /// Timing of a segment reported with [Overseer::on_segment], kept for debugging
+    async fn on_segment_timing(&self, _pipeline_id: &Uuid, _timing: &SegmentTiming) -> Result<()> {
+        Ok(())
+    }
+
+    /// Check if a viewer can load [file] from the output directory of a stream
+    ///
+    /// [token] is the share token passed by the viewer, if any
+    async fn check_playback(
+        &self,
+        _stream_id: &Uuid,
+        _file: &str,
+        _token: Option<&str>,
+    ) -> Result<bool> {
+        Ok(true)
+    }
+
+    /// A playlist of a stream was loaded by [viewer], an opaque id of the playback session
+    /// which is the same for all requests of one player
+    async fn on_view(&self, _stream_id: &Uuid, _viewer: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// [bytes] of a stream's files were served to a viewer by the HTTP server
+    async fn on_egress(&self, _stream_id: &Uuid, _bytes: u64) -> Result<()> {
+        Ok(())
+    }
+
+    /// A pipeline thread crashed, [restarting] is set when a new pipeline will be started
+    /// for the same ingest connection, otherwise [Overseer::on_end] is called next
+    async fn on_pipeline_crash(
+        &self,
+        _pipeline_id: &Uuid,
+        _error: &str,
+        _restarting: bool,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Recording of a pipeline was paused (or resumed) because the output dir is almost full
+    async fn on_recording_paused(&self, _pipeline_id: &Uuid, _paused: bool) -> Result<()> {
+        Ok(())
+    }
+
+    /// A recording of a pipeline was remuxed into [recordings] (one MP4 per video variant),
+    /// only called when [crate::pipeline::PipelineConfig::recording_mp4] is set
+    async fn on_recording_complete(
+        &self,
+        _pipeline_id: &Uuid,
+        _recordings: &[RecordingFile],
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Restore a segment which failed checksum verification from a backup copy
+    ///
+    /// Returns false if no backup of the segment is available
+    async fn repair_segment(&self, _path: &Path, _sha256: &str) -> Result<bool> {
+        Ok(false)
+    }
 }
 
 impl Settings {
+    /// See [PipelineConfig::settle_window]
+    pub fn settle_window(&self) -> f32 {
+        self.settle_window.unwrap_or(DEFAULT_SETTLE_WINDOW)
+    }
+
+    /// See [PipelineConfig::failover_window]
+    pub fn failover_window(&self) -> u64 {
+        self.failover_window.unwrap_or(DEFAULT_FAILOVER_WINDOW)
+    }
+
+    /// See [PipelineConfig::program_date_time_anchor]
+    pub fn program_date_time_anchor(&self) -> u64 {
+        self.program_date_time_anchor.unwrap_or(0)
+    }
+
+    /// Encoder of the transcoded video variants, with `auto` resolved
+    pub fn video_encoder(&self) -> VideoEncoder {
+        self.video_encoder.unwrap_or_default().resolve()
+    }
+
     pub async fn get_overseer(&self) -> Result<Arc<dyn Overseer>> {
         match &self.overseer {
             #[cfg(feature = "local-overseer")]
-            OverseerConfig::Local => Ok(Arc::new(LocalOverseer::new())),
+            OverseerConfig::Local => Ok(Arc::new(LocalOverseer::new(
+                self.settle_window(),
+                self.failover_window(),
+                self.program_date_time_anchor(),
+                self.video_encoder(),
+            ))),
             #[cfg(feature = "webhook-overseer")]
             OverseerConfig::Webhook { url } => Ok(Arc::new(WebhookOverseer::new(&url))),
             #[cfg(feature = "zap-stream")]
-            OverseerConfig::ZapStream {
-                nsec: private_key,
-                database,
-                lnd,
-                relays,
-                blossom,
-                cost,
-            } => Ok(Arc::new(
-                ZapStreamOverseer::new(
-                    &self.output_dir,
-                    &self.public_url,
-                    private_key,
-                    database,
-                    lnd,
-                    relays,
-                    blossom,
-                    *cost,
-                )
-                .await?,
-            )),
+            OverseerConfig::ZapStream { .. } => Ok(Arc::new(ZapStreamOverseer::new(self).await?)),
             _ => {
                 panic!("Unsupported overseer");
             }
@@ -174,6 +291,7 @@ pub(crate) fn get_default_variants(info: &IngressInfo) -> Result<Vec<VariantStre
             level: 51,
             keyframe_interval: video_src.fps as u16 * 2,
             pixel_format: AV_PIX_FMT_YUV420P as u32,
+            encoder: Default::default(),
         }));
     }
 
@@ -203,5 +321,26 @@ pub(crate) fn get_default_variants(info: &IngressInfo) -> Result<Vec<VariantStre
         }));
     }
 
+    // text subtitles of the input, or else captions transcribed from the audio when
+    // speech to text is configured, or else any CEA-608 captions carried in the video
+    let generated = if speech_to_text_enabled() {
+        IngressStreamType::Audio
+    } else {
+        IngressStreamType::Video
+    };
+    let captions_src = info
+        .streams
+        .iter()
+        .find(|c| c.stream_type == IngressStreamType::Subtitle && is_text_subtitle(c.codec))
+        .or_else(|| info.streams.iter().find(|c| c.stream_type == generated));
+    if let Some(src) = captions_src {
+        vars.push(VariantStream::Subtitle(VariantMapping {
+            id: Uuid::new_v4(),
+            src_index: src.index,
+            dst_index: 4,
+            group_id: 1,
+        }));
+    }
+
     Ok(vars)
 }
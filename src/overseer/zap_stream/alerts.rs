@@ -0,0 +1,244 @@
+use crate::overseer::zap_stream::api::ApiResponse;
+use crate::overseer::zap_stream::keys::KeyRing;
+use crate::overseer::zap_stream::zaps::ZapReceipt;
+use crate::overseer::zap_stream::{ZapStreamOverseer, STREAM_EVENT_KIND};
+use anyhow::{bail, Result};
+use futures_util::{SinkExt, StreamExt};
+use http_body_util::combinators::BoxBody;
+use hyper::body::Incoming;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use log::{info, warn};
+use nostr_sdk::prelude::Coordinate;
+use nostr_sdk::{
+    Client, Event, Filter, JsonUtil, Kind, Metadata, PublicKey, RelayPoolNotification,
+    SubscriptionId, Timestamp,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use url::Url;
+use zap_stream_db::{User, ZapStreamDb};
+
+/// How often to check if the user started a new stream
+const STREAM_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Max time to wait for a sender profile
+const PROFILE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Alert sent to overlay clients
+#[derive(Serialize)]
+struct Alert {
+    /// zap / chat
+    #[serde(rename = "type")]
+    kind: &'static str,
+    stream: String,
+    /// Amount in milli-sats for zaps
+    amount: Option<u64>,
+    sender: AlertSender,
+    message: String,
+    created: u64,
+}
+
+#[derive(Serialize)]
+struct AlertSender {
+    pubkey: String,
+    name: Option<String>,
+    picture: Option<String>,
+}
+
+impl ZapStreamOverseer {
+    /// Secret key for the alerts websocket of [user], derived from the service key so
+    /// it does not need to be stored
+    pub(super) fn alerts_key(&self, user: &User) -> String {
+        let mut hash = Sha256::new();
+        hash.update(self.keys.primary().secret_key().to_secret_hex().as_bytes());
+        hash.update(b"alerts");
+        hash.update(&user.pubkey);
+        hex::encode(hash.finalize())
+    }
+
+    /// Check [key] is the alerts key of [user] in constant time
+    pub(super) fn check_alerts_key(&self, user: &User, key: &str) -> bool {
+        self.alerts_key(user)
+            .as_bytes()
+            .ct_eq(key.as_bytes())
+            .into()
+    }
+
+    /// Websocket URL overlays connect to for [user]'s alerts
+    pub(super) fn alerts_url(&self, user: &User) -> Result<String> {
+        let mut u: Url = self.public_url.parse()?;
+        let scheme = if u.scheme() == "https" { "wss" } else { "ws" };
+        if u.set_scheme(scheme).is_err() {
+            bail!("Invalid public url");
+        }
+        u.set_path(&format!("/api/v1/alerts/{}", hex::encode(&user.pubkey)));
+        u.set_query(Some(&format!("key={}", self.alerts_key(user))));
+        Ok(u.to_string())
+    }
+
+    /// Accept the alerts websocket for [user], zaps and chat messages on the user's live
+    /// stream are relayed until the socket is closed
+    pub(super) fn alerts_websocket(
+        &self,
+        mut req: Request<Incoming>,
+        user: User,
+    ) -> Result<ApiResponse> {
+        let is_upgrade = req
+            .headers()
+            .get("upgrade")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+        let key = match req.headers().get("sec-websocket-key") {
+            Some(k) if is_upgrade => derive_accept_key(k.as_bytes()),
+            _ => bail!("Expected websocket upgrade"),
+        };
+
+        let on_upgrade = hyper::upgrade::on(&mut req);
+        let db = self.db.clone();
+        let client = self.client.clone();
+        let keys = self.keys.clone();
+        tokio::spawn(async move {
+            let ws = match on_upgrade.await {
+                Ok(u) => {
+                    WebSocketStream::from_raw_socket(TokioIo::new(u), Role::Server, None).await
+                }
+                Err(e) => {
+                    warn!("Alerts websocket upgrade failed: {}", e);
+                    return;
+                }
+            };
+            info!("Alerts websocket connected for user {}", user.id);
+            if let Err(e) = relay_alerts(ws, db, client, keys, user.id).await {
+                warn!("Alerts websocket failed: {}", e);
+            }
+        });
+
+        Ok(Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header("server", "zap-stream-core")
+            .header("upgrade", "websocket")
+            .header("connection", "upgrade")
+            .header("sec-websocket-accept", key)
+            .body(BoxBody::default())?)
+    }
+}
+
+/// Forward zap receipts and chat messages of the user's live stream to [ws]
+async fn relay_alerts<S>(
+    mut ws: WebSocketStream<S>,
+    db: ZapStreamDb,
+    client: Client,
+    keys: Arc<KeyRing>,
+    user_id: u64,
+) -> Result<()>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let mut notifications = client.notifications();
+    let mut check = tokio::time::interval(STREAM_CHECK_INTERVAL);
+    // current live stream and the relay subscription for its events
+    let mut sub: Option<(String, SubscriptionId)> = None;
+    let res = loop {
+        tokio::select! {
+            _ = check.tick() => {
+                let live = db.get_user_live_stream(user_id).await?;
+                if live.as_ref().map(|s| &s.id) == sub.as_ref().map(|(s, _)| s) {
+                    continue;
+                }
+                if let Some((_, id)) = sub.take() {
+                    client.unsubscribe(id).await;
+                }
+                if let Some(stream) = live {
+                    let coord = Coordinate::new(
+                        Kind::from(STREAM_EVENT_KIND),
+                        keys.stream_keys(&stream).public_key,
+                    )
+                    .identifier(&stream.id);
+                    let filter = Filter::new()
+                        .kinds([Kind::ZapReceipt, Kind::LiveEventMessage])
+                        .coordinate(&coord)
+                        .since(Timestamp::now());
+                    let id = client.subscribe(vec![filter], None).await?.val;
+                    sub = Some((stream.id.clone(), id));
+                }
+            }
+            msg = ws.next() => match msg {
+                Some(Ok(Message::Close(_))) | None => break Ok(()),
+                Some(Err(e)) => break Err(e.into()),
+                // pings are answered by the websocket, overlays don't send anything else
+                Some(Ok(_)) => {}
+            },
+            n = notifications.recv() => match n {
+                Ok(RelayPoolNotification::Event { subscription_id, event, .. }) => {
+                    let stream_id = match &sub {
+                        Some((s, id)) if *id == subscription_id => s.clone(),
+                        _ => continue,
+                    };
+                    if let Some(alert) = to_alert(&client, stream_id, &event).await {
+                        if let Err(e) = ws.send(Message::Text(serde_json::to_string(&alert)?)).await {
+                            break Err(e.into());
+                        }
+                    }
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break Ok(()),
+            }
+        }
+    };
+    if let Some((_, id)) = sub {
+        client.unsubscribe(id).await;
+    }
+    res
+}
+
+/// Convert a zap receipt or chat message to an alert
+async fn to_alert(client: &Client, stream: String, ev: &Event) -> Option<Alert> {
+    let (sender, amount, message) = match ev.kind {
+        Kind::ZapReceipt => {
+            // malformed or forged zaps are not shown
+            let zap = ZapReceipt::validate(ev).ok()?;
+            (zap.request.pubkey, Some(zap.amount), zap.request.content)
+        }
+        Kind::LiveEventMessage => (ev.pubkey, None, ev.content.clone()),
+        _ => return None,
+    };
+    let profile = fetch_profile(client, sender).await;
+    Some(Alert {
+        kind: if ev.kind == Kind::ZapReceipt {
+            "zap"
+        } else {
+            "chat"
+        },
+        stream,
+        amount,
+        sender: AlertSender {
+            pubkey: sender.to_hex(),
+            name: profile
+                .as_ref()
+                .and_then(|p| p.display_name.clone().or(p.name.clone())),
+            picture: profile.and_then(|p| p.picture),
+        },
+        message,
+        created: ev.created_at.as_u64(),
+    })
+}
+
+/// Latest profile metadata of [pubkey]
+async fn fetch_profile(client: &Client, pubkey: PublicKey) -> Option<Metadata> {
+    let filter = Filter::new().author(pubkey).kind(Kind::Metadata).limit(1);
+    let events = client
+        .fetch_events(vec![filter], Some(PROFILE_TIMEOUT))
+        .await
+        .ok()?;
+    let ev = events.into_iter().max_by_key(|e| e.created_at)?;
+    Metadata::from_json(&ev.content).ok()
+}
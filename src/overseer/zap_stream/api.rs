@@ -0,0 +1,3506 @@
+#[cfg(feature = "chaos")]
+use crate::chaos::ChaosConfig;
+use crate::http::{BadRequest, ClientIp, Forbidden, Unauthorized, Unavailable};
+use crate::ingress::{endpoint_name, EndpointStats};
+use crate::mux::trim_recording;
+use crate::nip98::{check_nip98_auth, check_nip98_payload};
+use crate::overseer::zap_stream::federation::{ApiDirectoryStream, PARAM_LOCAL};
+use crate::overseer::zap_stream::mirror::MirrorRequest;
+use crate::overseer::zap_stream::playback::PlaybackReport;
+use crate::overseer::zap_stream::premiere::ApiPremiere;
+use crate::overseer::zap_stream::restream::RestreamRequest;
+use crate::overseer::zap_stream::routing::NodeCapacity;
+use crate::overseer::zap_stream::usage::{DEFAULT_USAGE_MONTHS, MAX_USAGE_MONTHS};
+use crate::overseer::zap_stream::vod::{VodImport, VodInfo};
+use crate::overseer::zap_stream::webhooks::validate_webhook_url;
+use crate::overseer::zap_stream::{
+    check_input_limits, SlateFile, WatermarkOwner, ZapStreamOverseer, TRIMMED_RECORDING,
+};
+use crate::overseer::{IngressInfo, IngressStream, IngressStreamType, Overseer};
+use crate::pipeline::watermark::{probe_image, WatermarkPosition};
+use crate::pipeline::{AdBreak, EgressType};
+use crate::variant::{StreamMapping, VariantStream};
+use anyhow::{anyhow, bail, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use bytes::Bytes;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures_util::TryStreamExt;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full, Limited, StreamBody};
+use hyper::body::{Frame, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+use log::info;
+use nostr_sdk::{PublicKey, ToBech32};
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::SeekFrom;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::io::ReaderStream;
+use url::Url;
+use uuid::Uuid;
+use zap_stream_db::{
+    AdminApproval, AdminRole, ApprovalAction, ApprovalState, AuditLog, AuditLogFilter,
+    BalanceTransfer, EgressSource, IngestEndpoint, IngestEndpointVariant, RecordingDownload,
+    StreamConnection, StreamEgress, StreamEgressTotal, StreamIncident, StreamMarker, StreamRefund,
+    StreamShare, StreamVisibility, UsageAlert, User, UserStream, UserStreamState, Voucher,
+};
+
+/// Default number of items returned by list endpoints
+const DEFAULT_PAGE_LIMIT: u64 = 50;
+
+/// Max number of items returned by list endpoints
+const MAX_PAGE_LIMIT: u64 = 500;
+
+/// Max size of uploaded slate files and watermark images
+const MAX_SLATE_SIZE: usize = 10 * 1024 * 1024;
+
+/// Max length of stream marker labels
+const MAX_MARKER_LABEL: usize = 200;
+
+/// Max planned length of an ad break in seconds
+const MAX_AD_BREAK: u32 = 600;
+
+/// Max length of encoder preset / tune / profile names
+const MAX_ENCODER_OPTION: usize = 50;
+
+/// Max rungs of the transcode ladder of an ingest endpoint
+const MAX_LADDER_RUNGS: usize = 8;
+
+/// Range of target loudness (LUFS) an ingest endpoint can normalize audio to
+const MIN_LOUDNORM: f32 = -36.0;
+const MAX_LOUDNORM: f32 = -10.0;
+
+pub(super) type ApiResponse = Response<BoxBody<Bytes, anyhow::Error>>;
+
+/// Permissions required by admin API routes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AdminPermission {
+    /// View users and their accounts
+    ViewUsers,
+    /// View streams
+    ViewStreams,
+    /// End live streams
+    TerminateStream,
+    /// Republish the nostr event of a stream
+    RepublishStream,
+    /// View / export the audit log
+    ViewAuditLog,
+    /// Add balance to a user
+    CreditBalance,
+    /// Delete a user
+    DeleteUser,
+    /// List, approve and reject actions proposed by other admins
+    ManageApprovals,
+    /// View, add and remove IP bans
+    ManageBans,
+    /// View and change encoder settings and input limits of ingest endpoints
+    ManageEndpoints,
+    /// View and rotate the platform signing keys
+    ManageKeys,
+    /// Mirror streams of federation peers
+    ManageMirrors,
+    /// Control fault injection
+    #[cfg(feature = "chaos")]
+    Chaos,
+}
+
+impl AdminPermission {
+    fn allowed(&self, role: AdminRole) -> bool {
+        match role {
+            AdminRole::Admin => true,
+            AdminRole::Moderator => matches!(
+                self,
+                AdminPermission::ViewUsers
+                    | AdminPermission::ViewStreams
+                    | AdminPermission::TerminateStream
+                    | AdminPermission::RepublishStream
+                    | AdminPermission::ManageBans
+            ),
+        }
+    }
+}
+
+/// Pagination parameters for list endpoints
+///
+/// Clients should prefer `cursor` (returned as `next_cursor` on each page),
+/// `page` is kept for backwards compatibility and is ignored when `cursor` is set
+struct PageQuery {
+    cursor: Option<PageCursor>,
+    page: u64,
+    limit: u64,
+}
+
+impl PageQuery {
+    fn from_query(query: &HashMap<String, String>) -> Result<Self> {
+        Ok(Self {
+            cursor: match query.get("cursor") {
+                Some(c) => Some(PageCursor::decode(c)?),
+                None => None,
+            },
+            page: query
+                .get("page")
+                .map(|p| p.parse())
+                .transpose()?
+                .unwrap_or(0),
+            limit: query
+                .get("limit")
+                .map(|p| p.parse())
+                .transpose()?
+                .unwrap_or(DEFAULT_PAGE_LIMIT)
+                .clamp(1, MAX_PAGE_LIMIT),
+        })
+    }
+
+    fn offset(&self) -> u64 {
+        self.page * self.limit
+    }
+
+    /// Build the response page, adding the cursor for the next page if this page is full
+    fn to_response<T, R>(
+        &self,
+        rows: Vec<T>,
+        cursor: impl Fn(&T) -> PageCursor,
+        map: impl Fn(T) -> R,
+    ) -> PageResponse<R> {
+        let next_cursor = if rows.len() as u64 == self.limit {
+            rows.last().map(|r| cursor(r).encode())
+        } else {
+            None
+        };
+        PageResponse {
+            items: rows.into_iter().map(map).collect(),
+            page: self.page,
+            limit: self.limit,
+            next_cursor,
+        }
+    }
+}
+
+/// Opaque keyset cursor (created, id) of the last item on a page
+struct PageCursor {
+    created: DateTime<Utc>,
+    id: String,
+}
+
+impl PageCursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}", self.created.timestamp(), self.id))
+    }
+
+    fn decode(cursor: &str) -> Result<Self> {
+        let data = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor)?)?;
+        let (created, id) = match data.split_once(':') {
+            Some(x) => x,
+            None => bail!("Invalid cursor"),
+        };
+        let created = match DateTime::from_timestamp(created.parse()?, 0) {
+            Some(c) => c,
+            None => bail!("Invalid cursor"),
+        };
+        Ok(Self {
+            created,
+            id: id.to_string(),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct PageResponse<T> {
+    items: Vec<T>,
+    page: u64,
+    limit: u64,
+    /// Cursor to fetch the next page, missing on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ApiUser {
+    id: u64,
+    pubkey: String,
+    created: i64,
+    balance: i64,
+    tos_accepted: Option<i64>,
+    is_admin: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    admin_role: Option<String>,
+    is_blocked: bool,
+    recording: bool,
+    cost_multiplier: f32,
+}
+
+impl From<User> for ApiUser {
+    fn from(u: User) -> Self {
+        Self {
+            id: u.id,
+            pubkey: hex::encode(&u.pubkey),
+            created: u.created.timestamp(),
+            balance: u.balance,
+            tos_accepted: u.tos_accepted.map(|t| t.timestamp()),
+            is_admin: u.is_admin,
+            admin_role: u.is_admin.then(|| u.admin_role.to_string()),
+            is_blocked: u.is_blocked,
+            recording: u.recording,
+            cost_multiplier: u.cost_multiplier,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ApiUsageAlert {
+    id: u64,
+    user_id: u64,
+    /// Debit rate in milli-sats / minute
+    rate: u64,
+    streams: u32,
+    acknowledged_by: Option<u64>,
+    acknowledged: Option<i64>,
+    created: i64,
+}
+
+impl From<UsageAlert> for ApiUsageAlert {
+    fn from(a: UsageAlert) -> Self {
+        Self {
+            id: a.id,
+            user_id: a.user_id,
+            rate: a.rate,
+            streams: a.streams,
+            acknowledged_by: a.acknowledged_by,
+            acknowledged: a.acknowledged.map(|t| t.timestamp()),
+            created: a.created.timestamp(),
+        }
+    }
+}
+
+/// Platform signing key
+#[derive(Serialize)]
+struct ApiSigningKey {
+    pubkey: String,
+    npub: String,
+    /// New events are signed with this key
+    active: bool,
+    /// Last time the key was activated, never for the `nsec` key if no other key was
+    /// activated since
+    activated: Option<i64>,
+}
+
+/// Number of key activations searched for [ApiSigningKey::activated]
+const SIGNING_KEY_HISTORY: u64 = 100;
+
+/// Default number of days in the egress report
+const DEFAULT_EGRESS_DAYS: i64 = 30;
+
+/// Summarized logs of a CDN edge
+#[derive(Deserialize)]
+struct CdnLogReport {
+    /// Name of the edge / CDN, reports replace earlier reports of the same edge and day
+    edge: String,
+    streams: Vec<CdnStreamLog>,
+}
+
+/// Requests for the files of one stream on one day
+#[derive(Deserialize)]
+struct CdnStreamLog {
+    stream_id: String,
+    /// Day (UTC) in `YYYY-MM-DD` format
+    day: NaiveDate,
+    requests: u64,
+    bytes: u64,
+    /// Estimated number of distinct viewer IPs
+    #[serde(default)]
+    unique_ips: u64,
+}
+
+/// Max length of a CDN edge name
+const MAX_EDGE_NAME: usize = 50;
+
+#[derive(Serialize)]
+struct ApiDailyEgress {
+    day: NaiveDate,
+    /// `origin` (served by this node) or `cdn`
+    source: String,
+    /// CDN edge which reported the egress
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edge: Option<String>,
+    bytes: u64,
+    requests: u64,
+    unique_ips: u64,
+}
+
+impl From<StreamEgress> for ApiDailyEgress {
+    fn from(e: StreamEgress) -> Self {
+        Self {
+            day: e.day,
+            source: match e.source {
+                EgressSource::Origin => "origin",
+                EgressSource::Cdn => "cdn",
+            }
+            .to_string(),
+            edge: (!e.edge.is_empty()).then_some(e.edge),
+            bytes: e.bytes,
+            requests: e.requests,
+            unique_ips: e.unique_ips,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ApiEgressTotal {
+    stream_id: String,
+    user_id: u64,
+    origin_bytes: u64,
+    cdn_bytes: u64,
+    requests: u64,
+    /// Sum of the daily unique IP estimates reported by CDN edges
+    unique_ips: u64,
+}
+
+impl From<StreamEgressTotal> for ApiEgressTotal {
+    fn from(e: StreamEgressTotal) -> Self {
+        Self {
+            stream_id: e.stream_id,
+            user_id: e.user_id,
+            origin_bytes: e.origin_bytes,
+            cdn_bytes: e.cdn_bytes,
+            requests: e.requests,
+            unique_ips: e.unique_ips,
+        }
+    }
+}
+
+/// Max per-user multiplier of endpoint rates
+const MAX_COST_MULTIPLIER: f32 = 10.0;
+
+#[derive(Deserialize)]
+struct AdminUserUpdate {
+    /// Multiplier applied to endpoint rates (0 = free, 0.5 = half price)
+    cost_multiplier: Option<f32>,
+}
+
+#[derive(Serialize)]
+struct ApiStream {
+    id: String,
+    user_id: u64,
+    state: String,
+    starts: i64,
+    ends: Option<i64>,
+    title: Option<String>,
+    /// Duration in seconds
+    duration: f32,
+    /// Cost in milli-sats
+    cost: u64,
+    visibility: String,
+    /// Shown in the public stream directory
+    listed: bool,
+    /// Most concurrent viewers, set when the stream ended
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peak_viewers: Option<u32>,
+    /// Average concurrent viewers, set when the stream ended
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avg_viewers: Option<f32>,
+}
+
+/// Past stream on a streamer's public channel page
+#[derive(Serialize)]
+struct ApiArchiveStream {
+    id: String,
+    title: Option<String>,
+    starts: i64,
+    ends: Option<i64>,
+    /// Duration in seconds
+    duration: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thumb: Option<String>,
+    /// Trimmed recording, only set once the owner trimmed and published the recording
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replay: Option<String>,
+    /// MP4 of the full recording, if recordings are published
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recording: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StreamUpdate {
+    /// Show the stream in the public stream directory
+    listed: Option<bool>,
+}
+
+impl From<UserStream> for ApiStream {
+    fn from(s: UserStream) -> Self {
+        Self {
+            state: s.state.to_string(),
+            starts: s.starts.timestamp(),
+            ends: s.ends.map(|e| e.timestamp()),
+            id: s.id,
+            user_id: s.user_id,
+            title: s.title,
+            duration: s.duration,
+            cost: s.cost,
+            visibility: s.visibility.to_string(),
+            listed: s.listed,
+            peak_viewers: s.peak_viewers,
+            avg_viewers: s.avg_viewers,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ApiAuditLog {
+    id: u64,
+    admin_id: u64,
+    action: String,
+    target_type: String,
+    target_id: String,
+    message: Option<String>,
+    created: i64,
+}
+
+impl From<AuditLog> for ApiAuditLog {
+    fn from(a: AuditLog) -> Self {
+        Self {
+            id: a.id,
+            admin_id: a.admin_id,
+            action: a.action,
+            target_type: a.target_type,
+            target_id: a.target_id,
+            message: a.message,
+            created: a.created.timestamp(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ApiStreamConnection {
+    ip_addr: String,
+    endpoint: String,
+    user_agent: Option<String>,
+    params: HashMap<String, String>,
+    created: i64,
+}
+
+impl From<StreamConnection> for ApiStreamConnection {
+    fn from(c: StreamConnection) -> Self {
+        Self {
+            params: c
+                .params
+                .and_then(|p| serde_json::from_str(&p).ok())
+                .unwrap_or_default(),
+            ip_addr: c.ip_addr,
+            endpoint: c.endpoint,
+            user_agent: c.user_agent,
+            created: c.created.timestamp(),
+        }
+    }
+}
+
+/// Admin view of a single stream
+#[derive(Serialize)]
+struct ApiStreamIncident {
+    id: u64,
+    stream_id: String,
+    error: String,
+    restarted: bool,
+    created: i64,
+}
+
+impl From<StreamIncident> for ApiStreamIncident {
+    fn from(i: StreamIncident) -> Self {
+        Self {
+            id: i.id,
+            stream_id: i.stream_id,
+            error: i.error,
+            restarted: i.restarted,
+            created: i.created.timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RefundRequest {
+    /// Amount in milli-sats, defaults to the part of the stream cost not yet refunded
+    amount: Option<u64>,
+    reason: Option<String>,
+}
+
+/// [AdminApproval::params] of a refund waiting for approval
+#[derive(Serialize, Deserialize)]
+struct RefundParams {
+    stream_id: String,
+}
+
+#[derive(Serialize)]
+struct ApiStreamRefund {
+    id: u64,
+    stream_id: String,
+    admin_id: u64,
+    amount: u64,
+    reason: Option<String>,
+    created: i64,
+}
+
+impl From<StreamRefund> for ApiStreamRefund {
+    fn from(r: StreamRefund) -> Self {
+        Self {
+            id: r.id,
+            stream_id: r.stream_id,
+            admin_id: r.admin_id,
+            amount: r.amount,
+            reason: r.reason,
+            created: r.created.timestamp(),
+        }
+    }
+}
+
+/// Max length of a transfer memo
+const MAX_TRANSFER_MEMO: usize = 200;
+
+#[derive(Deserialize)]
+struct TransferRequest {
+    /// Hex pubkey of the recipient
+    pubkey: String,
+    /// Amount in milli-sats
+    amount: u64,
+    memo: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ApiBalanceTransfer {
+    id: u64,
+    /// Hex pubkey of the other user of the transfer, null if they were deleted
+    counterparty: Option<String>,
+    /// Amount in milli-sats, negative for sent transfers
+    amount: i64,
+    memo: Option<String>,
+    created: i64,
+}
+
+/// Max number of vouchers created in one request
+const MAX_VOUCHER_BATCH: u32 = 100;
+
+/// Characters used in voucher codes, without look-alikes (0/O, 1/I/L)
+const VOUCHER_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+
+#[derive(Serialize, Deserialize)]
+struct CreateVoucherRequest {
+    /// Amount of each voucher in sats
+    amount: u64,
+    /// Number of vouchers to create
+    #[serde(default = "default_voucher_count")]
+    count: u32,
+    /// Seconds until the vouchers expire, never expire if not set
+    expires_in: Option<u64>,
+    /// Internal note on why the vouchers were created
+    memo: Option<String>,
+}
+
+fn default_voucher_count() -> u32 {
+    1
+}
+
+#[derive(Deserialize)]
+struct RedeemRequest {
+    code: String,
+}
+
+#[derive(Serialize)]
+struct ApiVoucher {
+    id: u64,
+    code: String,
+    /// Amount in milli-sats
+    amount: u64,
+    created_by: u64,
+    memo: Option<String>,
+    expires: Option<i64>,
+    redeemed_by: Option<u64>,
+    redeemed: Option<i64>,
+    created: i64,
+}
+
+impl From<Voucher> for ApiVoucher {
+    fn from(v: Voucher) -> Self {
+        Self {
+            id: v.id,
+            code: v.code,
+            amount: v.amount,
+            created_by: v.created_by,
+            memo: v.memo,
+            expires: v.expires.map(|e| e.timestamp()),
+            redeemed_by: v.redeemed_by,
+            redeemed: v.redeemed.map(|r| r.timestamp()),
+            created: v.created.timestamp(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RedeemResponse {
+    /// Amount credited in milli-sats
+    amount: u64,
+    /// Balance after redeeming in milli-sats
+    balance: i64,
+}
+
+/// Random voucher code like `ABCD-EFGH-JKMN-PQRS`
+fn voucher_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..4)
+        .map(|_| {
+            (0..4)
+                .map(|_| VOUCHER_ALPHABET[rng.gen_range(0..VOUCHER_ALPHABET.len())] as char)
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[derive(Serialize)]
+struct AdminStreamInfo {
+    #[serde(flatten)]
+    stream: ApiStream,
+    connections: Vec<ApiStreamConnection>,
+    incidents: Vec<ApiStreamIncident>,
+    refunds: Vec<ApiStreamRefund>,
+    /// Link statistics of the current ingest connection
+    endpoint_stats: Option<ApiEndpointStats>,
+}
+
+/// Encoder settings and input limits of an ingest endpoint
+#[derive(Serialize, Deserialize)]
+struct ApiIngestEndpoint {
+    /// Set by the server, ignored on updates
+    #[serde(default)]
+    id: u64,
+    /// Listen address of the ingest (e.g. `0.0.0.0:1935`)
+    endpoint: String,
+    preset: Option<String>,
+    tune: Option<String>,
+    profile: Option<String>,
+    /// Codec specific options as `key=value:key=value`
+    params: Option<String>,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+    max_fps: Option<f32>,
+    /// Max input bitrate in bits per second
+    max_bitrate: Option<u64>,
+    /// Accepted input video codecs, comma separated ffmpeg codec names (`h264,hevc`)
+    video_codecs: Option<String>,
+    /// Accept high bit depth (HDR) input video
+    #[serde(default = "default_allow_hdr")]
+    allow_hdr: bool,
+    /// Target loudness in LUFS audio is normalized to (EBU R128, i.e. `-23` or `-16`)
+    loudnorm: Option<f32>,
+    /// Transcode with low latency encoder options for interactive streams, lowers
+    /// glass-to-glass latency at the cost of compression
+    #[serde(default)]
+    low_latency: bool,
+    /// Corner of the watermark uploaded for the endpoint or the user (default `top-right`)
+    watermark_position: Option<WatermarkPosition>,
+    /// Opacity of the watermark from 0 to 1 (default 1)
+    watermark_opacity: Option<f32>,
+}
+
+fn default_allow_hdr() -> bool {
+    true
+}
+
+impl From<IngestEndpoint> for ApiIngestEndpoint {
+    fn from(e: IngestEndpoint) -> Self {
+        Self {
+            id: e.id,
+            endpoint: e.endpoint,
+            preset: e.preset,
+            tune: e.tune,
+            profile: e.profile,
+            params: e.params,
+            max_width: e.max_width,
+            max_height: e.max_height,
+            max_fps: e.max_fps,
+            max_bitrate: e.max_bitrate,
+            video_codecs: e.video_codecs,
+            allow_hdr: e.allow_hdr,
+            loudnorm: e.loudnorm,
+            low_latency: e.low_latency,
+            watermark_position: e.watermark_position.and_then(|p| p.parse().ok()),
+            watermark_opacity: e.watermark_opacity,
+        }
+    }
+}
+
+impl ApiIngestEndpoint {
+    fn to_model(&self) -> IngestEndpoint {
+        IngestEndpoint {
+            endpoint: self.endpoint.clone(),
+            preset: self.preset.clone(),
+            tune: self.tune.clone(),
+            profile: self.profile.clone(),
+            params: self.params.clone(),
+            max_width: self.max_width,
+            max_height: self.max_height,
+            max_fps: self.max_fps,
+            max_bitrate: self.max_bitrate,
+            video_codecs: self.video_codecs.clone(),
+            allow_hdr: self.allow_hdr,
+            loudnorm: self.loudnorm,
+            low_latency: self.low_latency,
+            watermark_position: self.watermark_position.map(|p| p.to_string()),
+            watermark_opacity: self.watermark_opacity,
+            ..Default::default()
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.endpoint.is_empty() {
+            bail!("Endpoint is required");
+        }
+        for (name, value) in [
+            ("preset", &self.preset),
+            ("tune", &self.tune),
+            ("profile", &self.profile),
+        ] {
+            if let Some(v) = value {
+                if v.is_empty()
+                    || v.len() > MAX_ENCODER_OPTION
+                    || !v
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                {
+                    bail!("Invalid {}", name);
+                }
+            }
+        }
+        if let Some(p) = &self.params {
+            if p.chars().any(|c| c.is_control())
+                || p.split(':')
+                    .any(|kv| !matches!(kv.split_once('='), Some((k, _)) if !k.is_empty()))
+            {
+                bail!("Params must be key=value pairs separated by ':'");
+            }
+        }
+        if self.max_fps.is_some_and(|f| !f.is_finite() || f <= 0.0) {
+            bail!("Invalid max_fps");
+        }
+        if let Some(c) = &self.video_codecs {
+            if c.is_empty()
+                || !c
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == ',' || c == '_')
+            {
+                bail!("Invalid video_codecs");
+            }
+        }
+        if self
+            .watermark_opacity
+            .is_some_and(|o| !(0.0..=1.0).contains(&o))
+        {
+            bail!("Watermark opacity must be between 0 and 1");
+        }
+        if self
+            .loudnorm
+            .is_some_and(|l| !(MIN_LOUDNORM..=MAX_LOUDNORM).contains(&l))
+        {
+            bail!(
+                "Loudness target must be between {} and {} LUFS",
+                MIN_LOUDNORM,
+                MAX_LOUDNORM
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Rung of the transcode ladder of an ingest endpoint
+#[derive(Serialize, Deserialize)]
+struct ApiLadderRung {
+    width: u32,
+    height: u32,
+    /// Frame rate, the input frame rate if not set
+    fps: Option<f32>,
+    /// Bits per second
+    bitrate: u64,
+    /// ffmpeg encoder name, the configured video encoder if not set. AV1 (`libsvtav1`) and
+    /// HEVC (`libx265`, `hevc_nvenc`) rungs switch the HLS output to fMP4 segments
+    codec: Option<String>,
+    /// Audio-only rendition, [bitrate] is the audio bitrate and the other fields are unused
+    #[serde(default)]
+    audio_only: bool,
+}
+
+impl From<IngestEndpointVariant> for ApiLadderRung {
+    fn from(v: IngestEndpointVariant) -> Self {
+        Self {
+            width: v.width,
+            height: v.height,
+            fps: v.fps,
+            bitrate: v.bitrate,
+            codec: v.codec,
+            audio_only: v.audio_only,
+        }
+    }
+}
+
+impl ApiLadderRung {
+    fn to_model(&self) -> IngestEndpointVariant {
+        IngestEndpointVariant {
+            width: self.width,
+            height: self.height,
+            fps: self.fps,
+            bitrate: self.bitrate,
+            codec: self.codec.clone(),
+            audio_only: self.audio_only,
+            ..Default::default()
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.audio_only {
+            if !(32_000..=320_000).contains(&self.bitrate) {
+                bail!("Invalid bitrate, audio-only rungs must be 32kbps to 320kbps");
+            }
+            if self.width != 0 || self.height != 0 || self.fps.is_some() || self.codec.is_some() {
+                bail!("Audio-only rungs only have a bitrate");
+            }
+            return Ok(());
+        }
+        // encoders need even dimensions for yuv420p
+        for (name, v) in [("width", self.width), ("height", self.height)] {
+            if !(16..=7680).contains(&v) || v % 2 != 0 {
+                bail!(
+                    "Invalid {}, must be an even number of pixels up to 7680",
+                    name
+                );
+            }
+        }
+        if self
+            .fps
+            .is_some_and(|f| !f.is_finite() || !(1.0..=240.0).contains(&f))
+        {
+            bail!("Invalid fps");
+        }
+        if !(100_000..=100_000_000).contains(&self.bitrate) {
+            bail!("Invalid bitrate, must be 100kbps to 100Mbps");
+        }
+        if let Some(c) = &self.codec {
+            if c.is_empty()
+                || c.len() > MAX_ENCODER_OPTION
+                || !c
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                bail!("Invalid codec");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Format version of [ConfigBundle]
+const CONFIG_BUNDLE_VERSION: u32 = 1;
+
+/// Instance configuration for setting up another node, ingest endpoints are applied on
+/// import while pricing and relays are only compared as they are set in the config file
+#[derive(Serialize, Deserialize)]
+struct ConfigBundle {
+    version: u32,
+    ingest_endpoints: Vec<ApiIngestEndpoint>,
+    pricing: ApiPricing,
+    relays: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+struct ApiPricing {
+    /// Milli-sats / second / variant
+    cost: i64,
+    /// Milli-sats / GB served to viewers
+    egress_cost: i64,
+}
+
+#[derive(Serialize)]
+struct ConfigImportResult {
+    /// Ingest endpoints created or updated
+    imported: usize,
+    /// Ingest endpoints not in the bundle which were deleted
+    removed: usize,
+    /// Config file settings which differ from the bundle and must be changed by hand
+    differences: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct BanRequest {
+    ip: IpAddr,
+    reason: Option<String>,
+    /// Ban duration in seconds, permanent if omitted
+    duration: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct CreditRequest {
+    /// Amount in milli-sats
+    amount: i64,
+    memo: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ApiApproval {
+    id: u64,
+    action: String,
+    user_id: Option<u64>,
+    amount: Option<i64>,
+    memo: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    params: Option<serde_json::Value>,
+    state: String,
+    proposed_by: u64,
+    resolved_by: Option<u64>,
+    created: i64,
+}
+
+impl From<AdminApproval> for ApiApproval {
+    fn from(a: AdminApproval) -> Self {
+        Self {
+            id: a.id,
+            action: a.action.to_string(),
+            user_id: a.user_id,
+            amount: a.amount,
+            memo: a.memo,
+            params: a.params.and_then(|p| serde_json::from_str(&p).ok()),
+            state: a.state.to_string(),
+            proposed_by: a.proposed_by,
+            resolved_by: a.resolved_by,
+            created: a.created.timestamp(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AccountInfo {
+    endpoints: Vec<ApiEndpoint>,
+    /// Balance in milli-sats
+    balance: i64,
+    tos: AccountTos,
+    /// Visibility of new streams (public / unlisted)
+    visibility: String,
+    /// New streams are shown in the public stream directory
+    listed: bool,
+    /// Websocket URL for stream alert overlays
+    alerts_url: String,
+    /// Webhook notified when streams are created, updated or ended
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webhook: Option<ApiWebhook>,
+    /// Audio is loudness normalized on endpoints which have a target loudness
+    loudnorm: bool,
+}
+
+#[derive(Serialize)]
+struct ApiWebhook {
+    url: String,
+    /// Key of the HMAC-SHA256 body signature in the `x-zap-stream-signature` header
+    secret: String,
+}
+
+/// Output stream the pipeline would produce
+#[derive(Serialize)]
+struct ApiVariant {
+    /// video / audio
+    #[serde(rename = "type")]
+    kind: &'static str,
+    /// Input is passed through without transcoding
+    copy: bool,
+    /// Variants in the same group are played together
+    group: usize,
+    /// Only written to the recording, not played live
+    recording: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fps: Option<f32>,
+    /// Bitrate in bits/s
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bitrate: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    codec: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sample_rate: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channels: Option<u16>,
+}
+
+impl ApiVariant {
+    fn new(v: &VariantStream, info: &IngressInfo) -> Option<Self> {
+        let src = info.streams.iter().find(|s| s.index == v.src_index())?;
+        let mut var = Self {
+            kind: match src.stream_type {
+                IngressStreamType::Video => "video",
+                IngressStreamType::Audio => "audio",
+                IngressStreamType::Subtitle => return None,
+            },
+            copy: false,
+            group: v.group_id(),
+            recording: false,
+            width: None,
+            height: None,
+            fps: None,
+            bitrate: None,
+            codec: None,
+            sample_rate: None,
+            channels: None,
+        };
+        match v {
+            VariantStream::Video(v) => {
+                var.width = Some(v.width);
+                var.height = Some(v.height);
+                var.fps = Some(v.fps);
+                var.bitrate = Some(v.bitrate);
+                var.codec = Some(v.codec.clone());
+            }
+            VariantStream::Audio(a) => {
+                var.bitrate = Some(a.bitrate);
+                var.codec = Some(a.codec.clone());
+                var.sample_rate = Some(a.sample_rate);
+                var.channels = Some(a.channels);
+            }
+            VariantStream::CopyVideo(_) => {
+                var.copy = true;
+                var.width = Some(src.width as u16);
+                var.height = Some(src.height as u16);
+                var.fps = Some(src.fps);
+            }
+            VariantStream::CopyAudio(_) => {
+                var.copy = true;
+                var.sample_rate = Some(src.sample_rate);
+            }
+            VariantStream::Subtitle(_) => return None,
+        }
+        Some(var)
+    }
+}
+
+#[derive(Deserialize)]
+struct AccountUpdate {
+    /// Visibility of new streams (public / unlisted)
+    visibility: Option<String>,
+    /// Show new streams in the public stream directory
+    listed: Option<bool>,
+    /// Webhook URL for stream changes, an empty string removes the webhook
+    ///
+    /// A new signing secret is generated each time the URL is set
+    webhook_url: Option<String>,
+    /// Normalize the loudness of streams on endpoints which have a target loudness
+    loudnorm: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct PremiereRequest {
+    /// Unix timestamp the recording starts streaming at
+    starts: i64,
+}
+
+#[derive(Deserialize)]
+struct ShareRequest {
+    /// Seconds until the link expires, never expires if omitted
+    expires_in: Option<u64>,
+    /// Max number of viewers which can use the link, unlimited if omitted
+    max_uses: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct RepublishResponse {
+    /// Id of the new stream event
+    event_id: String,
+    /// Number of stale versions of the event deleted from relays
+    deleted: usize,
+}
+
+/// Ingest link health of a stream
+#[derive(Serialize)]
+struct ApiStreamHealth {
+    live: bool,
+    /// Link statistics of the ingest connection, only reported by some protocols (SRT)
+    stats: Option<ApiEndpointStats>,
+}
+
+#[derive(Serialize)]
+struct ApiEndpointStats {
+    #[serde(flatten)]
+    stats: EndpointStats,
+    /// Share of packets lost, 0-1
+    packet_loss: f32,
+}
+
+impl From<EndpointStats> for ApiEndpointStats {
+    fn from(stats: EndpointStats) -> Self {
+        Self {
+            packet_loss: stats.packet_loss(),
+            stats,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ApiStreamShare {
+    token: String,
+    /// Playback URL including the token
+    url: String,
+    expires: Option<i64>,
+    max_uses: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct TrimRequest {
+    /// In point in seconds, snapped to the next keyframe
+    start: f32,
+    /// Out point in seconds, the end of the recording if omitted
+    end: Option<f32>,
+    /// Recording part to trim when recording was restarted while live
+    #[serde(default)]
+    part: u32,
+    /// Make the trimmed recording the public replay of the stream, until then only the
+    /// owner can download it. Trimming a published replay again replaces it
+    #[serde(default)]
+    publish: bool,
+}
+
+#[derive(Serialize)]
+struct ApiTrimmedRecording {
+    url: String,
+    /// Duration of the trimmed recording in seconds
+    duration: f32,
+}
+
+#[derive(Deserialize)]
+struct MarkerRequest {
+    label: String,
+}
+
+#[derive(Deserialize)]
+struct AdBreakRequest {
+    /// Planned length in seconds, the break ends by itself after it
+    duration: u32,
+}
+
+#[derive(Serialize)]
+struct ApiAdBreak {
+    /// ID of the `EXT-X-DATERANGE` in the HLS playlists (`ad-<id>`)
+    id: u64,
+    duration: u32,
+}
+
+#[derive(Serialize)]
+struct ApiStreamMarker {
+    id: u64,
+    /// Seconds from the start of the stream
+    offset: f32,
+    label: String,
+}
+
+#[derive(Serialize)]
+struct AccountTos {
+    accepted: bool,
+}
+
+#[derive(Serialize)]
+struct ApiEndpoint {
+    name: String,
+    url: String,
+    key: String,
+    cost: EndpointCost,
+}
+
+#[derive(Serialize)]
+struct EndpointCost {
+    unit: String,
+    /// Sats per [unit] per variant
+    rate: f32,
+}
+
+impl ZapStreamOverseer {
+    /// Handle API requests
+    pub(super) async fn api_request(&self, req: Request<Incoming>) -> Result<ApiResponse> {
+        let path = req.uri().path().to_string();
+        let parts: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let query: HashMap<String, String> =
+            url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+                .into_owned()
+                .collect();
+
+        match (req.method().clone(), parts.as_slice()) {
+            (Method::GET, ["api", "v1", "account"]) => {
+                let user = self.check_auth(&req).await?;
+                Self::json_response(StatusCode::OK, &self.account_info(&user)?)
+            }
+            (Method::GET, ["api", "v1", "account", "usage"]) => {
+                let user = self.check_auth(&req).await?;
+                let months = query
+                    .get("months")
+                    .map(|m| m.parse())
+                    .transpose()?
+                    .unwrap_or(DEFAULT_USAGE_MONTHS)
+                    .clamp(1, MAX_USAGE_MONTHS);
+                let usage = self.account_usage(&user, months).await?;
+                Self::json_response(StatusCode::OK, &usage)
+            }
+            (Method::PATCH, ["api", "v1", "account"]) => {
+                let mut user = self.check_auth(&req).await?;
+                let body = Self::signed_body(req, None).await?;
+                let update: AccountUpdate = serde_json::from_slice(&body)?;
+                if let Some(v) = update.visibility {
+                    user.visibility = match v.as_str() {
+                        "public" => StreamVisibility::Public,
+                        "unlisted" => StreamVisibility::Unlisted,
+                        _ => bail!("Invalid visibility: {}", v),
+                    };
+                    self.db
+                        .update_user_visibility(user.id, user.visibility)
+                        .await?;
+                }
+                if let Some(listed) = update.listed {
+                    user.listed = listed;
+                    self.db.update_user_listed(user.id, listed).await?;
+                }
+                if let Some(loudnorm) = update.loudnorm {
+                    user.loudnorm = loudnorm;
+                    self.db.update_user_loudnorm(user.id, loudnorm).await?;
+                }
+                if let Some(url) = update.webhook_url {
+                    if url.is_empty() {
+                        user.webhook_url = None;
+                        user.webhook_secret = None;
+                    } else {
+                        validate_webhook_url(&url).await?;
+                        let mut secret = [0u8; 32];
+                        rand::thread_rng().fill_bytes(&mut secret);
+                        user.webhook_url = Some(url);
+                        user.webhook_secret = Some(hex::encode(secret));
+                    }
+                    self.db
+                        .update_user_webhook(
+                            user.id,
+                            user.webhook_url.as_deref(),
+                            user.webhook_secret.as_deref(),
+                        )
+                        .await?;
+                }
+                Self::json_response(StatusCode::OK, &self.account_info(&user)?)
+            }
+            (Method::PUT, ["api", "v1", "vod"]) => {
+                let user = self.check_auth(&req).await?;
+                let info = VodInfo {
+                    title: query.get("title").cloned(),
+                    summary: query.get("summary").cloned(),
+                    publish: query.get("publish").is_some_and(|p| p == "true"),
+                };
+                let vod = self.upload_vod(&user, req.into_body(), info).await?;
+                Self::json_response(StatusCode::OK, &vod)
+            }
+            (Method::POST, ["api", "v1", "vod"]) => {
+                let user = self.check_auth(&req).await?;
+                let body = Self::signed_body(req, None).await?;
+                let import: VodImport = serde_json::from_slice(&body)?;
+                let vod = self.import_vod(&user, import).await?;
+                Self::json_response(StatusCode::OK, &vod)
+            }
+            (Method::POST, ["api", "v1", "vod", id, "premiere"]) => {
+                let user = self.check_auth(&req).await?;
+                let body = Self::signed_body(req, None).await?;
+                let schedule: PremiereRequest = serde_json::from_slice(&body)?;
+                let starts = match DateTime::from_timestamp(schedule.starts, 0) {
+                    Some(s) => s,
+                    None => bail!("Invalid start time"),
+                };
+                let premiere = self
+                    .schedule_premiere(&user, &Uuid::parse_str(id)?, starts)
+                    .await?;
+                Self::json_response(StatusCode::OK, &premiere)
+            }
+            (Method::POST, ["api", "v1", "restream"]) => {
+                let user = self.check_auth(&req).await?;
+                let body = Self::signed_body(req, None).await?;
+                let restream: RestreamRequest = serde_json::from_slice(&body)?;
+                self.start_restream(&user, restream).await?;
+                Self::json_response(StatusCode::ACCEPTED, &())
+            }
+            (Method::GET, ["api", "v1", "premieres"]) => {
+                let user = self.check_auth(&req).await?;
+                let premieres: Vec<ApiPremiere> = self
+                    .db
+                    .list_user_premieres(user.id)
+                    .await?
+                    .into_iter()
+                    .map(|p| p.into())
+                    .collect();
+                Self::json_response(StatusCode::OK, &premieres)
+            }
+            (Method::DELETE, ["api", "v1", "premiere", id]) => {
+                let user = self.check_auth(&req).await?;
+                self.cancel_premiere(&user, id.parse()?).await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::PUT, ["api", "v1", "account", "slate", file]) => {
+                let user = self.check_auth(&req).await?;
+                let file: SlateFile = file.parse()?;
+                let body = Self::signed_body(req, Some(MAX_SLATE_SIZE)).await?;
+                if body.is_empty() {
+                    bail!("Empty slate file");
+                }
+                let path = self.slate_path(user.id, file);
+                if let Some(dir) = path.parent() {
+                    tokio::fs::create_dir_all(dir).await?;
+                }
+                let tmp = path.with_extension("tmp");
+                tokio::fs::write(&tmp, &body).await?;
+                tokio::fs::rename(&tmp, &path).await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::DELETE, ["api", "v1", "account", "slate", file]) => {
+                let user = self.check_auth(&req).await?;
+                let path = self.slate_path(user.id, file.parse()?);
+                if path.exists() {
+                    tokio::fs::remove_file(path).await?;
+                }
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::GET, ["api", "v1", "account", "ladder"]) => {
+                let user = self.check_auth(&req).await?;
+                let endpoint = match query.get("endpoint") {
+                    Some(e) => {
+                        let url = self
+                            .endpoints
+                            .iter()
+                            .filter_map(|u| u.parse::<Url>().ok())
+                            .find(|u| u.scheme().eq_ignore_ascii_case(e))
+                            .ok_or_else(|| anyhow!("Unknown endpoint: {}", e))?;
+                        self.db.get_ingest_endpoint(&endpoint_name(&url)?).await?
+                    }
+                    None => None,
+                };
+                let info = Self::ladder_input(&query)?;
+                if let Some(e) = &endpoint {
+                    check_input_limits(e, &info)?;
+                }
+                let (variants, egress) = self
+                    .stream_variants(&user, endpoint.as_ref(), &info)
+                    .await?;
+                let live: HashSet<Uuid> = egress
+                    .iter()
+                    .filter_map(|e| match e {
+                        EgressType::HLS(c) => Some(c.variants.iter().copied()),
+                        _ => None,
+                    })
+                    .flatten()
+                    .collect();
+                let variants: Vec<ApiVariant> = variants
+                    .iter()
+                    .filter_map(|v| {
+                        let mut var = ApiVariant::new(v, &info)?;
+                        var.recording = !live.contains(&v.id());
+                        Some(var)
+                    })
+                    .collect();
+                Self::json_response(StatusCode::OK, &variants)
+            }
+            (Method::GET, ["api", "v1", "streams"]) => {
+                let mut streams: Vec<ApiDirectoryStream> = self
+                    .db
+                    .list_listed_streams()
+                    .await?
+                    .into_iter()
+                    .map(|s| {
+                        Ok(ApiDirectoryStream {
+                            url: self.map_to_public_url(&s, "live.m3u8")?,
+                            starts: s.starts.timestamp(),
+                            id: s.id,
+                            title: s.title,
+                            summary: s.summary,
+                            image: s.image,
+                            instance: None,
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+                if let Some(f) = &self.federation {
+                    if !query.contains_key(PARAM_LOCAL) {
+                        // mirrored streams are listed with the local copy instead of the
+                        // origin's playlist
+                        let mirrored = self.mirror_streams().await?;
+                        streams.extend(
+                            f.streams()
+                                .await
+                                .into_iter()
+                                .filter(|s| !mirrored.iter().any(|m| m.id == s.id)),
+                        );
+                        streams.extend(mirrored);
+                    }
+                }
+                Self::json_response(StatusCode::OK, &streams)
+            }
+            (Method::POST, ["api", "v1", "federation", "mirror", id]) => {
+                match &self.federation {
+                    Some(f) => f.check_token(&req)?,
+                    None => return Self::not_found(),
+                }
+                let offer = self.mirror_offer(&Uuid::parse_str(id)?).await?;
+                Self::json_response(StatusCode::OK, &offer)
+            }
+            (Method::PATCH, ["api", "v1", "stream", id]) => {
+                let user = self.check_auth(&req).await?;
+                let mut stream = self.db.get_stream(&Uuid::parse_str(id)?).await?;
+                if stream.user_id != user.id {
+                    bail!(Forbidden);
+                }
+                let body = Self::signed_body(req, None).await?;
+                let update: StreamUpdate = serde_json::from_slice(&body)?;
+                if let Some(listed) = update.listed {
+                    stream.listed = listed;
+                    self.db.update_stream_listed(&stream.id, listed).await?;
+                }
+                Self::json_response(StatusCode::OK, &ApiStream::from(stream))
+            }
+            (Method::POST, ["api", "v1", "stream", id, "share"]) => {
+                let user = self.check_auth(&req).await?;
+                let stream = self.db.get_stream(&Uuid::parse_str(id)?).await?;
+                if stream.user_id != user.id {
+                    bail!(Forbidden);
+                }
+                if stream.visibility != StreamVisibility::Unlisted {
+                    bail!("Only unlisted streams can be shared");
+                }
+                let body = Self::signed_body(req, None).await?;
+                let share_req: ShareRequest = serde_json::from_slice(&body)?;
+                let mut token = [0u8; 24];
+                rand::thread_rng().fill_bytes(&mut token);
+                let share = StreamShare {
+                    stream_id: stream.id.clone(),
+                    token: URL_SAFE_NO_PAD.encode(token),
+                    expires: share_req
+                        .expires_in
+                        .map(|e| Utc::now() + chrono::Duration::seconds(e as i64)),
+                    max_uses: share_req.max_uses,
+                    ..Default::default()
+                };
+                self.db.insert_stream_share(&share).await?;
+                let mut url = self.map_to_public_url(&stream, "live.m3u8")?;
+                url.push_str(&format!("?token={}", share.token));
+                let rsp = ApiStreamShare {
+                    token: share.token,
+                    url,
+                    expires: share.expires.map(|e| e.timestamp()),
+                    max_uses: share.max_uses,
+                };
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::POST, ["api", "v1", "recording", id, "trim"]) => {
+                let user = self.check_auth(&req).await?;
+                let stream_id = Uuid::parse_str(id)?;
+                let stream = self.db.get_stream(&stream_id).await?;
+                if stream.user_id != user.id {
+                    bail!(Forbidden);
+                }
+                if stream.state != UserStreamState::Ended {
+                    bail!("Recordings can only be trimmed after the stream ended");
+                }
+                let body = Self::signed_body(req, None).await?;
+                let trim: TrimRequest = serde_json::from_slice(&body)?;
+                if trim.start < 0.0 || trim.end.is_some_and(|e| e <= trim.start) {
+                    bail!("Invalid trim range");
+                }
+                let src = self.recording_path(&stream_id, trim.part);
+                if !src.is_file() {
+                    bail!("Recording not found");
+                }
+                let dst = self.trimmed_recording_path(&stream_id);
+                let duration = tokio::task::spawn_blocking(move || {
+                    trim_recording(&src, &dst, trim.start, trim.end)
+                })
+                .await??;
+                info!(
+                    "Trimmed recording of {} to {:.1}s from {:.1}s",
+                    stream.id, duration, trim.start
+                );
+                if trim.publish {
+                    self.db
+                        .update_stream_recording(&stream.id, TRIMMED_RECORDING)
+                        .await?;
+                }
+                let rsp = ApiTrimmedRecording {
+                    url: self.recording_download_url(&stream)?,
+                    duration,
+                };
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::GET, ["api", "v1", "recording", id, "download"]) => {
+                let user = self.check_auth(&req).await?;
+                let stream_id = Uuid::parse_str(id)?;
+                let stream = self.db.get_stream(&stream_id).await?;
+                if stream.user_id != user.id {
+                    bail!(Forbidden);
+                }
+                // the trimmed recording replaces the original once created
+                let path = match query.get("part") {
+                    Some(p) => self.recording_path(&stream_id, p.parse()?),
+                    None => {
+                        let trimmed = self.trimmed_recording_path(&stream_id);
+                        if trimmed.is_file() {
+                            trimmed
+                        } else {
+                            self.recording_path(&stream_id, 0)
+                        }
+                    }
+                };
+                self.download_recording(&req, &user, &stream, &path).await
+            }
+            (Method::GET, ["api", "v1", "stream", id, "health"]) => {
+                let user = self.check_auth(&req).await?;
+                let stream_id = Uuid::parse_str(id)?;
+                let stream = self.db.get_stream(&stream_id).await?;
+                if stream.user_id != user.id {
+                    bail!(Forbidden);
+                }
+                let rsp = ApiStreamHealth {
+                    live: stream.state == UserStreamState::Live,
+                    stats: self.endpoint_stats(&stream_id).await.map(|s| s.into()),
+                };
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::POST, ["api", "v1", "stream", id, "republish"]) => {
+                let user = self.check_auth(&req).await?;
+                let mut stream = self.db.get_stream(&Uuid::parse_str(id)?).await?;
+                let is_owner = stream.user_id == user.id;
+                if !is_owner
+                    && !(user.is_admin && AdminPermission::RepublishStream.allowed(user.admin_role))
+                {
+                    bail!(Forbidden);
+                }
+                let (event, deleted) = self.republish_stream(&mut stream).await?;
+                if !is_owner {
+                    self.db
+                        .insert_audit_log(user.id, "republish_stream", "stream", id, None)
+                        .await?;
+                }
+                let rsp = RepublishResponse {
+                    event_id: event.id.to_hex(),
+                    deleted,
+                };
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::GET, ["api", "v1", "stream", id, "link"]) => {
+                let user = self.check_auth(&req).await?;
+                let stream = self.db.get_stream(&Uuid::parse_str(id)?).await?;
+                if stream.user_id != user.id {
+                    bail!(Forbidden);
+                }
+                Self::json_response(StatusCode::OK, &self.stream_link(&stream).await?)
+            }
+            (Method::GET, ["s", code]) => self.follow_stream_link(code, false).await,
+            (Method::GET, ["s", code, "embed"]) => self.follow_stream_link(code, true).await,
+            (Method::GET, ["api", "v1", "stream", id, "highlights"]) => {
+                let user = self.check_auth(&req).await?;
+                let stream = self.db.get_stream(&Uuid::parse_str(id)?).await?;
+                if stream.user_id != user.id {
+                    bail!(Forbidden);
+                }
+                let highlights = self.stream_highlights(&stream).await?;
+                Self::json_response(StatusCode::OK, &highlights)
+            }
+            (Method::POST, ["api", "v1", "stream", id, "marker"]) => {
+                // accepts controller tokens so a stream deck button can add markers
+                let user = self.check_control_auth(&req).await?;
+                let stream = self.db.get_stream(&Uuid::parse_str(id)?).await?;
+                if stream.user_id != user.id {
+                    bail!(Forbidden);
+                }
+                if stream.state != UserStreamState::Live {
+                    bail!("Markers can only be added while live");
+                }
+                let body = Self::signed_body(req, None).await?;
+                let marker_req: MarkerRequest = serde_json::from_slice(&body)?;
+                let label = marker_req.label.trim();
+                // labels are used as chapter titles, which can't span lines or contain cue arrows
+                if label.is_empty()
+                    || label.chars().count() > MAX_MARKER_LABEL
+                    || label.chars().any(char::is_control)
+                    || label.contains("-->")
+                {
+                    bail!("Invalid marker label");
+                }
+                let mut marker = StreamMarker {
+                    stream_id: stream.id.clone(),
+                    offset_secs: (Utc::now() - stream.starts).num_milliseconds() as f32 / 1000.0,
+                    label: label.to_string(),
+                    ..Default::default()
+                };
+                marker.id = self.db.insert_stream_marker(&marker).await?;
+                let rsp = ApiStreamMarker {
+                    id: marker.id,
+                    offset: marker.offset_secs,
+                    label: marker.label,
+                };
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::POST, ["api", "v1", "stream", id, "ad-break"]) => {
+                let user = self.check_control_auth(&req).await?;
+                let stream_id = Uuid::parse_str(id)?;
+                let stream = self.db.get_stream(&stream_id).await?;
+                if stream.user_id != user.id {
+                    bail!(Forbidden);
+                }
+                let body = Self::signed_body(req, None).await?;
+                let ad_req: AdBreakRequest = serde_json::from_slice(&body)?;
+                if ad_req.duration == 0 || ad_req.duration > MAX_AD_BREAK {
+                    bail!("Ad break duration must be 1-{} seconds", MAX_AD_BREAK);
+                }
+                let ad_break = AdBreak {
+                    id: Utc::now().timestamp_millis() as u64,
+                    duration: ad_req.duration,
+                };
+                match self.stream_controls.write().await.get_mut(&stream_id) {
+                    Some(c) => c.ad_break = Some(ad_break.clone()),
+                    None => bail!("Ad breaks can only be started while live"),
+                }
+                info!(
+                    "Ad break of {}s started on {}",
+                    ad_break.duration, stream_id
+                );
+                let rsp = ApiAdBreak {
+                    id: ad_break.id,
+                    duration: ad_break.duration,
+                };
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::DELETE, ["api", "v1", "stream", id, "ad-break"]) => {
+                let user = self.check_control_auth(&req).await?;
+                let stream_id = Uuid::parse_str(id)?;
+                let stream = self.db.get_stream(&stream_id).await?;
+                if stream.user_id != user.id {
+                    bail!(Forbidden);
+                }
+                if let Some(c) = self.stream_controls.write().await.get_mut(&stream_id) {
+                    c.ad_break = None;
+                }
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::GET, ["api", "v1", "stream", id, "chapters"]) => {
+                let stream_id = Uuid::parse_str(id)?;
+                let token = query.get("token").map(|t| t.as_str());
+                if !self
+                    .check_playback(&stream_id, "chapters.vtt", token)
+                    .await?
+                {
+                    bail!(Forbidden);
+                }
+                let stream = self.db.get_stream(&stream_id).await?;
+                let markers = self.db.list_stream_markers(&stream.id).await?;
+                Ok(Response::builder()
+                    .header("server", "zap-stream-core")
+                    .header("content-type", "text/vtt")
+                    .header("access-control-allow-origin", "*")
+                    .body(
+                        Full::from(Self::chapters_vtt(&stream, &markers))
+                            .map_err(anyhow::Error::new)
+                            .boxed(),
+                    )?)
+            }
+            (Method::GET, ["api", "v1", "topup", "quote"]) => {
+                let user = self.check_auth(&req).await?;
+                let currency = match query.get("currency") {
+                    Some(c) => c,
+                    None => bail!("Missing currency"),
+                };
+                let amount: f64 = match query.get("amount") {
+                    Some(a) => a.parse()?,
+                    None => bail!("Missing amount"),
+                };
+                let quote = self.topup_quote(&user, currency, amount).await?;
+                Self::json_response(StatusCode::OK, &quote)
+            }
+            (Method::POST, ["api", "v1", "transfer"]) => {
+                let user = self.check_auth(&req).await?;
+                let body = Self::signed_body(req, None).await?;
+                let transfer: TransferRequest = serde_json::from_slice(&body)?;
+                if transfer.amount == 0 {
+                    bail!("Amount must be positive");
+                }
+                if transfer
+                    .memo
+                    .as_ref()
+                    .is_some_and(|m| m.len() > MAX_TRANSFER_MEMO)
+                {
+                    bail!("Memo is longer than {} bytes", MAX_TRANSFER_MEMO);
+                }
+                let recipient = match self
+                    .db
+                    .find_user_pubkey(&hex::decode(&transfer.pubkey)?)
+                    .await?
+                {
+                    Some(u) => u,
+                    None => bail!("No user with pubkey {}", transfer.pubkey),
+                };
+                let entry = self
+                    .db
+                    .transfer_balance(
+                        user.id,
+                        recipient.id,
+                        transfer.amount,
+                        transfer.memo.as_deref(),
+                    )
+                    .await?;
+                info!(
+                    "User {} transferred {} msats to user {}",
+                    user.id, transfer.amount, recipient.id
+                );
+                let rsp = ApiBalanceTransfer {
+                    id: entry.id,
+                    counterparty: Some(transfer.pubkey.to_lowercase()),
+                    amount: entry.amount,
+                    memo: entry.memo,
+                    created: entry.created.timestamp(),
+                };
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::GET, ["api", "v1", "transfers"]) => {
+                let user = self.check_auth(&req).await?;
+                let page = PageQuery::from_query(&query)?;
+                let after = match &page.cursor {
+                    Some(c) => Some((c.created, c.id.parse()?)),
+                    None => None,
+                };
+                let transfers = self
+                    .db
+                    .list_balance_transfers(user.id, after, page.offset(), page.limit)
+                    .await?;
+                let mut pubkeys = HashMap::new();
+                for t in &transfers {
+                    if !pubkeys.contains_key(&t.counterparty_id) {
+                        let u = self.db.find_user(t.counterparty_id).await?;
+                        pubkeys.insert(t.counterparty_id, u.map(|u| hex::encode(u.pubkey)));
+                    }
+                }
+                let rsp = page.to_response(
+                    transfers,
+                    |t| PageCursor {
+                        created: t.created,
+                        id: t.id.to_string(),
+                    },
+                    |t: BalanceTransfer| ApiBalanceTransfer {
+                        id: t.id,
+                        counterparty: pubkeys[&t.counterparty_id].clone(),
+                        amount: t.amount,
+                        memo: t.memo,
+                        created: t.created.timestamp(),
+                    },
+                );
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::POST, ["api", "v1", "redeem"]) => {
+                let user = self.check_auth(&req).await?;
+                let body = Self::signed_body(req, None).await?;
+                let redeem: RedeemRequest = serde_json::from_slice(&body)?;
+                let code = redeem.code.trim().to_uppercase();
+                let voucher = self.db.redeem_voucher(&code, user.id).await?;
+                info!(
+                    "User {} redeemed voucher {} for {} msats",
+                    user.id, voucher.id, voucher.amount
+                );
+                let user = self.db.get_user(user.id).await?;
+                let rsp = RedeemResponse {
+                    amount: voucher.amount,
+                    balance: user.balance,
+                };
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::POST, ["api", "v1", "webhook", "payments", provider]) => {
+                let headers = req.headers().clone();
+                let body = req.into_body().collect().await?.to_bytes();
+                self.handle_payment_webhook(provider, &headers, &body)
+                    .await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::GET, ["api", "v1", "alerts", pubkey]) => {
+                // overlays can't sign requests, the key is part of the alerts url
+                let user = match self.db.find_user_pubkey(&hex::decode(pubkey)?).await? {
+                    Some(u)
+                        if query
+                            .get("key")
+                            .is_some_and(|k| self.check_alerts_key(&u, k)) =>
+                    {
+                        u
+                    }
+                    _ => bail!(Forbidden),
+                };
+                self.alerts_websocket(req, user)
+            }
+            (Method::GET, ["api", "v1", "stream", id, "chat"]) => {
+                let id = Uuid::parse_str(id)?;
+                let token = query.get("token").map(|t| t.as_str());
+                if !self.check_playback(&id, "chat", token).await? {
+                    bail!("Stream not found");
+                }
+                let stream = self.db.get_stream(&id).await?;
+                let from: f32 = query
+                    .get("from")
+                    .map(|f| f.parse())
+                    .transpose()?
+                    .unwrap_or(0.0);
+                let to: f32 = query
+                    .get("to")
+                    .map(|t| t.parse())
+                    .transpose()?
+                    .unwrap_or(f32::MAX);
+                let chat = self.stream_chat(&stream, from, to).await?;
+                Self::json_response(StatusCode::OK, &chat)
+            }
+            (Method::GET, ["api", "v1", "stream", id, "p2p"]) => {
+                let swarm = self
+                    .stream_swarm(
+                        &Uuid::parse_str(id)?,
+                        query.get("token").map(|t| t.as_str()),
+                    )
+                    .await?;
+                Self::json_response(StatusCode::OK, &swarm)
+            }
+            (Method::POST, ["api", "v1", "stream", id, "playback"]) => {
+                let id = Uuid::parse_str(id)?;
+                let token = query.get("token").map(|t| t.as_str());
+                if !self.check_playback(&id, "playback", token).await? {
+                    bail!("Stream not found");
+                }
+                let ip = Self::client_ip(&req);
+                let body = req.into_body().collect().await?.to_bytes();
+                let report: PlaybackReport = serde_json::from_slice(&body)?;
+                self.record_playback(&id, ip, &report).await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::GET, ["api", "v1", "p2p", swarm_id]) => {
+                // the swarm id is only handed out to viewers allowed to play the stream
+                self.p2p_websocket(req, swarm_id).await
+            }
+            (Method::GET, ["api", "v1", "capacity"]) => {
+                // public, polled by the ingest routing of other nodes, it only reveals the number
+                // of live streams which is public through the stream events anyway
+                let rsp = NodeCapacity {
+                    active_streams: self.active_streams.read().await.len() as u64,
+                };
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::GET, ["api", "v1", "ingest", "best"]) => {
+                self.check_auth(&req).await?;
+                let router = match &self.ingest_router {
+                    Some(r) => r,
+                    None => bail!(Unavailable("Ingest routing is not configured".to_string())),
+                };
+                match router.best_node(Self::client_ip(&req)).await {
+                    Some(n) => Self::json_response(StatusCode::OK, &n),
+                    None => bail!(Unavailable("No healthy ingest nodes available".to_string())),
+                }
+            }
+            (Method::GET, ["api", "v1", "admin", "users", id, "account"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ViewUsers)
+                    .await?;
+                let user = self.db.get_user(id.parse()?).await?;
+                self.db
+                    .insert_audit_log(admin.id, "view_account", "user", id, None)
+                    .await?;
+                Self::json_response(StatusCode::OK, &self.account_info(&user)?)
+            }
+            (Method::GET, ["api", "v1", "users", pubkey, "streams"]) => {
+                let pubkey = PublicKey::parse(pubkey)?;
+                let user = match self.db.find_user_pubkey(&pubkey.to_bytes()).await? {
+                    Some(u) => u,
+                    None => return Self::not_found(),
+                };
+                let page = PageQuery::from_query(&query)?;
+                let streams = self
+                    .db
+                    .list_past_streams(
+                        user.id,
+                        page.cursor.as_ref().map(|c| (c.created, c.id.clone())),
+                        page.offset(),
+                        page.limit,
+                    )
+                    .await?;
+                let rsp =
+                    page.to_response(streams, Self::stream_cursor, |s| self.archive_stream(s));
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::GET, ["api", "v1", "history"]) => {
+                let user = self.check_auth(&req).await?;
+                let page = PageQuery::from_query(&query)?;
+                let streams = self
+                    .db
+                    .list_streams(
+                        Some(user.id),
+                        page.cursor.as_ref().map(|c| (c.created, c.id.clone())),
+                        page.offset(),
+                        page.limit,
+                    )
+                    .await?;
+                let rsp = page.to_response(streams, Self::stream_cursor, ApiStream::from);
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::GET, ["api", "v1", "admin", "users"]) => {
+                self.check_admin_access(&req, AdminPermission::ViewUsers)
+                    .await?;
+                let page = PageQuery::from_query(&query)?;
+                let after = match &page.cursor {
+                    Some(c) => Some((c.created, c.id.parse()?)),
+                    None => None,
+                };
+                let users = self.db.list_users(after, page.offset(), page.limit).await?;
+                let rsp = page.to_response(
+                    users,
+                    |u| PageCursor {
+                        created: u.created,
+                        id: u.id.to_string(),
+                    },
+                    ApiUser::from,
+                );
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::GET, ["api", "v1", "admin", "streams"]) => {
+                self.check_admin_access(&req, AdminPermission::ViewStreams)
+                    .await?;
+                let page = PageQuery::from_query(&query)?;
+                let streams = self
+                    .db
+                    .list_streams(
+                        None,
+                        page.cursor.as_ref().map(|c| (c.created, c.id.clone())),
+                        page.offset(),
+                        page.limit,
+                    )
+                    .await?;
+                let rsp = page.to_response(streams, Self::stream_cursor, ApiStream::from);
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::GET, ["api", "v1", "admin", "egress"]) => {
+                self.check_admin_access(&req, AdminPermission::ViewStreams)
+                    .await?;
+                let today = Utc::now().date_naive();
+                let to = match query.get("to") {
+                    Some(t) => t.parse()?,
+                    None => today,
+                };
+                let from = match query.get("from") {
+                    Some(f) => f.parse()?,
+                    None => to - chrono::Duration::days(DEFAULT_EGRESS_DAYS - 1),
+                };
+                let limit = query
+                    .get("limit")
+                    .map(|l| l.parse())
+                    .transpose()?
+                    .unwrap_or(DEFAULT_PAGE_LIMIT)
+                    .clamp(1, MAX_PAGE_LIMIT);
+                let rsp: Vec<ApiEgressTotal> = self
+                    .db
+                    .top_stream_egress(from, to, limit)
+                    .await?
+                    .into_iter()
+                    .map(ApiEgressTotal::from)
+                    .collect();
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::POST, ["api", "v1", "admin", "cdn-logs"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ManageEndpoints)
+                    .await?;
+                let body = Self::signed_body(req, None).await?;
+                let report: CdnLogReport = serde_json::from_slice(&body)?;
+                if report.edge.is_empty() || report.edge.len() > MAX_EDGE_NAME {
+                    bail!("Edge name must be 1-{} bytes", MAX_EDGE_NAME);
+                }
+                for log in &report.streams {
+                    self.db
+                        .set_cdn_egress(&StreamEgress {
+                            stream_id: Uuid::parse_str(&log.stream_id)?.to_string(),
+                            day: log.day,
+                            source: EgressSource::Cdn,
+                            edge: report.edge.clone(),
+                            bytes: log.bytes,
+                            requests: log.requests,
+                            unique_ips: log.unique_ips,
+                        })
+                        .await?;
+                }
+                self.db
+                    .insert_audit_log(
+                        admin.id,
+                        "import_cdn_logs",
+                        "edge",
+                        &report.edge,
+                        Some(&format!("{} streams", report.streams.len())),
+                    )
+                    .await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::GET, ["api", "v1", "admin", "streams", id, "egress"]) => {
+                self.check_admin_access(&req, AdminPermission::ViewStreams)
+                    .await?;
+                let rsp: Vec<ApiDailyEgress> = self
+                    .db
+                    .list_stream_egress(id)
+                    .await?
+                    .into_iter()
+                    .map(ApiDailyEgress::from)
+                    .collect();
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::GET, ["api", "v1", "admin", "streams", id, "segments"]) => {
+                self.check_admin_access(&req, AdminPermission::ViewStreams)
+                    .await?;
+                let timings = self.segment_timings(&Uuid::parse_str(id)?).await;
+                Self::json_response(StatusCode::OK, &timings)
+            }
+            (Method::GET, ["api", "v1", "admin", "streams", id]) => {
+                self.check_admin_access(&req, AdminPermission::ViewStreams)
+                    .await?;
+                let stream = self.db.get_stream(&Uuid::parse_str(id)?).await?;
+                let connections = self.db.list_stream_connections(id).await?;
+                let incidents = self
+                    .db
+                    .list_stream_incidents(Some(id), None, 0, MAX_PAGE_LIMIT)
+                    .await?;
+                let refunds = self.db.list_stream_refunds(id).await?;
+                let endpoint_stats = self.endpoint_stats(&Uuid::parse_str(id)?).await;
+                let rsp = AdminStreamInfo {
+                    stream: stream.into(),
+                    connections: connections.into_iter().map(|c| c.into()).collect(),
+                    incidents: incidents.into_iter().map(|i| i.into()).collect(),
+                    refunds: refunds.into_iter().map(|r| r.into()).collect(),
+                    endpoint_stats: endpoint_stats.map(|s| s.into()),
+                };
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::GET, ["api", "v1", "admin", "incidents"]) => {
+                self.check_admin_access(&req, AdminPermission::ViewStreams)
+                    .await?;
+                let page = PageQuery::from_query(&query)?;
+                let after = match &page.cursor {
+                    Some(c) => Some((c.created, c.id.parse()?)),
+                    None => None,
+                };
+                let incidents = self
+                    .db
+                    .list_stream_incidents(
+                        query.get("stream").map(|s| s.as_str()),
+                        after,
+                        page.offset(),
+                        page.limit,
+                    )
+                    .await?;
+                let rsp = page.to_response(
+                    incidents,
+                    |i| PageCursor {
+                        created: i.created,
+                        id: i.id.to_string(),
+                    },
+                    ApiStreamIncident::from,
+                );
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::GET, ["api", "v1", "admin", "usage-alerts"]) => {
+                self.check_admin_access(&req, AdminPermission::ViewUsers)
+                    .await?;
+                let page = PageQuery::from_query(&query)?;
+                let after = match &page.cursor {
+                    Some(c) => Some((c.created, c.id.parse()?)),
+                    None => None,
+                };
+                let open = query.get("open").is_some_and(|o| o == "true");
+                let alerts = self
+                    .db
+                    .list_usage_alerts(open, after, page.offset(), page.limit)
+                    .await?;
+                let rsp = page.to_response(
+                    alerts,
+                    |a| PageCursor {
+                        created: a.created,
+                        id: a.id.to_string(),
+                    },
+                    ApiUsageAlert::from,
+                );
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::POST, ["api", "v1", "admin", "usage-alerts", id, "ack"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ViewUsers)
+                    .await?;
+                if !self
+                    .db
+                    .acknowledge_usage_alert(id.parse()?, admin.id)
+                    .await?
+                {
+                    bail!("Alert not found or already acknowledged");
+                }
+                self.db
+                    .insert_audit_log(admin.id, "ack_usage_alert", "usage_alert", id, None)
+                    .await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::POST, ["api", "v1", "admin", "streams", id, "terminate"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::TerminateStream)
+                    .await?;
+                self.on_end(&Uuid::parse_str(id)?).await?;
+                self.db
+                    .insert_audit_log(admin.id, "terminate_stream", "stream", id, None)
+                    .await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::POST, ["api", "v1", "admin", "streams", id, "refund"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::CreditBalance)
+                    .await?;
+                let stream = self.db.get_stream(&Uuid::parse_str(id)?).await?;
+                let body = Self::signed_body(req, None).await?;
+                let refund_req: RefundRequest = serde_json::from_slice(&body)?;
+                // only failures on our side are refunded, not streams which ended normally
+                let incidents = self.db.list_stream_incidents(Some(id), None, 0, 1).await?;
+                if incidents.is_empty() {
+                    bail!("Stream has no incidents, only failed streams can be refunded");
+                }
+                let amount = match refund_req.amount {
+                    Some(a) => a,
+                    None => {
+                        let refunded: u64 = self
+                            .db
+                            .list_stream_refunds(id)
+                            .await?
+                            .iter()
+                            .map(|r| r.amount)
+                            .sum();
+                        stream.cost.saturating_sub(refunded)
+                    }
+                };
+                if amount == 0 {
+                    bail!("Amount must be positive");
+                }
+                let amount_i64 = i64::try_from(amount)?;
+                if self.approval_threshold.is_some_and(|t| amount_i64 >= t) {
+                    let params = serde_json::to_string(&RefundParams {
+                        stream_id: stream.id.clone(),
+                    })?;
+                    let approval = self
+                        .propose_action(
+                            &admin,
+                            ApprovalAction::RefundStream,
+                            Some(stream.user_id),
+                            Some(amount_i64),
+                            refund_req.reason.as_deref(),
+                            Some(&params),
+                        )
+                        .await?;
+                    return Self::json_response(StatusCode::ACCEPTED, &approval);
+                }
+                let refund = self
+                    .refund_stream(admin.id, &stream, amount, refund_req.reason)
+                    .await?;
+                Self::json_response(StatusCode::OK, &ApiStreamRefund::from(refund))
+            }
+            (Method::GET, ["api", "v1", "admin", "mirrors"]) => {
+                self.check_admin_access(&req, AdminPermission::ManageMirrors)
+                    .await?;
+                Self::json_response(StatusCode::OK, &self.list_mirrors().await?)
+            }
+            (Method::POST, ["api", "v1", "admin", "mirrors"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ManageMirrors)
+                    .await?;
+                let body = Self::signed_body(req, None).await?;
+                let mirror: MirrorRequest = serde_json::from_slice(&body)?;
+                let rsp = self.start_mirror(&mirror).await?;
+                self.db
+                    .insert_audit_log(admin.id, "start_mirror", "stream", &rsp.stream.id, None)
+                    .await?;
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::DELETE, ["api", "v1", "admin", "mirrors", id]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ManageMirrors)
+                    .await?;
+                self.stop_mirror(&Uuid::parse_str(id)?).await?;
+                self.db
+                    .insert_audit_log(admin.id, "stop_mirror", "stream", id, None)
+                    .await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::POST, ["api", "v1", "admin", "vouchers"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::CreditBalance)
+                    .await?;
+                let body = Self::signed_body(req, None).await?;
+                let create: CreateVoucherRequest = serde_json::from_slice(&body)?;
+                let (_, total) = Self::voucher_amounts(&create)?;
+                // the threshold applies to the whole batch so it can't be split into
+                // vouchers just below it
+                if self.approval_threshold.is_some_and(|t| total >= t) {
+                    let approval = self
+                        .propose_action(
+                            &admin,
+                            ApprovalAction::CreateVouchers,
+                            None,
+                            Some(total),
+                            create.memo.as_deref(),
+                            Some(&serde_json::to_string(&create)?),
+                        )
+                        .await?;
+                    return Self::json_response(StatusCode::ACCEPTED, &approval);
+                }
+                let vouchers = self.create_vouchers(admin.id, &create).await?;
+                Self::json_response(StatusCode::OK, &vouchers)
+            }
+            (Method::GET, ["api", "v1", "admin", "vouchers"]) => {
+                self.check_admin_access(&req, AdminPermission::CreditBalance)
+                    .await?;
+                let page = PageQuery::from_query(&query)?;
+                let after = match &page.cursor {
+                    Some(c) => Some((c.created, c.id.parse()?)),
+                    None => None,
+                };
+                let vouchers = self
+                    .db
+                    .list_vouchers(after, page.offset(), page.limit)
+                    .await?;
+                let rsp = page.to_response(
+                    vouchers,
+                    |v| PageCursor {
+                        created: v.created,
+                        id: v.id.to_string(),
+                    },
+                    ApiVoucher::from,
+                );
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::DELETE, ["api", "v1", "admin", "vouchers", id]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::CreditBalance)
+                    .await?;
+                if !self.db.delete_voucher(id.parse()?).await? {
+                    bail!("Voucher not found or already redeemed");
+                }
+                self.db
+                    .insert_audit_log(admin.id, "revoke_voucher", "voucher", id, None)
+                    .await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::POST, ["api", "v1", "admin", "users", id, "credit"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::CreditBalance)
+                    .await?;
+                let user = self.db.get_user(id.parse()?).await?;
+                let body = Self::signed_body(req, None).await?;
+                let credit: CreditRequest = serde_json::from_slice(&body)?;
+                if credit.amount <= 0 {
+                    bail!("Amount must be positive");
+                }
+                if self.approval_threshold.is_some_and(|t| credit.amount >= t) {
+                    let approval = self
+                        .propose_action(
+                            &admin,
+                            ApprovalAction::CreditBalance,
+                            Some(user.id),
+                            Some(credit.amount),
+                            credit.memo.as_deref(),
+                            None,
+                        )
+                        .await?;
+                    return Self::json_response(StatusCode::ACCEPTED, &approval);
+                }
+                self.db.add_balance(user.id, credit.amount).await?;
+                self.db
+                    .insert_audit_log(
+                        admin.id,
+                        "credit_balance",
+                        "user",
+                        id,
+                        Some(&format!(
+                            "{} msats: {}",
+                            credit.amount,
+                            credit.memo.as_deref().unwrap_or("")
+                        )),
+                    )
+                    .await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::PATCH, ["api", "v1", "admin", "users", id]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::CreditBalance)
+                    .await?;
+                let user = self.db.get_user(id.parse()?).await?;
+                let body = Self::signed_body(req, None).await?;
+                let update: AdminUserUpdate = serde_json::from_slice(&body)?;
+                if let Some(m) = update.cost_multiplier {
+                    if !m.is_finite() || !(0.0..=MAX_COST_MULTIPLIER).contains(&m) {
+                        bail!(
+                            "Cost multiplier must be between 0 and {}",
+                            MAX_COST_MULTIPLIER
+                        );
+                    }
+                    self.db.update_user_cost_multiplier(user.id, m).await?;
+                    self.db
+                        .insert_audit_log(
+                            admin.id,
+                            "set_cost_multiplier",
+                            "user",
+                            id,
+                            Some(&format!("{} -> {}", user.cost_multiplier, m)),
+                        )
+                        .await?;
+                }
+                let user = self.db.get_user(user.id).await?;
+                Self::json_response(StatusCode::OK, &ApiUser::from(user))
+            }
+            (Method::DELETE, ["api", "v1", "admin", "users", id]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::DeleteUser)
+                    .await?;
+                let user = self.db.get_user(id.parse()?).await?;
+                let approval = self
+                    .propose_action(
+                        &admin,
+                        ApprovalAction::DeleteUser,
+                        Some(user.id),
+                        None,
+                        None,
+                        None,
+                    )
+                    .await?;
+                Self::json_response(StatusCode::ACCEPTED, &approval)
+            }
+            (Method::GET, ["api", "v1", "admin", "approvals"]) => {
+                self.check_admin_access(&req, AdminPermission::ManageApprovals)
+                    .await?;
+                let approvals: Vec<ApiApproval> = self
+                    .db
+                    .list_pending_approvals()
+                    .await?
+                    .into_iter()
+                    .map(ApiApproval::from)
+                    .collect();
+                Self::json_response(StatusCode::OK, &approvals)
+            }
+            (Method::POST, ["api", "v1", "admin", "approvals", id, action]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ManageApprovals)
+                    .await?;
+                let approval_id = id.parse()?;
+                let state = match *action {
+                    "approve" => ApprovalState::Approved,
+                    "reject" => ApprovalState::Rejected,
+                    _ => bail!("Unknown approval action"),
+                };
+                let approval = self.db.get_approval(approval_id).await?;
+                self.resolve_action(&admin, approval, state).await?;
+                let approval: ApiApproval = self.db.get_approval(approval_id).await?.into();
+                Self::json_response(StatusCode::OK, &approval)
+            }
+            (Method::GET, ["api", "v1", "admin", "bans"]) => {
+                self.check_admin_access(&req, AdminPermission::ManageBans)
+                    .await?;
+                Self::json_response(StatusCode::OK, &self.bans.list_bans().await)
+            }
+            (Method::POST, ["api", "v1", "admin", "bans"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ManageBans)
+                    .await?;
+                let body = Self::signed_body(req, None).await?;
+                let ban: BanRequest = serde_json::from_slice(&body)?;
+                let reason = ban.reason.unwrap_or("Manual ban".to_string());
+                let ban = self.bans.add_ban(ban.ip, reason, ban.duration).await;
+                self.db
+                    .insert_audit_log(
+                        admin.id,
+                        "ban_ip",
+                        "ip",
+                        &ban.ip.to_string(),
+                        Some(&ban.reason),
+                    )
+                    .await?;
+                Self::json_response(StatusCode::OK, &ban)
+            }
+            (Method::DELETE, ["api", "v1", "admin", "bans", ip]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ManageBans)
+                    .await?;
+                let ip: IpAddr = ip.parse()?;
+                if !self.bans.remove_ban(&ip).await {
+                    bail!("IP is not banned");
+                }
+                self.db
+                    .insert_audit_log(admin.id, "unban_ip", "ip", &ip.to_string(), None)
+                    .await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::GET, ["api", "v1", "admin", "ingest-endpoints"]) => {
+                self.check_admin_access(&req, AdminPermission::ManageEndpoints)
+                    .await?;
+                let endpoints: Vec<ApiIngestEndpoint> = self
+                    .db
+                    .list_ingest_endpoints()
+                    .await?
+                    .into_iter()
+                    .map(|e| e.into())
+                    .collect();
+                Self::json_response(StatusCode::OK, &endpoints)
+            }
+            (Method::POST, ["api", "v1", "admin", "ingest-endpoints"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ManageEndpoints)
+                    .await?;
+                let body = Self::signed_body(req, None).await?;
+                let endpoint: ApiIngestEndpoint = serde_json::from_slice(&body)?;
+                endpoint.validate()?;
+                self.db.upsert_ingest_endpoint(&endpoint.to_model()).await?;
+                self.db
+                    .insert_audit_log(
+                        admin.id,
+                        "update_ingest_endpoint",
+                        "endpoint",
+                        &endpoint.endpoint,
+                        Some(&serde_json::to_string(&endpoint)?),
+                    )
+                    .await?;
+                Self::json_response(StatusCode::OK, &endpoint)
+            }
+            (Method::DELETE, ["api", "v1", "admin", "ingest-endpoints"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ManageEndpoints)
+                    .await?;
+                let endpoint = match query.get("endpoint") {
+                    Some(e) => e,
+                    None => bail!("Missing endpoint"),
+                };
+                if !self.db.delete_ingest_endpoint(endpoint).await? {
+                    bail!("Endpoint has no encoder settings");
+                }
+                self.db
+                    .insert_audit_log(
+                        admin.id,
+                        "delete_ingest_endpoint",
+                        "endpoint",
+                        endpoint,
+                        None,
+                    )
+                    .await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::GET, ["api", "v1", "admin", "ingest-endpoints", id, "variants"]) => {
+                self.check_admin_access(&req, AdminPermission::ManageEndpoints)
+                    .await?;
+                let endpoint = match self.db.get_ingest_endpoint_by_id(id.parse()?).await? {
+                    Some(e) => e,
+                    None => return Self::not_found(),
+                };
+                let ladder: Vec<ApiLadderRung> = self
+                    .db
+                    .list_ingest_endpoint_variants(endpoint.id)
+                    .await?
+                    .into_iter()
+                    .map(|v| v.into())
+                    .collect();
+                Self::json_response(StatusCode::OK, &ladder)
+            }
+            (Method::POST, ["api", "v1", "admin", "ingest-endpoints", id, "variants"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ManageEndpoints)
+                    .await?;
+                let endpoint = match self.db.get_ingest_endpoint_by_id(id.parse()?).await? {
+                    Some(e) => e,
+                    None => return Self::not_found(),
+                };
+                let body = Self::signed_body(req, None).await?;
+                let ladder: Vec<ApiLadderRung> = serde_json::from_slice(&body)?;
+                if ladder.len() > MAX_LADDER_RUNGS {
+                    bail!("Max {} ladder rungs", MAX_LADDER_RUNGS);
+                }
+                for r in &ladder {
+                    r.validate()?;
+                }
+                let variants: Vec<_> = ladder.iter().map(|r| r.to_model()).collect();
+                self.db
+                    .set_ingest_endpoint_variants(endpoint.id, &variants)
+                    .await?;
+                self.db
+                    .insert_audit_log(
+                        admin.id,
+                        "update_ingest_endpoint_variants",
+                        "endpoint",
+                        &endpoint.endpoint,
+                        Some(&serde_json::to_string(&ladder)?),
+                    )
+                    .await?;
+                Self::json_response(StatusCode::OK, &ladder)
+            }
+            (Method::PUT, ["api", "v1", "admin", "ingest-endpoints", id, "watermark"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ManageEndpoints)
+                    .await?;
+                let endpoint = match self.db.get_ingest_endpoint_by_id(id.parse()?).await? {
+                    Some(e) => e,
+                    None => return Self::not_found(),
+                };
+                self.upload_watermark(WatermarkOwner::Endpoint(endpoint.id), req)
+                    .await?;
+                self.db
+                    .insert_audit_log(
+                        admin.id,
+                        "upload_watermark",
+                        "endpoint",
+                        &endpoint.endpoint,
+                        None,
+                    )
+                    .await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::DELETE, ["api", "v1", "admin", "ingest-endpoints", id, "watermark"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ManageEndpoints)
+                    .await?;
+                let endpoint = match self.db.get_ingest_endpoint_by_id(id.parse()?).await? {
+                    Some(e) => e,
+                    None => return Self::not_found(),
+                };
+                self.delete_watermark(WatermarkOwner::Endpoint(endpoint.id))
+                    .await?;
+                self.db
+                    .insert_audit_log(
+                        admin.id,
+                        "delete_watermark",
+                        "endpoint",
+                        &endpoint.endpoint,
+                        None,
+                    )
+                    .await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::PUT, ["api", "v1", "admin", "users", id, "watermark"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ManageEndpoints)
+                    .await?;
+                let user = self.db.get_user(id.parse()?).await?;
+                self.upload_watermark(WatermarkOwner::User(user.id), req)
+                    .await?;
+                self.db
+                    .insert_audit_log(
+                        admin.id,
+                        "upload_watermark",
+                        "user",
+                        &user.id.to_string(),
+                        None,
+                    )
+                    .await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::DELETE, ["api", "v1", "admin", "users", id, "watermark"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ManageEndpoints)
+                    .await?;
+                let user = self.db.get_user(id.parse()?).await?;
+                self.delete_watermark(WatermarkOwner::User(user.id)).await?;
+                self.db
+                    .insert_audit_log(
+                        admin.id,
+                        "delete_watermark",
+                        "user",
+                        &user.id.to_string(),
+                        None,
+                    )
+                    .await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            (Method::GET, ["api", "v1", "admin", "config"]) => {
+                self.check_admin_access(&req, AdminPermission::ManageEndpoints)
+                    .await?;
+                let bundle = self.export_config().await?;
+                Self::json_response(StatusCode::OK, &bundle)
+            }
+            (Method::POST, ["api", "v1", "admin", "config"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ManageEndpoints)
+                    .await?;
+                let body = Self::signed_body(req, None).await?;
+                let bundle: ConfigBundle = serde_json::from_slice(&body)?;
+                // endpoints missing from the bundle are kept unless asked to replace them
+                let replace = query.get("replace").is_some_and(|r| r == "true");
+                let result = self.import_config(bundle, replace).await?;
+                self.db
+                    .insert_audit_log(
+                        admin.id,
+                        "import_config",
+                        "config",
+                        "ingest_endpoints",
+                        Some(&format!(
+                            "{} imported, {} removed",
+                            result.imported, result.removed
+                        )),
+                    )
+                    .await?;
+                Self::json_response(StatusCode::OK, &result)
+            }
+            (Method::GET, ["api", "v1", "admin", "audit-log"]) => {
+                self.check_admin_access(&req, AdminPermission::ViewAuditLog)
+                    .await?;
+                let filter = Self::audit_log_filter(&query)?;
+                if query.get("format").is_some_and(|f| f == "csv") {
+                    return self.audit_log_csv(filter).await;
+                }
+                let page = PageQuery::from_query(&query)?;
+                let after = match &page.cursor {
+                    Some(c) => Some((c.created, c.id.parse()?)),
+                    None => None,
+                };
+                let entries = self
+                    .db
+                    .list_audit_log(&filter, after, page.offset(), page.limit)
+                    .await?;
+                let rsp = page.to_response(entries, Self::audit_log_cursor, ApiAuditLog::from);
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::GET, ["api", "v1", "admin", "keys"]) => {
+                self.check_admin_access(&req, AdminPermission::ManageKeys)
+                    .await?;
+                let history = self.db.list_signing_keys(SIGNING_KEY_HISTORY).await?;
+                let active = self.keys.active().public_key;
+                let mut keys = vec![];
+                for k in self.keys.public_keys() {
+                    let pubkey = k.to_bytes();
+                    keys.push(ApiSigningKey {
+                        pubkey: k.to_hex(),
+                        npub: k.to_bech32()?,
+                        active: k == active,
+                        activated: history
+                            .iter()
+                            .find(|h| h.pubkey == pubkey)
+                            .map(|h| h.created.timestamp()),
+                    });
+                }
+                Self::json_response(StatusCode::OK, &keys)
+            }
+            (Method::POST, ["api", "v1", "admin", "keys", pubkey, "activate"]) => {
+                let admin = self
+                    .check_admin_access(&req, AdminPermission::ManageKeys)
+                    .await?;
+                let pubkey = PublicKey::parse(pubkey)?;
+                self.activate_signing_key(&pubkey).await?;
+                self.db
+                    .insert_audit_log(
+                        admin.id,
+                        "activate_signing_key",
+                        "signing_key",
+                        &pubkey.to_hex(),
+                        None,
+                    )
+                    .await?;
+                Self::json_response(StatusCode::OK, &())
+            }
+            #[cfg(feature = "chaos")]
+            (Method::GET, ["api", "v1", "admin", "chaos"]) => {
+                self.check_admin_access(&req, AdminPermission::Chaos)
+                    .await?;
+                Self::json_response(StatusCode::OK, &self.chaos.get().await)
+            }
+            #[cfg(feature = "chaos")]
+            (Method::POST, ["api", "v1", "admin", "chaos"]) => {
+                self.check_admin_access(&req, AdminPermission::Chaos)
+                    .await?;
+                let body = Self::signed_body(req, None).await?;
+                let config: ChaosConfig = serde_json::from_slice(&body)?;
+                self.chaos.set(config).await;
+                Self::json_response(StatusCode::OK, &self.chaos.get().await)
+            }
+            (_, ["api", "v1", "control", path @ ..]) => self.control_request(req, path).await,
+            _ => Self::not_found(),
+        }
+    }
+
+    /// Export the ingest endpoints, pricing and relays of this instance
+    async fn export_config(&self) -> Result<ConfigBundle> {
+        let mut relays: Vec<String> = self
+            .client
+            .relays()
+            .await
+            .into_keys()
+            .map(|u| u.to_string())
+            .collect();
+        relays.sort();
+        Ok(ConfigBundle {
+            version: CONFIG_BUNDLE_VERSION,
+            ingest_endpoints: self
+                .db
+                .list_ingest_endpoints()
+                .await?
+                .into_iter()
+                .map(|e| e.into())
+                .collect(),
+            pricing: ApiPricing {
+                cost: self.cost,
+                egress_cost: self.egress_cost,
+            },
+            relays,
+        })
+    }
+
+    /// Apply the ingest endpoints of [bundle], nothing is changed if any of them is invalid
+    ///
+    /// With [replace] ingest endpoints which are not in the bundle are deleted
+    async fn import_config(
+        &self,
+        bundle: ConfigBundle,
+        replace: bool,
+    ) -> Result<ConfigImportResult> {
+        if bundle.version != CONFIG_BUNDLE_VERSION {
+            bail!("Unsupported config bundle version {}", bundle.version);
+        }
+        for e in &bundle.ingest_endpoints {
+            e.validate()
+                .map_err(|err| anyhow!("Endpoint {}: {}", e.endpoint, err))?;
+        }
+
+        let current = self.export_config().await?;
+        let mut differences = vec![];
+        if bundle.pricing != current.pricing {
+            differences.push(format!(
+                "pricing: bundle cost {} / egress_cost {}, this instance {} / {}",
+                bundle.pricing.cost,
+                bundle.pricing.egress_cost,
+                current.pricing.cost,
+                current.pricing.egress_cost
+            ));
+        }
+        for r in bundle.relays.iter().filter(|r| !current.relays.contains(r)) {
+            differences.push(format!("relays: {} is not configured", r));
+        }
+        for r in current.relays.iter().filter(|r| !bundle.relays.contains(r)) {
+            differences.push(format!("relays: {} is not in the bundle", r));
+        }
+
+        let mut removed = 0;
+        if replace {
+            for e in &current.ingest_endpoints {
+                if !bundle
+                    .ingest_endpoints
+                    .iter()
+                    .any(|b| b.endpoint == e.endpoint)
+                    && self.db.delete_ingest_endpoint(&e.endpoint).await?
+                {
+                    removed += 1;
+                }
+            }
+        }
+        for e in &bundle.ingest_endpoints {
+            self.db.upsert_ingest_endpoint(&e.to_model()).await?;
+        }
+        Ok(ConfigImportResult {
+            imported: bundle.ingest_endpoints.len(),
+            removed,
+            differences,
+        })
+    }
+
+    /// Account details as seen by the user
+    fn account_info(&self, user: &User) -> Result<AccountInfo> {
+        let public_url: Url = self.public_url.parse()?;
+        let host = public_url.host_str().unwrap_or("localhost");
+        let mut endpoints = vec![];
+        for e in &self.endpoints {
+            let u: Url = e.parse()?;
+            if let Some(port) = u.port() {
+                endpoints.push(ApiEndpoint {
+                    name: u.scheme().to_uppercase(),
+                    url: format!("{}://{}:{}", u.scheme(), host, port),
+                    key: user.stream_key.clone(),
+                    cost: EndpointCost {
+                        unit: "min".to_string(),
+                        rate: self.cost as f32 * user.cost_multiplier * 60.0 / 1000.0,
+                    },
+                });
+            }
+        }
+        Ok(AccountInfo {
+            endpoints,
+            balance: user.balance,
+            tos: AccountTos {
+                accepted: user.tos_accepted.is_some(),
+            },
+            visibility: user.visibility.to_string(),
+            listed: user.listed,
+            alerts_url: self.alerts_url(user)?,
+            webhook: match (&user.webhook_url, &user.webhook_secret) {
+                (Some(url), Some(secret)) => Some(ApiWebhook {
+                    url: url.clone(),
+                    secret: secret.clone(),
+                }),
+                _ => None,
+            },
+            loudnorm: user.loudnorm,
+        })
+    }
+
+    /// Describe the input of an encoder from the ladder preview query,
+    /// e.g. `?height=1080&fps=60&bitrate=6000` (kbps)
+    fn ladder_input(query: &HashMap<String, String>) -> Result<IngressInfo> {
+        let height: usize = match query.get("height") {
+            Some(h) => h.parse()?,
+            None => 1080,
+        };
+        let width: usize = match query.get("width") {
+            Some(w) => w.parse()?,
+            None => height * 16 / 9,
+        };
+        let fps: f32 = match query.get("fps") {
+            Some(f) => f.parse()?,
+            None => 30.0,
+        };
+        let bitrate: usize = match query.get("bitrate") {
+            Some(b) => b.parse::<usize>()? * 1000,
+            None => 6_000_000,
+        };
+        if width == 0 || height == 0 || width > 7680 || height > 4320 || fps <= 0.0 || fps > 240.0 {
+            bail!("Invalid video settings");
+        }
+        let stream = |index, stream_type| IngressStream {
+            index,
+            stream_type,
+            codec: 0,
+            format: 0,
+            width: 0,
+            height: 0,
+            fps: 0.0,
+            sample_rate: 0,
+            language: String::new(),
+        };
+        Ok(IngressInfo {
+            bitrate,
+            streams: vec![
+                IngressStream {
+                    width,
+                    height,
+                    fps,
+                    ..stream(0, IngressStreamType::Video)
+                },
+                IngressStream {
+                    sample_rate: 48_000,
+                    ..stream(1, IngressStreamType::Audio)
+                },
+            ],
+        })
+    }
+
+    /// Owner-only download URL of a stream recording
+    fn recording_download_url(&self, stream: &UserStream) -> Result<String> {
+        let u: Url = self.public_url.parse()?;
+        Ok(
+            u.join(&format!("/api/v1/recording/{}/download", stream.id))?
+                .to_string(),
+        )
+    }
+
+    /// Send the recording at [path], honouring a single byte range so interrupted downloads
+    /// can be resumed
+    async fn download_recording(
+        &self,
+        req: &Request<Incoming>,
+        user: &User,
+        stream: &UserStream,
+        path: &Path,
+    ) -> Result<ApiResponse> {
+        let mut f = match File::open(path).await {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::not_found(),
+            Err(e) => return Err(e.into()),
+        };
+        let size = f.metadata().await?.len();
+        let range = req
+            .headers()
+            .get("range")
+            .and_then(|v| v.to_str().ok())
+            .map(|r| Self::parse_range(r, size));
+        let rsp = Response::builder()
+            .header("server", "zap-stream-core")
+            .header("access-control-allow-origin", "*")
+            .header("accept-ranges", "bytes");
+        let (rsp, start, len) = match range {
+            None => (rsp.status(StatusCode::OK), 0, size),
+            Some(Some((start, end))) => (
+                rsp.status(StatusCode::PARTIAL_CONTENT)
+                    .header("content-range", format!("bytes {}-{}/{}", start, end, size)),
+                start,
+                end - start + 1,
+            ),
+            Some(None) => {
+                return Ok(rsp
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header("content-range", format!("bytes */{}", size))
+                    .body(BoxBody::default())?);
+            }
+        };
+
+        let file = path
+            .file_name()
+            .and_then(|f| f.to_str())
+            .unwrap_or_default()
+            .to_string();
+        // count each request once, resumed downloads show up as additional ranges
+        self.db
+            .insert_recording_download(&RecordingDownload {
+                stream_id: stream.id.clone(),
+                user_id: user.id,
+                ip_addr: Self::client_ip(req).map(|i| i.to_string()),
+                file: file.clone(),
+                range_start: start,
+                length: len,
+                ..Default::default()
+            })
+            .await?;
+
+        f.seek(SeekFrom::Start(start)).await?;
+        let body = StreamBody::new(
+            ReaderStream::new(f.take(len))
+                .map_ok(Frame::data)
+                .map_err(anyhow::Error::new),
+        )
+        .boxed();
+        let content_type = if file.ends_with(".mp4") {
+            "video/mp4"
+        } else {
+            "video/mp2t"
+        };
+        Ok(rsp
+            .header("content-type", content_type)
+            .header("content-length", len)
+            .header(
+                "content-disposition",
+                format!("attachment; filename=\"{}-{}\"", stream.id, file),
+            )
+            .body(body)?)
+    }
+
+    /// Parse a `bytes=` range header into an inclusive (start, end), None if the range
+    /// can't be satisfied. Only the first range of a multi-range request is used
+    fn parse_range(range: &str, size: u64) -> Option<(u64, u64)> {
+        let spec = range.strip_prefix("bytes=")?.split(',').next()?.trim();
+        let (start, end) = spec.split_once('-')?;
+        // either side may be left out, but what is there must be a number
+        let parse = |v: &str| -> Option<Option<u64>> {
+            match v.trim() {
+                "" => Some(None),
+                v => v.parse().ok().map(Some),
+            }
+        };
+        let (start, end) = match (parse(start)?, parse(end)?) {
+            (Some(s), Some(e)) => (s, e.min(size.saturating_sub(1))),
+            (Some(s), None) => (s, size.saturating_sub(1)),
+            // suffix range, the last n bytes
+            (None, Some(n)) => (size.saturating_sub(n), size.saturating_sub(1)),
+            (None, None) => return None,
+        };
+        (start <= end && start < size).then_some((start, end))
+    }
+
+    /// WebVTT chapters of a stream, each marker starts a chapter which runs until the next
+    fn chapters_vtt(stream: &UserStream, markers: &[StreamMarker]) -> String {
+        let end =
+            (stream.ends.unwrap_or(Utc::now()) - stream.starts).num_milliseconds() as f32 / 1000.0;
+        let mut starts: Vec<(f32, &str)> = markers
+            .iter()
+            .map(|m| (m.offset_secs, m.label.as_str()))
+            .collect();
+        if !matches!(starts.first(), Some((s, _)) if *s <= 0.0) {
+            starts.insert(0, (0.0, stream.title.as_deref().unwrap_or("Start")));
+        }
+
+        let ts = |secs: f32| {
+            let ms = (secs.max(0.0) * 1000.0) as u64;
+            format!(
+                "{:02}:{:02}:{:02}.{:03}",
+                ms / 3_600_000,
+                ms / 60_000 % 60,
+                ms / 1000 % 60,
+                ms % 1000
+            )
+        };
+        let mut ret = String::from("WEBVTT\n");
+        for (i, (start, label)) in starts.iter().enumerate() {
+            let next = starts.get(i + 1).map(|(s, _)| *s).unwrap_or(end);
+            if next <= *start {
+                continue;
+            }
+            ret.push_str(&format!(
+                "\n{}\n{} --> {}\n{}\n",
+                i + 1,
+                ts(*start),
+                ts(next),
+                label
+            ));
+        }
+        ret
+    }
+
+    /// Channel page entry of the ended stream [s], the thumbnail is the one set on the
+    /// stream or else the last frame captured while live
+    fn archive_stream(&self, s: UserStream) -> ApiArchiveStream {
+        let dir = PathBuf::from(&self.out_dir).join(&s.id);
+        let thumb = match s.thumb.clone().or(s.image.clone()) {
+            Some(t) => Some(t),
+            None if dir.join("thumb.webp").is_file() => {
+                self.map_to_public_url(&s, "thumb.webp").ok()
+            }
+            None => None,
+        };
+        let replay = if s.recording.as_deref() == Some(TRIMMED_RECORDING)
+            && dir.join(TRIMMED_RECORDING).is_file()
+        {
+            self.map_to_public_url(&s, TRIMMED_RECORDING).ok()
+        } else {
+            None
+        };
+        let recording = s
+            .recording
+            .as_deref()
+            .and_then(|r| self.map_to_public_url(&s, r).ok());
+        ApiArchiveStream {
+            recording,
+            starts: s.starts.timestamp(),
+            ends: s.ends.map(|e| e.timestamp()),
+            duration: s.duration,
+            id: s.id,
+            title: s.title,
+            thumb,
+            replay,
+        }
+    }
+
+    fn stream_cursor(s: &UserStream) -> PageCursor {
+        PageCursor {
+            created: s.starts,
+            id: s.id.clone(),
+        }
+    }
+
+    /// Create a pending approval for a sensitive action
+    async fn propose_action(
+        &self,
+        admin: &User,
+        action: ApprovalAction,
+        user_id: Option<u64>,
+        amount: Option<i64>,
+        memo: Option<&str>,
+        params: Option<&str>,
+    ) -> Result<ApiApproval> {
+        let id = self
+            .db
+            .insert_approval(action, user_id, amount, memo, params, admin.id)
+            .await?;
+        self.db
+            .insert_audit_log(
+                admin.id,
+                &format!("propose_{}", action),
+                "approval",
+                &id.to_string(),
+                memo,
+            )
+            .await?;
+        Ok(self.db.get_approval(id).await?.into())
+    }
+
+    /// Approve or reject a pending action, approved actions are executed immediately
+    ///
+    /// The admin which proposed the action cannot resolve it. Approvals whose action fails
+    /// go back to pending and are not written to the audit log
+    async fn resolve_action(
+        &self,
+        admin: &User,
+        approval: AdminApproval,
+        state: ApprovalState,
+    ) -> Result<()> {
+        if approval.proposed_by == admin.id {
+            bail!("Actions must be approved by a different admin");
+        }
+        // claim the approval first so concurrent approvals can't run the action twice
+        if !self
+            .db
+            .resolve_approval(approval.id, state, admin.id)
+            .await?
+        {
+            bail!("Approval is not pending");
+        }
+        if state == ApprovalState::Approved {
+            if let Err(e) = self.run_approved_action(&approval).await {
+                self.db.reopen_approval(approval.id).await?;
+                return Err(e);
+            }
+        }
+        self.db
+            .insert_audit_log(
+                admin.id,
+                &format!("{}_{}", state, approval.action),
+                "approval",
+                &approval.id.to_string(),
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn run_approved_action(&self, approval: &AdminApproval) -> Result<()> {
+        match approval.action {
+            ApprovalAction::CreditBalance => {
+                let user_id = approval.user_id.ok_or(anyhow!("Approval has no user"))?;
+                let amount = approval.amount.unwrap_or(0);
+                self.db.add_balance(user_id, amount).await?;
+                info!("Credited {} msats to user {}", amount, user_id);
+            }
+            ApprovalAction::DeleteUser => {
+                let user_id = approval.user_id.ok_or(anyhow!("Approval has no user"))?;
+                self.db.delete_user(user_id).await?;
+                info!("Deleted user {}", user_id);
+            }
+            ApprovalAction::RefundStream => {
+                let params: RefundParams =
+                    serde_json::from_str(approval.params.as_deref().unwrap_or_default())?;
+                let stream = self
+                    .db
+                    .get_stream(&Uuid::parse_str(&params.stream_id)?)
+                    .await?;
+                let amount = u64::try_from(approval.amount.unwrap_or(0))?;
+                self.refund_stream(approval.proposed_by, &stream, amount, approval.memo.clone())
+                    .await?;
+            }
+            ApprovalAction::CreateVouchers => {
+                let create: CreateVoucherRequest =
+                    serde_json::from_str(approval.params.as_deref().unwrap_or_default())?;
+                let vouchers = self.create_vouchers(approval.proposed_by, &create).await?;
+                info!("Created {} vouchers", vouchers.len());
+            }
+        }
+        Ok(())
+    }
+
+    /// Msats of each voucher and of the whole batch of a voucher request
+    fn voucher_amounts(create: &CreateVoucherRequest) -> Result<(u64, i64)> {
+        if create.amount == 0 {
+            bail!(BadRequest("Amount must be positive".to_string()));
+        }
+        if create.count == 0 || create.count > MAX_VOUCHER_BATCH {
+            bail!(BadRequest(format!(
+                "Count must be between 1 and {}",
+                MAX_VOUCHER_BATCH
+            )));
+        }
+        let amount = create.amount.checked_mul(1000);
+        let total = amount
+            .and_then(|a| a.checked_mul(create.count as u64))
+            .and_then(|t| i64::try_from(t).ok());
+        match (amount, total) {
+            (Some(a), Some(t)) => Ok((a, t)),
+            _ => bail!(BadRequest("Amount is too large".to_string())),
+        }
+    }
+
+    /// Create the vouchers of [create], issued by [admin_id], expiry starts now
+    async fn create_vouchers(
+        &self,
+        admin_id: u64,
+        create: &CreateVoucherRequest,
+    ) -> Result<Vec<ApiVoucher>> {
+        let (amount, _) = Self::voucher_amounts(create)?;
+        let now = Utc::now();
+        let expires = match create.expires_in {
+            Some(s) => Some(
+                i64::try_from(s)
+                    .ok()
+                    .and_then(chrono::Duration::try_seconds)
+                    .and_then(|d| now.checked_add_signed(d))
+                    .ok_or_else(|| BadRequest("Invalid expiry".to_string()))?,
+            ),
+            None => None,
+        };
+        let mut vouchers = Vec::new();
+        for _ in 0..create.count {
+            let mut voucher = Voucher {
+                code: voucher_code(),
+                amount,
+                created_by: admin_id,
+                memo: create.memo.clone(),
+                expires,
+                created: now,
+                ..Default::default()
+            };
+            voucher.id = self.db.insert_voucher(&voucher).await?;
+            self.db
+                .insert_audit_log(
+                    admin_id,
+                    "create_voucher",
+                    "voucher",
+                    &voucher.id.to_string(),
+                    Some(&format!(
+                        "{} msats: {}",
+                        voucher.amount,
+                        voucher.memo.as_deref().unwrap_or("")
+                    )),
+                )
+                .await?;
+            vouchers.push(ApiVoucher::from(voucher));
+        }
+        Ok(vouchers)
+    }
+
+    /// Credit [amount] msats of the cost of [stream] back to its owner, the database
+    /// rejects refunds exceeding the cost
+    async fn refund_stream(
+        &self,
+        admin_id: u64,
+        stream: &UserStream,
+        amount: u64,
+        reason: Option<String>,
+    ) -> Result<StreamRefund> {
+        let mut refund = StreamRefund {
+            stream_id: stream.id.clone(),
+            admin_id,
+            amount,
+            reason,
+            created: Utc::now(),
+            ..Default::default()
+        };
+        refund.id = self.db.insert_stream_refund(&refund).await?;
+        self.db
+            .insert_audit_log(
+                admin_id,
+                "refund_stream",
+                "stream",
+                &stream.id,
+                Some(&format!(
+                    "{} msats to user {}: {}",
+                    amount,
+                    stream.user_id,
+                    refund.reason.as_deref().unwrap_or("")
+                )),
+            )
+            .await?;
+        Ok(refund)
+    }
+
+    fn audit_log_cursor(a: &AuditLog) -> PageCursor {
+        PageCursor {
+            created: a.created,
+            id: a.id.to_string(),
+        }
+    }
+
+    /// Parse audit log filters from query params
+    ///
+    /// `from` / `to` are unix timestamps (seconds)
+    fn audit_log_filter(query: &HashMap<String, String>) -> Result<AuditLogFilter> {
+        let timestamp = |k: &str| -> Result<Option<DateTime<Utc>>> {
+            match query.get(k) {
+                Some(v) => Ok(DateTime::from_timestamp(v.parse()?, 0)),
+                None => Ok(None),
+            }
+        };
+        Ok(AuditLogFilter {
+            admin_id: query.get("admin").map(|a| a.parse()).transpose()?,
+            action: query.get("action").cloned(),
+            target_type: query.get("target_type").cloned(),
+            target_id: query.get("target_id").cloned(),
+            search: query.get("q").cloned(),
+            from: timestamp("from")?,
+            to: timestamp("to")?,
+        })
+    }
+
+    /// Export all audit log entries matching [filter] as CSV, pages are sent as they are
+    /// loaded so the export is never held in memory
+    async fn audit_log_csv(&self, filter: AuditLogFilter) -> Result<ApiResponse> {
+        let db = self.db.clone();
+        let (tx, rx) = mpsc::channel(2);
+        tokio::spawn(async move {
+            let mut csv =
+                String::from("id,created,admin_id,action,target_type,target_id,message\n");
+            let mut after = None;
+            loop {
+                let entries = match db.list_audit_log(&filter, after, 0, MAX_PAGE_LIMIT).await {
+                    Ok(e) => e,
+                    Err(e) => {
+                        // the headers were sent, aborting the body is all that's left
+                        let _ = tx.send(Err(e)).await;
+                        return;
+                    }
+                };
+                for e in &entries {
+                    csv.push_str(&format!(
+                        "{},{},{},{},{},{},{}\n",
+                        e.id,
+                        e.created.to_rfc3339(),
+                        e.admin_id,
+                        csv_escape(&e.action),
+                        csv_escape(&e.target_type),
+                        csv_escape(&e.target_id),
+                        csv_escape(e.message.as_deref().unwrap_or("")),
+                    ));
+                }
+                if tx
+                    .send(Ok(Bytes::from(std::mem::take(&mut csv))))
+                    .await
+                    .is_err()
+                {
+                    // client went away
+                    return;
+                }
+                if (entries.len() as u64) < MAX_PAGE_LIMIT {
+                    return;
+                }
+                after = entries.last().map(|e| (e.created, e.id));
+            }
+        });
+        let body = StreamBody::new(ReceiverStream::new(rx).map_ok(Frame::data)).boxed();
+        Ok(Response::builder()
+            .header("server", "zap-stream-core")
+            .header("content-type", "text/csv")
+            .header(
+                "content-disposition",
+                "attachment; filename=\"audit-log.csv\"",
+            )
+            .header("access-control-allow-origin", "*")
+            .body(body)?)
+    }
+
+    /// Check the request is NIP-98 signed and return the user, creating it if new
+    pub(super) async fn check_auth<T>(&self, req: &Request<T>) -> Result<User> {
+        let pubkey =
+            check_nip98_auth(req, &self.public_url).map_err(|e| Unauthorized(e.to_string()))?;
+        let uid = self.db.upsert_user(&pubkey.to_bytes()).await?;
+        self.db.get_user(uid).await
+    }
+
+    /// Read the body of [req], up to [limit] bytes if set
+    ///
+    /// NIP-98 signed requests must carry the hash of the body in the `payload` tag, otherwise the
+    /// body of a captured request could be swapped while its auth event is still fresh
+    pub(super) async fn signed_body(req: Request<Incoming>, limit: Option<usize>) -> Result<Bytes> {
+        let (parts, body) = req.into_parts();
+        let body = match limit {
+            Some(limit) => Limited::new(body, limit)
+                .collect()
+                .await
+                .map_err(|e| anyhow!(e))?
+                .to_bytes(),
+            None => body.collect().await?.to_bytes(),
+        };
+        check_nip98_payload(&Request::from_parts(parts, ()), &body)
+            .map_err(|e| Unauthorized(e.to_string()))?;
+        Ok(body)
+    }
+
+    /// Check the request is NIP-98 signed by an admin user whose role grants [permission]
+    async fn check_admin_access<T>(
+        &self,
+        req: &Request<T>,
+        permission: AdminPermission,
+    ) -> Result<User> {
+        let pubkey =
+            check_nip98_auth(req, &self.public_url).map_err(|e| Unauthorized(e.to_string()))?;
+        match self.db.find_user_pubkey(&pubkey.to_bytes()).await? {
+            Some(user) if user.is_admin && permission.allowed(user.admin_role) => Ok(user),
+            _ => bail!(Forbidden),
+        }
+    }
+
+    /// Replace the watermark image of [owner] with the body of [req], images which can't be
+    /// decoded are rejected
+    async fn upload_watermark(&self, owner: WatermarkOwner, req: Request<Incoming>) -> Result<()> {
+        let body = Self::signed_body(req, Some(MAX_SLATE_SIZE)).await?;
+        if body.is_empty() {
+            bail!("Empty watermark image");
+        }
+        let path = self.watermark_path(owner);
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, &body).await?;
+        let probe = tmp.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || probe_image(&probe)).await? {
+            tokio::fs::remove_file(&tmp).await?;
+            bail!(BadRequest(format!("Invalid watermark image: {}", e)));
+        }
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    async fn delete_watermark(&self, owner: WatermarkOwner) -> Result<()> {
+        let path = self.watermark_path(owner);
+        if path.exists() {
+            tokio::fs::remove_file(path).await?;
+        }
+        Ok(())
+    }
+
+    /// Client IP, resolved by the http server from the connection and trusted proxies
+    fn client_ip<T>(req: &Request<T>) -> Option<IpAddr> {
+        req.extensions().get::<ClientIp>().map(|c| c.0)
+    }
+
+    pub(super) fn not_found() -> Result<ApiResponse> {
+        Ok(Response::builder()
+            .header("server", "zap-stream-core")
+            .status(404)
+            .body(Full::from("").map_err(anyhow::Error::new).boxed())?)
+    }
+
+    pub(super) fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Result<ApiResponse> {
+        Ok(Response::builder()
+            .header("server", "zap-stream-core")
+            .header("content-type", "application/json")
+            .header("access-control-allow-origin", "*")
+            .status(status)
+            .body(
+                Full::from(serde_json::to_vec(body)?)
+                    .map_err(anyhow::Error::new)
+                    .boxed(),
+            )?)
+    }
+}
+
+/// Quote a CSV field if it contains any special chars
+fn csv_escape(v: &str) -> String {
+    if v.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", v.replace('"', "\"\""))
+    } else {
+        v.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_range_bounds() {
+        let range = |r: &str| ZapStreamOverseer::parse_range(r, 1000);
+        assert_eq!(range("bytes=0-499"), Some((0, 499)));
+        // the end is clamped to the file
+        assert_eq!(range("bytes=500-2000"), Some((500, 999)));
+        // open ended
+        assert_eq!(range("bytes=900-"), Some((900, 999)));
+        assert_eq!(range("bytes=1000-"), None);
+        // suffix, the last n bytes
+        assert_eq!(range("bytes=-500"), Some((500, 999)));
+        assert_eq!(range("bytes=-2000"), Some((0, 999)));
+        assert_eq!(range("bytes=-0"), None);
+        // start after end
+        assert_eq!(range("bytes=500-100"), None);
+        // only the first range of a multi-range request is served
+        assert_eq!(range("bytes=0-99, 200-299"), Some((0, 99)));
+        assert_eq!(range("bytes=0-99,-100"), Some((0, 99)));
+        assert_eq!(range("bytes=-"), None);
+        assert_eq!(range("bytes=5-abc"), None);
+        assert_eq!(range("items=0-99"), None);
+        assert_eq!(ZapStreamOverseer::parse_range("bytes=0-", 0), None);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::settings::IpBanSettings;
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use tokio::sync::RwLock;
+
+/// An active IP ban
+#[derive(Clone, Serialize)]
+pub struct IpBan {
+    pub ip: IpAddr,
+    pub reason: String,
+    pub created: DateTime<Utc>,
+    /// When the ban is lifted, [None] means permanent
+    pub expires: Option<DateTime<Utc>>,
+}
+
+/// Tracks failed connection attempts per IP and temp-bans IPs which exceed
+/// [IpBanSettings::max_failures] within [IpBanSettings::window]
+pub struct IpBanList {
+    settings: IpBanSettings,
+    failures: RwLock<HashMap<IpAddr, Vec<DateTime<Utc>>>>,
+    bans: RwLock<HashMap<IpAddr, IpBan>>,
+}
+
+impl IpBanList {
+    pub fn new(settings: IpBanSettings) -> Self {
+        Self {
+            settings,
+            failures: RwLock::new(HashMap::new()),
+            bans: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Parse the IP from a [crate::ingress::ConnectionInfo::ip_addr] which may include a port
+    pub fn parse_ip(addr: &str) -> Option<IpAddr> {
+        addr.parse::<SocketAddr>()
+            .map(|a| a.ip())
+            .or_else(|_| addr.parse::<IpAddr>())
+            .ok()
+    }
+
+    /// Returns the active ban for this IP, if any
+    pub async fn get_ban(&self, ip: &IpAddr) -> Option<IpBan> {
+        let now = Utc::now();
+        let mut bans = self.bans.write().await;
+        match bans.get(ip) {
+            Some(b) if b.expires.is_some_and(|e| e <= now) => {
+                bans.remove(ip);
+                None
+            }
+            Some(b) => Some(b.clone()),
+            None => None,
+        }
+    }
+
+    /// Record a failed connection attempt, banning the IP if it exceeds the threshold
+    pub async fn add_failure(&self, ip: &IpAddr) {
+        let now = Utc::now();
+        let window_start = now - Duration::seconds(self.settings.window as i64);
+        let count = {
+            let mut failures = self.failures.write().await;
+            failures.retain(|_, v| v.last().is_some_and(|t| *t > window_start));
+            let attempts = failures.entry(*ip).or_default();
+            attempts.retain(|t| *t > window_start);
+            attempts.push(now);
+            attempts.len()
+        };
+        if count >= self.settings.max_failures as usize {
+            warn!(
+                "Banning {} after {} failed attempts in {}s",
+                ip, count, self.settings.window
+            );
+            self.failures.write().await.remove(ip);
+            self.add_ban(
+                *ip,
+                format!("{} failed connection attempts", count),
+                Some(self.settings.ban_duration),
+            )
+            .await;
+        }
+    }
+
+    /// Ban an IP for [duration] seconds, or permanently if [None]
+    pub async fn add_ban(&self, ip: IpAddr, reason: String, duration: Option<u64>) -> IpBan {
+        let now = Utc::now();
+        let ban = IpBan {
+            ip,
+            reason,
+            created: now,
+            expires: duration.map(|d| now + Duration::seconds(d as i64)),
+        };
+        self.bans.write().await.insert(ip, ban.clone());
+        ban
+    }
+
+    /// Lift a ban, returns false if the IP was not banned
+    pub async fn remove_ban(&self, ip: &IpAddr) -> bool {
+        self.failures.write().await.remove(ip);
+        self.bans.write().await.remove(ip).is_some()
+    }
+
+    /// List all active bans
+    pub async fn list_bans(&self) -> Vec<IpBan> {
+        let now = Utc::now();
+        let mut bans = self.bans.write().await;
+        bans.retain(|_, b| b.expires.is_none_or(|e| e > now));
+        bans.values().cloned().collect()
+    }
+}
@@ -0,0 +1,63 @@
+use crate::settings::BurnAlertSettings;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{HashMap, HashSet};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// Debit rate of a user which exceeded [BurnAlertSettings::max_rate]
+pub struct BurnSpike {
+    /// Milli-sats / minute averaged over [BurnAlertSettings::window]
+    pub rate: u64,
+    /// Number of streams debited within the window
+    pub streams: u32,
+}
+
+/// Tracks debits per user and reports users debited faster than
+/// [BurnAlertSettings::max_rate], at most once per [BurnAlertSettings::cooldown]
+pub struct BurnMonitor {
+    settings: BurnAlertSettings,
+    /// Debits within the window by user (time, stream, amount)
+    debits: RwLock<HashMap<u64, Vec<(DateTime<Utc>, Uuid, i64)>>>,
+    /// Last alert by user
+    alerted: RwLock<HashMap<u64, DateTime<Utc>>>,
+}
+
+impl BurnMonitor {
+    pub fn new(settings: BurnAlertSettings) -> Self {
+        Self {
+            settings,
+            debits: RwLock::new(HashMap::new()),
+            alerted: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Record a debit of [amount] milli-sats for [stream], returns the user's rate if it
+    /// exceeds the threshold and the user was not alerted within the cooldown
+    pub async fn add_debit(&self, user_id: u64, stream: &Uuid, amount: i64) -> Option<BurnSpike> {
+        let now = Utc::now();
+        let window_start = now - Duration::seconds(self.settings.window as i64);
+        let (total, streams) = {
+            let mut debits = self.debits.write().await;
+            debits.retain(|_, v| v.last().is_some_and(|(t, _, _)| *t > window_start));
+            let user = debits.entry(user_id).or_default();
+            user.retain(|(t, _, _)| *t > window_start);
+            user.push((now, *stream, amount));
+            let total: i64 = user.iter().map(|(_, _, a)| *a).sum();
+            let streams: HashSet<&Uuid> = user.iter().map(|(_, s, _)| s).collect();
+            (total, streams.len() as u32)
+        };
+
+        let rate = (total.max(0) as u64) * 60 / self.settings.window.max(1);
+        if rate < self.settings.max_rate * 1000 {
+            return None;
+        }
+        let mut alerted = self.alerted.write().await;
+        let cooldown_start = now - Duration::seconds(self.settings.cooldown as i64);
+        alerted.retain(|_, t| *t > cooldown_start);
+        if alerted.contains_key(&user_id) {
+            return None;
+        }
+        alerted.insert(user_id, now);
+        Some(BurnSpike { rate, streams })
+    }
+}
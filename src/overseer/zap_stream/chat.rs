@@ -0,0 +1,95 @@
+use crate::overseer::zap_stream::ZapStreamOverseer;
+use anyhow::{bail, Result};
+use log::{info, warn};
+use nostr_sdk::{Client, Coordinate, Filter, JsonUtil, Kind, Timestamp};
+use serde::Serialize;
+use std::time::Duration;
+use zap_stream_db::{StreamChatMessage, UserStream, UserStreamState, ZapStreamDb};
+
+/// Max time to wait for relays to return the stream's chat
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Max number of messages returned per request, players request the next range once
+/// they get close to the last message
+const MAX_CHAT_MESSAGES: u64 = 1000;
+
+/// Chat message at [offset] seconds into the recording
+#[derive(Serialize)]
+pub(super) struct ApiChatMessage {
+    offset: f32,
+    /// Signed kind 1311 event
+    event: serde_json::Value,
+}
+
+impl ZapStreamOverseer {
+    /// Archive the chat of the ended [stream] in the background
+    pub(super) fn spawn_chat_archive(&self, stream: &UserStream) {
+        let client = self.client.clone();
+        let db = self.db.clone();
+        let coord = self.stream_coordinate(stream);
+        let stream = stream.clone();
+        tokio::spawn(async move {
+            match archive_chat(&client, &db, &coord, &stream).await {
+                Ok(n) => info!("Archived {} chat messages of stream {}", n, stream.id),
+                Err(e) => warn!("Failed to archive chat of stream {}: {}", stream.id, e),
+            }
+        });
+    }
+
+    /// Archived chat of [stream] between [from] and [to] seconds from its start
+    pub(super) async fn stream_chat(
+        &self,
+        stream: &UserStream,
+        from: f32,
+        to: f32,
+    ) -> Result<Vec<ApiChatMessage>> {
+        if stream.state != UserStreamState::Ended {
+            bail!("Chat is archived once the stream ended");
+        }
+        Ok(self
+            .db
+            .list_stream_chat(&stream.id, from, to, MAX_CHAT_MESSAGES)
+            .await?
+            .into_iter()
+            .filter_map(|m| {
+                Some(ApiChatMessage {
+                    offset: m.offset_secs,
+                    event: serde_json::from_str(&m.event).ok()?,
+                })
+            })
+            .collect())
+    }
+}
+
+/// Store the kind 1311 messages sent to [coord] while [stream] was live with their offset
+/// from the start of the stream, returns the number of messages found
+async fn archive_chat(
+    client: &Client,
+    db: &ZapStreamDb,
+    coord: &Coordinate,
+    stream: &UserStream,
+) -> Result<usize> {
+    let ends = match stream.ends {
+        Some(e) => e,
+        None => bail!("Stream has not ended"),
+    };
+    let filter = Filter::new()
+        .kind(Kind::LiveEventMessage)
+        .coordinate(coord)
+        .since(Timestamp::from(stream.starts.timestamp() as u64))
+        .until(Timestamp::from(ends.timestamp() as u64));
+    let messages: Vec<StreamChatMessage> = client
+        .fetch_events(vec![filter], Some(FETCH_TIMEOUT))
+        .await?
+        .into_iter()
+        .map(|ev| StreamChatMessage {
+            stream_id: stream.id.clone(),
+            event_id: ev.id.to_bytes().to_vec(),
+            offset_secs: (ev.created_at.as_u64() as i64 - stream.starts.timestamp()).max(0) as f32,
+            event: ev.as_json(),
+            ..Default::default()
+        })
+        .collect();
+    db.insert_stream_chat(&messages).await?;
+    Ok(messages.len())
+}
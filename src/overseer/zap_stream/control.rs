@@ -0,0 +1,211 @@
+use crate::http::Unauthorized;
+use crate::overseer::zap_stream::api::ApiResponse;
+use crate::overseer::zap_stream::webhooks::StreamChange;
+use crate::overseer::zap_stream::{SlateFile, ZapStreamOverseer};
+use anyhow::{bail, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration, Utc};
+use hyper::body::Incoming;
+use hyper::{Method, Request, StatusCode};
+use log::info;
+use nostr_sdk::JsonUtil;
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use zap_stream_db::{User, UserStream};
+
+/// Default lifetime of controller tokens in seconds
+const DEFAULT_TOKEN_TTL: u64 = 60 * 60 * 12;
+
+/// Max lifetime of controller tokens in seconds
+const MAX_TOKEN_TTL: u64 = 60 * 60 * 24 * 7;
+
+#[derive(Deserialize, Default)]
+struct TokenRequest {
+    /// Seconds until the token expires
+    expires_in: Option<u64>,
+}
+
+/// Controller token, tokens are only kept in memory so controllers must request a new one
+/// after the server restarted
+#[derive(Serialize)]
+struct ControlToken {
+    token: String,
+    expires: i64,
+}
+
+#[derive(Deserialize)]
+struct SetEnabled {
+    enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct SetTitle {
+    title: String,
+}
+
+#[derive(Serialize)]
+struct ControlStatus {
+    live: bool,
+    stream: Option<String>,
+    title: Option<String>,
+    recording: bool,
+    /// Recording is enabled but paused because the server is low on disk space
+    recording_paused: bool,
+    slate: bool,
+}
+
+impl ZapStreamOverseer {
+    /// Routes for hardware controllers (Stream Deck etc.) under `/api/v1/control`
+    ///
+    /// Actions set an explicit state rather than toggling, so repeating a request is safe.
+    /// Every action responds with the current [ControlStatus]
+    pub(super) async fn control_request(
+        &self,
+        req: Request<Incoming>,
+        path: &[&str],
+    ) -> Result<ApiResponse> {
+        match (req.method().clone(), path) {
+            (Method::POST, ["token"]) => {
+                let user = self.check_auth(&req).await?;
+                let body = Self::signed_body(req, None).await?;
+                let token_req: TokenRequest = if body.is_empty() {
+                    Default::default()
+                } else {
+                    serde_json::from_slice(&body)?
+                };
+                let ttl = token_req
+                    .expires_in
+                    .unwrap_or(DEFAULT_TOKEN_TTL)
+                    .min(MAX_TOKEN_TTL);
+
+                let mut token = [0u8; 24];
+                rand::thread_rng().fill_bytes(&mut token);
+                let token = URL_SAFE_NO_PAD.encode(token);
+                let expires = Utc::now() + Duration::seconds(ttl as i64);
+
+                let mut tokens = self.control_tokens.write().await;
+                tokens.retain(|_, (_, e)| *e > Utc::now());
+                tokens.insert(token.clone(), (user.id, expires));
+                let rsp = ControlToken {
+                    token,
+                    expires: expires.timestamp(),
+                };
+                Self::json_response(StatusCode::OK, &rsp)
+            }
+            (Method::GET, ["status"]) => {
+                let user = self.check_control_auth(&req).await?;
+                self.control_status(&user).await
+            }
+            (Method::POST, ["recording"]) => {
+                let mut user = self.check_control_auth(&req).await?;
+                let set: SetEnabled = Self::read_json(req).await?;
+                self.db.update_user_recording(user.id, set.enabled).await?;
+                user.recording = set.enabled;
+                if let Some(stream) = self.db.get_user_live_stream(user.id).await? {
+                    let id = Uuid::parse_str(&stream.id)?;
+                    if let Some(c) = self.stream_controls.write().await.get_mut(&id) {
+                        c.recording = set.enabled;
+                    }
+                }
+                self.control_status(&user).await
+            }
+            (Method::POST, ["slate"]) => {
+                let user = self.check_control_auth(&req).await?;
+                let set: SetEnabled = Self::read_json(req).await?;
+                let stream = self.live_stream(&user).await?;
+                let id = Uuid::parse_str(&stream.id)?;
+                if let Some(c) = self.stream_controls.write().await.get_mut(&id) {
+                    c.slate = set.enabled;
+                    c.slate_image = self.slate_file(user.id, SlateFile::Image);
+                    c.slate_audio = self.slate_file(user.id, SlateFile::Audio);
+                }
+                self.control_status(&user).await
+            }
+            (Method::POST, ["title"]) => {
+                let user = self.check_control_auth(&req).await?;
+                let set: SetTitle = Self::read_json(req).await?;
+                let mut stream = self.live_stream(&user).await?;
+                if stream.title.as_ref() != Some(&set.title) {
+                    stream.title = Some(set.title);
+                    let event = self
+                        .publish_stream_event(&stream, &user, StreamChange::Update)
+                        .await?;
+                    stream.event = Some(event.as_json());
+                    self.db.update_stream(&stream).await?;
+                }
+                self.control_status(&user).await
+            }
+            (Method::POST, ["end"]) => {
+                let user = self.check_control_auth(&req).await?;
+                if let Some(stream) = self.db.get_user_live_stream(user.id).await? {
+                    info!("Stream {} ended by controller", stream.id);
+                    // ending the stream makes [Overseer::pipeline_control] stop its pipeline
+                    // after the next segment, which disconnects the encoder
+                    self.end_stream(&Uuid::parse_str(&stream.id)?, None).await?;
+                }
+                self.control_status(&user).await
+            }
+            _ => Self::not_found(),
+        }
+    }
+
+    /// Accept a controller token (`Bearer`) or a NIP-98 signed request
+    pub(super) async fn check_control_auth<T>(&self, req: &Request<T>) -> Result<User> {
+        let token = req
+            .headers()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        let token = match token {
+            Some(t) => t,
+            None => return self.check_auth(req).await,
+        };
+        let user_id = match self.control_tokens.read().await.get(token) {
+            Some((uid, expires)) if *expires > Utc::now() => *uid,
+            _ => bail!(Unauthorized("Invalid control token".to_string())),
+        };
+        self.db.get_user(user_id).await
+    }
+
+    /// The user's current live stream
+    async fn live_stream(&self, user: &User) -> Result<UserStream> {
+        match self.db.get_user_live_stream(user.id).await? {
+            Some(s) => Ok(s),
+            None => bail!("No live stream"),
+        }
+    }
+
+    async fn control_status(&self, user: &User) -> Result<ApiResponse> {
+        let stream = self.db.get_user_live_stream(user.id).await?;
+        let (control, recording_paused) = match &stream {
+            Some(s) => {
+                let id = Uuid::parse_str(&s.id)?;
+                (
+                    self.stream_controls.read().await.get(&id).cloned(),
+                    self.recording_paused.read().await.contains(&id),
+                )
+            }
+            None => (None, false),
+        };
+        let rsp = ControlStatus {
+            live: stream.is_some(),
+            recording: control
+                .as_ref()
+                .map(|c| c.recording)
+                .unwrap_or(user.recording),
+            recording_paused,
+            slate: control.is_some_and(|c| c.slate),
+            title: stream.as_ref().and_then(|s| s.title.clone()),
+            stream: stream.map(|s| s.id),
+        };
+        Self::json_response(StatusCode::OK, &rsp)
+    }
+
+    async fn read_json<T: DeserializeOwned>(req: Request<Incoming>) -> Result<T> {
+        let body = Self::signed_body(req, None).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
@@ -0,0 +1,80 @@
+use crate::overseer::zap_stream::{ZapStreamOverseer, STREAM_EVENT_KIND};
+use anyhow::Result;
+use log::{info, warn};
+use nostr_sdk::{Event, EventBuilder, EventId, Filter, Kind, PublicKey, Timestamp};
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+use zap_stream_db::UserStreamState;
+
+/// Live events older than this are not checked, clients treat them as ended anyway
+const MAX_EVENT_AGE: u64 = 7 * 24 * 60 * 60;
+
+/// Max time to wait for relays to return our live events
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl ZapStreamOverseer {
+    /// Delete live stream events on relays which don't belong to an active stream
+    ///
+    /// A crash between publishing the event of a new stream and saving it, or while ending a
+    /// stream, leaves events marked live which are never updated again; clients then show the
+    /// user live more than once. Only the event of a stream which is live in the database and
+    /// has a running pipeline is kept.
+    ///
+    /// Returns the number of events deleted
+    pub(super) async fn cleanup_duplicate_events(&self) -> Result<usize> {
+        let filter = Filter::new()
+            .kind(Kind::from(STREAM_EVENT_KIND))
+            .authors(self.keys.public_keys())
+            .since(Timestamp::now() - MAX_EVENT_AGE);
+        let events = self
+            .client
+            .fetch_events(vec![filter], Some(FETCH_TIMEOUT))
+            .await?;
+
+        // deletions must be signed by the author, events may be signed by any of our keys
+        let mut stale: HashMap<PublicKey, Vec<EventId>> = HashMap::new();
+        for ev in events.into_iter() {
+            if tag_value(&ev, "status") != Some("live") {
+                continue;
+            }
+            let id = match tag_value(&ev, "d").and_then(|d| Uuid::parse_str(d).ok()) {
+                Some(id) => id,
+                None => continue,
+            };
+            if self.active_streams.read().await.contains(&id) {
+                continue;
+            }
+            match self.db.get_stream(&id).await {
+                // the stream was running before a restart, check_streams ends it or the
+                // encoder resumes it
+                Ok(s) if s.state == UserStreamState::Live => continue,
+                Ok(_) => {}
+                Err(_) => info!("Live event {} has no stream record", ev.id),
+            }
+            stale.entry(ev.pubkey).or_default().push(ev.id);
+        }
+        let count = stale.values().map(|v| v.len()).sum();
+        if count == 0 {
+            return Ok(0);
+        }
+
+        warn!("Deleting {} stale live events", count);
+        for (pubkey, ids) in stale {
+            let keys = match self.keys.get(&pubkey) {
+                Some(k) => k,
+                None => continue,
+            };
+            let delete = EventBuilder::delete(ids).sign_with_keys(keys)?;
+            self.send_event(delete).await?;
+        }
+        Ok(count)
+    }
+}
+
+fn tag_value<'a>(ev: &'a Event, name: &str) -> Option<&'a str> {
+    ev.tags.iter().find_map(|t| match t.as_slice() {
+        [k, v, ..] if k == name => Some(v.as_str()),
+        _ => None,
+    })
+}
@@ -0,0 +1,152 @@
+use crate::http::Forbidden;
+use crate::settings::FederationSettings;
+use anyhow::{bail, Result};
+use futures_util::future::join_all;
+use hyper::Request;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use url::Url;
+
+/// Max time to wait for the directory of a peer
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Max streams listed from one peer
+const MAX_PEER_STREAMS: usize = 500;
+
+/// Query param asking an instance for only its own streams, so peers listing each other
+/// don't relist the streams they fetched
+pub(super) const PARAM_LOCAL: &str = "local";
+
+/// Live stream in the public stream directory
+#[derive(Clone, Serialize, Deserialize)]
+pub(super) struct ApiDirectoryStream {
+    pub id: String,
+    pub title: Option<String>,
+    pub summary: Option<String>,
+    pub image: Option<String>,
+    pub starts: i64,
+    /// HLS playlist URL
+    pub url: String,
+    /// Host of the peer instance the stream is live on, unset for local streams
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+}
+
+/// Live streams of trusted peer instances, merged into the public stream directory
+pub(super) struct Federation {
+    peers: Vec<Url>,
+    /// Shared secret authenticating requests between peers
+    token: Option<String>,
+    refresh_interval: Duration,
+    client: reqwest::Client,
+    streams: RwLock<Vec<ApiDirectoryStream>>,
+    refreshed: RwLock<Option<Instant>>,
+}
+
+impl Federation {
+    pub fn new(settings: &FederationSettings) -> Result<Self> {
+        Ok(Self {
+            peers: settings
+                .peers
+                .iter()
+                .map(|p| Url::parse(p))
+                .collect::<Result<_, _>>()?,
+            token: settings.token.clone(),
+            refresh_interval: Duration::from_secs(settings.refresh_interval),
+            client: reqwest::Client::builder().timeout(FETCH_TIMEOUT).build()?,
+            streams: RwLock::new(Vec::new()),
+            refreshed: RwLock::new(None),
+        })
+    }
+
+    /// Configured peer with the base URL [url]
+    pub fn peer(&self, url: &str) -> Option<&Url> {
+        let url = url.trim_end_matches('/');
+        self.peers
+            .iter()
+            .find(|p| p.as_str().trim_end_matches('/') == url)
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// Check a request of a peer presents the federation token
+    pub fn check_token<T>(&self, req: &Request<T>) -> Result<()> {
+        let auth = req
+            .headers()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        match (&self.token, auth) {
+            (Some(t), Some(a)) if t == a => Ok(()),
+            _ => bail!(Forbidden),
+        }
+    }
+
+    /// Live streams of all peers as of the last refresh
+    pub async fn streams(&self) -> Vec<ApiDirectoryStream> {
+        self.streams.read().await.clone()
+    }
+
+    /// Fetch the directories of all peers when the refresh interval elapsed, peers which
+    /// fail to answer are left out until the next refresh
+    pub async fn refresh(&self) {
+        if self
+            .refreshed
+            .read()
+            .await
+            .is_some_and(|t| t.elapsed() < self.refresh_interval)
+        {
+            return;
+        }
+        *self.refreshed.write().await = Some(Instant::now());
+
+        let results = join_all(self.peers.iter().map(|p| self.fetch(p))).await;
+        let mut streams = Vec::new();
+        for (peer, res) in self.peers.iter().zip(results) {
+            match res {
+                Ok(s) => streams.extend(s),
+                Err(e) => warn!("Failed to fetch streams of peer {}: {}", peer, e),
+            }
+        }
+        *self.streams.write().await = streams;
+    }
+
+    async fn fetch(&self, peer: &Url) -> Result<Vec<ApiDirectoryStream>> {
+        let host = match peer.host_str() {
+            Some(h) => h.to_string(),
+            None => bail!("Peer has no host"),
+        };
+        let mut url = Url::parse(&format!(
+            "{}/api/v1/streams",
+            peer.as_str().trim_end_matches('/')
+        ))?;
+        url.query_pairs_mut().append_pair(PARAM_LOCAL, "true");
+        let streams: Vec<ApiDirectoryStream> = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(streams
+            .into_iter()
+            // only the peer's own streams, with a playlist players can load
+            .filter(|s| s.instance.is_none())
+            .filter(|s| Url::parse(&s.url).is_ok_and(|u| u.scheme() == "https"))
+            .take(MAX_PEER_STREAMS)
+            .map(|s| ApiDirectoryStream {
+                instance: Some(host.clone()),
+                ..s
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,145 @@
+use crate::overseer::zap_stream::zaps::ZapReceipt;
+use crate::overseer::zap_stream::ZapStreamOverseer;
+use anyhow::Result;
+use chrono::Utc;
+use nostr_sdk::{Filter, Kind, Timestamp};
+use serde::Serialize;
+use std::time::Duration;
+use zap_stream_db::UserStream;
+
+/// Activity is counted per bucket of this many seconds
+const BUCKET_SECS: i64 = 60;
+
+/// Buckets with a combined z-score at or above this are considered a spike
+const SPIKE_THRESHOLD: f64 = 2.0;
+
+/// Seconds to include before a spike, viewers react after the moment happened
+const LEAD_IN_SECS: i64 = 30;
+
+/// Max number of highlights suggested per stream
+const MAX_HIGHLIGHTS: usize = 10;
+
+/// Seconds before a marker included in its highlight, markers are set after the moment
+const MARKER_BEFORE_SECS: f32 = 60.0;
+
+/// Seconds after a marker included in its highlight
+const MARKER_AFTER_SECS: f32 = 15.0;
+
+/// Max time to wait for relays to return the stream's events
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Suggested highlight, [start] and [end] are seconds from the start of the stream
+#[derive(Serialize, Clone, Debug, Default)]
+pub(super) struct Highlight {
+    /// Label of the streamer's marker, None for activity spikes
+    label: Option<String>,
+    start: f32,
+    end: f32,
+    /// Peak combined z-score of chat and zap activity
+    score: f32,
+    zaps: u32,
+    /// Total zap amount in milli-sats
+    amount: u64,
+    messages: u32,
+}
+
+#[derive(Clone, Default)]
+struct Bucket {
+    zaps: u32,
+    amount: u64,
+    messages: u32,
+}
+
+impl ZapStreamOverseer {
+    /// Suggest highlights of [stream] from the streamer's markers and spikes in zap and
+    /// chat activity
+    pub(super) async fn stream_highlights(&self, stream: &UserStream) -> Result<Vec<Highlight>> {
+        let until = stream.ends.unwrap_or(Utc::now());
+        let coord = self.stream_coordinate(stream);
+        let filter = Filter::new()
+            .kinds([Kind::ZapReceipt, Kind::LiveEventMessage])
+            .coordinate(&coord)
+            .since(Timestamp::from(stream.starts.timestamp() as u64))
+            .until(Timestamp::from(until.timestamp() as u64));
+        let events = self
+            .client
+            .fetch_events(vec![filter], Some(FETCH_TIMEOUT))
+            .await?;
+
+        let len = (until - stream.starts).num_seconds().max(0) / BUCKET_SECS + 1;
+        let mut buckets = vec![Bucket::default(); len as usize];
+        for ev in events.into_iter() {
+            let offset = ev.created_at.as_u64() as i64 - stream.starts.timestamp();
+            let bucket = match usize::try_from(offset / BUCKET_SECS)
+                .ok()
+                .and_then(|i| buckets.get_mut(i))
+            {
+                Some(b) => b,
+                None => continue,
+            };
+            match ev.kind {
+                Kind::ZapReceipt => {
+                    if let Ok(zap) = ZapReceipt::validate(&ev) {
+                        bucket.zaps += 1;
+                        bucket.amount += zap.amount;
+                    }
+                }
+                Kind::LiveEventMessage => bucket.messages += 1,
+                _ => {}
+            }
+        }
+        let mut ret = find_highlights(&buckets);
+
+        // markers are always suggested, the streamer picked them
+        let markers = self.db.list_stream_markers(&stream.id).await?;
+        ret.extend(markers.into_iter().map(|m| Highlight {
+            start: (m.offset_secs - MARKER_BEFORE_SECS).max(0.0),
+            end: m.offset_secs + MARKER_AFTER_SECS,
+            label: Some(m.label),
+            ..Default::default()
+        }));
+        ret.sort_by(|a, b| a.start.total_cmp(&b.start));
+        Ok(ret)
+    }
+}
+
+/// Merge consecutive spike buckets into highlights, keeping the highest scoring
+fn find_highlights(buckets: &[Bucket]) -> Vec<Highlight> {
+    let chat = z_scores(buckets.iter().map(|b| b.messages as f64).collect());
+    let zaps = z_scores(buckets.iter().map(|b| (b.amount / 1000) as f64).collect());
+
+    let mut ret = vec![];
+    let mut current: Option<Highlight> = None;
+    for (i, bucket) in buckets.iter().enumerate() {
+        let score = chat[i] + zaps[i];
+        if score < SPIKE_THRESHOLD {
+            ret.extend(current.take());
+            continue;
+        }
+        let h = current.get_or_insert_with(|| Highlight {
+            start: (i as i64 * BUCKET_SECS - LEAD_IN_SECS).max(0) as f32,
+            ..Default::default()
+        });
+        h.end = ((i as i64 + 1) * BUCKET_SECS) as f32;
+        h.score = h.score.max(score as f32);
+        h.zaps += bucket.zaps;
+        h.amount += bucket.amount;
+        h.messages += bucket.messages;
+    }
+    ret.extend(current);
+
+    ret.sort_by(|a, b| b.score.total_cmp(&a.score));
+    ret.truncate(MAX_HIGHLIGHTS);
+    ret
+}
+
+/// Standard score of each value, all zero when there is no variation
+fn z_scores(values: Vec<f64>) -> Vec<f64> {
+    let n = values.len().max(1) as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let std_dev = (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n).sqrt();
+    if std_dev == 0.0 {
+        return vec![0.0; values.len()];
+    }
+    values.into_iter().map(|v| (v - mean) / std_dev).collect()
+}
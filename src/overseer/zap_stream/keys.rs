@@ -0,0 +1,100 @@
+use crate::overseer::zap_stream::ZapStreamOverseer;
+use anyhow::{bail, Result};
+use log::info;
+use nostr_sdk::{Event, JsonUtil, Keys, PublicKey};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use zap_stream_db::UserStream;
+
+/// Platform keys, one of them signs new events while the others are kept to update and
+/// delete the events they signed before a key rotation
+pub struct KeyRing {
+    /// Configured keys, the first one is the `nsec` setting
+    keys: Vec<Keys>,
+    /// Index of the key signing new events
+    active: AtomicUsize,
+}
+
+impl KeyRing {
+    pub fn new(nsec: &str, extra: &[String]) -> Result<Self> {
+        let mut keys = vec![Keys::from_str(nsec)?];
+        for k in extra {
+            let k = Keys::from_str(k)?;
+            if !keys.iter().any(|x| x.public_key == k.public_key) {
+                keys.push(k);
+            }
+        }
+        Ok(Self {
+            keys,
+            active: AtomicUsize::new(0),
+        })
+    }
+
+    /// Key configured as `nsec`, secrets derived from the platform key use it so they don't
+    /// change on rotation
+    pub fn primary(&self) -> &Keys {
+        &self.keys[0]
+    }
+
+    /// Key signing new events
+    pub fn active(&self) -> &Keys {
+        &self.keys[self.active.load(Ordering::Relaxed)]
+    }
+
+    pub fn get(&self, pubkey: &PublicKey) -> Option<&Keys> {
+        self.keys.iter().find(|k| k.public_key == *pubkey)
+    }
+
+    pub fn public_keys(&self) -> Vec<PublicKey> {
+        self.keys.iter().map(|k| k.public_key).collect()
+    }
+
+    /// Sign new events with [pubkey]
+    pub fn activate(&self, pubkey: &PublicKey) -> Result<()> {
+        match self.keys.iter().position(|k| k.public_key == *pubkey) {
+            Some(i) => {
+                self.active.store(i, Ordering::Relaxed);
+                Ok(())
+            }
+            None => bail!("Key {} is not configured", pubkey),
+        }
+    }
+
+    /// Key which signed the current event of [stream], streams which were not published
+    /// yet or whose key is no longer configured use the active key
+    pub fn stream_keys(&self, stream: &UserStream) -> &Keys {
+        stream
+            .event
+            .as_deref()
+            .and_then(|e| Event::from_json(e).ok())
+            .and_then(|e| self.get(&e.pubkey))
+            .unwrap_or(self.active())
+    }
+}
+
+impl ZapStreamOverseer {
+    /// Switch to the key activated in the database, the key may have been rotated by
+    /// another node
+    pub(super) async fn sync_signing_key(&self) -> Result<()> {
+        let pubkey = match self.db.get_active_signing_key().await? {
+            Some(p) => PublicKey::from_slice(&p)?,
+            None => return Ok(()),
+        };
+        if pubkey != self.keys.active().public_key {
+            self.keys.activate(&pubkey)?;
+            info!("Signing key changed to {}", pubkey);
+        }
+        Ok(())
+    }
+
+    /// Sign new events with [pubkey] on all nodes
+    pub(super) async fn activate_signing_key(&self, pubkey: &PublicKey) -> Result<()> {
+        if self.keys.get(pubkey).is_none() {
+            bail!("Key {} is not configured", pubkey);
+        }
+        self.db.activate_signing_key(&pubkey.to_bytes()).await?;
+        self.keys.activate(pubkey)?;
+        info!("Signing key changed to {}", pubkey);
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use crate::overseer::zap_stream::api::ApiResponse;
+use crate::overseer::zap_stream::{ZapStreamOverseer, WATCH_URL};
+use anyhow::{bail, Result};
+use http_body_util::combinators::BoxBody;
+use hyper::{Response, StatusCode};
+use nostr_sdk::ToBech32;
+use rand::Rng;
+use serde::Serialize;
+use url::Url;
+use uuid::Uuid;
+use zap_stream_db::{StreamLink, StreamVisibility, UserStream};
+
+/// Characters of short link codes, without look-alikes
+const LINK_ALPHABET: &[u8] = b"abcdefghijkmnpqrstuvwxyz23456789";
+
+/// Length of short link codes
+const LINK_CODE_LENGTH: usize = 7;
+
+/// Attempts at picking an unused code
+const MAX_CODE_ATTEMPTS: usize = 5;
+
+/// Short link of a stream
+#[derive(Serialize)]
+pub(super) struct ApiStreamLink {
+    code: String,
+    /// Redirects to the watch page
+    url: String,
+    /// Redirects to the embeddable player
+    embed_url: String,
+    clicks: u32,
+}
+
+fn link_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..LINK_CODE_LENGTH)
+        .map(|_| LINK_ALPHABET[rng.gen_range(0..LINK_ALPHABET.len())] as char)
+        .collect()
+}
+
+impl ZapStreamOverseer {
+    /// Short link of [stream], created on first use
+    pub(super) async fn stream_link(&self, stream: &UserStream) -> Result<ApiStreamLink> {
+        if stream.visibility != StreamVisibility::Public {
+            bail!("Unlisted streams have no short link");
+        }
+        let link = match self.db.get_stream_link(&stream.id).await? {
+            Some(l) => l,
+            None => self.create_stream_link(&stream.id).await?,
+        };
+        let mut url: Url = self.public_url.parse()?;
+        url.set_path(&format!("/s/{}", link.code));
+        Ok(ApiStreamLink {
+            embed_url: format!("{}/embed", url),
+            url: url.to_string(),
+            code: link.code,
+            clicks: link.clicks,
+        })
+    }
+
+    async fn create_stream_link(&self, stream_id: &str) -> Result<StreamLink> {
+        for _ in 0..MAX_CODE_ATTEMPTS {
+            self.db.insert_stream_link(stream_id, &link_code()).await?;
+            // also returns the link created by a concurrent request
+            if let Some(l) = self.db.get_stream_link(stream_id).await? {
+                return Ok(l);
+            }
+        }
+        bail!("Failed to create short link");
+    }
+
+    /// Count a click on the short link [code] and redirect to the watch page of its stream,
+    /// or the embeddable player if [embed]
+    pub(super) async fn follow_stream_link(&self, code: &str, embed: bool) -> Result<ApiResponse> {
+        let stream_id = match self.db.click_stream_link(code).await? {
+            Some(s) => s,
+            None => return Self::not_found(),
+        };
+        let stream = self.db.get_stream(&Uuid::parse_str(&stream_id)?).await?;
+        let naddr = self.stream_coordinate(&stream).to_bech32()?;
+        let location = if embed {
+            format!("{}/embed/{}", WATCH_URL, naddr)
+        } else {
+            format!("{}/{}", WATCH_URL, naddr)
+        };
+        Ok(Response::builder()
+            .status(StatusCode::FOUND)
+            .header("server", "zap-stream-core")
+            .header("location", location)
+            .body(BoxBody::default())?)
+    }
+}
@@ -0,0 +1,310 @@
+use crate::overseer::zap_stream::federation::ApiDirectoryStream;
+use crate::overseer::zap_stream::ZapStreamOverseer;
+use crate::replication::accept_replica;
+use anyhow::{bail, Result};
+use log::{info, warn};
+use m3u8_rs::Playlist;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio::task::AbortHandle;
+use url::Url;
+use uuid::Uuid;
+use zap_stream_db::{StreamVisibility, UserStreamState};
+
+/// Time between polls of the origin playlists, about a third of a segment
+const PULL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Failed polls in a row after which a mirror is stopped
+const MAX_PULL_FAILURES: u32 = 15;
+
+/// Max streams mirrored at once
+const MAX_MIRRORS: usize = 50;
+
+/// Max size of a playlist or segment pulled from an origin
+const MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Public live stream an origin instance agreed to have mirrored
+#[derive(Clone, Serialize, Deserialize)]
+pub(super) struct MirrorOffer {
+    stream_id: String,
+    title: Option<String>,
+    summary: Option<String>,
+    image: Option<String>,
+    starts: i64,
+    /// Hex pubkey of the streamer
+    pubkey: String,
+    /// Master playlist on the origin
+    playlist: String,
+}
+
+#[derive(Deserialize)]
+pub(super) struct MirrorRequest {
+    /// Base URL of the origin, one of the federation peers
+    peer: String,
+    stream_id: String,
+}
+
+/// Stream mirrored from a peer, [stream] is the local copy attributed to the origin
+#[derive(Serialize)]
+pub(super) struct ApiMirror {
+    #[serde(flatten)]
+    pub stream: ApiDirectoryStream,
+    pubkey: String,
+    /// Master playlist on the origin
+    origin: String,
+}
+
+struct Mirror {
+    offer: MirrorOffer,
+    /// Host of the origin instance
+    instance: String,
+    task: AbortHandle,
+}
+
+/// Streams pulled from peer instances and re-served from [ZapStreamOverseer::out_dir]
+#[derive(Clone, Default)]
+pub(super) struct Mirrors {
+    mirrors: Arc<RwLock<HashMap<Uuid, Mirror>>>,
+}
+
+impl ZapStreamOverseer {
+    /// Agree to have the live stream [stream_id] mirrored by a federation peer
+    pub(super) async fn mirror_offer(&self, stream_id: &Uuid) -> Result<MirrorOffer> {
+        let stream = self.db.get_stream(stream_id).await?;
+        if stream.state != UserStreamState::Live || stream.visibility != StreamVisibility::Public {
+            bail!("Only public live streams can be mirrored");
+        }
+        let user = self.db.get_user(stream.user_id).await?;
+        info!("Stream {} offered for mirroring", stream.id);
+        Ok(MirrorOffer {
+            playlist: self.map_to_public_url(&stream, "live.m3u8")?,
+            pubkey: hex::encode(&user.pubkey),
+            starts: stream.starts.timestamp(),
+            stream_id: stream.id,
+            title: stream.title,
+            summary: stream.summary,
+            image: stream.image,
+        })
+    }
+
+    /// Ask the peer for an agreement to mirror one of its streams, then pull and serve it
+    /// until the origin ends it
+    pub(super) async fn start_mirror(&self, req: &MirrorRequest) -> Result<ApiMirror> {
+        let federation = match &self.federation {
+            Some(f) => f,
+            None => bail!("Federation is not configured"),
+        };
+        let (peer, token) = match (federation.peer(&req.peer), federation.token()) {
+            (Some(p), Some(t)) => (p, t),
+            (None, _) => bail!("Not a federation peer"),
+            (_, None) => bail!("Federation token is not configured"),
+        };
+        let id = Uuid::parse_str(&req.stream_id)?;
+        if self.db.find_stream(&id).await?.is_some() {
+            bail!("Stream already exists on this instance");
+        }
+
+        let url = Url::parse(&format!(
+            "{}/api/v1/federation/mirror/{}",
+            peer.as_str().trim_end_matches('/'),
+            id
+        ))?;
+        let offer: MirrorOffer = federation
+            .client()
+            .post(url)
+            .bearer_auth(token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let playlist = Url::parse(&offer.playlist)?;
+        if offer.stream_id != id.to_string() || playlist.scheme() != "https" {
+            bail!("Invalid mirror offer");
+        }
+
+        let mut mirrors = self.mirrors.mirrors.write().await;
+        if mirrors.contains_key(&id) {
+            bail!("Stream is already mirrored");
+        }
+        if mirrors.len() >= MAX_MIRRORS {
+            bail!("Max {} streams can be mirrored", MAX_MIRRORS);
+        }
+        // the lock is held until the mirror is added, so a mirror ending right away
+        // can't be removed before it was added
+        let task = tokio::spawn(run_mirror(
+            federation.client().clone(),
+            PathBuf::from(&self.out_dir),
+            id,
+            playlist,
+            self.mirrors.clone(),
+        ));
+        let mirror = Mirror {
+            offer,
+            instance: peer.host_str().unwrap_or_default().to_string(),
+            task: task.abort_handle(),
+        };
+        let ret = self.api_mirror(&id, &mirror)?;
+        mirrors.insert(id, mirror);
+        info!("Mirroring stream {} from {}", id, peer);
+        Ok(ret)
+    }
+
+    /// Stop mirroring [stream_id] and remove the local copy
+    pub(super) async fn stop_mirror(&self, stream_id: &Uuid) -> Result<()> {
+        let mirror = match self.mirrors.mirrors.write().await.remove(stream_id) {
+            Some(m) => m,
+            None => bail!("Stream is not mirrored"),
+        };
+        mirror.task.abort();
+        remove_mirror_files(Path::new(&self.out_dir), stream_id).await;
+        info!("Stopped mirroring stream {}", stream_id);
+        Ok(())
+    }
+
+    pub(super) async fn list_mirrors(&self) -> Result<Vec<ApiMirror>> {
+        self.mirrors
+            .mirrors
+            .read()
+            .await
+            .iter()
+            .map(|(id, m)| self.api_mirror(id, m))
+            .collect()
+    }
+
+    /// Mirrored streams listed in the stream directory
+    pub(super) async fn mirror_streams(&self) -> Result<Vec<ApiDirectoryStream>> {
+        Ok(self
+            .list_mirrors()
+            .await?
+            .into_iter()
+            .map(|m| m.stream)
+            .collect())
+    }
+
+    fn api_mirror(&self, id: &Uuid, mirror: &Mirror) -> Result<ApiMirror> {
+        let u: Url = self.public_url.parse()?;
+        let o = &mirror.offer;
+        Ok(ApiMirror {
+            stream: ApiDirectoryStream {
+                id: o.stream_id.clone(),
+                title: o.title.clone(),
+                summary: o.summary.clone(),
+                image: o.image.clone(),
+                starts: o.starts,
+                url: u.join(&format!("/{}/live.m3u8", id))?.to_string(),
+                instance: Some(mirror.instance.clone()),
+            },
+            pubkey: o.pubkey.clone(),
+            origin: o.playlist.clone(),
+        })
+    }
+}
+
+/// Pull the stream at [playlist] into [out_dir] until the origin ends it or stops
+/// answering
+async fn run_mirror(
+    client: reqwest::Client,
+    out_dir: PathBuf,
+    id: Uuid,
+    playlist: Url,
+    mirrors: Mirrors,
+) {
+    let mut failures = 0;
+    loop {
+        match pull_stream(&client, &out_dir, &id, &playlist).await {
+            Ok(true) => {
+                info!("Mirrored stream {} ended", id);
+                break;
+            }
+            Ok(false) => failures = 0,
+            Err(e) => {
+                failures += 1;
+                if failures >= MAX_PULL_FAILURES {
+                    warn!("Stopped mirroring stream {}: {}", id, e);
+                    break;
+                }
+            }
+        }
+        tokio::time::sleep(PULL_INTERVAL).await;
+    }
+    mirrors.mirrors.write().await.remove(&id);
+    remove_mirror_files(&out_dir, &id).await;
+}
+
+/// Copy the playlists of [master] and any segments not pulled yet into [out_dir], returns
+/// true once the origin ended the stream
+///
+/// Files are written like replicas so segments which left the playlist window are removed
+async fn pull_stream(
+    client: &reqwest::Client,
+    out_dir: &Path,
+    id: &Uuid,
+    master: &Url,
+) -> Result<bool> {
+    let data = fetch(client, master).await?;
+    let variants = match m3u8_rs::parse_playlist_res(&data) {
+        Ok(Playlist::MasterPlaylist(pl)) => pl.variants,
+        _ => bail!("Invalid master playlist"),
+    };
+    let mut ended = false;
+    for v in variants {
+        let rel = relative_path(&v.uri)?;
+        let dir = rel.parent().unwrap_or(Path::new("")).to_path_buf();
+        let url = master.join(&v.uri)?;
+        let pl_data = fetch(client, &url).await?;
+        let pl = match m3u8_rs::parse_media_playlist_res(&pl_data) {
+            Ok(pl) => pl,
+            Err(_) => bail!("Invalid media playlist"),
+        };
+        for s in &pl.segments {
+            // init segments (fMP4) are pulled before the first segment using them
+            for uri in s.map.iter().map(|m| &m.uri).chain([&s.uri]) {
+                let dst = format!("{}/{}", id, dir.join(relative_path(uri)?).display());
+                if tokio::fs::try_exists(out_dir.join(&dst)).await? {
+                    continue;
+                }
+                let seg = fetch(client, &url.join(uri)?).await?;
+                accept_replica(out_dir, &dst, &seg).await?;
+            }
+        }
+        accept_replica(out_dir, &format!("{}/{}", id, rel.display()), &pl_data).await?;
+        ended |= pl.end_list;
+    }
+    accept_replica(out_dir, &format!("{}/live.m3u8", id), &data).await?;
+    Ok(ended)
+}
+
+async fn fetch(client: &reqwest::Client, url: &Url) -> Result<Vec<u8>> {
+    let rsp = client.get(url.clone()).send().await?.error_for_status()?;
+    if rsp.content_length().is_some_and(|l| l > MAX_FILE_SIZE) {
+        bail!("{} is larger than {} bytes", url, MAX_FILE_SIZE);
+    }
+    let data = rsp.bytes().await?;
+    if data.len() as u64 > MAX_FILE_SIZE {
+        bail!("{} is larger than {} bytes", url, MAX_FILE_SIZE);
+    }
+    Ok(data.to_vec())
+}
+
+/// Path of a playlist entry relative to its playlist, only plain relative paths are
+/// mirrored so the copy can't be written outside the stream dir
+fn relative_path(uri: &str) -> Result<PathBuf> {
+    let p = Path::new(uri);
+    if uri.contains("://")
+        || uri.contains('?')
+        || p.components().any(|c| !matches!(c, Component::Normal(_)))
+    {
+        bail!("Unsupported playlist entry {}", uri);
+    }
+    Ok(p.to_path_buf())
+}
+
+async fn remove_mirror_files(out_dir: &Path, id: &Uuid) {
+    // nothing was written when the first pull failed
+    let _ = tokio::fs::remove_dir_all(out_dir.join(id.to_string())).await;
+}
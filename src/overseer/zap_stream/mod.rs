@@ -0,0 +1,1723 @@
+use crate::blossom::{BlobDescriptor, Blossom};
+#[cfg(feature = "chaos")]
+use crate::chaos::Chaos;
+use crate::egress::hls::HlsEgress;
+use crate::egress::recorder::RecordingFile;
+use crate::egress::EgressConfig;
+use crate::ingress::file::FileInput;
+use crate::ingress::{ConnectionInfo, EndpointStats, PulledInput, Rejection};
+use crate::overseer::zap_stream::ban::IpBanList;
+use crate::overseer::zap_stream::burn::{BurnMonitor, BurnSpike};
+use crate::overseer::zap_stream::federation::Federation;
+use crate::overseer::zap_stream::keys::KeyRing;
+use crate::overseer::zap_stream::mirror::Mirrors;
+use crate::overseer::zap_stream::p2p::P2pSwarms;
+use crate::overseer::zap_stream::payments::{payment_providers, PaymentProvider};
+use crate::overseer::zap_stream::playback::PlaybackMonitor;
+use crate::overseer::zap_stream::routing::IngestRouter;
+use crate::overseer::zap_stream::topup::{spawn_invoice_listener, ExchangeRates};
+use crate::overseer::zap_stream::viewers::ViewerCounter;
+use crate::overseer::zap_stream::webhooks::StreamChange;
+use crate::overseer::{get_default_variants, IngressInfo, IngressStreamType, Overseer};
+use crate::pipeline::timing::SegmentTiming;
+use crate::pipeline::watermark::Watermark;
+use crate::pipeline::{EgressType, PipelineConfig, PipelineControl};
+use crate::replication::Replicator;
+use crate::settings::{AudioCodec, OverseerConfig, Settings};
+use crate::status::{ComponentStatus, ServerStatus, StatusIncident};
+use crate::variant::video::{EncoderParams, VideoEncoder};
+use crate::variant::{StreamMapping, VariantStream};
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use fedimint_tonic_lnd::verrpc::VersionRequest;
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVCodecID::AV_CODEC_ID_MJPEG;
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::{av_pix_fmt_desc_get, avcodec_get_name, AVFrame};
+use ffmpeg_rs_raw::{rstr, Encoder};
+use futures_util::future::join_all;
+use futures_util::FutureExt;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::{Method, Request, Response};
+use log::{error, info, warn};
+use nostr_sdk::bitcoin::PrivateKey;
+use nostr_sdk::prelude::Coordinate;
+use nostr_sdk::{
+    Client, Event, EventBuilder, EventId, Filter, JsonUtil, Kind, PublicKey, RelayStatus, Tag,
+    ToBech32,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env::temp_dir;
+use std::fmt::{Display, Formatter};
+use std::fs::create_dir_all;
+use std::mem::transmute;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{Mutex, RwLock};
+use url::Url;
+use uuid::Uuid;
+use zap_stream_db::sqlx::Encode;
+use zap_stream_db::{
+    EgressSource, IngestEndpoint, IngestEndpointVariant, StreamConnection, StreamEgress,
+    StreamIncident, StreamVisibility, UsageAlert, User, UserStream, UserStreamState, ZapStreamDb,
+};
+
+mod alerts;
+mod api;
+mod ban;
+mod burn;
+mod chat;
+mod control;
+mod duplicates;
+mod federation;
+mod highlights;
+mod keys;
+mod links;
+mod mirror;
+mod p2p;
+mod payments;
+mod playback;
+mod premiere;
+mod restream;
+mod routing;
+mod topup;
+mod usage;
+mod viewers;
+mod vod;
+mod webhooks;
+mod zaps;
+
+const STREAM_EVENT_KIND: u16 = 30_311;
+
+/// How often relays are checked for stale live events
+const DUPLICATE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// Max time to wait for relays to return old versions of a stream event when republishing
+const REPUBLISH_FETCH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// N94 stream announcement, segments uploaded to blossom reference the latest one
+const N94_STREAM_KIND: u16 = 1_053;
+
+/// Web client linked from stream events and short links
+const WATCH_URL: &str = "https://zap.stream";
+
+/// Default seconds without a new segment before a live stream is considered dead
+const DEFAULT_STREAM_TIMEOUT: u64 = 120;
+
+/// Number of recent incidents shown on the status page
+const STATUS_INCIDENTS: u64 = 10;
+
+/// Segment timings kept per active stream (all variants), 10 minutes of a 4 variant
+/// ladder with 2s segments
+const SEGMENT_TIMINGS: usize = 1200;
+
+/// File name of the trimmed recording in the stream dir
+const TRIMMED_RECORDING: &str = "recording-trim.mp4";
+
+/// Custom slate files users can upload, used when the stream controller turns on
+/// [PipelineControl::slate]
+///
+/// The reconnect (failover) window is not covered, see [PipelineControl::slate]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SlateFile {
+    /// Image shown instead of the video
+    Image,
+    /// Audio looped instead of the input audio
+    Audio,
+}
+
+impl Display for SlateFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SlateFile::Image => write!(f, "image"),
+            SlateFile::Audio => write!(f, "audio"),
+        }
+    }
+}
+
+impl FromStr for SlateFile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "image" => Ok(SlateFile::Image),
+            "audio" => Ok(SlateFile::Audio),
+            _ => bail!("Unknown slate file: {}", s),
+        }
+    }
+}
+
+/// Owner of a watermark image uploaded with the admin API, the watermark of a user
+/// replaces the one of the endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WatermarkOwner {
+    Endpoint(u64),
+    User(u64),
+}
+
+impl Display for WatermarkOwner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WatermarkOwner::Endpoint(id) => write!(f, "endpoint-{}", id),
+            WatermarkOwner::User(id) => write!(f, "user-{}", id),
+        }
+    }
+}
+
+/// zap.stream NIP-53 overseer
+pub struct ZapStreamOverseer {
+    /// Dir where HTTP server serves files from
+    out_dir: String,
+    /// Database instance for accounts/streams
+    db: ZapStreamDb,
+    /// LND node connection, not connected in dry run
+    lnd: Option<fedimint_tonic_lnd::Client>,
+    /// Nostr client for publishing events
+    client: Client,
+    /// Nostr keys used to sign events
+    keys: Arc<KeyRing>,
+    /// List of blossom servers to upload segments to
+    blossom_servers: Vec<Blossom>,
+    /// Public facing URL pointing to [out_dir]
+    public_url: String,
+    /// Ingest endpoints users can stream to
+    endpoints: Vec<String>,
+    /// Cost / second / variant
+    cost: i64,
+    /// Balance credits at or above this amount require a second admin to approve
+    approval_threshold: Option<i64>,
+    /// IPs banned for repeated failed connection attempts
+    bans: IpBanList,
+    /// Raises usage alerts for users debited faster than the configured rate
+    burn_monitor: Option<BurnMonitor>,
+    /// Cost / GB served to viewers
+    egress_cost: i64,
+    /// Signaling of peer-assisted delivery
+    p2p: Option<Arc<P2pSwarms>>,
+    /// Archive the chat of streams when they end
+    chat_archive: bool,
+    /// Live streams of peer instances listed in the stream directory
+    federation: Option<Federation>,
+    /// Streams of peer instances pulled and re-served by this instance
+    mirrors: Mirrors,
+    /// Pushes HLS output to peer nodes
+    replicator: Option<Replicator>,
+    /// Recommends ingest nodes to streamers
+    ingest_router: Option<IngestRouter>,
+    /// Live streams which have not produced a segment for this long are ended
+    stream_timeout: chrono::Duration,
+    /// Live streams whose encoder disconnected are kept this long to be resumed
+    reconnect_window: Option<chrono::Duration>,
+    /// Record with a dedicated CRF encoder instead of the live variants
+    recording_crf: Option<u8>,
+    /// Remux finished recordings into MP4s and publish them
+    recording_mp4: bool,
+    /// Codec of the transcoded audio variant
+    audio_codec: AudioCodec,
+    /// See [PipelineConfig::settle_window]
+    settle_window: f32,
+    /// See [PipelineConfig::failover_window]
+    failover_window: u64,
+    /// See [PipelineConfig::program_date_time_anchor]
+    program_date_time_anchor: u64,
+    /// Encoder of the transcoded video variants
+    video_encoder: VideoEncoder,
+    /// External payment processors accepting topups, by name in the webhook path
+    payment_providers: HashMap<String, Box<dyn PaymentProvider>>,
+    /// Exchange rates for fiat topup quotes
+    exchange_rates: ExchangeRates,
+    /// Currently active streams
+    /// Any streams which are not contained in this set are dead
+    active_streams: Arc<RwLock<HashSet<Uuid>>>,
+    /// Visibility of active streams, checked on every playback request
+    stream_visibility: Arc<RwLock<HashMap<Uuid, StreamVisibility>>>,
+    /// Runtime changes (slate, recording) requested for active streams
+    stream_controls: Arc<RwLock<HashMap<Uuid, PipelineControl>>>,
+    /// Active streams with recording paused because the disk is almost full
+    recording_paused: Arc<RwLock<HashSet<Uuid>>>,
+    /// Viewer buffer health of active streams, adjusts their playlist window
+    playback: PlaybackMonitor,
+    /// Distinct viewers of active streams, published in their stream events
+    viewers: ViewerCounter,
+    /// Latest ingest link statistics of active streams
+    endpoint_stats: Arc<RwLock<HashMap<Uuid, EndpointStats>>>,
+    /// Timing of the latest segments of active streams, see [SEGMENT_TIMINGS]
+    segment_timings: Arc<RwLock<HashMap<Uuid, VecDeque<SegmentTiming>>>>,
+    /// Egress (bytes, requests) by stream not yet written to the database
+    egress: Arc<RwLock<HashMap<Uuid, (u64, u64)>>>,
+    /// Last check for stale live events on relays
+    last_duplicate_check: Arc<RwLock<Option<Instant>>>,
+    /// Latest N94 stream event of active streams
+    n94_events: Arc<RwLock<HashMap<Uuid, EventId>>>,
+    /// Short-lived tokens for stream controllers (token, (user id, expires)), not persisted so
+    /// a restart revokes all of them
+    control_tokens: Arc<RwLock<HashMap<String, (u64, DateTime<Utc>)>>>,
+    /// Streams pulled for users which are waiting for their pipeline
+    restreams: Mutex<Vec<PulledInput>>,
+    /// Simulate lightning, log events instead of publishing them and charge streams to
+    /// the shadow ledger
+    dry_run: bool,
+    /// Fault injection hooks
+    #[cfg(feature = "chaos")]
+    chaos: Arc<Chaos>,
+}
+
+impl ZapStreamOverseer {
+    /// Overseer configured by the `zap-stream` [OverseerConfig::ZapStream] of [settings]
+    pub async fn new(settings: &Settings) -> Result<Self> {
+        let OverseerConfig::ZapStream {
+            nsec: private_key,
+            extra_nsec: extra_keys,
+            database: db,
+            database_replica: db_replica,
+            lnd,
+            relays,
+            blossom: blossom_servers,
+            cost,
+            approval_threshold,
+            ip_ban,
+            ingest_routing,
+            stream_timeout,
+            reconnect_window,
+            recording_crf,
+            recording_mp4,
+            audio_codec,
+            payments,
+            exchange_rates,
+            burn_alert,
+            egress_cost,
+            p2p,
+            chat_archive,
+            federation,
+            ..
+        } = &settings.overseer
+        else {
+            bail!("Overseer is not configured as zap-stream");
+        };
+        let out_dir = &settings.output_dir;
+        let dry_run = settings.dry_run.unwrap_or(false);
+        let mut db = ZapStreamDb::new(db).await?;
+        db.migrate().await?;
+        if let Some(r) = db_replica {
+            db = db.with_replica(r).await?;
+        }
+        if dry_run {
+            warn!("Dry run: lightning is simulated, events are not published and stream charges go to the shadow ledger");
+            db = db.with_shadow_ledger();
+        }
+
+        let lnd = if dry_run {
+            None
+        } else {
+            let mut lnd = fedimint_tonic_lnd::connect(
+                lnd.address.clone(),
+                PathBuf::from(&lnd.cert),
+                PathBuf::from(&lnd.macaroon),
+            )
+            .await?;
+
+            let version = lnd
+                .versioner()
+                .get_version(VersionRequest::default())
+                .await?;
+            info!("LND connected: v{}", version.into_inner().version);
+            Some(lnd)
+        };
+
+        let keys = KeyRing::new(private_key, extra_keys.as_deref().unwrap_or(&[]))?;
+        if let Some(pk) = db.get_active_signing_key().await? {
+            if let Err(e) = keys.activate(&PublicKey::from_slice(&pk)?) {
+                warn!("Active signing key is not configured: {}", e);
+            }
+        }
+        info!(
+            "Signing events with {}",
+            keys.active().public_key.to_bech32()?
+        );
+        let client = nostr_sdk::ClientBuilder::new()
+            .signer(keys.active().clone())
+            .build();
+        for r in relays {
+            client.add_relay(r).await?;
+        }
+        client.connect().await;
+        #[cfg(feature = "chaos")]
+        let chaos = Arc::new(Chaos::default());
+        if let Some(lnd) = &lnd {
+            spawn_invoice_listener(
+                lnd.clone(),
+                db.clone(),
+                #[cfg(feature = "chaos")]
+                chaos.clone(),
+            );
+        }
+
+        Ok(Self {
+            out_dir: out_dir.clone(),
+            db,
+            lnd,
+            client,
+            keys: Arc::new(keys),
+            blossom_servers: blossom_servers
+                .as_ref()
+                .unwrap_or(&Vec::new())
+                .into_iter()
+                .map(|b| Blossom::new(b))
+                .collect(),
+            public_url: settings.public_url.clone(),
+            endpoints: settings.endpoints.clone(),
+            cost: *cost,
+            approval_threshold: *approval_threshold,
+            bans: IpBanList::new(ip_ban.clone().unwrap_or_default()),
+            burn_monitor: burn_alert.clone().map(BurnMonitor::new),
+            egress_cost: egress_cost.unwrap_or(0),
+            p2p: p2p.clone().map(|p| Arc::new(P2pSwarms::new(p))),
+            chat_archive: chat_archive.unwrap_or(false),
+            federation: federation.as_ref().map(Federation::new).transpose()?,
+            mirrors: Mirrors::default(),
+            replicator: match &settings.replication {
+                Some(r) if !r.peers.is_empty() => Some(Replicator::new(out_dir, r)?),
+                _ => None,
+            },
+            ingest_router: match ingest_routing {
+                Some(r) => Some(IngestRouter::new(r)?),
+                None => None,
+            },
+            stream_timeout: chrono::Duration::seconds(
+                stream_timeout.unwrap_or(DEFAULT_STREAM_TIMEOUT) as i64,
+            ),
+            reconnect_window: reconnect_window.map(|w| chrono::Duration::seconds(w as i64)),
+            recording_crf: *recording_crf,
+            recording_mp4: recording_mp4.unwrap_or(false),
+            audio_codec: audio_codec.unwrap_or_default(),
+            settle_window: settings.settle_window(),
+            failover_window: settings.failover_window(),
+            program_date_time_anchor: settings.program_date_time_anchor(),
+            video_encoder: settings.video_encoder(),
+            payment_providers: payment_providers(payments).await?,
+            exchange_rates: ExchangeRates::new(exchange_rates.clone().unwrap_or_default())?,
+            active_streams: Arc::new(RwLock::new(HashSet::new())),
+            stream_visibility: Arc::new(RwLock::new(HashMap::new())),
+            stream_controls: Arc::new(RwLock::new(HashMap::new())),
+            control_tokens: Arc::new(RwLock::new(HashMap::new())),
+            restreams: Mutex::new(Vec::new()),
+            n94_events: Arc::new(RwLock::new(HashMap::new())),
+            recording_paused: Arc::new(RwLock::new(HashSet::new())),
+            endpoint_stats: Arc::new(RwLock::new(HashMap::new())),
+            segment_timings: Arc::new(RwLock::new(HashMap::new())),
+            playback: PlaybackMonitor::new(),
+            viewers: ViewerCounter::default(),
+            egress: Arc::new(RwLock::new(HashMap::new())),
+            last_duplicate_check: Arc::new(RwLock::new(None)),
+            dry_run,
+            #[cfg(feature = "chaos")]
+            chaos,
+        })
+    }
+
+    fn stream_to_event_builder(&self, stream: &UserStream) -> Result<EventBuilder> {
+        let kind = Kind::from(STREAM_EVENT_KIND);
+        Ok(EventBuilder::new(kind, "", self.stream_tags(stream)?))
+    }
+
+    /// Tags describing [stream], shared by the NIP-53 and N94 stream events
+    fn stream_tags(&self, stream: &UserStream) -> Result<Vec<Tag>> {
+        let mut tags = vec![
+            Tag::parse(&["d".to_string(), stream.id.to_string()])?,
+            Tag::parse(&["status".to_string(), stream.state.to_string()])?,
+            Tag::parse(&["starts".to_string(), stream.starts.timestamp().to_string()])?,
+        ];
+        if let Some(ref ends) = stream.ends {
+            tags.push(Tag::parse(&[
+                "ends".to_string(),
+                ends.timestamp().to_string(),
+            ])?);
+        }
+        if let Some(ref title) = stream.title {
+            tags.push(Tag::parse(&["title".to_string(), title.to_string()])?);
+        }
+        if let Some(ref summary) = stream.summary {
+            tags.push(Tag::parse(&["summary".to_string(), summary.to_string()])?);
+        }
+        if let Some(ref image) = stream.image {
+            tags.push(Tag::parse(&["image".to_string(), image.to_string()])?);
+        }
+        if let Some(ref thumb) = stream.thumb {
+            tags.push(Tag::parse(&["thumb".to_string(), thumb.to_string()])?);
+        }
+        if let Some(ref content_warning) = stream.content_warning {
+            tags.push(Tag::parse(&[
+                "content_warning".to_string(),
+                content_warning.to_string(),
+            ])?);
+        }
+        if stream.state == UserStreamState::Ended {
+            tags.push(Tag::parse(&[
+                "duration".to_string(),
+                (stream.duration.round() as u64).to_string(),
+            ])?);
+            if let Some(peak) = stream.peak_viewers {
+                tags.push(Tag::parse(&[
+                    "peak_participants".to_string(),
+                    peak.to_string(),
+                ])?);
+            }
+            if let Some(avg) = stream.avg_viewers {
+                tags.push(Tag::parse(&[
+                    "avg_participants".to_string(),
+                    format!("{:.1}", avg),
+                ])?);
+            }
+        }
+        if let Some(ref goal) = stream.goal {
+            tags.push(Tag::parse(&["goal".to_string(), goal.to_string()])?);
+        }
+        if let Some(ref pinned) = stream.pinned {
+            tags.push(Tag::parse(&["pinned".to_string(), pinned.to_string()])?);
+        }
+        if let Some(ref tags_csv) = stream.tags {
+            for tag in tags_csv.split(',') {
+                tags.push(Tag::parse(&["t".to_string(), tag.to_string()])?);
+            }
+        }
+
+        let coord = self.stream_coordinate(stream);
+        tags.push(Tag::parse(&[
+            "alt",
+            &format!("Watch live on {}/{}", WATCH_URL, coord.to_bech32()?),
+        ])?);
+        Ok(tags)
+    }
+
+    /// Address of the NIP-53 event of [stream]
+    fn stream_coordinate(&self, stream: &UserStream) -> Coordinate {
+        Coordinate::new(
+            Kind::from(STREAM_EVENT_KIND),
+            self.keys.stream_keys(stream).public_key,
+        )
+        .identifier(&stream.id)
+    }
+
+    fn blob_to_event_builder(&self, stream: &BlobDescriptor) -> Result<EventBuilder> {
+        let tags = if let Some(tags) = stream.nip94.as_ref() {
+            tags.iter()
+                .map_while(|(k, v)| Tag::parse(&[k, v]).ok())
+                .collect()
+        } else {
+            let mut tags = vec![
+                Tag::parse(&["x", &stream.sha256])?,
+                Tag::parse(&["url", &stream.url])?,
+                Tag::parse(&["size", &stream.size.to_string()])?,
+            ];
+            if let Some(m) = stream.mime_type.as_ref() {
+                tags.push(Tag::parse(&["m", m])?)
+            }
+            tags
+        };
+
+        Ok(EventBuilder::new(Kind::FileMetadata, "", tags))
+    }
+
+    /// Sign the current stream event (kind 30311) of [stream]
+    fn sign_stream_event(&self, stream: &UserStream, pubkey: &Vec<u8>) -> Result<Event> {
+        let mut extra_tags = vec![
+            Tag::parse(&["p", hex::encode(pubkey).as_str(), "", "host"])?,
+            Tag::parse(&[
+                "streaming",
+                self.map_to_public_url(stream, "live.m3u8")?.as_str(),
+            ])?,
+            Tag::parse(&[
+                "image",
+                self.map_to_public_url(stream, "thumb.webp")?.as_str(),
+            ])?,
+        ];
+        if stream.state == UserStreamState::Live {
+            if let Some(n) = self.viewers.current(&Uuid::parse_str(&stream.id)?) {
+                extra_tags.push(Tag::parse(&["current_participants", &n.to_string()])?);
+            }
+        }
+        // flag NIP94 streaming when using blossom servers
+        if self.blossom_servers.len() > 0 {
+            extra_tags.push(Tag::parse(&["streaming", "nip94"])?);
+        }
+        Ok(self
+            .stream_to_event_builder(stream)?
+            .add_tags(extra_tags)
+            .sign_with_keys(self.keys.stream_keys(stream))?)
+    }
+
+    /// Sign the stream event of [stream], publish it to relays if the stream is public and
+    /// notify the user's webhook about the [change]
+    async fn publish_stream_event(
+        &self,
+        stream: &UserStream,
+        user: &User,
+        change: StreamChange,
+    ) -> Result<Event> {
+        let pubkey = &user.pubkey;
+        let ev = self.sign_stream_event(stream, pubkey)?;
+        // unlisted streams are only reachable with a share link
+        if stream.visibility == StreamVisibility::Public {
+            self.send_event(ev.clone()).await?;
+            if !self.blossom_servers.is_empty() {
+                self.publish_n94_stream(stream, pubkey).await?;
+            }
+        }
+        self.send_stream_webhook(user, stream, change, &ev);
+        Ok(ev)
+    }
+
+    /// Rebuild the stream event of [stream] and broadcast it to all relays again, older
+    /// versions still held by relays are deleted (NIP-09)
+    ///
+    /// Returns the new event and the number of stale events deleted
+    pub(super) async fn republish_stream(&self, stream: &mut UserStream) -> Result<(Event, usize)> {
+        if stream.visibility != StreamVisibility::Public {
+            bail!("Unlisted streams are not published to relays");
+        }
+        let user = self.db.get_user(stream.user_id).await?;
+        let keys = self.keys.stream_keys(stream);
+        let ev = self.sign_stream_event(stream, &user.pubkey)?;
+
+        let filter = Filter::new()
+            .kind(Kind::from(STREAM_EVENT_KIND))
+            .author(keys.public_key)
+            .identifier(&stream.id);
+        let stale: Vec<EventId> = self
+            .client
+            .fetch_events(vec![filter], Some(REPUBLISH_FETCH_TIMEOUT))
+            .await?
+            .into_iter()
+            .filter(|e| e.id != ev.id && e.created_at < ev.created_at)
+            .map(|e| e.id)
+            .collect();
+
+        self.send_event(ev.clone()).await?;
+        if !stale.is_empty() {
+            let delete = EventBuilder::delete(stale.clone()).sign_with_keys(keys)?;
+            self.send_event(delete).await?;
+        }
+        if !self.blossom_servers.is_empty() && stream.state != UserStreamState::Ended {
+            self.publish_n94_stream(stream, &user.pubkey).await?;
+        }
+        stream.event = Some(ev.as_json());
+        self.db.update_stream(stream).await?;
+        Ok((ev, stale.len()))
+    }
+
+    /// Announce [stream] as an N94 stream, segments uploaded to blossom are linked to the
+    /// latest announcement so clients can play the stream without the platform CDN
+    async fn publish_n94_stream(&self, stream: &UserStream, pubkey: &Vec<u8>) -> Result<()> {
+        let ev = EventBuilder::new(Kind::from(N94_STREAM_KIND), "", self.stream_tags(stream)?)
+            .add_tags([
+                Tag::parse(&["p", hex::encode(pubkey).as_str(), "", "host"])?,
+                Tag::parse(&["a", &self.stream_coordinate(stream).to_string()])?,
+            ])
+            .sign_with_keys(self.keys.stream_keys(stream))?;
+        let id = Uuid::parse_str(&stream.id)?;
+        if stream.state == UserStreamState::Ended {
+            self.n94_events.write().await.remove(&id);
+        } else {
+            self.n94_events.write().await.insert(id, ev.id);
+        }
+        self.send_event(ev).await
+    }
+
+    /// Upload a segment to all blossom servers at once and publish it as an N94 segment
+    ///
+    /// Failed uploads are logged but don't end the stream, the platform CDN still serves it
+    async fn publish_n94_segment(
+        &self,
+        stream: &UserStream,
+        variant_id: &Uuid,
+        index: u64,
+        duration: f32,
+        path: &PathBuf,
+    ) -> Result<()> {
+        let mime = if path.extension().is_some_and(|e| e == "m4s") {
+            "video/iso.segment"
+        } else {
+            "video/mp2t"
+        };
+        let uploads = self.blossom_servers.iter().map(|b| async move {
+            #[cfg(feature = "chaos")]
+            self.chaos.fail_blossom_upload().await?;
+            b.upload(path, self.keys.active(), Some(mime)).await
+        });
+        let mut blobs = vec![];
+        for res in join_all(uploads).await {
+            match res {
+                Ok(b) => blobs.push(b),
+                Err(e) => warn!("Failed to upload segment {}: {}", path.display(), e),
+            }
+        }
+        let blob = match blobs.first() {
+            Some(b) => b,
+            None => return Ok(()),
+        };
+
+        let mut n94 = self.blob_to_event_builder(blob)?.add_tags([
+            Tag::parse(&["a", &self.stream_coordinate(stream).to_string()])?,
+            Tag::parse(&["d", variant_id.to_string().as_str()])?,
+            Tag::parse(&["index", index.to_string().as_str()])?,
+            Tag::parse(&["duration", duration.to_string().as_str()])?,
+        ]);
+        let stream_id = Uuid::parse_str(&stream.id)?;
+        if let Some(ev) = self.n94_events.read().await.get(&stream_id) {
+            n94 = n94.add_tags(Tag::parse(&["e", &ev.to_hex()]));
+        }
+        for b in blobs.iter().skip(1) {
+            n94 = n94.add_tags(Tag::parse(&["url", &b.url]));
+        }
+        let n94 = n94.sign_with_keys(self.keys.stream_keys(stream))?;
+        #[cfg(feature = "chaos")]
+        if self.chaos.drop_relay_publish().await {
+            warn!("Chaos: dropped relay publish {}", n94.id);
+            return Ok(());
+        }
+        if self.dry_run {
+            info!("Dry run: not publishing {}", n94.as_json());
+            return Ok(());
+        }
+        let cc = self.client.clone();
+        tokio::spawn(async move {
+            if let Err(e) = cc.send_event(n94).await {
+                warn!("Error sending event: {}", e);
+            }
+        });
+        info!("Published N94 segment to {}", blob.url);
+        Ok(())
+    }
+
+    /// Move a stream to a new state, publishing the updated stream event
+    ///
+    /// Returns false without publishing anything if another caller already performed this
+    /// transition, so each state change is published by one caller
+    async fn transition_stream(
+        &self,
+        stream: &mut UserStream,
+        to: UserStreamState,
+        ends: Option<DateTime<Utc>>,
+    ) -> Result<bool> {
+        if stream.state == to {
+            return Ok(false);
+        }
+        if !self
+            .db
+            .transition_stream(&stream.id, stream.state, to, ends)
+            .await?
+        {
+            return Ok(false);
+        }
+        // reload to pick up fields set by the transition (ends)
+        *stream = self.db.get_stream(&Uuid::parse_str(&stream.id)?).await?;
+        self.publish_state_event(stream).await?;
+        Ok(true)
+    }
+
+    /// Publish the stream event of the current state of [stream], clearing the pending event
+    /// marker set by the state transition. Streams left pending are retried by
+    /// [Overseer::check_streams]
+    async fn publish_state_event(&self, stream: &mut UserStream) -> Result<()> {
+        let user = self.db.get_user(stream.user_id).await?;
+        let change = if stream.state == UserStreamState::Ended {
+            StreamChange::End
+        } else {
+            StreamChange::Update
+        };
+        let event = self
+            .publish_stream_event(stream, &user, change)
+            .await?
+            .as_json();
+        self.db.set_stream_event(&stream.id, &event).await?;
+        stream.event = Some(event);
+        stream.event_pending = false;
+        Ok(())
+    }
+
+    /// Replace the transcoded video and audio variant of [variants] with one pair per rung of
+    /// the endpoint [ladder], rungs taller than the input are skipped and audio-only rungs
+    /// get a group with just the audio variant
+    fn ladder_variants(
+        variants: Vec<VariantStream>,
+        ladder: &[IngestEndpointVariant],
+        stream_info: &IngressInfo,
+    ) -> Vec<VariantStream> {
+        let video = variants.iter().find_map(|v| match v {
+            VariantStream::Video(v) => Some(v.clone()),
+            _ => None,
+        });
+        let audio = variants.iter().find_map(|v| match v {
+            VariantStream::Audio(a) => Some(a.clone()),
+            _ => None,
+        });
+        let video = match video {
+            Some(v) => v,
+            None => return variants,
+        };
+        let src_height = stream_info
+            .streams
+            .iter()
+            .find(|s| s.stream_type == IngressStreamType::Video)
+            .map_or(0, |s| s.height);
+
+        let mut ret: Vec<VariantStream> = variants
+            .into_iter()
+            .filter(|v| !matches!(v, VariantStream::Video(_) | VariantStream::Audio(_)))
+            .collect();
+        let group = ret.iter().map(|v| v.group_id()).max().unwrap_or(0) + 1;
+        for (i, rung) in ladder
+            .iter()
+            .filter(|r| r.audio_only || r.height as usize <= src_height)
+            .enumerate()
+        {
+            if rung.audio_only {
+                if let Some(a) = &audio {
+                    let mut a = a.clone();
+                    a.mapping.id = Uuid::new_v4();
+                    a.mapping.group_id = group + i;
+                    a.bitrate = rung.bitrate;
+                    ret.push(VariantStream::Audio(a));
+                }
+                continue;
+            }
+            let mut v = video.clone();
+            v.mapping.id = Uuid::new_v4();
+            v.mapping.group_id = group + i;
+            v.width = rung.width as u16;
+            v.height = rung.height as u16;
+            v.bitrate = rung.bitrate;
+            v.fps = rung.fps.unwrap_or(video.fps);
+            v.keyframe_interval = v.fps as u16 * 2;
+            if let Some(c) = &rung.codec {
+                v.codec = c.clone();
+            }
+            ret.push(VariantStream::Video(v));
+            if let Some(a) = &audio {
+                let mut a = a.clone();
+                a.mapping.id = Uuid::new_v4();
+                a.mapping.group_id = group + i;
+                ret.push(VariantStream::Audio(a));
+            }
+        }
+        for (i, v) in ret.iter_mut().enumerate() {
+            v.set_dst_index(i);
+        }
+        ret
+    }
+
+    /// Add a CRF encoded copy of each transcoded video variant for the recording, VOD
+    /// files don't need the constant bitrate of the live output
+    ///
+    /// Returns the variants to record: the CRF video and the live audio
+    fn add_crf_variants(variants: &mut Vec<VariantStream>, crf: u8) -> HashSet<Uuid> {
+        let group = variants.iter().map(|v| v.group_id()).max().unwrap_or(0) + 1;
+        let mut recorded = HashSet::new();
+        let mut crf_vars = vec![];
+        for v in variants.iter() {
+            match v {
+                VariantStream::Video(v) => {
+                    let mut rec = v.clone();
+                    rec.mapping.id = Uuid::new_v4();
+                    rec.mapping.dst_index = variants.len() + crf_vars.len();
+                    rec.mapping.group_id = group;
+                    rec.encoder.crf = Some(crf);
+                    // recordings aren't watched live
+                    rec.encoder.low_latency = false;
+                    recorded.insert(rec.id());
+                    crf_vars.push(VariantStream::Video(rec));
+                }
+                VariantStream::Audio(a) => {
+                    recorded.insert(a.id());
+                }
+                _ => {}
+            }
+        }
+        variants.extend(crf_vars);
+        recorded
+    }
+
+    /// Latest ingest link statistics of stream [id], if it is live and its protocol reports them
+    pub(super) async fn endpoint_stats(&self, id: &Uuid) -> Option<EndpointStats> {
+        self.endpoint_stats.read().await.get(id).cloned()
+    }
+
+    /// Timing of the latest segments of stream [id], oldest first
+    pub(super) async fn segment_timings(&self, id: &Uuid) -> Vec<SegmentTiming> {
+        self.segment_timings
+            .read()
+            .await
+            .get(id)
+            .map(|t| t.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// User allowed to stream with [connection], errors are shown to the streamer
+    async fn authorize(&self, connection: &ConnectionInfo) -> Result<User> {
+        let ip = IpBanList::parse_ip(&connection.ip_addr);
+        if let Some(ip) = &ip {
+            if let Some(ban) = self.bans.get_ban(ip).await {
+                return Err(Rejection::Denied(format!("{} is banned: {}", ip, ban.reason)).into());
+            }
+        }
+        let uid = match self.db.find_user_stream_key(&connection.key).await? {
+            Some(uid) => uid,
+            None => {
+                if let Some(ip) = &ip {
+                    self.bans.add_failure(ip).await;
+                }
+                return Err(Rejection::BadKey.into());
+            }
+        };
+
+        let user = self.db.get_user(uid).await?;
+        if user.is_blocked {
+            return Err(Rejection::Denied("Account is blocked".to_string()).into());
+        }
+        if user.balance <= 0 && user.cost_multiplier > 0.0 {
+            return Err(Rejection::Denied(
+                "Balance exhausted, top up your account to go live".to_string(),
+            )
+            .into());
+        }
+        Ok(user)
+    }
+
+    /// Write the egress counted since the last flush to the daily aggregates and bill it
+    async fn flush_egress(&self) {
+        let egress = std::mem::take(&mut *self.egress.write().await);
+        let day = Utc::now().date_naive();
+        for (id, (bytes, requests)) in egress {
+            // files of deleted streams can still be cached by viewers
+            let stream = match self.db.get_stream(&id).await {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+            let user = match self.db.get_user(stream.user_id).await {
+                Ok(u) => u,
+                Err(e) => {
+                    warn!("Failed to load owner of stream {}: {}", id, e);
+                    continue;
+                }
+            };
+            let cost = (bytes as f64 / 1e9
+                * self.egress_cost as f64
+                * user.cost_multiplier.max(0.0) as f64)
+                .round() as i64;
+            let row = StreamEgress {
+                stream_id: stream.id.clone(),
+                day,
+                source: EgressSource::Origin,
+                bytes,
+                requests,
+                ..Default::default()
+            };
+            if let Err(e) = self.db.add_stream_egress(&row, user.id, cost).await {
+                warn!("Failed to save egress of stream {}: {}", id, e);
+            }
+        }
+    }
+
+    /// Record a usage alert for admins, failures are only logged as billing continues
+    async fn raise_usage_alert(&self, user: &User, spike: BurnSpike) {
+        warn!(
+            "User {} is spending {} msats/min across {} streams",
+            user.id, spike.rate, spike.streams
+        );
+        let alert = UsageAlert {
+            user_id: user.id,
+            rate: spike.rate,
+            streams: spike.streams,
+            ..Default::default()
+        };
+        if let Err(e) = self.db.insert_usage_alert(&alert).await {
+            warn!("Failed to save usage alert for user {}: {}", user.id, e);
+        }
+    }
+
+    /// Cost in milli-sats of [duration] seconds of streaming at the user's rate
+    fn user_cost(&self, user: &User, duration: f32) -> i64 {
+        (self.cost as f64 * duration.round() as f64 * user.cost_multiplier.max(0.0) as f64).round()
+            as i64
+    }
+
+    /// Variants and egress a new stream of [user] on [endpoint] is transcoded to, shared
+    /// with the ladder preview so it shows what is actually produced
+    pub(super) async fn stream_variants(
+        &self,
+        user: &User,
+        endpoint: Option<&IngestEndpoint>,
+        stream_info: &IngressInfo,
+    ) -> Result<(Vec<VariantStream>, Vec<EgressType>)> {
+        let mut variants = get_default_variants(stream_info)?;
+        self.video_encoder.apply(&mut variants);
+        if self.audio_codec == AudioCodec::Opus {
+            for v in variants.iter_mut() {
+                if let VariantStream::Audio(a) = v {
+                    a.codec = "libopus".to_string();
+                    a.bitrate = 128_000;
+                    a.sample_rate = 48_000;
+                    a.sample_fmt = "flt".to_string();
+                }
+            }
+        }
+        if let Some(e) = endpoint {
+            let ladder = self.db.list_ingest_endpoint_variants(e.id).await?;
+            if !ladder.is_empty() {
+                variants = Self::ladder_variants(variants, &ladder, stream_info);
+            }
+            let params = EncoderParams {
+                preset: e.preset.clone(),
+                tune: e.tune.clone(),
+                profile: e.profile.clone(),
+                params: e.params.clone(),
+                low_latency: e.low_latency,
+                ..Default::default()
+            };
+            for v in variants.iter_mut() {
+                if let VariantStream::Video(v) = v {
+                    v.encoder = params.clone();
+                }
+            }
+        }
+
+        let mut egress = vec![];
+        egress.push(EgressType::HLS(EgressConfig {
+            name: "hls".to_string(),
+            variants: variants.iter().map(|v| v.id()).collect(),
+        }));
+        if user.recording {
+            let recorded = match self.recording_crf {
+                Some(crf) => Self::add_crf_variants(&mut variants, crf),
+                None => variants.iter().map(|v| v.id()).collect(),
+            };
+            egress.push(EgressType::Recorder(EgressConfig {
+                name: "recorder".to_string(),
+                variants: recorded,
+            }));
+        }
+        Ok((variants, egress))
+    }
+
+    /// Create a new stream for the user
+    async fn new_stream(
+        &self,
+        user: &User,
+        endpoint: Option<IngestEndpoint>,
+        stream_info: &IngressInfo,
+    ) -> Result<PipelineConfig> {
+        let (variants, egress) = self
+            .stream_variants(user, endpoint.as_ref(), stream_info)
+            .await?;
+        let loudnorm = endpoint
+            .as_ref()
+            .and_then(|e| e.loudnorm)
+            .filter(|_| user.loudnorm);
+        let watermark = self.watermark(user, endpoint.as_ref());
+
+        let stream_id = Uuid::new_v4();
+        let pipeline = PipelineConfig {
+            id: stream_id,
+            variants,
+            egress,
+            settle_window: self.settle_window,
+            recording_mp4: self.recording_mp4,
+            failover_window: self.failover_window,
+            program_date_time_anchor: self.program_date_time_anchor,
+            loudnorm,
+            segment_type: None,
+            watermark,
+        };
+        // insert new stream record
+        let mut new_stream = UserStream {
+            id: stream_id.to_string(),
+            user_id: user.id,
+            starts: Utc::now(),
+            state: UserStreamState::Live,
+            pipeline: Some(serde_json::to_string(&pipeline)?),
+            visibility: user.visibility,
+            listed: user.listed,
+            ..Default::default()
+        };
+        let stream_event = self
+            .publish_stream_event(&new_stream, &user, StreamChange::Create)
+            .await?;
+        new_stream.event = Some(stream_event.as_json());
+
+        let mut streams = self.active_streams.write().await;
+        streams.insert(stream_id.clone());
+        self.stream_visibility
+            .write()
+            .await
+            .insert(stream_id, new_stream.visibility);
+        self.stream_controls.write().await.insert(
+            stream_id,
+            PipelineControl {
+                recording: user.recording,
+                slate_image: self.slate_file(user.id, SlateFile::Image),
+                slate_audio: self.slate_file(user.id, SlateFile::Audio),
+                ..Default::default()
+            },
+        );
+
+        self.db.insert_stream(&new_stream).await?;
+        self.db.update_stream(&new_stream).await?;
+        Ok(pipeline)
+    }
+
+    /// Resume the user's live stream if its pipeline died less than [Self::resume_window] ago
+    /// (i.e. the server was restarted or the encoder reconnected) and the new input has the
+    /// same streams
+    ///
+    /// The stream keeps its id and the HLS playlists continue from the last segment
+    async fn try_resume_stream(
+        &self,
+        user_id: u64,
+        stream_info: &IngressInfo,
+    ) -> Result<Option<PipelineConfig>> {
+        let stream = match self.db.get_user_live_stream(user_id).await? {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+        let id = Uuid::parse_str(&stream.id)?;
+        let last_segment = stream.last_segment.unwrap_or(stream.starts);
+        let mut streams = self.active_streams.write().await;
+        if streams.contains(&id) || Utc::now() - last_segment > self.resume_window() {
+            return Ok(None);
+        }
+        let pipeline: PipelineConfig = match &stream.pipeline {
+            Some(p) => serde_json::from_str(p)?,
+            None => return Ok(None),
+        };
+
+        let compatible = pipeline.variants.iter().all(|v| {
+            let stream_types: &[IngressStreamType] = match v {
+                VariantStream::Video(_) | VariantStream::CopyVideo(_) => {
+                    &[IngressStreamType::Video]
+                }
+                VariantStream::Audio(_) | VariantStream::CopyAudio(_) => {
+                    &[IngressStreamType::Audio]
+                }
+                // captions can be carried in the video or generated from the audio
+                VariantStream::Subtitle(_) => &[
+                    IngressStreamType::Subtitle,
+                    IngressStreamType::Video,
+                    IngressStreamType::Audio,
+                ],
+            };
+            stream_info
+                .streams
+                .iter()
+                .any(|s| s.index == v.src_index() && stream_types.contains(&s.stream_type))
+        });
+        if !compatible {
+            info!("Input changed, not resuming stream {}", id);
+            drop(streams);
+            self.end_stream(&id, Some(last_segment)).await?;
+            return Ok(None);
+        }
+
+        info!("Resuming stream {}", id);
+        streams.insert(id);
+        self.stream_visibility
+            .write()
+            .await
+            .insert(id, stream.visibility);
+        self.stream_controls.write().await.insert(
+            id,
+            PipelineControl {
+                recording: pipeline
+                    .egress
+                    .iter()
+                    .any(|e| matches!(e, EgressType::Recorder(_))),
+                slate_image: self.slate_file(user_id, SlateFile::Image),
+                slate_audio: self.slate_file(user_id, SlateFile::Audio),
+                ..Default::default()
+            },
+        );
+        Ok(Some(pipeline))
+    }
+
+    /// Live streams without a pipeline are kept this long after their last segment so the
+    /// encoder can reconnect and resume them
+    fn resume_window(&self) -> chrono::Duration {
+        self.reconnect_window.unwrap_or(self.stream_timeout)
+    }
+
+    /// Stop accepting segments for a stream and mark it as ended
+    ///
+    /// [ends] overrides the end time, used when a stream died without ending so the
+    /// published end time matches the last segment that was billed
+    async fn end_stream(&self, id: &Uuid, ends: Option<DateTime<Utc>>) -> Result<()> {
+        let mut stream = self.db.get_stream(id).await?;
+
+        self.active_streams.write().await.remove(id);
+        self.stream_visibility.write().await.remove(id);
+        self.stream_controls.write().await.remove(id);
+        self.n94_events.write().await.remove(id);
+        self.recording_paused.write().await.remove(id);
+        self.endpoint_stats.write().await.remove(id);
+        self.segment_timings.write().await.remove(id);
+        self.playback.remove(id).await;
+        if let Some((peak, avg)) = self.viewers.remove(id) {
+            // picked up by the final stream event
+            self.db.update_stream_viewers(&stream.id, peak, avg).await?;
+        }
+
+        if self
+            .transition_stream(&mut stream, UserStreamState::Ended, ends)
+            .await?
+        {
+            info!("Stream ended {}", stream.id);
+            if self.chat_archive {
+                self.spawn_chat_archive(&stream);
+            }
+        }
+        Ok(())
+    }
+
+    /// Publish an event to the configured relays
+    async fn send_event(&self, ev: Event) -> Result<()> {
+        #[cfg(feature = "chaos")]
+        if self.chaos.drop_relay_publish().await {
+            warn!("Chaos: dropped relay publish {}", ev.id);
+            return Ok(());
+        }
+        if self.dry_run {
+            info!("Dry run: not publishing {}", ev.as_json());
+            return Ok(());
+        }
+        self.client.send_event(ev).await?;
+        Ok(())
+    }
+
+    /// Path of a custom slate file uploaded by the user
+    fn slate_path(&self, user_id: u64, file: SlateFile) -> PathBuf {
+        PathBuf::from(&self.out_dir)
+            .join("slate")
+            .join(user_id.to_string())
+            .join(file.to_string())
+    }
+
+    /// Custom slate file of the user, if uploaded
+    fn slate_file(&self, user_id: u64, file: SlateFile) -> Option<PathBuf> {
+        let path = self.slate_path(user_id, file);
+        path.is_file().then_some(path)
+    }
+
+    /// Path of the watermark image of [owner]
+    fn watermark_path(&self, owner: WatermarkOwner) -> PathBuf {
+        PathBuf::from(&self.out_dir)
+            .join("watermark")
+            .join(owner.to_string())
+    }
+
+    /// Watermark of streams of [user] on [endpoint], placed as configured on the endpoint
+    fn watermark(&self, user: &User, endpoint: Option<&IngestEndpoint>) -> Option<Watermark> {
+        let image = [
+            Some(WatermarkOwner::User(user.id)),
+            endpoint.map(|e| WatermarkOwner::Endpoint(e.id)),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|o| self.watermark_path(o))
+        .find(|p| p.is_file())?;
+        Some(Watermark {
+            image,
+            position: endpoint
+                .and_then(|e| e.watermark_position.as_ref())
+                .and_then(|p| p.parse().ok())
+                .unwrap_or_default(),
+            opacity: endpoint.and_then(|e| e.watermark_opacity).unwrap_or(1.0),
+        })
+    }
+
+    /// Path of a recording part, parts are numbered when recording was restarted while live
+    fn recording_path(&self, stream_id: &Uuid, part: u32) -> PathBuf {
+        let base = PathBuf::from(&self.out_dir).join(stream_id.to_string());
+        if part == 0 {
+            base.join("recording.ts")
+        } else {
+            base.join(format!("recording-{}.ts", part))
+        }
+    }
+
+    /// Path of the trimmed recording of a stream
+    fn trimmed_recording_path(&self, stream_id: &Uuid) -> PathBuf {
+        PathBuf::from(&self.out_dir)
+            .join(stream_id.to_string())
+            .join(TRIMMED_RECORDING)
+    }
+
+    fn map_to_public_url<'a>(
+        &self,
+        stream: &UserStream,
+        path: impl Into<&'a str>,
+    ) -> Result<String> {
+        let u: Url = self.public_url.parse()?;
+        Ok(u.join(&format!("/{}/", stream.id))?
+            .join(path.into())?
+            .to_string())
+    }
+}
+
+/// Reject input exceeding the limits of its ingest endpoint, errors are shown to the
+/// streamer so they should say what to change
+fn check_input_limits(limits: &IngestEndpoint, info: &IngressInfo) -> Result<()> {
+    if let Some(max) = limits.max_bitrate {
+        if info.bitrate as u64 > max {
+            bail!(
+                "Bitrate {}kbps is above the {}kbps limit of this server",
+                info.bitrate / 1000,
+                max / 1000
+            );
+        }
+    }
+    for s in info
+        .streams
+        .iter()
+        .filter(|s| s.stream_type == IngressStreamType::Video)
+    {
+        if let Some(codecs) = &limits.video_codecs {
+            let name = unsafe { rstr!(avcodec_get_name(transmute(s.codec as i32))) };
+            if !codecs
+                .split(',')
+                .any(|c| c.trim().eq_ignore_ascii_case(name))
+            {
+                bail!(
+                    "Video codec {} is not accepted by this server, use {}",
+                    name,
+                    codecs
+                );
+            }
+        }
+        let too_wide = limits.max_width.is_some_and(|w| s.width > w as usize);
+        let too_high = limits.max_height.is_some_and(|h| s.height > h as usize);
+        if too_wide || too_high {
+            bail!(
+                "Resolution {}x{} is above the {}x{} limit of this server",
+                s.width,
+                s.height,
+                limits
+                    .max_width
+                    .map(|w| w.to_string())
+                    .unwrap_or("*".into()),
+                limits
+                    .max_height
+                    .map(|h| h.to_string())
+                    .unwrap_or("*".into())
+            );
+        }
+        if let Some(max) = limits.max_fps {
+            // encoders report 59.94 etc. as slightly above the nominal rate
+            if s.fps > max + 0.5 {
+                bail!(
+                    "Frame rate {:.2}fps is above the {}fps limit of this server",
+                    s.fps,
+                    max
+                );
+            }
+        }
+        if !limits.allow_hdr {
+            let desc = unsafe { av_pix_fmt_desc_get(transmute(s.format as i32)) };
+            if !desc.is_null() && unsafe { (*desc).comp[0].depth } > 8 {
+                bail!("HDR / 10-bit video is not accepted by this server, stream 8-bit SDR");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Overseer for ZapStreamOverseer {
+    async fn api(&self, req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, anyhow::Error>>> {
+        self.api_request(req).await
+    }
+
+    async fn check_streams(&self) -> Result<()> {
+        // reconcile Live rows which have no running pipeline (i.e. after a crash)
+        // or whose pipeline stopped producing segments
+        let active_streams = self.db.list_live_streams().await?;
+        for stream in active_streams {
+            // check
+            let id = Uuid::parse_str(&stream.id)?;
+            info!("Checking stream is alive: {}", stream.id);
+            let is_active = {
+                let streams = self.active_streams.read().await;
+                streams.contains(&id)
+            };
+            let last_segment = stream.last_segment.unwrap_or(stream.starts);
+            // streams without a pipeline are kept for [Self::resume_window] so the encoder
+            // can reconnect and resume the stream after a restart or disconnect
+            let timeout = if is_active {
+                self.stream_timeout
+            } else {
+                self.resume_window()
+            };
+            let is_zombie = Utc::now() - last_segment > timeout;
+            if is_zombie {
+                if is_active {
+                    warn!(
+                        "Stream {} has not produced a segment since {}, ending",
+                        id, last_segment
+                    );
+                }
+                if let Err(e) = self.end_stream(&id, Some(last_segment)).await {
+                    error!("Failed to end dead stream {}: {}", &id, e);
+                }
+            }
+        }
+        // transitions whose stream event failed to publish, i.e. relays were unreachable
+        for mut stream in self.db.list_event_pending_streams().await? {
+            if let Err(e) = self.publish_state_event(&mut stream).await {
+                warn!("Failed to publish event of stream {}: {}", stream.id, e);
+            }
+        }
+        self.flush_egress().await;
+        self.publish_viewer_counts().await;
+        if let Some(f) = &self.federation {
+            f.refresh().await;
+        }
+        if let Err(e) = self.sync_signing_key().await {
+            warn!("Failed to sync signing key: {}", e);
+        }
+
+        let check_duplicates = self
+            .last_duplicate_check
+            .read()
+            .await
+            .is_none_or(|t| t.elapsed() >= DUPLICATE_CHECK_INTERVAL);
+        if check_duplicates {
+            *self.last_duplicate_check.write().await = Some(Instant::now());
+            if let Err(e) = self.cleanup_duplicate_events().await {
+                warn!("Failed to clean up duplicate live events: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn due_inputs(&self) -> Result<Vec<FileInput>> {
+        self.due_premieres().await
+    }
+
+    async fn due_pulls(&self) -> Result<Vec<PulledInput>> {
+        Ok(self.due_restreams().await)
+    }
+
+    async fn on_view(&self, stream_id: &Uuid, viewer: &str) -> Result<()> {
+        if self.active_streams.read().await.contains(stream_id) {
+            self.viewers.seen(stream_id, viewer);
+        }
+        Ok(())
+    }
+
+    async fn on_egress(&self, stream_id: &Uuid, bytes: u64) -> Result<()> {
+        let mut egress = self.egress.write().await;
+        let e = egress.entry(*stream_id).or_default();
+        e.0 += bytes;
+        e.1 += 1;
+        Ok(())
+    }
+
+    async fn status(&self) -> Result<ServerStatus> {
+        let mut components = vec![ComponentStatus::new(
+            "Database",
+            self.db.ping().await.map_err(|e| e.to_string()),
+        )];
+        #[cfg(feature = "chaos")]
+        if self.lnd.is_some() {
+            self.chaos.delay_lnd().await;
+        }
+        let lightning = match &self.lnd {
+            Some(lnd) => lnd
+                .clone()
+                .versioner()
+                .get_version(VersionRequest::default())
+                .await
+                .map(|_| ())
+                .map_err(|e| e.message().to_string()),
+            // simulated
+            None => Ok(()),
+        };
+        components.push(ComponentStatus::new("Lightning", lightning));
+        for (url, relay) in self.client.relays().await {
+            let status = relay.status();
+            let result = if status == RelayStatus::Connected {
+                Ok(())
+            } else {
+                Err(status.to_string())
+            };
+            components.push(ComponentStatus::new(&format!("Relay {}", url), result));
+        }
+
+        // incidents are informational, a broken database is already reported above
+        let incidents = self
+            .db
+            .list_stream_incidents(None, None, 0, STATUS_INCIDENTS)
+            .await
+            .unwrap_or_default();
+        Ok(ServerStatus {
+            active_streams: self.active_streams.read().await.len() as u64,
+            incidents: incidents
+                .into_iter()
+                .map(|i| StatusIncident {
+                    error: i.error,
+                    restarted: i.restarted,
+                    created: i.created,
+                })
+                .collect(),
+            ..Default::default()
+        }
+        .with_components(components))
+    }
+
+    async fn check_connection(&self, connection: &ConnectionInfo) -> Result<()> {
+        self.authorize(connection).await?;
+        Ok(())
+    }
+
+    async fn start_stream(
+        &self,
+        connection: &ConnectionInfo,
+        stream_info: &IngressInfo,
+    ) -> Result<PipelineConfig> {
+        let user = self.authorize(connection).await?;
+        let endpoint = self.db.get_ingest_endpoint(&connection.endpoint).await?;
+        if let Some(e) = &endpoint {
+            check_input_limits(e, stream_info)?;
+        }
+
+        let pipeline = match self.try_resume_stream(user.id, stream_info).await? {
+            Some(p) => p,
+            None => self.new_stream(&user, endpoint, stream_info).await?,
+        };
+        self.db
+            .insert_stream_connection(&StreamConnection {
+                stream_id: pipeline.id.to_string(),
+                ip_addr: connection.ip_addr.clone(),
+                endpoint: connection.endpoint.clone(),
+                user_agent: connection.user_agent.clone(),
+                params: Some(serde_json::to_string(&connection.params)?),
+                ..Default::default()
+            })
+            .await?;
+        if let Err(e) = self.attach_premiere(connection, &pipeline.id).await {
+            warn!("Failed to attach premiere to stream {}: {}", pipeline.id, e);
+        }
+
+        Ok(pipeline)
+    }
+
+    async fn on_segment(
+        &self,
+        pipeline_id: &Uuid,
+        variant_id: &Uuid,
+        index: u64,
+        duration: f32,
+        path: &PathBuf,
+    ) -> Result<()> {
+        // the pipeline is stopped by [Self::pipeline_control] after this segment
+        if !self.active_streams.read().await.contains(pipeline_id) {
+            return Ok(());
+        }
+
+        let stream = self.db.get_stream(pipeline_id).await?;
+        let user = self.db.get_user(stream.user_id).await?;
+        let cost = self.user_cost(&user, duration);
+        let bal = self
+            .db
+            .tick_stream(pipeline_id, stream.user_id, duration, cost)
+            .await?;
+        if let Some(m) = &self.burn_monitor {
+            if let Some(spike) = m.add_debit(user.id, pipeline_id, cost).await {
+                self.raise_usage_alert(&user, spike).await;
+            }
+        }
+        if bal <= 0 && cost > 0 {
+            bail!("Not enough balance");
+        }
+
+        if let Some(r) = &self.replicator {
+            r.push_segment(path);
+        }
+
+        // Upload to blossom servers if configured, unlisted segments are kept private
+        if stream.visibility == StreamVisibility::Public && !self.blossom_servers.is_empty() {
+            self.publish_n94_segment(&stream, variant_id, index, duration, path)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn on_thumbnail(
+        &self,
+        pipeline_id: &Uuid,
+        width: usize,
+        height: usize,
+        pixels: &PathBuf,
+    ) -> Result<()> {
+        // nothing to do
+        Ok(())
+    }
+
+    async fn on_end(&self, pipeline_id: &Uuid) -> Result<()> {
+        // terminated streams are no longer active, they end right away
+        if let Some(w) = self.reconnect_window {
+            if self.active_streams.write().await.remove(pipeline_id) {
+                info!(
+                    "Stream {} disconnected, kept live for {}s to be resumed",
+                    pipeline_id,
+                    w.num_seconds()
+                );
+                return Ok(());
+            }
+        }
+        self.end_stream(pipeline_id, None).await
+    }
+
+    async fn on_shutdown(&self) -> Result<()> {
+        // streams stay live in the database with their pipeline config, the stream check after
+        // the restart ends those which are not resumed within the resume window
+        let live = self.active_streams.read().await.len();
+        info!(
+            "Keeping {} live streams for resuming after the restart",
+            live
+        );
+        self.flush_egress().await;
+        Ok(())
+    }
+
+    async fn pipeline_control(&self, pipeline_id: &Uuid) -> Result<Option<PipelineControl>> {
+        if !self.active_streams.read().await.contains(pipeline_id) {
+            // ended while the pipeline is still running (controller, admin or balance)
+            return Ok(Some(PipelineControl {
+                stop: true,
+                ..Default::default()
+            }));
+        }
+        Ok(self.stream_controls.read().await.get(pipeline_id).cloned())
+    }
+
+    async fn on_pipeline_crash(
+        &self,
+        pipeline_id: &Uuid,
+        error: &str,
+        restarting: bool,
+    ) -> Result<()> {
+        error!("Pipeline {} crashed: {}", pipeline_id, error);
+        // the restarted pipeline resumes the stream, which requires it to be inactive
+        self.active_streams.write().await.remove(pipeline_id);
+        self.db
+            .insert_stream_incident(&StreamIncident {
+                stream_id: pipeline_id.to_string(),
+                error: error.to_string(),
+                restarted: restarting,
+                ..Default::default()
+            })
+            .await
+    }
+
+    async fn on_endpoint_stats(&self, pipeline_id: &Uuid, stats: &EndpointStats) -> Result<()> {
+        self.endpoint_stats
+            .write()
+            .await
+            .insert(*pipeline_id, stats.clone());
+        Ok(())
+    }
+
+    async fn on_segment_timing(&self, pipeline_id: &Uuid, timing: &SegmentTiming) -> Result<()> {
+        let mut timings = self.segment_timings.write().await;
+        let t = timings.entry(*pipeline_id).or_default();
+        if t.len() >= SEGMENT_TIMINGS {
+            t.pop_front();
+        }
+        t.push_back(timing.clone());
+        Ok(())
+    }
+
+    async fn on_recording_paused(&self, pipeline_id: &Uuid, paused: bool) -> Result<()> {
+        let mut set = self.recording_paused.write().await;
+        if paused {
+            set.insert(*pipeline_id);
+        } else {
+            set.remove(pipeline_id);
+        }
+        Ok(())
+    }
+
+    async fn on_recording_complete(
+        &self,
+        pipeline_id: &Uuid,
+        recordings: &[RecordingFile],
+    ) -> Result<()> {
+        self.publish_recording(pipeline_id, recordings).await
+    }
+
+    async fn repair_segment(&self, path: &Path, sha256: &str) -> Result<bool> {
+        for b in &self.blossom_servers {
+            match b.download(sha256).await {
+                Ok(data) => {
+                    let tmp = path.with_extension("repair");
+                    tokio::fs::write(&tmp, &data).await?;
+                    tokio::fs::rename(&tmp, path).await?;
+                    return Ok(true);
+                }
+                Err(e) => warn!("Failed to fetch segment {} from blossom: {}", sha256, e),
+            }
+        }
+        Ok(false)
+    }
+
+    async fn check_playback(
+        &self,
+        stream_id: &Uuid,
+        file: &str,
+        token: Option<&str>,
+    ) -> Result<bool> {
+        // raw recordings are private, the owner downloads them through the API, MP4s are the
+        // replay of the stream once published, the trimmed recording only if the owner
+        // published it
+        if file.starts_with("recording") {
+            if !file.ends_with(".mp4") {
+                return Ok(false);
+            }
+            let published = match self.db.find_stream(stream_id).await? {
+                Some(s) => match s.recording.as_deref() {
+                    Some(r) => file != TRIMMED_RECORDING || r == TRIMMED_RECORDING,
+                    None => false,
+                },
+                None => false,
+            };
+            if !published {
+                return Ok(false);
+            }
+        }
+        let cached = self.stream_visibility.read().await.get(stream_id).copied();
+        let visibility = match cached {
+            Some(v) => v,
+            None => match self.db.find_stream(stream_id).await? {
+                Some(s) => s.visibility,
+                None => return Ok(true),
+            },
+        };
+        if visibility == StreamVisibility::Public {
+            return Ok(true);
+        }
+        let token = match token {
+            Some(t) => t,
+            None => return Ok(false),
+        };
+        let id = stream_id.to_string();
+        // a use is counted once per playback session when the master playlist is loaded
+        if file == "live.m3u8" {
+            self.db.redeem_stream_share(&id, token).await
+        } else {
+            self.db.check_stream_share(&id, token).await
+        }
+    }
+}
@@ -0,0 +1,360 @@
+use crate::overseer::zap_stream::api::ApiResponse;
+use crate::overseer::zap_stream::ZapStreamOverseer;
+use crate::settings::P2pSettings;
+use anyhow::{bail, Result};
+use futures_util::{SinkExt, StreamExt};
+use http_body_util::combinators::BoxBody;
+use hyper::body::Incoming;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::sync::RwLock;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use url::Url;
+use uuid::Uuid;
+
+/// Signaling messages larger than this are dropped, SDP offers are a few KB
+const MAX_SIGNAL_SIZE: usize = 64 * 1024;
+
+/// Messages queued for a peer, peers which don't read their socket fast enough to keep up
+/// are disconnected
+const PEER_QUEUE: usize = 64;
+
+/// Peer-assisted delivery hints for a live stream
+#[derive(Serialize)]
+pub(super) struct SwarmInfo {
+    swarm_id: String,
+    /// Websocket URL of the swarm's signaling
+    signaling: String,
+    ice_servers: Vec<String>,
+    /// Viewers currently in the swarm
+    peers: usize,
+}
+
+/// Signaling message from a peer, [data] (SDP offer / answer, ICE candidate) is forwarded
+/// to peer [to] unchanged
+#[derive(Deserialize)]
+struct PeerMessage {
+    to: u64,
+    data: serde_json::Value,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum SignalMessage {
+    /// Sent to a peer after joining, with the peers already in the swarm
+    Welcome {
+        peer: u64,
+        peers: Vec<u64>,
+    },
+    Join {
+        peer: u64,
+    },
+    Leave {
+        peer: u64,
+    },
+    Signal {
+        from: u64,
+        data: serde_json::Value,
+    },
+}
+
+/// Viewers connected to the signaling of each stream
+pub(super) struct P2pSwarms {
+    settings: P2pSettings,
+    next_peer: AtomicU64,
+    /// Peers by swarm id
+    swarms: RwLock<HashMap<String, HashMap<u64, Sender<String>>>>,
+}
+
+impl P2pSwarms {
+    pub fn new(settings: P2pSettings) -> Self {
+        Self {
+            settings,
+            next_peer: AtomicU64::new(1),
+            swarms: RwLock::new(HashMap::new()),
+        }
+    }
+
+    async fn peers(&self, swarm: &str) -> usize {
+        self.swarms.read().await.get(swarm).map_or(0, |p| p.len())
+    }
+
+    /// Add a peer receiving messages on [tx], the other peers are told about it
+    async fn join(&self, swarm: &str, tx: Sender<String>) -> u64 {
+        let peer = self.next_peer.fetch_add(1, Ordering::Relaxed);
+        let mut swarms = self.swarms.write().await;
+        let peers = swarms.entry(swarm.to_string()).or_default();
+        send(
+            peer,
+            &tx,
+            &SignalMessage::Welcome {
+                peer,
+                peers: peers.keys().copied().collect(),
+            },
+        );
+        let lagging = peers
+            .iter()
+            .filter(|(id, p)| !send(**id, p, &SignalMessage::Join { peer }))
+            .map(|(id, _)| *id)
+            .collect();
+        peers.insert(peer, tx);
+        remove_peers(peers, lagging);
+        peer
+    }
+
+    async fn leave(&self, swarm: &str, peer: u64) {
+        let mut swarms = self.swarms.write().await;
+        if let Some(peers) = swarms.get_mut(swarm) {
+            remove_peers(peers, vec![peer]);
+            if peers.is_empty() {
+                swarms.remove(swarm);
+            }
+        }
+    }
+
+    async fn forward(&self, swarm: &str, from: u64, msg: PeerMessage) {
+        let to = msg.to;
+        let sent = match self.swarms.read().await.get(swarm).and_then(|s| s.get(&to)) {
+            Some(p) => send(
+                to,
+                p,
+                &SignalMessage::Signal {
+                    from,
+                    data: msg.data,
+                },
+            ),
+            None => true,
+        };
+        if !sent {
+            self.leave(swarm, to).await;
+        }
+    }
+}
+
+/// Remove [gone] from [peers] and tell the others, peers which can't take the message are
+/// removed as well. Dropping the sender of a peer closes its socket
+fn remove_peers(peers: &mut HashMap<u64, Sender<String>>, mut gone: Vec<u64>) {
+    while let Some(peer) = gone.pop() {
+        if peers.remove(&peer).is_none() {
+            continue;
+        }
+        for (id, p) in peers.iter() {
+            if !send(*id, p, &SignalMessage::Leave { peer }) {
+                gone.push(*id);
+            }
+        }
+    }
+}
+
+/// Queue [msg] for [peer], false if its queue is full
+fn send(peer: u64, tx: &Sender<String>, msg: &SignalMessage) -> bool {
+    let json = match serde_json::to_string(msg) {
+        Ok(j) => j,
+        Err(_) => return true,
+    };
+    match tx.try_send(json) {
+        Err(TrySendError::Full(_)) => {
+            info!("P2P peer {} is lagging, disconnecting", peer);
+            false
+        }
+        // the peer is leaving if its channel is closed
+        Ok(()) | Err(TrySendError::Closed(_)) => true,
+    }
+}
+
+impl ZapStreamOverseer {
+    /// Swarm of [stream_id], derived from the service key so the signaling of unlisted
+    /// streams can't be joined without asking for it with a share token
+    fn swarm_id(&self, stream_id: &Uuid) -> String {
+        let mut hash = Sha256::new();
+        hash.update(self.keys.primary().secret_key().to_secret_hex().as_bytes());
+        hash.update(b"p2p");
+        hash.update(stream_id.as_bytes());
+        hex::encode(&hash.finalize()[..16])
+    }
+
+    /// P2P delivery hints for the live stream [stream_id], [token] is the share token of
+    /// unlisted streams
+    pub(super) async fn stream_swarm(
+        &self,
+        stream_id: &Uuid,
+        token: Option<&str>,
+    ) -> Result<SwarmInfo> {
+        let p2p = match &self.p2p {
+            Some(p) => p,
+            None => bail!("P2P delivery is not enabled"),
+        };
+        if !self.active_streams.read().await.contains(stream_id) {
+            bail!("Stream is not live");
+        }
+        if !self.check_playback(stream_id, "p2p", token).await? {
+            bail!("Stream not found");
+        }
+        let swarm_id = self.swarm_id(stream_id);
+        let mut u: Url = self.public_url.parse()?;
+        let scheme = if u.scheme() == "https" { "wss" } else { "ws" };
+        if u.set_scheme(scheme).is_err() {
+            bail!("Invalid public url");
+        }
+        u.set_path(&format!("/api/v1/p2p/{}", swarm_id));
+        Ok(SwarmInfo {
+            peers: p2p.peers(&swarm_id).await,
+            signaling: u.to_string(),
+            ice_servers: p2p.settings.ice_servers.clone(),
+            swarm_id,
+        })
+    }
+
+    /// Accept a signaling websocket for [swarm_id], messages are relayed between the peers
+    /// of the swarm until the socket is closed
+    pub(super) async fn p2p_websocket(
+        &self,
+        mut req: Request<Incoming>,
+        swarm_id: &str,
+    ) -> Result<ApiResponse> {
+        let p2p = match &self.p2p {
+            Some(p) => p.clone(),
+            None => bail!("P2P delivery is not enabled"),
+        };
+        let is_live = self
+            .active_streams
+            .read()
+            .await
+            .iter()
+            .any(|id| self.swarm_id(id) == swarm_id);
+        if !is_live {
+            bail!("Unknown swarm");
+        }
+        if p2p.peers(swarm_id).await >= p2p.settings.max_peers {
+            bail!("Swarm is full");
+        }
+        let is_upgrade = req
+            .headers()
+            .get("upgrade")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+        let key = match req.headers().get("sec-websocket-key") {
+            Some(k) if is_upgrade => derive_accept_key(k.as_bytes()),
+            _ => bail!("Expected websocket upgrade"),
+        };
+
+        let on_upgrade = hyper::upgrade::on(&mut req);
+        let swarm_id = swarm_id.to_string();
+        tokio::spawn(async move {
+            let ws = match on_upgrade.await {
+                Ok(u) => {
+                    WebSocketStream::from_raw_socket(TokioIo::new(u), Role::Server, None).await
+                }
+                Err(e) => {
+                    warn!("P2P websocket upgrade failed: {}", e);
+                    return;
+                }
+            };
+            if let Err(e) = relay_signals(ws, p2p, swarm_id).await {
+                info!("P2P websocket closed: {}", e);
+            }
+        });
+
+        Ok(Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header("server", "zap-stream-core")
+            .header("upgrade", "websocket")
+            .header("connection", "upgrade")
+            .header("sec-websocket-accept", key)
+            .body(BoxBody::default())?)
+    }
+}
+
+/// Join [swarm_id] and relay signaling messages between [ws] and the other peers
+async fn relay_signals<S>(
+    mut ws: WebSocketStream<S>,
+    swarms: Arc<P2pSwarms>,
+    swarm_id: String,
+) -> Result<()>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let (tx, mut rx) = channel(PEER_QUEUE);
+    let peer = swarms.join(&swarm_id, tx).await;
+    let res = loop {
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Some(m) => {
+                    if let Err(e) = ws.send(Message::Text(m)).await {
+                        break Err(e.into());
+                    }
+                }
+                None => break Ok(()),
+            },
+            msg = ws.next() => match msg {
+                Some(Ok(Message::Text(t))) if t.len() <= MAX_SIGNAL_SIZE => {
+                    match serde_json::from_str::<PeerMessage>(&t) {
+                        Ok(m) => swarms.forward(&swarm_id, peer, m).await,
+                        Err(e) => warn!("Invalid P2P signal from peer {}: {}", peer, e),
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => break Ok(()),
+                Some(Err(e)) => break Err(e.into()),
+                // pings are answered by the websocket
+                Some(Ok(_)) => {}
+            },
+        }
+    };
+    swarms.leave(&swarm_id, peer).await;
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn disconnects_lagging_peers() {
+        let swarms = P2pSwarms::new(P2pSettings {
+            ice_servers: vec![],
+            max_peers: 10,
+        });
+        let (slow_tx, mut slow_rx) = channel(PEER_QUEUE);
+        let slow = swarms.join("s", slow_tx).await;
+        let (tx, mut rx) = channel(PEER_QUEUE);
+        let peer = swarms.join("s", tx).await;
+        while rx.try_recv().is_ok() {}
+
+        // the slow peer never reads its queue, which holds its welcome and the join already
+        for _ in 0..PEER_QUEUE - 2 {
+            let msg = PeerMessage {
+                to: slow,
+                data: serde_json::Value::Null,
+            };
+            swarms.forward("s", peer, msg).await;
+        }
+        assert_eq!(swarms.peers("s").await, 2);
+        let msg = PeerMessage {
+            to: slow,
+            data: serde_json::Value::Null,
+        };
+        swarms.forward("s", peer, msg).await;
+        assert_eq!(swarms.peers("s").await, 1);
+        // the other peers are told it left
+        let leave = rx.try_recv().unwrap();
+        assert_eq!(leave, format!(r#"{{"type":"leave","peer":{}}}"#, slow));
+
+        // the queued messages are delivered and then the channel is closed
+        let mut queued = 0;
+        while slow_rx.recv().await.is_some() {
+            queued += 1;
+        }
+        assert_eq!(queued, PEER_QUEUE);
+    }
+}
@@ -0,0 +1,507 @@
+use crate::overseer::zap_stream::ZapStreamOverseer;
+use crate::settings::{BitvoraSettings, OpenNodeSettings, PaymentSettings, StrikeSettings};
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use hyper::HeaderMap;
+use log::info;
+use nostr_sdk::nips::nip47::{
+    LookupInvoiceRequestParams, NostrWalletConnectURI, Request as NwcRequest,
+    Response as NwcResponse,
+};
+use nostr_sdk::{Client, Event, Filter, Kind, RelayPoolNotification};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use zap_stream_db::Payment;
+
+/// Milli-sats per BTC
+const MSATS_PER_BTC: u64 = 100_000_000_000;
+
+/// How long to wait for a NWC wallet to answer an invoice lookup
+const NWC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Topup settled at an external payment processor
+pub(super) struct SettledPayment {
+    /// Id of the invoice / charge at the processor
+    pub external_id: String,
+    /// Hex pubkey of the user to credit, set as the reference of the invoice when it was
+    /// created
+    pub pubkey: String,
+    /// Amount in milli-sats
+    pub amount: u64,
+}
+
+/// Verifies the webhooks of a payment processor
+#[async_trait]
+pub(super) trait PaymentProvider: Send + Sync {
+    /// Verify a webhook request and return the payment it settled
+    ///
+    /// Returns [None] for valid events which don't settle a payment (pending, expired ..)
+    async fn settled_payment(
+        &self,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<Option<SettledPayment>>;
+}
+
+/// Payment providers enabled in [settings] by the name used in the webhook path
+pub(super) async fn payment_providers(
+    settings: &Option<PaymentSettings>,
+) -> Result<HashMap<String, Box<dyn PaymentProvider>>> {
+    let mut providers: HashMap<String, Box<dyn PaymentProvider>> = HashMap::new();
+    let settings = match settings {
+        Some(s) => s,
+        None => return Ok(providers),
+    };
+    if let Some(b) = &settings.bitvora {
+        providers.insert("bitvora".to_string(), Box::new(Bitvora(b.clone())));
+    }
+    if let Some(s) = &settings.strike {
+        providers.insert(
+            "strike".to_string(),
+            Box::new(Strike {
+                settings: s.clone(),
+                client: reqwest::Client::new(),
+            }),
+        );
+    }
+    if let Some(o) = &settings.opennode {
+        providers.insert(
+            "opennode".to_string(),
+            Box::new(OpenNode {
+                settings: o.clone(),
+                client: reqwest::Client::new(),
+            }),
+        );
+    }
+    if let Some(n) = &settings.nwc {
+        providers.insert("nwc".to_string(), Box::new(Nwc::new(&n.uri).await?));
+    }
+    Ok(providers)
+}
+
+impl ZapStreamOverseer {
+    /// Verify a webhook of [provider] and credit the payment it settled
+    pub(super) async fn handle_payment_webhook(
+        &self,
+        provider: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<()> {
+        let handler = match self.payment_providers.get(provider) {
+            Some(p) => p,
+            None => bail!("Unknown payment provider: {}", provider),
+        };
+        let payment = match handler.settled_payment(headers, body).await? {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let pubkey = hex::decode(&payment.pubkey)?;
+        let user = match self.db.find_user_pubkey(&pubkey).await? {
+            Some(u) => u,
+            None => bail!("No user with pubkey {}", payment.pubkey),
+        };
+        let credited = self
+            .db
+            .credit_payment(&Payment {
+                user_id: user.id,
+                provider: provider.to_string(),
+                external_id: payment.external_id.clone(),
+                amount: payment.amount,
+                ..Default::default()
+            })
+            .await?;
+        if credited {
+            info!(
+                "Credited {} msats to user {} from {} payment {}",
+                payment.amount, user.id, provider, payment.external_id
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Check the hex HMAC-SHA256 [signature] of [body]
+fn verify_hmac(secret: &[u8], body: &[u8], signature: Option<&str>) -> Result<()> {
+    let signature = match signature {
+        Some(s) => hex::decode(s.trim())?,
+        None => bail!("Missing webhook signature"),
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret)?;
+    mac.update(body);
+    mac.verify_slice(&signature)
+        .map_err(|_| anyhow!("Invalid webhook signature"))
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Parse a decimal BTC amount (`0.00021`) to milli-sats
+fn btc_to_msats(amount: &str) -> Result<u64> {
+    let (whole, frac) = amount.split_once('.').unwrap_or((amount, ""));
+    if frac.len() > 11 {
+        bail!("Invalid BTC amount: {}", amount);
+    }
+    let whole: u64 = whole.parse()?;
+    let frac: u64 = if frac.is_empty() {
+        0
+    } else {
+        format!("{:0<11}", frac).parse()?
+    };
+    whole
+        .checked_mul(MSATS_PER_BTC)
+        .and_then(|w| w.checked_add(frac))
+        .ok_or(anyhow!("Invalid BTC amount: {}", amount))
+}
+
+/// [Bitvora](https://bitvora.com) lightning deposits, the user pubkey is set as `pubkey`
+/// in the deposit metadata
+struct Bitvora(BitvoraSettings);
+
+#[derive(Deserialize)]
+struct BitvoraWebhook {
+    event: String,
+    data: BitvoraDeposit,
+}
+
+#[derive(Deserialize)]
+struct BitvoraDeposit {
+    id: String,
+    amount_sats: u64,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+#[async_trait]
+impl PaymentProvider for Bitvora {
+    async fn settled_payment(
+        &self,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<Option<SettledPayment>> {
+        verify_hmac(
+            self.0.webhook_secret.as_bytes(),
+            body,
+            header(headers, "bitvora-signature"),
+        )?;
+        let hook: BitvoraWebhook = serde_json::from_slice(body)?;
+        if hook.event != "deposit.lightning.completed" {
+            return Ok(None);
+        }
+        let pubkey = match hook.data.metadata.get("pubkey") {
+            Some(p) => p.clone(),
+            None => bail!("Deposit {} has no pubkey metadata", hook.data.id),
+        };
+        Ok(Some(SettledPayment {
+            external_id: hook.data.id,
+            pubkey,
+            amount: hook
+                .data
+                .amount_sats
+                .checked_mul(1000)
+                .ok_or(anyhow!("Invalid amount of deposit {}", hook.data.id))?,
+        }))
+    }
+}
+
+/// [Strike](https://strike.me) invoices, the user pubkey is the `correlationId` of the
+/// invoice
+///
+/// Strike webhooks only name the changed invoice, its state is looked up with the API
+struct Strike {
+    settings: StrikeSettings,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StrikeWebhook {
+    event_type: String,
+    data: StrikeWebhookData,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StrikeWebhookData {
+    entity_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StrikeInvoice {
+    invoice_id: String,
+    amount: StrikeAmount,
+    state: String,
+    correlation_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StrikeAmount {
+    amount: String,
+    currency: String,
+}
+
+#[async_trait]
+impl PaymentProvider for Strike {
+    async fn settled_payment(
+        &self,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<Option<SettledPayment>> {
+        verify_hmac(
+            self.settings.webhook_secret.as_bytes(),
+            body,
+            header(headers, "x-webhook-signature"),
+        )?;
+        let hook: StrikeWebhook = serde_json::from_slice(body)?;
+        if hook.event_type != "invoice.updated" {
+            return Ok(None);
+        }
+        let invoice: StrikeInvoice = self
+            .client
+            .get(format!(
+                "https://api.strike.me/v1/invoices/{}",
+                hook.data.entity_id
+            ))
+            .bearer_auth(&self.settings.api_key)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if invoice.state != "PAID" {
+            return Ok(None);
+        }
+        if invoice.amount.currency != "BTC" {
+            bail!(
+                "Invoice {} is in {}, only BTC invoices are accepted",
+                invoice.invoice_id,
+                invoice.amount.currency
+            );
+        }
+        let pubkey = match invoice.correlation_id {
+            Some(p) => p,
+            None => bail!("Invoice {} has no correlation id", invoice.invoice_id),
+        };
+        Ok(Some(SettledPayment {
+            amount: btc_to_msats(&invoice.amount.amount)?,
+            external_id: invoice.invoice_id,
+            pubkey,
+        }))
+    }
+}
+
+/// [OpenNode](https://opennode.com) charges, the user pubkey is the `order_id` of the
+/// charge
+///
+/// Only the charge id of a webhook is used, the charge is looked up with the API as the
+/// other fields are not signed
+struct OpenNode {
+    settings: OpenNodeSettings,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct OpenNodeResponse {
+    data: OpenNodeCharge,
+}
+
+#[derive(Deserialize)]
+struct OpenNodeCharge {
+    id: String,
+    status: String,
+    /// Amount in sats
+    amount: u64,
+    order_id: Option<String>,
+}
+
+#[async_trait]
+impl PaymentProvider for OpenNode {
+    async fn settled_payment(
+        &self,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<Option<SettledPayment>> {
+        // charge callbacks are sent as a form or json depending on the account settings
+        let is_json =
+            header(headers, "content-type").is_some_and(|t| t.starts_with("application/json"));
+        let fields: HashMap<String, String> = if is_json {
+            let v: HashMap<String, serde_json::Value> = serde_json::from_slice(body)?;
+            v.into_iter()
+                .map(|(k, v)| match v {
+                    serde_json::Value::String(s) => (k, s),
+                    v => (k, v.to_string()),
+                })
+                .collect()
+        } else {
+            url::form_urlencoded::parse(body).into_owned().collect()
+        };
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .ok_or(anyhow!("Missing field {} in OpenNode webhook", name))
+        };
+
+        // OpenNode signs the charge id with the API key
+        let id = field("id")?;
+        verify_hmac(
+            self.settings.api_key.as_bytes(),
+            id.as_bytes(),
+            Some(field("hashed_order")?),
+        )?;
+        let charge = self
+            .client
+            .get(format!("https://api.opennode.com/v1/charge/{}", id))
+            .header("authorization", &self.settings.api_key)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<OpenNodeResponse>()
+            .await?
+            .data;
+        if charge.id != *id {
+            bail!("OpenNode returned charge {} for {}", charge.id, id);
+        }
+        if charge.status != "paid" {
+            return Ok(None);
+        }
+        let pubkey = match charge.order_id {
+            Some(p) => p,
+            None => bail!("Charge {} has no order id", charge.id),
+        };
+        Ok(Some(SettledPayment {
+            amount: charge
+                .amount
+                .checked_mul(1000)
+                .ok_or(anyhow!("Invalid amount of charge {}", charge.id))?,
+            external_id: charge.id,
+            pubkey,
+        }))
+    }
+}
+
+/// Invoices of a [NWC](https://github.com/nostr-protocol/nips/blob/master/47.md) wallet, the
+/// user pubkey is set as `pubkey` in the invoice metadata
+///
+/// Webhooks only name the payment hash (`{"payment_hash": ".."}`), the invoice is looked up
+/// in the wallet so the webhook needs no signature
+struct Nwc {
+    uri: NostrWalletConnectURI,
+    client: Client,
+}
+
+#[derive(Deserialize)]
+struct NwcWebhook {
+    payment_hash: String,
+}
+
+impl Nwc {
+    async fn new(uri: &str) -> Result<Self> {
+        let uri = NostrWalletConnectURI::from_str(uri)?;
+        let client = Client::default();
+        client.add_relay(uri.relay_url.clone()).await?;
+        client.connect().await;
+        Ok(Self { uri, client })
+    }
+
+    /// Send [request] to the wallet and wait for its response
+    async fn request(&self, request: Event) -> Result<Event> {
+        let filter = Filter::new()
+            .kind(Kind::WalletConnectResponse)
+            .author(self.uri.public_key)
+            .event(request.id);
+        let mut notifications = self.client.notifications();
+        let sub = self.client.subscribe(vec![filter], None).await?.val;
+        let res = tokio::time::timeout(NWC_TIMEOUT, async {
+            self.client.send_event(request).await?;
+            loop {
+                match notifications.recv().await {
+                    Ok(RelayPoolNotification::Event {
+                        subscription_id,
+                        event,
+                        ..
+                    }) if subscription_id == sub => return Ok(*event),
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => bail!("NWC client was shut down"),
+                }
+            }
+        })
+        .await;
+        self.client.unsubscribe(sub).await;
+        match res {
+            Ok(r) => r,
+            Err(_) => bail!("NWC wallet did not respond"),
+        }
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for Nwc {
+    async fn settled_payment(
+        &self,
+        _headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<Option<SettledPayment>> {
+        let hook: NwcWebhook = serde_json::from_slice(body)?;
+        let request = NwcRequest::lookup_invoice(LookupInvoiceRequestParams {
+            payment_hash: Some(hook.payment_hash.clone()),
+            invoice: None,
+        })
+        .to_event(&self.uri)?;
+        let response = self.request(request).await?;
+        let invoice = NwcResponse::from_event(&self.uri, &response)?.to_lookup_invoice()?;
+        if invoice.payment_hash != hook.payment_hash {
+            bail!(
+                "NWC wallet returned invoice {} for {}",
+                invoice.payment_hash,
+                hook.payment_hash
+            );
+        }
+        if invoice.settled_at.is_none() {
+            return Ok(None);
+        }
+        let pubkey = match invoice
+            .metadata
+            .as_ref()
+            .and_then(|m| m.get("pubkey"))
+            .and_then(|p| p.as_str())
+        {
+            Some(p) => p.to_string(),
+            None => bail!("Invoice {} has no pubkey metadata", invoice.payment_hash),
+        };
+        Ok(Some(SettledPayment {
+            external_id: invoice.payment_hash,
+            pubkey,
+            // NWC amounts are in milli-sats
+            amount: invoice.amount,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn btc_to_msats_parses_decimal_amounts() {
+        assert_eq!(btc_to_msats("1").unwrap(), MSATS_PER_BTC);
+        assert_eq!(btc_to_msats("0.00021").unwrap(), 21_000_000);
+        // the smallest unit is one milli-sat
+        assert_eq!(btc_to_msats("0.00000000001").unwrap(), 1);
+        // anything below a milli-sat is rejected rather than rounded
+        assert!(btc_to_msats("0.000000000001").is_err());
+        // u64::MAX is ~184467440.7 BTC in milli-sats
+        assert_eq!(
+            btc_to_msats("184467440").unwrap(),
+            184_467_440 * MSATS_PER_BTC
+        );
+        assert!(btc_to_msats("184467441").is_err());
+        assert!(btc_to_msats("-1").is_err());
+        assert!(btc_to_msats("abc").is_err());
+    }
+}
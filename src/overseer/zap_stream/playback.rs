@@ -0,0 +1,244 @@
+use crate::mux::DEFAULT_PLAYLIST_WINDOW;
+use crate::overseer::zap_stream::ZapStreamOverseer;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::info;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// Reports older than this are not counted
+const REPORT_WINDOW: Duration = Duration::seconds(30);
+
+/// Viewers buffering less than this many seconds ahead count as stalling
+const LOW_BUFFER: f32 = 1.0;
+
+/// Min viewers reporting before the playlist window of a stream is changed
+const MIN_VIEWERS: usize = 5;
+
+/// Share of stalling viewers at which the playlist window grows
+const STALL_RATIO: f32 = 0.2;
+
+/// Share of stalling viewers below which the playlist window shrinks back
+const RECOVER_RATIO: f32 = 0.05;
+
+/// Segments added or removed per change
+const WINDOW_STEP: usize = 5;
+
+/// Max segments in the playlist window
+const MAX_PLAYLIST_WINDOW: usize = 30;
+
+/// Min time between changes of the window of one stream, players need a few playlist
+/// reloads to pick up a change
+const ADJUST_INTERVAL: Duration = Duration::seconds(60);
+
+/// Max playback sessions tracked per stream
+const MAX_SESSIONS: usize = 10_000;
+
+/// Max playback sessions of a stream counted per viewer IP, so a single client can't make up
+/// the stalling share of the audience with made up sessions
+const MAX_SESSIONS_PER_IP: usize = 3;
+
+/// Reports of a session sent sooner than this after its previous report are ignored
+const MIN_REPORT_INTERVAL: Duration = Duration::seconds(2);
+
+/// Playback heartbeat sent by players every few seconds
+#[derive(Deserialize)]
+pub(super) struct PlaybackReport {
+    /// Random id of the playback session
+    session: String,
+    /// Seconds of media buffered ahead of the playhead
+    buffer: f32,
+    /// Number of stalls since the previous report
+    #[serde(default)]
+    stalls: u32,
+}
+
+/// Latest report of a playback session
+struct Session {
+    time: DateTime<Utc>,
+    stalling: bool,
+    /// Viewer IP which started the session, reports from other IPs are ignored
+    ip: Option<IpAddr>,
+}
+
+/// Tracks viewer buffer health per stream
+pub(super) struct PlaybackMonitor {
+    /// Sessions by stream
+    reports: RwLock<HashMap<Uuid, HashMap<String, Session>>>,
+    /// Last playlist window change by stream
+    adjusted: RwLock<HashMap<Uuid, DateTime<Utc>>>,
+}
+
+impl PlaybackMonitor {
+    pub fn new() -> Self {
+        Self {
+            reports: RwLock::new(HashMap::new()),
+            adjusted: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Record a report from [ip] and return the number of (reporting, stalling) viewers of
+    /// the stream
+    ///
+    /// Reports which come too often, from another IP than the session started on or start
+    /// a session past the limits are not counted
+    async fn add(
+        &self,
+        stream_id: &Uuid,
+        ip: Option<IpAddr>,
+        report: &PlaybackReport,
+    ) -> (usize, usize) {
+        let now = Utc::now();
+        let mut reports = self.reports.write().await;
+        let sessions = reports.entry(*stream_id).or_default();
+        sessions.retain(|_, s| now - s.time < REPORT_WINDOW);
+        let accept = match sessions.get(&report.session) {
+            Some(s) => s.ip == ip && now - s.time >= MIN_REPORT_INTERVAL,
+            None => {
+                sessions.len() < MAX_SESSIONS
+                    && sessions.values().filter(|s| s.ip == ip).count() < MAX_SESSIONS_PER_IP
+            }
+        };
+        if accept {
+            sessions.insert(
+                report.session.clone(),
+                Session {
+                    time: now,
+                    stalling: report.stalls > 0 || report.buffer < LOW_BUFFER,
+                    ip,
+                },
+            );
+        }
+        let stalling = sessions.values().filter(|s| s.stalling).count();
+        (sessions.len(), stalling)
+    }
+
+    /// Stop tracking an ended stream
+    pub async fn remove(&self, stream_id: &Uuid) {
+        self.reports.write().await.remove(stream_id);
+        self.adjusted.write().await.remove(stream_id);
+    }
+}
+
+impl ZapStreamOverseer {
+    /// Record a playback heartbeat from [ip] for the live stream [stream_id], the playlist
+    /// window of the stream grows while many of its viewers are stalling and shrinks back
+    /// once they play smoothly again
+    pub(super) async fn record_playback(
+        &self,
+        stream_id: &Uuid,
+        ip: Option<IpAddr>,
+        report: &PlaybackReport,
+    ) -> Result<()> {
+        if report.session.is_empty() || report.session.len() > 64 || !report.buffer.is_finite() {
+            bail!("Invalid playback report");
+        }
+        if !self.active_streams.read().await.contains(stream_id) {
+            bail!("Stream is not live");
+        }
+        let (viewers, stalling) = self.playback.add(stream_id, ip, report).await;
+        if viewers < MIN_VIEWERS {
+            return Ok(());
+        }
+        let now = Utc::now();
+        if let Some(t) = self.playback.adjusted.read().await.get(stream_id) {
+            if now - *t < ADJUST_INTERVAL {
+                return Ok(());
+            }
+        }
+
+        let ratio = stalling as f32 / viewers as f32;
+        let mut controls = self.stream_controls.write().await;
+        let control = match controls.get_mut(stream_id) {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        let current = control.playlist_window.unwrap_or(DEFAULT_PLAYLIST_WINDOW);
+        let window = if ratio >= STALL_RATIO {
+            (current + WINDOW_STEP).min(MAX_PLAYLIST_WINDOW)
+        } else if ratio < RECOVER_RATIO {
+            current
+                .saturating_sub(WINDOW_STEP)
+                .max(DEFAULT_PLAYLIST_WINDOW)
+        } else {
+            current
+        };
+        if window == current {
+            return Ok(());
+        }
+        info!(
+            "{}/{} viewers of {} stalling, playlist window {} -> {} segments",
+            stalling, viewers, stream_id, current, window
+        );
+        control.playlist_window = if window == DEFAULT_PLAYLIST_WINDOW {
+            None
+        } else {
+            Some(window)
+        };
+        self.playback.adjusted.write().await.insert(*stream_id, now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(session: &str) -> PlaybackReport {
+        PlaybackReport {
+            session: session.to_string(),
+            buffer: 0.0,
+            stalls: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn limits_sessions_per_ip() {
+        let monitor = PlaybackMonitor::new();
+        let stream = Uuid::new_v4();
+        let ip = Some("192.0.2.1".parse().unwrap());
+        for i in 0..10 {
+            monitor.add(&stream, ip, &report(&i.to_string())).await;
+        }
+        assert_eq!(
+            monitor.add(&stream, ip, &report("a")).await,
+            (MAX_SESSIONS_PER_IP, MAX_SESSIONS_PER_IP)
+        );
+        let other = Some("192.0.2.2".parse().unwrap());
+        assert_eq!(
+            monitor.add(&stream, other, &report("a")).await,
+            (MAX_SESSIONS_PER_IP + 1, MAX_SESSIONS_PER_IP + 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn ignores_frequent_and_moved_reports() {
+        let monitor = PlaybackMonitor::new();
+        let stream = Uuid::new_v4();
+        let ip = Some("192.0.2.1".parse().unwrap());
+        assert_eq!(monitor.add(&stream, ip, &report("a")).await, (1, 1));
+        let smooth = PlaybackReport {
+            session: "a".to_string(),
+            buffer: 10.0,
+            stalls: 0,
+        };
+        // too soon after the last report
+        assert_eq!(monitor.add(&stream, ip, &smooth).await, (1, 1));
+        // another IP can't take over the session
+        let other = Some("192.0.2.2".parse().unwrap());
+        monitor
+            .reports
+            .write()
+            .await
+            .get_mut(&stream)
+            .unwrap()
+            .get_mut("a")
+            .unwrap()
+            .time -= MIN_REPORT_INTERVAL;
+        assert_eq!(monitor.add(&stream, other, &smooth).await, (1, 1));
+        assert_eq!(monitor.add(&stream, ip, &smooth).await, (1, 0));
+    }
+}
@@ -0,0 +1,199 @@
+use crate::http::Forbidden;
+use crate::ingress::file::FileInput;
+use crate::ingress::{ConnectionInfo, PARAM_REALTIME};
+use crate::overseer::zap_stream::webhooks::StreamChange;
+use crate::overseer::zap_stream::{ZapStreamOverseer, TRIMMED_RECORDING};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::{info, warn};
+use nostr_sdk::JsonUtil;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use uuid::Uuid;
+use zap_stream_db::{Premiere, PremiereState, User, UserStream, UserStreamState};
+
+/// Endpoint name of premiere pipelines
+const PREMIERE_ENDPOINT: &str = "premiere";
+
+/// [ConnectionInfo::params] key with the id of the premiere a pipeline plays
+const PARAM_PREMIERE: &str = "premiere";
+
+/// Premieres which were missed by more than this (i.e. the server was down) are cancelled
+const MAX_START_DELAY: Duration = Duration::minutes(10);
+
+/// Premieres can be scheduled at most this far ahead
+const MAX_SCHEDULE_AHEAD: Duration = Duration::days(90);
+
+#[derive(Serialize)]
+pub(super) struct ApiPremiere {
+    id: u64,
+    vod_id: String,
+    starts: i64,
+    state: String,
+    /// Live stream the premiere is played as, set once it started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_id: Option<String>,
+}
+
+impl From<Premiere> for ApiPremiere {
+    fn from(p: Premiere) -> Self {
+        Self {
+            id: p.id,
+            vod_id: p.vod_id,
+            starts: p.starts.timestamp(),
+            state: p.state.to_string(),
+            stream_id: p.stream_id,
+        }
+    }
+}
+
+impl ZapStreamOverseer {
+    /// Schedule the imported recording [vod_id] of [user] to be streamed live at [starts]
+    pub(super) async fn schedule_premiere(
+        &self,
+        user: &User,
+        vod_id: &Uuid,
+        starts: DateTime<Utc>,
+    ) -> Result<ApiPremiere> {
+        let vod = self.db.get_stream(vod_id).await?;
+        if vod.user_id != user.id {
+            bail!(Forbidden);
+        }
+        if vod.state != UserStreamState::Ended || !self.premiere_path(&vod).is_file() {
+            bail!("Only imported recordings can be premiered");
+        }
+        let now = Utc::now();
+        if starts <= now || starts > now + MAX_SCHEDULE_AHEAD {
+            bail!(
+                "Premieres must start within the next {} days",
+                MAX_SCHEDULE_AHEAD.num_days()
+            );
+        }
+        let mut premiere = Premiere {
+            user_id: user.id,
+            vod_id: vod.id,
+            starts,
+            ..Default::default()
+        };
+        premiere.id = self.db.insert_premiere(&premiere).await?;
+        info!(
+            "Premiere {} of {} scheduled for {}",
+            premiere.id, premiere.vod_id, starts
+        );
+        Ok(premiere.into())
+    }
+
+    /// Cancel a scheduled premiere of [user]
+    pub(super) async fn cancel_premiere(&self, user: &User, id: u64) -> Result<()> {
+        match self.db.get_premiere(id).await? {
+            Some(p) if p.user_id == user.id => {}
+            _ => bail!("Premiere not found"),
+        }
+        if !self
+            .db
+            .transition_premiere(id, PremiereState::Cancelled)
+            .await?
+        {
+            bail!("Premiere already started");
+        }
+        Ok(())
+    }
+
+    /// Start the premieres which are due, the recordings are streamed like an encoder
+    /// connecting with the user's stream key
+    pub(super) async fn due_premieres(&self) -> Result<Vec<FileInput>> {
+        let mut ret = vec![];
+        for p in self.db.list_due_premieres().await? {
+            let missed = Utc::now() - p.starts > MAX_START_DELAY;
+            let live = self.db.get_user_live_stream(p.user_id).await?.is_some();
+            if missed || live {
+                if self
+                    .db
+                    .transition_premiere(p.id, PremiereState::Cancelled)
+                    .await?
+                {
+                    warn!(
+                        "Premiere {} cancelled, {}",
+                        p.id,
+                        if live {
+                            "the user is already live"
+                        } else {
+                            "its start was missed"
+                        }
+                    );
+                }
+                continue;
+            }
+            // another node may have started it
+            if !self
+                .db
+                .transition_premiere(p.id, PremiereState::Started)
+                .await?
+            {
+                continue;
+            }
+            let vod = self.db.get_stream(&Uuid::parse_str(&p.vod_id)?).await?;
+            let user = self.db.get_user(p.user_id).await?;
+            info!("Starting premiere {} of {}", p.id, vod.id);
+            ret.push(FileInput {
+                path: self.premiere_path(&vod),
+                connection: ConnectionInfo {
+                    endpoint: PREMIERE_ENDPOINT.to_string(),
+                    ip_addr: PREMIERE_ENDPOINT.to_string(),
+                    key: user.stream_key,
+                    params: HashMap::from([
+                        (PARAM_REALTIME.to_string(), "true".to_string()),
+                        (PARAM_PREMIERE.to_string(), p.id.to_string()),
+                    ]),
+                    ..Default::default()
+                },
+            });
+        }
+        Ok(ret)
+    }
+
+    /// Link the live stream started for a premiere pipeline to its premiere, the stream
+    /// gets the title and description of the recording
+    pub(super) async fn attach_premiere(
+        &self,
+        connection: &ConnectionInfo,
+        stream_id: &Uuid,
+    ) -> Result<()> {
+        let id: u64 = match connection.params.get(PARAM_PREMIERE) {
+            Some(id) => id.parse()?,
+            None => return Ok(()),
+        };
+        let premiere = match self.db.get_premiere(id).await? {
+            Some(p) => p,
+            None => bail!("Premiere {} not found", id),
+        };
+        self.db
+            .update_premiere_stream(premiere.id, &stream_id.to_string())
+            .await?;
+        let vod = self
+            .db
+            .get_stream(&Uuid::parse_str(&premiere.vod_id)?)
+            .await?;
+        let mut stream = self.db.get_stream(stream_id).await?;
+        stream.title = vod.title;
+        stream.summary = vod.summary;
+        stream.image = vod.image;
+        stream.tags = vod.tags;
+        stream.content_warning = vod.content_warning;
+        let user = self.db.get_user(stream.user_id).await?;
+        let ev = self
+            .publish_stream_event(&stream, &user, StreamChange::Update)
+            .await?;
+        stream.event = Some(ev.as_json());
+        self.db.update_stream(&stream).await?;
+        Ok(())
+    }
+
+    /// File played for premieres of [vod]
+    fn premiere_path(&self, vod: &UserStream) -> PathBuf {
+        PathBuf::from(&self.out_dir)
+            .join(&vod.id)
+            .join(TRIMMED_RECORDING)
+    }
+}
@@ -0,0 +1,180 @@
+use crate::ingress::channel::ChannelReader;
+#[cfg(feature = "rtmp")]
+use crate::ingress::rtmp;
+use crate::ingress::{ConnectionInfo, IngressReader, PulledInput};
+use crate::overseer::zap_stream::webhooks::public_client;
+#[cfg(feature = "rtmp")]
+use crate::overseer::zap_stream::webhooks::resolve_public_addr;
+use crate::overseer::zap_stream::ZapStreamOverseer;
+use anyhow::{bail, Result};
+use log::{info, warn};
+use m3u8_rs::Playlist;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::Sender;
+use url::Url;
+use zap_stream_db::User;
+
+/// Endpoint name of restream pipelines
+const RESTREAM_ENDPOINT: &str = "restream";
+
+/// [ConnectionInfo::params] key with the source URL of a restream
+const PARAM_SOURCE: &str = "source";
+
+/// Timeout of each playlist or segment request to the source
+const FETCH_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Max size of a playlist or segment pulled from the source
+const MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Segments behind the end of a live playlist the restream starts with
+const LIVE_EDGE_SEGMENTS: usize = 3;
+
+/// A source playlist which has no new segment for this long is ended
+const STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+pub(super) struct RestreamRequest {
+    /// `https://` HLS playlist or `rtmp://host/app/key` stream
+    url: String,
+}
+
+impl ZapStreamOverseer {
+    /// Pull the stream at the source URL of [req] and publish it as a live stream of [user],
+    /// the pipeline is started with the user's stream key like an encoder connecting
+    pub(super) async fn start_restream(&self, user: &User, req: RestreamRequest) -> Result<()> {
+        if self.db.get_user_live_stream(user.id).await?.is_some() {
+            bail!("Already live");
+        }
+        let url: Url = req.url.parse()?;
+        let (ip_addr, app_name, reader): (String, String, Box<dyn IngressReader>) =
+            match url.scheme() {
+                "https" => {
+                    let client = public_client(url.as_str(), "Restream URL", FETCH_TIMEOUT).await?;
+                    let (tx, reader) = ChannelReader::new();
+                    let source = url.clone();
+                    tokio::spawn(async move {
+                        match pull_hls(&client, &source, tx).await {
+                            Ok(()) => info!("Restream of {} ended", source),
+                            Err(e) => warn!("Restream of {} stopped: {}", source, e),
+                        }
+                    });
+                    (
+                        url.host_str().unwrap_or("").to_string(),
+                        String::new(),
+                        Box::new(reader),
+                    )
+                }
+                #[cfg(feature = "rtmp")]
+                "rtmp" => {
+                    let addr = resolve_public_addr(&url, 1935, "Restream URL").await?;
+                    let reader = rtmp::pull(&url, addr).await?;
+                    let app = url.path_segments().and_then(|mut s| s.next());
+                    (addr.to_string(), app.unwrap_or("").to_string(), reader)
+                }
+                _ => bail!("Restream URL must be an https HLS playlist or an rtmp URL"),
+            };
+        info!("Restreaming {} for user {}", url, user.id);
+        self.restreams.lock().await.push(PulledInput {
+            connection: ConnectionInfo {
+                endpoint: RESTREAM_ENDPOINT.to_string(),
+                ip_addr,
+                app_name,
+                key: user.stream_key.clone(),
+                params: HashMap::from([(PARAM_SOURCE.to_string(), url.to_string())]),
+                ..Default::default()
+            },
+            reader,
+        });
+        Ok(())
+    }
+
+    /// Restreams waiting for their pipeline, see [crate::overseer::Overseer::due_pulls]
+    pub(super) async fn due_restreams(&self) -> Vec<PulledInput> {
+        std::mem::take(&mut *self.restreams.lock().await)
+    }
+}
+
+/// Send the segments of the HLS stream at [source] into [tx] until it ends, stalls or the
+/// pipeline reading them stopped
+///
+/// The highest bandwidth variant of master playlists is pulled. Only playlists and segments
+/// on the host of [source] are fetched, [client] is pinned to its checked address
+async fn pull_hls(client: &reqwest::Client, source: &Url, tx: Sender<Vec<u8>>) -> Result<()> {
+    let mut playlist = source.clone();
+    let mut last_seq: Option<u64> = None;
+    let mut last_map: Option<String> = None;
+    let mut last_new = Instant::now();
+    loop {
+        let data = fetch(client, source, &playlist).await?;
+        let pl = match m3u8_rs::parse_playlist_res(&data) {
+            // only [source] may be a master playlist, its variants are media playlists
+            Ok(Playlist::MasterPlaylist(m)) if playlist == *source => {
+                let Some(v) = m
+                    .variants
+                    .iter()
+                    .filter(|v| !v.is_i_frame)
+                    .max_by_key(|v| v.bandwidth)
+                else {
+                    bail!("Master playlist has no variants");
+                };
+                playlist = playlist.join(&v.uri)?;
+                continue;
+            }
+            Ok(Playlist::MediaPlaylist(pl)) => pl,
+            _ => bail!("Invalid playlist"),
+        };
+        // live playlists start near the live edge, ended ones from the start
+        let skip = match last_seq {
+            None if !pl.end_list => pl.segments.len().saturating_sub(LIVE_EDGE_SEGMENTS),
+            _ => 0,
+        };
+        for (i, s) in pl.segments.iter().enumerate().skip(skip) {
+            let seq = pl.media_sequence + i as u64;
+            if last_seq.is_some_and(|l| seq <= l) {
+                continue;
+            }
+            // init segments (fMP4) are sent once before the segments using them
+            if let Some(m) = &s.map {
+                if last_map.as_ref() != Some(&m.uri) {
+                    let init = fetch(client, source, &playlist.join(&m.uri)?).await?;
+                    if tx.send(init).await.is_err() {
+                        return Ok(());
+                    }
+                    last_map = Some(m.uri.clone());
+                }
+            }
+            let seg = fetch(client, source, &playlist.join(&s.uri)?).await?;
+            if tx.send(seg).await.is_err() {
+                return Ok(());
+            }
+            last_seq = Some(seq);
+            last_new = Instant::now();
+        }
+        if pl.end_list {
+            return Ok(());
+        }
+        if last_new.elapsed() > STALL_TIMEOUT {
+            bail!("No new segments for {}s", STALL_TIMEOUT.as_secs());
+        }
+        // poll about twice per segment
+        tokio::time::sleep(Duration::from_secs(pl.target_duration.max(2)) / 2).await;
+    }
+}
+
+async fn fetch(client: &reqwest::Client, source: &Url, url: &Url) -> Result<Vec<u8>> {
+    if url.scheme() != source.scheme() || url.host() != source.host() || url.port() != source.port()
+    {
+        bail!("{} is not on the host of the restream URL", url);
+    }
+    let rsp = client.get(url.clone()).send().await?.error_for_status()?;
+    if rsp.content_length().is_some_and(|l| l > MAX_FILE_SIZE) {
+        bail!("{} is larger than {} bytes", url, MAX_FILE_SIZE);
+    }
+    let data = rsp.bytes().await?;
+    if data.len() as u64 > MAX_FILE_SIZE {
+        bail!("{} is larger than {} bytes", url, MAX_FILE_SIZE);
+    }
+    Ok(data.to_vec())
+}
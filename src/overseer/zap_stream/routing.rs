@@ -0,0 +1,143 @@
+use crate::settings::{IngestNode, IngestRoutingSettings};
+use anyhow::Result;
+use log::{info, warn};
+use maxminddb::geoip2;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// How often node capacity is polled
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Response of `GET /api/v1/capacity`, which needs no auth so other nodes can poll it
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NodeCapacity {
+    pub active_streams: u64,
+}
+
+/// Latest known state of an ingest node
+#[derive(Clone)]
+struct NodeState {
+    node: IngestNode,
+    /// Active streams / max streams, [None] if the node did not respond
+    load: Option<f64>,
+}
+
+/// Recommended ingest node for a client
+#[derive(Serialize)]
+pub struct IngestRecommendation {
+    pub name: String,
+    pub endpoints: Vec<String>,
+    pub distance_km: Option<f64>,
+    pub load: f64,
+}
+
+/// Recommends the best ingest node for a streamer based on distance and node load
+pub struct IngestRouter {
+    geoip: Option<maxminddb::Reader<Vec<u8>>>,
+    nodes: Arc<RwLock<Vec<NodeState>>>,
+}
+
+impl IngestRouter {
+    pub fn new(settings: &IngestRoutingSettings) -> Result<Self> {
+        let geoip = match &settings.geoip_db {
+            Some(p) => Some(maxminddb::Reader::open_readfile(p)?),
+            None => None,
+        };
+        let nodes = Arc::new(RwLock::new(
+            settings
+                .nodes
+                .iter()
+                .map(|n| NodeState {
+                    node: n.clone(),
+                    load: None,
+                })
+                .collect(),
+        ));
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()?;
+        Self::spawn_poller(client, nodes.clone());
+        Ok(Self { geoip, nodes })
+    }
+
+    fn spawn_poller(client: reqwest::Client, nodes: Arc<RwLock<Vec<NodeState>>>) {
+        tokio::spawn(async move {
+            loop {
+                let list: Vec<IngestNode> =
+                    nodes.read().await.iter().map(|n| n.node.clone()).collect();
+                for (i, node) in list.iter().enumerate() {
+                    let load = match Self::poll_node(&client, node).await {
+                        Ok(l) => Some(l),
+                        Err(e) => {
+                            warn!("Ingest node {} is unhealthy: {}", node.name, e);
+                            None
+                        }
+                    };
+                    if let Some(n) = nodes.write().await.get_mut(i) {
+                        n.load = load;
+                    }
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        });
+    }
+
+    async fn poll_node(client: &reqwest::Client, node: &IngestNode) -> Result<f64> {
+        let cap: NodeCapacity = client
+            .get(&node.capacity_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(cap.active_streams as f64 / node.max_streams.max(1) as f64)
+    }
+
+    /// Pick the healthy node with the lowest distance weighted by load
+    pub async fn best_node(&self, client_ip: Option<IpAddr>) -> Option<IngestRecommendation> {
+        let location = client_ip.and_then(|ip| self.locate(ip));
+        let nodes = self.nodes.read().await;
+        nodes
+            .iter()
+            .filter_map(|n| n.load.filter(|l| *l < 1.0).map(|l| (n, l)))
+            .map(|(n, load)| {
+                let distance = location
+                    .map(|(lat, lon)| haversine_km(lat, lon, n.node.latitude, n.node.longitude));
+                // a fully loaded node counts as 3x further away
+                let score = distance.unwrap_or(1.0) * (1.0 + load * 2.0);
+                (n, load, distance, score)
+            })
+            .min_by(|a, b| a.3.total_cmp(&b.3))
+            .map(|(n, load, distance, _)| {
+                info!(
+                    "Routing {:?} to ingest node {} (load={:.2})",
+                    client_ip, n.node.name, load
+                );
+                IngestRecommendation {
+                    name: n.node.name.clone(),
+                    endpoints: n.node.endpoints.clone(),
+                    distance_km: distance,
+                    load,
+                }
+            })
+    }
+
+    fn locate(&self, ip: IpAddr) -> Option<(f64, f64)> {
+        let city: geoip2::City = self.geoip.as_ref()?.lookup(ip).ok()?;
+        let loc = city.location?;
+        Some((loc.latitude?, loc.longitude?))
+    }
+}
+
+/// Great-circle distance between 2 points in km
+fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    const EARTH_RADIUS_KM: f64 = 6371.0;
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
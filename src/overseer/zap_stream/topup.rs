@@ -0,0 +1,277 @@
+#[cfg(feature = "chaos")]
+use crate::chaos::Chaos;
+use crate::http::BadRequest;
+use crate::overseer::zap_stream::ZapStreamOverseer;
+use crate::settings::RateSource;
+use anyhow::{bail, Result};
+use chrono::Utc;
+use fedimint_tonic_lnd::lnrpc::invoice::InvoiceState;
+use fedimint_tonic_lnd::lnrpc::{Invoice, InvoiceSubscription};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+#[cfg(feature = "chaos")]
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use zap_stream_db::{Payment, TopupInvoice, User, ZapStreamDb};
+
+/// How long fetched exchange rates are reused
+const RATE_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Seconds until topup invoices expire
+const INVOICE_EXPIRY: i64 = 600;
+
+/// Delay before subscribing to LND invoices again after the subscription failed
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(10);
+
+/// Milli-sats per BTC
+const MSATS_PER_BTC: f64 = 100_000_000_000.0;
+
+/// Payment provider name of topups paid to the LND node
+const LIGHTNING_PROVIDER: &str = "lightning";
+
+/// Fiat exchange rates from the configured [RateSource]
+pub(super) struct ExchangeRates {
+    source: RateSource,
+    client: reqwest::Client,
+    /// Fiat price of 1 BTC by currency code, with the time it was fetched
+    cache: RwLock<Option<(Instant, HashMap<String, f64>)>>,
+}
+
+#[derive(Deserialize)]
+struct CoinbaseRates {
+    data: CoinbaseRatesData,
+}
+
+#[derive(Deserialize)]
+struct CoinbaseRatesData {
+    rates: HashMap<String, String>,
+}
+
+impl ExchangeRates {
+    pub fn new(source: RateSource) -> Result<Self> {
+        Ok(Self {
+            source,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()?,
+            cache: RwLock::new(None),
+        })
+    }
+
+    /// Price of 1 BTC in [currency]
+    pub async fn rate(&self, currency: &str) -> Result<f64> {
+        let cached = match &*self.cache.read().await {
+            Some((fetched, rates)) if fetched.elapsed() < RATE_CACHE_TTL => Some(rates.clone()),
+            _ => None,
+        };
+        let rates = match cached {
+            Some(r) => r,
+            None => {
+                let rates = self.fetch().await?;
+                *self.cache.write().await = Some((Instant::now(), rates.clone()));
+                rates
+            }
+        };
+        match rates.get(currency) {
+            Some(r) if *r > 0.0 => Ok(*r),
+            _ => bail!("No exchange rate for {}", currency),
+        }
+    }
+
+    async fn fetch(&self) -> Result<HashMap<String, f64>> {
+        match &self.source {
+            RateSource::Coinbase => {
+                let rsp: CoinbaseRates = self
+                    .client
+                    .get("https://api.coinbase.com/v2/exchange-rates?currency=BTC")
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok(rsp
+                    .data
+                    .rates
+                    .into_iter()
+                    .filter_map(|(k, v)| Some((k, v.parse().ok()?)))
+                    .collect())
+            }
+            RateSource::Custom { url } => Ok(self
+                .client
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?),
+        }
+    }
+}
+
+/// Invoice for a fiat amount converted at the current exchange rate
+#[derive(Serialize)]
+pub(super) struct TopupQuote {
+    currency: String,
+    /// Fiat amount requested
+    amount: f64,
+    /// Price of 1 BTC in [currency]
+    rate: f64,
+    /// Amount of the invoice in milli-sats
+    amount_msats: u64,
+    /// Lightning invoice
+    pr: String,
+    expires: i64,
+}
+
+impl ZapStreamOverseer {
+    /// Create a topup invoice for [amount] of fiat [currency]
+    pub(super) async fn topup_quote(
+        &self,
+        user: &User,
+        currency: &str,
+        amount: f64,
+    ) -> Result<TopupQuote> {
+        if !amount.is_finite() || amount <= 0.0 {
+            bail!("Amount must be positive");
+        }
+        let currency = currency.to_uppercase();
+        let rate = self.exchange_rates.rate(&currency).await?;
+        // whole sats, lightning wallets can't always pay msat amounts
+        let sats = (amount / rate * MSATS_PER_BTC / 1000.0).round() as u64;
+        if sats == 0 {
+            bail!("Amount is less than 1 sat");
+        }
+        let memo = format!("Topup {:.2} {}", amount, currency);
+        let amount_msats = sats
+            .checked_mul(1000)
+            .ok_or_else(|| BadRequest("Amount is too large".to_string()))?;
+        let (pr, expires) = self.create_topup_invoice(user, amount_msats, &memo).await?;
+        Ok(TopupQuote {
+            currency,
+            amount,
+            rate,
+            amount_msats,
+            pr,
+            expires,
+        })
+    }
+
+    /// Create an invoice which credits [amount] milli-sats to [user] once paid, returns the
+    /// payment request and its expiry
+    pub(super) async fn create_topup_invoice(
+        &self,
+        user: &User,
+        amount: u64,
+        memo: &str,
+    ) -> Result<(String, i64)> {
+        let (payment_hash, pr) = match &self.lnd {
+            Some(lnd) => {
+                #[cfg(feature = "chaos")]
+                self.chaos.delay_lnd().await;
+                let invoice = lnd
+                    .clone()
+                    .lightning()
+                    .add_invoice(Invoice {
+                        memo: memo.to_string(),
+                        value_msat: amount as i64,
+                        expiry: INVOICE_EXPIRY,
+                        ..Default::default()
+                    })
+                    .await?
+                    .into_inner();
+                (invoice.r_hash, invoice.payment_request)
+            }
+            // dry run, the simulated invoice is never paid
+            None => {
+                let hash: [u8; 32] = rand::random();
+                let pr = format!("lnbcdryrun{}", hex::encode(hash));
+                (hash.to_vec(), pr)
+            }
+        };
+        self.db
+            .insert_topup_invoice(&TopupInvoice {
+                payment_hash: hex::encode(&payment_hash),
+                user_id: user.id,
+                amount,
+                ..Default::default()
+            })
+            .await?;
+        Ok((pr, Utc::now().timestamp() + INVOICE_EXPIRY))
+    }
+}
+
+/// Credit topup invoices as they are paid, resubscribing when the subscription fails
+pub(super) fn spawn_invoice_listener(
+    lnd: fedimint_tonic_lnd::Client,
+    db: ZapStreamDb,
+    #[cfg(feature = "chaos")] chaos: Arc<Chaos>,
+) {
+    tokio::spawn(async move {
+        let mut lnd = lnd;
+        // replay invoices settled before startup once, crediting is idempotent
+        let mut settle_index = 1;
+        loop {
+            #[cfg(feature = "chaos")]
+            chaos.delay_lnd().await;
+            let res = listen_invoices(
+                &mut lnd,
+                &db,
+                &mut settle_index,
+                #[cfg(feature = "chaos")]
+                &chaos,
+            )
+            .await;
+            if let Err(e) = res {
+                warn!("LND invoice subscription failed: {}", e);
+            }
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    });
+}
+
+async fn listen_invoices(
+    lnd: &mut fedimint_tonic_lnd::Client,
+    db: &ZapStreamDb,
+    settle_index: &mut u64,
+    #[cfg(feature = "chaos")] chaos: &Chaos,
+) -> Result<()> {
+    let mut invoices = lnd
+        .lightning()
+        .subscribe_invoices(InvoiceSubscription {
+            settle_index: *settle_index,
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    while let Some(invoice) = invoices.message().await? {
+        if invoice.state() != InvoiceState::Settled {
+            continue;
+        }
+        #[cfg(feature = "chaos")]
+        chaos.delay_lnd().await;
+        *settle_index = (*settle_index).max(invoice.settle_index);
+        let hash = hex::encode(&invoice.r_hash);
+        let topup = match db.get_topup_invoice(&hash).await? {
+            Some(t) => t,
+            None => continue,
+        };
+        let credited = db
+            .credit_payment(&Payment {
+                user_id: topup.user_id,
+                provider: LIGHTNING_PROVIDER.to_string(),
+                external_id: hash.clone(),
+                amount: topup.amount,
+                ..Default::default()
+            })
+            .await?;
+        if credited {
+            info!(
+                "Credited {} msats to user {} from topup invoice {}",
+                topup.amount, topup.user_id, hash
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use crate::overseer::zap_stream::ZapStreamOverseer;
+use crate::pipeline::PipelineConfig;
+use crate::variant::VariantStream;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+use zap_stream_db::{User, UserStream, UserStreamState};
+
+/// Default number of months in the usage breakdown
+pub(super) const DEFAULT_USAGE_MONTHS: u32 = 12;
+
+/// Max number of months in the usage breakdown
+pub(super) const MAX_USAGE_MONTHS: u32 = 36;
+
+/// Usage of one calendar month (UTC), streams count towards the month they started in
+#[derive(Serialize, Default)]
+pub(super) struct MonthUsage {
+    /// Month in `YYYY-MM` format
+    month: String,
+    streams: u32,
+    /// Minutes live
+    streamed_minutes: f32,
+    /// Minutes live with transcoded video variants
+    transcoded_minutes: f32,
+    /// Size of the recordings currently stored for the month's streams
+    recording_bytes: u64,
+    /// Amount charged in milli-sats
+    spend: u64,
+}
+
+impl ZapStreamOverseer {
+    /// Usage of [user] over the last [months] months including the current one, oldest first
+    ///
+    /// Every month is included, months without streams are reported with zero usage
+    pub(super) async fn account_usage(&self, user: &User, months: u32) -> Result<Vec<MonthUsage>> {
+        let now = Utc::now();
+        let (mut year, mut month) = (now.year(), now.month());
+        let mut starts: Vec<DateTime<Utc>> = Vec::new();
+        for _ in 0..months.max(1) {
+            starts.push(
+                Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0)
+                    .single()
+                    .ok_or(anyhow!("Invalid month {}-{}", year, month))?,
+            );
+            if month == 1 {
+                year -= 1;
+                month = 12;
+            } else {
+                month -= 1;
+            }
+        }
+        starts.reverse();
+
+        let mut usage: Vec<MonthUsage> = starts
+            .iter()
+            .map(|s| MonthUsage {
+                month: s.format("%Y-%m").to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let streams = self.db.list_user_streams_since(user.id, starts[0]).await?;
+        for stream in streams {
+            let idx = starts.partition_point(|s| *s <= stream.starts).max(1) - 1;
+            let minutes = Self::stream_minutes(&stream, now);
+            let u = &mut usage[idx];
+            u.streams += 1;
+            u.streamed_minutes += minutes;
+            if Self::is_transcoded(&stream) {
+                u.transcoded_minutes += minutes;
+            }
+            u.recording_bytes += self.recording_size(&Uuid::parse_str(&stream.id)?).await;
+            u.spend += stream.cost;
+        }
+        Ok(usage)
+    }
+
+    /// Wall clock minutes [stream] was live
+    fn stream_minutes(stream: &UserStream, now: DateTime<Utc>) -> f32 {
+        let ends = match stream.ends {
+            Some(e) => e,
+            None if stream.state == UserStreamState::Live => now,
+            None => stream.last_segment.unwrap_or(stream.starts),
+        };
+        (ends - stream.starts).num_seconds().max(0) as f32 / 60.0
+    }
+
+    /// True if the stream's pipeline transcoded video, streams without a stored pipeline
+    /// config are counted as not transcoded
+    fn is_transcoded(stream: &UserStream) -> bool {
+        stream
+            .pipeline
+            .as_deref()
+            .and_then(|p| serde_json::from_str::<PipelineConfig>(p).ok())
+            .is_some_and(|p| {
+                p.variants
+                    .iter()
+                    .any(|v| matches!(v, VariantStream::Video(_)))
+            })
+    }
+
+    /// Total size of the recording parts and trimmed recording of a stream
+    async fn recording_size(&self, stream_id: &Uuid) -> u64 {
+        let mut size = 0;
+        let mut part = 0;
+        while let Ok(m) = tokio::fs::metadata(self.recording_path(stream_id, part)).await {
+            size += m.len();
+            part += 1;
+        }
+        if let Ok(m) = tokio::fs::metadata(self.trimmed_recording_path(stream_id)).await {
+            size += m.len();
+        }
+        size
+    }
+}
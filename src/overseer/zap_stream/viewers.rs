@@ -0,0 +1,136 @@
+use crate::overseer::zap_stream::ZapStreamOverseer;
+use anyhow::Result;
+use log::{info, warn};
+use nostr_sdk::JsonUtil;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use zap_stream_db::{StreamVisibility, UserStreamState};
+
+/// Viewers which have not loaded a playlist for this long are no longer counted, players
+/// reload live playlists every segment
+const VIEWER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Number of samples (one per stream check) averaged into the published count
+const SMOOTHING_SAMPLES: usize = 6;
+
+/// Min time between publishing new viewer counts of one stream
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Max viewer sessions tracked per stream, requests of new sessions beyond this are not
+/// counted
+const MAX_SESSIONS: usize = 100_000;
+
+#[derive(Default)]
+struct StreamViewers {
+    /// Last playlist request by viewer
+    sessions: HashMap<String, Instant>,
+    /// Recent distinct viewer counts, oldest first
+    samples: VecDeque<usize>,
+    /// Count in the current stream event, with the time it was published
+    published: Option<(usize, Instant)>,
+    /// Highest smoothed count
+    peak: usize,
+    /// Sum of all samples, with [sampled] the average count
+    total: u64,
+    sampled: u64,
+}
+
+/// Counts distinct viewers of live streams from their playlist requests
+///
+/// Viewers are de-duplicated by session so players polling several playlists or
+/// prefetching don't inflate the count, the count is averaged over a sliding window so it
+/// doesn't jump around as viewers come and go
+#[derive(Default)]
+pub(super) struct ViewerCounter {
+    streams: Mutex<HashMap<Uuid, StreamViewers>>,
+}
+
+impl ViewerCounter {
+    /// Mark [viewer] as watching [stream_id]
+    pub fn seen(&self, stream_id: &Uuid, viewer: &str) {
+        let mut streams = self.streams.lock().unwrap();
+        let s = streams.entry(*stream_id).or_default();
+        if s.sessions.len() < MAX_SESSIONS || s.sessions.contains_key(viewer) {
+            s.sessions.insert(viewer.to_string(), Instant::now());
+        }
+    }
+
+    /// Sample the number of viewers of [stream_id], returns the smoothed count when it
+    /// should be published
+    fn sample(&self, stream_id: &Uuid) -> Option<usize> {
+        let mut streams = self.streams.lock().unwrap();
+        let s = streams.entry(*stream_id).or_default();
+        s.sessions.retain(|_, t| t.elapsed() < VIEWER_TIMEOUT);
+        s.samples.push_back(s.sessions.len());
+        while s.samples.len() > SMOOTHING_SAMPLES {
+            s.samples.pop_front();
+        }
+        let count = (s.samples.iter().sum::<usize>() as f32 / s.samples.len() as f32).round();
+        let count = count as usize;
+        s.peak = s.peak.max(count);
+        s.total += s.sessions.len() as u64;
+        s.sampled += 1;
+        match s.published {
+            Some((c, _)) if c == count => None,
+            Some((_, t)) if t.elapsed() < PUBLISH_INTERVAL => None,
+            _ => {
+                s.published = Some((count, Instant::now()));
+                Some(count)
+            }
+        }
+    }
+
+    /// Viewer count of the current stream event of [stream_id]
+    pub fn current(&self, stream_id: &Uuid) -> Option<usize> {
+        self.streams
+            .lock()
+            .unwrap()
+            .get(stream_id)
+            .and_then(|s| s.published)
+            .map(|(c, _)| c)
+    }
+
+    /// Stop counting viewers of an ended stream, returns the (peak, average) viewers if
+    /// the stream was sampled
+    pub fn remove(&self, stream_id: &Uuid) -> Option<(u32, f32)> {
+        let s = self.streams.lock().unwrap().remove(stream_id)?;
+        if s.sampled == 0 {
+            return None;
+        }
+        Some((s.peak as u32, s.total as f32 / s.sampled as f32))
+    }
+}
+
+impl ZapStreamOverseer {
+    /// Sample the viewers of all live streams and publish the stream events of streams
+    /// whose smoothed viewer count changed
+    pub(super) async fn publish_viewer_counts(&self) {
+        let ids: Vec<Uuid> = self.active_streams.read().await.iter().copied().collect();
+        for id in ids {
+            let count = match self.viewers.sample(&id) {
+                Some(c) => c,
+                None => continue,
+            };
+            if let Err(e) = self.publish_viewer_count(&id).await {
+                warn!("Failed to publish viewer count of {}: {}", id, e);
+            } else {
+                info!("Stream {} has {} viewers", id, count);
+            }
+        }
+    }
+
+    async fn publish_viewer_count(&self, stream_id: &Uuid) -> Result<()> {
+        let mut stream = self.db.get_stream(stream_id).await?;
+        if stream.state != UserStreamState::Live || stream.visibility != StreamVisibility::Public {
+            return Ok(());
+        }
+        let user = self.db.get_user(stream.user_id).await?;
+        let ev = self.sign_stream_event(&stream, &user.pubkey)?;
+        self.send_event(ev.clone()).await?;
+        stream.event = Some(ev.as_json());
+        self.db.update_stream(&stream).await?;
+        Ok(())
+    }
+}
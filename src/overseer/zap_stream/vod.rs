@@ -0,0 +1,364 @@
+use crate::egress::recorder::RecordingFile;
+use crate::mux::{save_thumbnail, trim_recording};
+use crate::overseer::zap_stream::webhooks::public_client;
+use crate::overseer::zap_stream::{ZapStreamOverseer, TRIMMED_RECORDING, WATCH_URL};
+use crate::pipeline::PipelineConfig;
+use crate::variant::{StreamMapping, VariantStream};
+use anyhow::{bail, Result};
+use chrono::Utc;
+use http_body_util::BodyExt;
+use hyper::body::Incoming;
+use log::{info, warn};
+use nostr_sdk::{Event, EventBuilder, JsonUtil, Kind, Tag};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+use zap_stream_db::{StreamVisibility, User, UserStream, UserStreamState};
+
+/// NIP-71 (normal) video event
+const VIDEO_EVENT_KIND: u16 = 21;
+
+/// Max size of imported recordings
+const MAX_VOD_SIZE: u64 = 8 * 1024 * 1024 * 1024;
+
+/// Max time to download a recording imported by URL
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Max length of VOD titles and summaries
+const MAX_VOD_TEXT: usize = 1000;
+
+/// Recording referenced by URL
+#[derive(Deserialize)]
+pub(super) struct VodImport {
+    url: String,
+    #[serde(flatten)]
+    info: VodInfo,
+}
+
+#[derive(Deserialize, Default)]
+pub(super) struct VodInfo {
+    pub title: Option<String>,
+    pub summary: Option<String>,
+    /// Publish a NIP-71 video event
+    #[serde(default)]
+    pub publish: bool,
+}
+
+/// Variant of a video in its NIP-71 event
+struct VideoFile {
+    url: String,
+    /// Width, height
+    dim: Option<(u16, u16)>,
+}
+
+#[derive(Serialize)]
+pub(super) struct ApiVod {
+    id: String,
+    /// Duration in seconds
+    duration: f32,
+    url: String,
+    thumb: Option<String>,
+    /// Id of the NIP-71 video event, if published
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event_id: Option<String>,
+}
+
+impl ZapStreamOverseer {
+    /// Check [user] may import recordings, imports take up to [MAX_VOD_SIZE] of disk space
+    /// so they need the same funded, unblocked account as going live
+    fn check_vod_import(user: &User) -> Result<()> {
+        if user.is_blocked {
+            bail!("Account is blocked");
+        }
+        if user.balance <= 0 && user.cost_multiplier > 0.0 {
+            bail!("Balance exhausted, top up your account to import recordings");
+        }
+        Ok(())
+    }
+
+    /// Import the MP4 uploaded in [body] as a VOD of [user]
+    pub(super) async fn upload_vod(
+        &self,
+        user: &User,
+        mut body: Incoming,
+        info: VodInfo,
+    ) -> Result<ApiVod> {
+        Self::check_vod_import(user)?;
+        let id = Uuid::new_v4();
+        let tmp = self.vod_upload_path(&id).await?;
+        let res = async {
+            let mut f = File::create(&tmp).await?;
+            let mut size = 0u64;
+            while let Some(frame) = body.frame().await {
+                if let Some(data) = frame?.data_ref() {
+                    size += data.len() as u64;
+                    if size > MAX_VOD_SIZE {
+                        bail!("Recording is larger than {} bytes", MAX_VOD_SIZE);
+                    }
+                    f.write_all(data).await?;
+                }
+            }
+            f.flush().await?;
+            Ok(())
+        }
+        .await;
+        self.finish_vod_import(user, &id, &tmp, res, info).await
+    }
+
+    /// Download the MP4 at [import.url] and import it as a VOD of [user]
+    pub(super) async fn import_vod(&self, user: &User, import: VodImport) -> Result<ApiVod> {
+        Self::check_vod_import(user)?;
+        let client = public_client(&import.url, "Recording URL", DOWNLOAD_TIMEOUT).await?;
+        let id = Uuid::new_v4();
+        let tmp = self.vod_upload_path(&id).await?;
+        let res = async {
+            let mut rsp = client.get(&import.url).send().await?;
+            // redirects are not followed, see [public_client]
+            if !rsp.status().is_success() {
+                bail!("Recording URL returned {}", rsp.status());
+            }
+            if rsp.content_length().is_some_and(|l| l > MAX_VOD_SIZE) {
+                bail!("Recording is larger than {} bytes", MAX_VOD_SIZE);
+            }
+            let mut f = File::create(&tmp).await?;
+            let mut size = 0u64;
+            while let Some(chunk) = rsp.chunk().await? {
+                size += chunk.len() as u64;
+                if size > MAX_VOD_SIZE {
+                    bail!("Recording is larger than {} bytes", MAX_VOD_SIZE);
+                }
+                f.write_all(&chunk).await?;
+            }
+            f.flush().await?;
+            Ok(())
+        }
+        .await;
+        self.finish_vod_import(user, &id, &tmp, res, import.info)
+            .await
+    }
+
+    /// Temp file an imported recording is written to before it is processed
+    async fn vod_upload_path(&self, id: &Uuid) -> Result<PathBuf> {
+        let dir = PathBuf::from(&self.out_dir).join(id.to_string());
+        tokio::fs::create_dir_all(&dir).await?;
+        Ok(dir.join("upload.tmp"))
+    }
+
+    /// Remux the recording at [tmp] and extract its thumbnail, then register it as an
+    /// ended stream so it shows up with the user's past streams
+    async fn finish_vod_import(
+        &self,
+        user: &User,
+        id: &Uuid,
+        tmp: &Path,
+        upload: Result<()>,
+        info: VodInfo,
+    ) -> Result<ApiVod> {
+        let dir = tmp.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        let res = match upload {
+            Ok(()) => self.register_vod(user, id, tmp, &dir, info).await,
+            Err(e) => Err(e),
+        };
+        let _ = tokio::fs::remove_file(tmp).await;
+        if res.is_err() {
+            let _ = tokio::fs::remove_dir_all(&dir).await;
+        }
+        res
+    }
+
+    async fn register_vod(
+        &self,
+        user: &User,
+        id: &Uuid,
+        tmp: &Path,
+        dir: &Path,
+        info: VodInfo,
+    ) -> Result<ApiVod> {
+        if info.title.as_ref().is_some_and(|t| t.len() > MAX_VOD_TEXT)
+            || info
+                .summary
+                .as_ref()
+                .is_some_and(|s| s.len() > MAX_VOD_TEXT)
+        {
+            bail!("Title and summary must be at most {} bytes", MAX_VOD_TEXT);
+        }
+        // the remux checks the upload is a playable recording and moves the index to the
+        // front so players can start before the whole file is loaded
+        let (src, dst) = (tmp.to_path_buf(), dir.join(TRIMMED_RECORDING));
+        let duration =
+            tokio::task::spawn_blocking(move || trim_recording(&src, &dst, 0.0, None)).await??;
+        let (src, thumb) = (dir.join(TRIMMED_RECORDING), dir.join("thumb.webp"));
+        let has_thumb =
+            match tokio::task::spawn_blocking(move || save_thumbnail(&src, &thumb)).await? {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Failed to extract thumbnail of VOD {}: {}", id, e);
+                    false
+                }
+            };
+
+        let now = Utc::now();
+        let mut vod = UserStream {
+            id: id.to_string(),
+            user_id: user.id,
+            starts: now,
+            ends: Some(now),
+            state: UserStreamState::Ended,
+            title: info.title,
+            summary: info.summary,
+            duration,
+            visibility: user.visibility,
+            listed: user.listed,
+            // the remuxed upload is the published recording of the VOD
+            recording: Some(TRIMMED_RECORDING.to_string()),
+            ..Default::default()
+        };
+        let url = self.map_to_public_url(&vod, TRIMMED_RECORDING)?;
+        let thumb = if has_thumb {
+            Some(self.map_to_public_url(&vod, "thumb.webp")?)
+        } else {
+            None
+        };
+        // unlisted recordings are only reachable with a share link
+        let event = if info.publish && vod.visibility == StreamVisibility::Public {
+            let file = VideoFile {
+                url: url.clone(),
+                dim: None,
+            };
+            Some(self.video_event(&vod, user, &[file], duration, thumb.as_deref())?)
+        } else {
+            None
+        };
+        vod.event = event.as_ref().map(|e| e.as_json());
+        self.db.insert_vod(&vod).await?;
+        if let Some(ev) = &event {
+            // the VOD is imported either way
+            if let Err(e) = self.send_event(ev.clone()).await {
+                warn!("Failed to publish video event of VOD {}: {}", vod.id, e);
+            }
+        }
+        info!(
+            "Imported VOD {} of user {} ({:.1}s)",
+            vod.id, user.id, duration
+        );
+        Ok(ApiVod {
+            id: vod.id,
+            duration,
+            url,
+            thumb,
+            event_id: event.map(|e| e.id.to_hex()),
+        })
+    }
+
+    /// Attach the MP4 [files] of a stream recording to the stream, public streams get a
+    /// NIP-71 video event with one variant per file
+    pub(super) async fn publish_recording(
+        &self,
+        stream_id: &Uuid,
+        files: &[RecordingFile],
+    ) -> Result<()> {
+        let stream = self.db.get_stream(stream_id).await?;
+        let pipeline: PipelineConfig = match &stream.pipeline {
+            Some(p) => serde_json::from_str(p)?,
+            None => bail!("Stream {} has no pipeline config", stream.id),
+        };
+        let mut videos = vec![];
+        for f in files {
+            let name = match f.path.file_name() {
+                Some(n) => n.to_string_lossy().to_string(),
+                None => continue,
+            };
+            let dim = pipeline.variants.iter().find_map(|v| match v {
+                VariantStream::Video(v) if v.id() == f.variant => Some((v.width, v.height)),
+                _ => None,
+            });
+            videos.push((name, dim, f.duration));
+        }
+        // largest variant first, it's the one attached to the stream
+        videos.sort_by_key(|(_, dim, _)| std::cmp::Reverse(dim.map(|(_, h)| h)));
+        let (name, _, duration) = match videos.first() {
+            Some(v) => v.clone(),
+            None => bail!("No recordings of stream {}", stream.id),
+        };
+        self.db.update_stream_recording(&stream.id, &name).await?;
+        info!(
+            "Recording of stream {} attached ({} variants, {:.1}s)",
+            stream.id,
+            videos.len(),
+            duration
+        );
+
+        if stream.visibility != StreamVisibility::Public {
+            return Ok(());
+        }
+        let user = self.db.get_user(stream.user_id).await?;
+        let files = videos
+            .iter()
+            .map(|(name, dim, _)| {
+                Ok(VideoFile {
+                    url: self.map_to_public_url(&stream, name.as_str())?,
+                    dim: *dim,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let thumb = stream.thumb.clone().or(stream.image.clone());
+        let ev = self.video_event(&stream, &user, &files, duration, thumb.as_deref())?;
+        self.send_event(ev).await?;
+        Ok(())
+    }
+
+    /// NIP-71 video event of a recording, [files] are variants of the same video
+    fn video_event(
+        &self,
+        vod: &UserStream,
+        user: &User,
+        files: &[VideoFile],
+        duration: f32,
+        thumb: Option<&str>,
+    ) -> Result<Event> {
+        let title = vod.title.clone().unwrap_or_default();
+        let mut tags = vec![
+            Tag::parse(&["title".to_string(), title.clone()])?,
+            Tag::parse(&[
+                "published_at".to_string(),
+                vod.starts.timestamp().to_string(),
+            ])?,
+            Tag::parse(&[
+                "duration".to_string(),
+                (duration.round() as u64).to_string(),
+            ])?,
+        ];
+        for f in files {
+            let mut imeta = vec![
+                "imeta".to_string(),
+                format!("url {}", f.url),
+                "m video/mp4".to_string(),
+            ];
+            if let Some((w, h)) = f.dim {
+                imeta.push(format!("dim {}x{}", w, h));
+            }
+            if let Some(t) = thumb {
+                imeta.push(format!("image {}", t));
+            }
+            tags.push(Tag::parse(&imeta)?);
+        }
+        tags.push(Tag::parse(&[
+            "p",
+            hex::encode(&user.pubkey).as_str(),
+            "",
+            "host",
+        ])?);
+        tags.push(Tag::parse(&[
+            "alt",
+            &format!("Video on {}: {}", WATCH_URL, title),
+        ])?);
+        let content = vod.summary.clone().unwrap_or_default();
+        Ok(
+            EventBuilder::new(Kind::from(VIDEO_EVENT_KIND), content, tags)
+                .sign_with_keys(self.keys.active())?,
+        )
+    }
+}
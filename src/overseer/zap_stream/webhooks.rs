@@ -0,0 +1,217 @@
+use crate::overseer::zap_stream::ZapStreamOverseer;
+use anyhow::{bail, Result};
+use hmac::{Hmac, Mac};
+use log::warn;
+use nostr_sdk::Event;
+use serde::Serialize;
+use sha2::Sha256;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use url::{Host, Url};
+use zap_stream_db::{User, UserStream};
+
+/// Header carrying the hex HMAC-SHA256 of the request body, keyed with the webhook secret
+const SIGNATURE_HEADER: &str = "x-zap-stream-signature";
+
+/// Attempts to deliver a webhook before it is dropped
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry, doubled for each following attempt
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Max time to deliver a webhook
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum StreamChange {
+    Create,
+    Update,
+    End,
+}
+
+/// Body of a stream webhook
+#[derive(Serialize)]
+struct StreamWebhook<'a> {
+    #[serde(rename = "type")]
+    change: StreamChange,
+    stream_id: &'a str,
+    /// The new stream event (kind 30311)
+    event: &'a Event,
+    created: i64,
+}
+
+/// Check a webhook URL set by a user, only https URLs outside private networks are accepted
+pub(super) async fn validate_webhook_url(url: &str) -> Result<()> {
+    resolve_public_url(url, "Webhook URL").await?;
+    Ok(())
+}
+
+/// Check a URL the server will fetch or call on behalf of a user, only https URLs whose
+/// host resolves to public addresses only are accepted. [name] describes the URL in errors
+///
+/// Returns the address to connect to, see [public_client]
+pub(super) async fn resolve_public_url(url: &str, name: &str) -> Result<(Url, SocketAddr)> {
+    let u: Url = url.parse()?;
+    if u.scheme() != "https" {
+        bail!("{} must use https", name);
+    }
+    let addr = resolve_public_addr(&u, 443, name).await?;
+    Ok((u, addr))
+}
+
+/// Address to connect to for [u], the port is [default_port] if the URL has none. Fails if
+/// any address of the host is not public, see [resolve_public_url]
+pub(super) async fn resolve_public_addr(
+    u: &Url,
+    default_port: u16,
+    name: &str,
+) -> Result<SocketAddr> {
+    let port = u.port_or_known_default().unwrap_or(default_port);
+    let addrs: Vec<SocketAddr> = match u.host() {
+        Some(Host::Ipv4(ip)) => vec![SocketAddr::new(IpAddr::V4(ip), port)],
+        Some(Host::Ipv6(ip)) => vec![SocketAddr::new(IpAddr::V6(ip), port)],
+        Some(Host::Domain(d)) => tokio::net::lookup_host((d, port)).await?.collect(),
+        None => bail!("{} has no host", name),
+    };
+    // every address is checked, a host may resolve to a public and a private address
+    if addrs.iter().any(|a| !is_public_ip(a.ip())) {
+        bail!("{} must not point to a private address", name);
+    }
+    match addrs.first() {
+        Some(a) => Ok(*a),
+        None => bail!("{} does not resolve to an address", name),
+    }
+}
+
+/// Client for a request to [url] on behalf of a user, see [resolve_public_url]
+///
+/// The connection is pinned to the checked address so the host can't be re-resolved to a
+/// private address, and redirects are not followed as their targets are not checked
+pub(super) async fn public_client(
+    url: &str,
+    name: &str,
+    timeout: Duration,
+) -> Result<reqwest::Client> {
+    let (u, addr) = resolve_public_url(url, name).await?;
+    let mut builder = reqwest::Client::builder()
+        .timeout(timeout)
+        .redirect(reqwest::redirect::Policy::none());
+    if let Some(Host::Domain(d)) = u.host() {
+        builder = builder.resolve(d, addr);
+    }
+    Ok(builder.build()?)
+}
+
+/// [ip] is globally routable, not a private, loopback, link-local or otherwise reserved
+/// address
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // 0.0.0.0/8, 100.64.0.0/10 (CGNAT), 198.18.0.0/15 (benchmarking), 240.0.0.0/4
+                || a == 0
+                || (a == 100 && (b & 0xc0) == 64)
+                || (a == 198 && (b & 0xfe) == 18)
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_public_ip(IpAddr::V4(v4));
+            }
+            let s = ip.segments();
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // fc00::/7 unique local
+                || (s[0] & 0xfe00) == 0xfc00
+                // fe80::/10 link-local
+                || (s[0] & 0xffc0) == 0xfe80
+                // ::/96 IPv4-compatible, 64:ff9b::/96 NAT64 and 2001:db8::/32 documentation
+                || s[..6] == [0; 6]
+                || s[..6] == [0x64, 0xff9b, 0, 0, 0, 0]
+                || s[..2] == [0x2001, 0xdb8])
+        }
+    }
+}
+
+impl ZapStreamOverseer {
+    /// Notify the webhook of [user] about a change of [stream], delivered in the background
+    pub(super) fn send_stream_webhook(
+        &self,
+        user: &User,
+        stream: &UserStream,
+        change: StreamChange,
+        event: &Event,
+    ) {
+        let (url, secret) = match (&user.webhook_url, &user.webhook_secret) {
+            (Some(u), Some(s)) => (u.clone(), s.clone()),
+            _ => return,
+        };
+        let body = match serde_json::to_vec(&StreamWebhook {
+            change,
+            stream_id: &stream.id,
+            event,
+            created: event.created_at.as_u64() as i64,
+        }) {
+            Ok(b) => b,
+            Err(e) => {
+                warn!("Failed to encode webhook for stream {}: {}", stream.id, e);
+                return;
+            }
+        };
+        let stream_id = stream.id.clone();
+        tokio::spawn(async move {
+            // the host is resolved again for each delivery, it may have changed since the
+            // URL was set
+            let client = match public_client(&url, "Webhook URL", WEBHOOK_TIMEOUT).await {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!("Dropped webhook for stream {} to {}: {}", stream_id, url, e);
+                    return;
+                }
+            };
+            let signature = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+                Ok(mut mac) => {
+                    mac.update(&body);
+                    hex::encode(mac.finalize().into_bytes())
+                }
+                Err(e) => {
+                    warn!("Invalid webhook secret: {}", e);
+                    return;
+                }
+            };
+            let mut delay = RETRY_DELAY;
+            for attempt in 1..=MAX_ATTEMPTS {
+                let rsp = client
+                    .post(&url)
+                    .header("content-type", "application/json")
+                    .header(SIGNATURE_HEADER, &signature)
+                    .body(body.clone())
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status());
+                match rsp {
+                    Ok(_) => return,
+                    Err(e) if attempt == MAX_ATTEMPTS => {
+                        warn!(
+                            "Giving up on {:?} webhook for stream {} to {}: {}",
+                            change, stream_id, url, e
+                        );
+                    }
+                    Err(_) => {
+                        tokio::time::sleep(delay).await;
+                        delay *= 2;
+                    }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::{anyhow, bail, Result};
+use nostr_sdk::{Event, JsonUtil, Kind};
+use sha2::{Digest, Sha256};
+
+/// Milli-sats per BTC
+const MSATS_PER_BTC: u64 = 100_000_000_000;
+
+/// Bech32 alphabet, the index of a char is its 5-bit value
+const BECH32_CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Zap receipt which passed NIP-57 validation
+pub(super) struct ZapReceipt {
+    /// Zap request signed by the sender
+    pub request: Event,
+    /// Amount of the paid invoice in milli-sats
+    pub amount: u64,
+}
+
+impl ZapReceipt {
+    /// Validate a zap receipt and the zap request embedded in it (NIP-57 appendix D / E)
+    ///
+    /// Both events must be signed, the request must name the relays to publish the receipt
+    /// to and one recipient, which is also the recipient of the receipt, the paid invoice
+    /// must commit to the request with its description hash and the amount requested (if
+    /// set) must match the amount of the paid invoice
+    pub fn validate(receipt: &Event) -> Result<Self> {
+        if receipt.kind != Kind::ZapReceipt {
+            bail!("Not a zap receipt: kind {}", receipt.kind);
+        }
+        receipt.verify()?;
+
+        let description = match tag_value(receipt, "description") {
+            Some(d) => d,
+            None => bail!("Zap receipt has no description"),
+        };
+        let request = Event::from_json(description)?;
+        if request.kind != Kind::ZapRequest {
+            bail!("Zap receipt description is not a zap request");
+        }
+        request.verify()?;
+
+        if tag_value(&request, "relays").is_none() {
+            bail!("Zap request has no relays");
+        }
+        let recipients: Vec<&str> = tag_values(&request, "p").collect();
+        if recipients.len() != 1 {
+            bail!("Zap request must have exactly one p tag");
+        }
+        if tag_values(&request, "e").count() > 1 {
+            bail!("Zap request must have at most one e tag");
+        }
+        if tag_value(receipt, "p") != Some(recipients[0]) {
+            bail!("Zap receipt recipient doesn't match the zap request");
+        }
+
+        let pr = match tag_value(receipt, "bolt11") {
+            Some(pr) => pr,
+            None => bail!("Zap receipt has no bolt11 invoice"),
+        };
+        if bolt11_description_hash(pr)?[..] != Sha256::digest(description.as_bytes())[..] {
+            bail!("Zap invoice description hash doesn't match the zap request");
+        }
+        let amount = bolt11_amount(pr)?;
+        if let Some(requested) = tag_value(&request, "amount") {
+            let requested: u64 = requested.parse()?;
+            if requested != amount {
+                bail!(
+                    "Zap request amount {} doesn't match the invoice amount {}",
+                    requested,
+                    amount
+                );
+            }
+        }
+        Ok(Self { request, amount })
+    }
+}
+
+fn tag_values<'a>(ev: &'a Event, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    ev.tags.iter().filter_map(move |t| match t.as_slice() {
+        [k, v, ..] if k == name => Some(v.as_str()),
+        _ => None,
+    })
+}
+
+fn tag_value<'a>(ev: &'a Event, name: &'a str) -> Option<&'a str> {
+    tag_values(ev, name).next()
+}
+
+/// Amount of a BOLT11 invoice in milli-sats, from the human readable part (`lnbc2500u1..`)
+fn bolt11_amount(pr: &str) -> Result<u64> {
+    let pr = pr.to_lowercase();
+    let hrp = match pr.rsplit_once('1') {
+        Some((hrp, _)) => hrp,
+        None => bail!("Invalid bolt11 invoice"),
+    };
+    let hrp = match hrp.strip_prefix("ln") {
+        Some(h) => h,
+        None => bail!("Invalid bolt11 invoice"),
+    };
+    // skip the currency prefix (bc, tb, bcrt ..)
+    let amount = hrp.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    if amount.is_empty() {
+        bail!("Zap invoice has no amount");
+    }
+    let (digits, multiplier) = match amount.chars().last() {
+        Some(c) if c.is_ascii_alphabetic() => (&amount[..amount.len() - 1], Some(c)),
+        _ => (amount, None),
+    };
+    let value: u64 = digits.parse()?;
+    let msats = match multiplier {
+        None => value.checked_mul(MSATS_PER_BTC),
+        Some('m') => value.checked_mul(MSATS_PER_BTC / 1_000),
+        Some('u') => value.checked_mul(MSATS_PER_BTC / 1_000_000),
+        Some('n') => value.checked_mul(MSATS_PER_BTC / 1_000_000_000),
+        Some('p') if value % 10 == 0 => Some(value / 10),
+        _ => None,
+    };
+    msats.ok_or(anyhow!("Invalid bolt11 amount: {}", amount))
+}
+
+/// Description hash (`h` field) of a BOLT11 invoice
+///
+/// The bech32 checksum is not checked, the invoice is only trusted as far as the receipt
+/// signed by the recipient's zapper
+fn bolt11_description_hash(pr: &str) -> Result<[u8; 32]> {
+    let pr = pr.to_lowercase();
+    let data = match pr.rsplit_once('1') {
+        Some((_, data)) => data,
+        None => bail!("Invalid bolt11 invoice"),
+    };
+    let words = data
+        .chars()
+        .map(|c| BECH32_CHARSET.find(c).map(|i| i as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or(anyhow!("Invalid bolt11 invoice"))?;
+    // timestamp, tagged fields, signature (65 bytes) and checksum
+    if words.len() < 7 + 104 + 6 {
+        bail!("Invalid bolt11 invoice");
+    }
+    let mut fields = &words[7..words.len() - 104 - 6];
+    while fields.len() >= 3 {
+        let len = ((fields[1] as usize) << 5) | fields[2] as usize;
+        let value = match fields.get(3..3 + len) {
+            Some(v) => v,
+            None => bail!("Invalid bolt11 invoice"),
+        };
+        // `h` is 23, the 256 bit hash is padded to 52 words
+        if fields[0] == 23 && len == 52 {
+            let mut hash = [0u8; 32];
+            let mut acc = 0u32;
+            let mut bits = 0;
+            let mut i = 0;
+            for w in value {
+                acc = (acc << 5) | *w as u32;
+                bits += 5;
+                if bits >= 8 && i < hash.len() {
+                    bits -= 8;
+                    hash[i] = (acc >> bits) as u8;
+                    i += 1;
+                }
+            }
+            return Ok(hash);
+        }
+        fields = &fields[3 + len..];
+    }
+    bail!("Zap invoice has no description hash")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::{EventBuilder, Keys, Tag};
+
+    /// Invoice for [amount] (`2500u`) committing to [hash], with a zeroed signature and
+    /// checksum
+    fn invoice(amount: &str, hash: &[u8]) -> String {
+        let mut words = vec![0u8; 7];
+        words.extend([23, 1, 20]);
+        let mut acc = 0u32;
+        let mut bits = 0;
+        for b in hash {
+            acc = (acc << 8) | *b as u32;
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                words.push(((acc >> bits) & 31) as u8);
+            }
+        }
+        words.push(((acc << (5 - bits)) & 31) as u8);
+        words.extend([0u8; 104 + 6]);
+        let data: String = words
+            .into_iter()
+            .map(|w| BECH32_CHARSET.as_bytes()[w as usize] as char)
+            .collect();
+        format!("lnbc{}1{}", amount, data)
+    }
+
+    /// Zap receipt for a zap request of [requested] msats paid with a [amount] invoice, the
+    /// invoice commits to [hashed] instead of the request if set
+    fn receipt(requested: &str, amount: &str, hashed: Option<&str>) -> Event {
+        let sender = Keys::generate();
+        let zapper = Keys::generate();
+        let recipient = Keys::generate().public_key().to_hex();
+        let request = EventBuilder::new(
+            Kind::ZapRequest,
+            "",
+            [
+                Tag::parse(&["relays", "wss://relay.example.com"]).unwrap(),
+                Tag::parse(&["p", recipient.as_str()]).unwrap(),
+                Tag::parse(&["amount", requested]).unwrap(),
+            ],
+        )
+        .sign_with_keys(&sender)
+        .unwrap()
+        .as_json();
+        let hash = Sha256::digest(hashed.unwrap_or(&request).as_bytes());
+        EventBuilder::new(
+            Kind::ZapReceipt,
+            "",
+            [
+                Tag::parse(&["p", recipient.as_str()]).unwrap(),
+                Tag::parse(&["bolt11", invoice(amount, &hash).as_str()]).unwrap(),
+                Tag::parse(&["description", request.as_str()]).unwrap(),
+            ],
+        )
+        .sign_with_keys(&zapper)
+        .unwrap()
+    }
+
+    #[test]
+    fn bolt11_amount_multipliers() {
+        let hash = [0u8; 32];
+        assert_eq!(
+            bolt11_amount(&invoice("2", &hash)).unwrap(),
+            200_000_000_000
+        );
+        assert_eq!(bolt11_amount(&invoice("2m", &hash)).unwrap(), 200_000_000);
+        assert_eq!(
+            bolt11_amount(&invoice("2500u", &hash)).unwrap(),
+            250_000_000
+        );
+        assert_eq!(bolt11_amount(&invoice("10n", &hash)).unwrap(), 1_000);
+        assert_eq!(bolt11_amount(&invoice("10p", &hash)).unwrap(), 1);
+        assert_eq!(
+            bolt11_amount(&invoice("2500U", &hash).to_uppercase()).unwrap(),
+            250_000_000
+        );
+        // sub milli-sat amounts can't be paid
+        assert!(bolt11_amount(&invoice("15p", &hash)).is_err());
+        assert!(bolt11_amount(&invoice("1x", &hash)).is_err());
+        assert!(bolt11_amount(&invoice("", &hash)).is_err());
+        assert!(bolt11_amount("lnbc").is_err());
+    }
+
+    #[test]
+    fn bolt11_description_hash_is_decoded() {
+        let hash: [u8; 32] = Sha256::digest(b"test").into();
+        assert_eq!(
+            bolt11_description_hash(&invoice("10n", &hash)).unwrap(),
+            hash
+        );
+    }
+
+    #[test]
+    fn validates_zap_receipts() {
+        let zap = ZapReceipt::validate(&receipt("250000000", "2500u", None)).unwrap();
+        assert_eq!(zap.amount, 250_000_000);
+    }
+
+    #[test]
+    fn rejects_receipts_for_other_requests() {
+        assert!(ZapReceipt::validate(&receipt("250000000", "2500u", Some("{}"))).is_err());
+    }
+
+    #[test]
+    fn rejects_receipts_with_other_amounts() {
+        assert!(ZapReceipt::validate(&receipt("1000", "2500u", None)).is_err());
+    }
+}
@@ -0,0 +1,397 @@
+use crate::egress::EgressResult;
+use crate::ingress::{ConnectionInfo, IngressReader, Rejection, SharedReader};
+use crate::overseer::Overseer;
+use crate::pipeline::log::PipelineLog;
+use crate::pipeline::runner::PipelineRunner;
+use anyhow::{bail, Result};
+use log::{error, info, warn};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::UnboundedSender;
+use uuid::Uuid;
+
+/// Automatic restarts of a crashed pipeline for the same ingest connection
+const MAX_PIPELINE_RESTARTS: u32 = 1;
+
+/// Builds [PipelineRunner]s for any [IngressReader], the ingress listeners use it through
+/// [crate::ingress::spawn_pipeline] and applications embedding the pipeline use it directly
+/// with their own reader (i.e. [crate::ingress::channel::ChannelReader]), no listener is
+/// needed to run a pipeline
+#[derive(Clone)]
+pub struct PipelineBuilder {
+    overseer: Arc<dyn Overseer>,
+    handle: Option<Handle>,
+    out_dir: String,
+    connection: ConnectionInfo,
+    egress_results: Option<UnboundedSender<EgressResult>>,
+}
+
+impl PipelineBuilder {
+    pub fn new(overseer: Arc<dyn Overseer>) -> Self {
+        Self {
+            overseer,
+            handle: None,
+            out_dir: "./out".to_string(),
+            connection: ConnectionInfo {
+                endpoint: "embedded".to_string(),
+                ip_addr: "embedded".to_string(),
+                key: "test".to_string(),
+                ..Default::default()
+            },
+            egress_results: None,
+        }
+    }
+
+    /// Runtime the overseer is called on, defaults to the runtime [Self::build] is called
+    /// from
+    pub fn with_handle(mut self, handle: Handle) -> Self {
+        self.handle = Some(handle);
+        self
+    }
+
+    /// Directory the egress write to
+    pub fn with_out_dir(mut self, out_dir: impl Into<String>) -> Self {
+        self.out_dir = out_dir.into();
+        self
+    }
+
+    /// Connection passed to [Overseer::start_stream], the stream key picks the stream
+    pub fn with_connection(mut self, connection: ConnectionInfo) -> Self {
+        self.connection = connection;
+        self
+    }
+
+    /// Also send every [EgressResult] of the pipeline to [tx], after the overseer handled it
+    pub fn with_egress_results(mut self, tx: UnboundedSender<EgressResult>) -> Self {
+        self.egress_results = Some(tx);
+        self
+    }
+
+    fn handle(&self) -> Result<Handle> {
+        match &self.handle {
+            Some(h) => Ok(h.clone()),
+            None => match Handle::try_current() {
+                Ok(h) => Ok(h),
+                Err(_) => bail!("PipelineBuilder needs a tokio runtime handle"),
+            },
+        }
+    }
+
+    /// Create a runner reading [reader], driven by the caller with [PipelineRunner::run]
+    /// until it returns false and then [PipelineRunner::flush]
+    pub fn build(&self, reader: SharedReader) -> Result<PipelineRunner> {
+        let handle = self.handle()?;
+        let mut pl = PipelineRunner::new(
+            handle,
+            self.out_dir.clone(),
+            self.overseer.clone(),
+            self.connection.clone(),
+            reader,
+        )?;
+        if let Some(tx) = &self.egress_results {
+            pl.set_egress_results(tx.clone());
+        }
+        Ok(pl)
+    }
+
+    /// Run the pipeline reading [reader] on its own thread until the input ends, a crashed
+    /// pipeline is restarted once if a new demuxer can start on the reader
+    pub fn spawn(self, reader: Box<dyn IngressReader>) -> Result<JoinHandle<()>> {
+        let handle = self.handle()?;
+        let builder = self.with_handle(handle.clone());
+        let info = builder.connection.clone();
+        let seer = builder.overseer.clone();
+        info!("New client connected: {}", &info.ip_addr);
+        Ok(std::thread::spawn(move || {
+            PipelineLog::attach_client(&info.ip_addr);
+            supervise(
+                &handle,
+                seer.as_ref(),
+                &info,
+                Arc::new(Mutex::new(reader)),
+                |reader| {
+                    let mut pl = builder.build(reader)?;
+                    let exit = run_pipeline(&mut pl);
+                    Ok((exit, pl.pipeline_id()))
+                },
+            );
+            PipelineLog::detach();
+        }))
+    }
+}
+
+/// Run pipelines on [reader] with [run] until one exits without crashing, a crashed pipeline
+/// is restarted when the reader is in a state a new demuxer can start from, otherwise its
+/// stream is ended
+fn supervise(
+    handle: &Handle,
+    seer: &dyn Overseer,
+    info: &ConnectionInfo,
+    reader: Arc<Mutex<Box<dyn IngressReader>>>,
+    mut run: impl FnMut(SharedReader) -> Result<(PipelineExit, Option<Uuid>)>,
+) {
+    let mut restarts = 0;
+    // stream of the crashed pipeline, ended unless a restarted pipeline takes it over
+    let mut crashed: Option<Uuid> = None;
+    loop {
+        let shared = SharedReader::new(reader.clone());
+        let (exit, id) = match run(shared.clone()) {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Failed to create PipelineRunner: {}", e);
+                break;
+            }
+        };
+        let msg = match exit {
+            // a pipeline which started a stream ended it when flushing
+            PipelineExit::Done if id.is_some() => {
+                crashed = crashed.filter(|c| Some(*c) != id);
+                break;
+            }
+            PipelineExit::Done => break,
+            PipelineExit::Rejected(rejection) => {
+                warn!("Stream from {} rejected: {}", info.ip_addr, rejection);
+                reader
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .reject(&rejection);
+                break;
+            }
+            PipelineExit::Panic(msg) => msg,
+        };
+
+        // the crashed demuxer stopped somewhere in the input, a new one probing from there
+        // would read garbage unless the format can be joined at any point
+        let restart = restarts < MAX_PIPELINE_RESTARTS && shared.can_restart();
+        error!("Pipeline crashed: {}", msg);
+        crashed = id.or(crashed);
+        if let Some(id) = id {
+            handle.block_on(async {
+                if let Err(e) = seer.on_pipeline_crash(&id, &msg, restart).await {
+                    error!("Failed to record pipeline crash: {}", e);
+                }
+            });
+        }
+        if !restart {
+            break;
+        }
+        restarts += 1;
+        warn!("Restarting pipeline for {}", info.ip_addr);
+    }
+    if let Some(id) = crashed {
+        handle.block_on(async {
+            if let Err(e) = seer.on_end(&id).await {
+                error!("Failed to end stream: {}", e);
+            }
+        });
+    }
+}
+
+enum PipelineExit {
+    /// Ingest ended or the pipeline failed with an error, the pipeline was flushed
+    Done,
+    /// The overseer refused to start the stream
+    Rejected(Rejection),
+    /// The pipeline thread panicked
+    Panic(String),
+}
+
+/// Run [pl] until the ingest ends, catching panics so they don't take down the stream
+/// without cleanup
+fn run_pipeline(pl: &mut PipelineRunner) -> PipelineExit {
+    loop {
+        match catch_unwind(AssertUnwindSafe(|| unsafe { pl.run() })) {
+            Ok(Ok(true)) => {}
+            Ok(Ok(false)) => {
+                if let Err(e) = unsafe { pl.flush() } {
+                    error!("Pipeline flush failed: {}", e);
+                }
+                return PipelineExit::Done;
+            }
+            Ok(Err(e)) => {
+                if let Err(e) = unsafe { pl.flush() } {
+                    error!("Pipeline flush failed: {}", e);
+                }
+                if let Some(rejection) = pl.rejection() {
+                    return PipelineExit::Rejected(rejection.clone());
+                }
+                error!("Pipeline run failed: {}", e);
+                return PipelineExit::Done;
+            }
+            Err(p) => {
+                let msg = match (p.downcast_ref::<&str>(), p.downcast_ref::<String>()) {
+                    (Some(s), _) => s.to_string(),
+                    (_, Some(s)) => s.clone(),
+                    _ => "unknown panic".to_string(),
+                };
+                return PipelineExit::Panic(msg);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::overseer::IngressInfo;
+    use crate::pipeline::PipelineConfig;
+    use bytes::Bytes;
+    use http_body_util::combinators::BoxBody;
+    use hyper::body::Incoming;
+    use hyper::{Request, Response};
+    use std::io::{Cursor, Read};
+    use std::path::PathBuf;
+
+    /// Records the crash and end callbacks
+    #[derive(Default)]
+    struct TestOverseer {
+        crashes: Mutex<Vec<(Uuid, bool)>>,
+        ended: Mutex<Vec<Uuid>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Overseer for TestOverseer {
+        async fn api(
+            &self,
+            _req: Request<Incoming>,
+        ) -> Result<Response<BoxBody<Bytes, anyhow::Error>>> {
+            unimplemented!()
+        }
+
+        async fn check_streams(&self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn start_stream(
+            &self,
+            _connection: &ConnectionInfo,
+            _stream_info: &IngressInfo,
+        ) -> Result<PipelineConfig> {
+            unimplemented!()
+        }
+
+        async fn on_segment(
+            &self,
+            _pipeline_id: &Uuid,
+            _variant_id: &Uuid,
+            _index: u64,
+            _duration: f32,
+            _path: &PathBuf,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn on_thumbnail(
+            &self,
+            _pipeline_id: &Uuid,
+            _width: usize,
+            _height: usize,
+            _path: &PathBuf,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn on_end(&self, pipeline_id: &Uuid) -> Result<()> {
+            self.ended.lock().unwrap().push(*pipeline_id);
+            Ok(())
+        }
+
+        async fn on_pipeline_crash(
+            &self,
+            pipeline_id: &Uuid,
+            _error: &str,
+            restarting: bool,
+        ) -> Result<()> {
+            self.crashes
+                .lock()
+                .unwrap()
+                .push((*pipeline_id, restarting));
+            Ok(())
+        }
+    }
+
+    struct TestReader {
+        data: Cursor<Vec<u8>>,
+        resumable: bool,
+    }
+
+    impl Read for TestReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.data.read(buf)
+        }
+    }
+
+    impl IngressReader for TestReader {
+        fn resumable(&self) -> bool {
+            self.resumable
+        }
+    }
+
+    /// Crash the first pipeline after it read [read] bytes, a restarted one reads the rest
+    /// of the input, returns the number of pipelines started and what the last one read
+    fn crash_once(seer: &TestOverseer, read: usize, resumable: bool) -> (usize, Vec<u8>) {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let reader: Box<dyn IngressReader> = Box::new(TestReader {
+            data: Cursor::new((0..16).collect()),
+            resumable,
+        });
+        let id = Uuid::new_v4();
+        let mut runs = 0;
+        let mut rest = vec![];
+        supervise(
+            rt.handle(),
+            seer,
+            &ConnectionInfo::default(),
+            Arc::new(Mutex::new(reader)),
+            |mut reader| {
+                runs += 1;
+                if runs == 1 {
+                    reader.read_exact(&mut vec![0; read])?;
+                    return Ok((PipelineExit::Panic("test".to_string()), Some(id)));
+                }
+                reader.read_to_end(&mut rest)?;
+                Ok((PipelineExit::Done, Some(id)))
+            },
+        );
+        (runs, rest)
+    }
+
+    #[test]
+    fn restarts_pipeline_which_crashed_before_reading() {
+        let seer = TestOverseer::default();
+        let (runs, rest) = crash_once(&seer, 0, false);
+        assert_eq!(runs, 2);
+        assert_eq!(rest.len(), 16);
+        let crashes = seer.crashes.lock().unwrap();
+        assert_eq!(crashes.len(), 1);
+        assert!(crashes[0].1);
+        // the restarted pipeline ended the stream itself
+        assert!(seer.ended.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn ends_stream_of_pipeline_which_crashed_mid_input() {
+        let seer = TestOverseer::default();
+        let (runs, _) = crash_once(&seer, 4, false);
+        assert_eq!(runs, 1);
+        let crashes = seer.crashes.lock().unwrap();
+        assert_eq!(crashes.len(), 1);
+        assert!(!crashes[0].1);
+        assert_eq!(*seer.ended.lock().unwrap(), vec![crashes[0].0]);
+    }
+
+    #[test]
+    fn restarts_resumable_input_mid_stream() {
+        let seer = TestOverseer::default();
+        let (runs, rest) = crash_once(&seer, 4, true);
+        assert_eq!(runs, 2);
+        // the new pipeline continues where the crashed one stopped
+        assert_eq!(rest, (4..16).collect::<Vec<u8>>());
+        assert!(seer.crashes.lock().unwrap()[0].1);
+        assert!(seer.ended.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::mux::Cue;
+use anyhow::{bail, Result};
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVCodecID::AV_CODEC_ID_EIA_608;
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVFrameSideDataType::AV_FRAME_DATA_A53_CC;
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVMediaType::AVMEDIA_TYPE_VIDEO;
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVSubtitleType::{SUBTITLE_ASS, SUBTITLE_TEXT};
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::{
+    av_frame_get_side_data, av_packet_alloc, av_packet_free, av_q2d, avcodec_alloc_context3,
+    avcodec_decode_subtitle2, avcodec_descriptor_get, avcodec_find_decoder, avcodec_free_context,
+    avcodec_open2, avcodec_parameters_to_context, avsubtitle_free, AVCodecContext, AVCodecID,
+    AVFrame, AVPacket, AVStream, AVSubtitle, AV_CODEC_PROP_TEXT_SUB, AV_NOPTS_VALUE, AV_TIME_BASE,
+};
+use std::ffi::CStr;
+use std::mem::{transmute, zeroed};
+use std::ptr;
+
+/// Seconds a caption is shown when the input doesn't say, until the next one replaces it
+const MAX_CUE_SECS: f64 = 5.0;
+
+/// True if [codec] (an [AVCodecID]) is a text based subtitle format which can be turned
+/// into WebVTT, bitmap subtitles (DVB, PGS) are not supported
+pub fn is_text_subtitle(codec: isize) -> bool {
+    unsafe {
+        let desc = avcodec_descriptor_get(transmute::<i32, AVCodecID>(codec as i32));
+        !desc.is_null() && (*desc).props & AV_CODEC_PROP_TEXT_SUB as i32 != 0
+    }
+}
+
+/// Captions can be generated from the audio of streams (feature `stt`)
+#[cfg(feature = "stt")]
+pub fn speech_to_text_enabled() -> bool {
+    crate::pipeline::stt::enabled()
+}
+
+#[cfg(not(feature = "stt"))]
+pub fn speech_to_text_enabled() -> bool {
+    false
+}
+
+/// Decodes the captions of an input stream into [Cue]s, from a text subtitle stream or
+/// the CEA-608 captions carried in the frames of a video stream (A53 side data)
+pub struct CaptionDecoder {
+    ctx: *mut AVCodecContext,
+}
+
+unsafe impl Send for CaptionDecoder {}
+
+impl CaptionDecoder {
+    /// Decoder for the captions of [stream]
+    pub unsafe fn new(stream: *const AVStream) -> Result<Self> {
+        let par = (*stream).codecpar;
+        let codec = if (*par).codec_type == AVMEDIA_TYPE_VIDEO {
+            avcodec_find_decoder(AV_CODEC_ID_EIA_608)
+        } else if is_text_subtitle((*par).codec_id as isize) {
+            avcodec_find_decoder((*par).codec_id)
+        } else {
+            bail!("Only text subtitles can be converted to WebVTT");
+        };
+        if codec.is_null() {
+            bail!("No decoder for captions");
+        }
+        let mut ctx = avcodec_alloc_context3(codec);
+        if (*par).codec_type != AVMEDIA_TYPE_VIDEO {
+            let ret = avcodec_parameters_to_context(ctx, par);
+            if ret < 0 {
+                avcodec_free_context(&mut ctx);
+                bail!("Failed to copy subtitle codec parameters: {}", ret);
+            }
+        }
+        (*ctx).pkt_timebase = (*stream).time_base;
+        (*ctx).time_base = (*stream).time_base;
+        let ret = avcodec_open2(ctx, codec, ptr::null_mut());
+        if ret < 0 {
+            avcodec_free_context(&mut ctx);
+            bail!("Failed to open caption decoder: {}", ret);
+        }
+        Ok(Self { ctx })
+    }
+
+    /// Decode the captions of a decoded video [frame], if it has any
+    pub unsafe fn decode_frame(&mut self, frame: *const AVFrame) -> Result<Vec<Cue>> {
+        let sd = av_frame_get_side_data(frame, AV_FRAME_DATA_A53_CC);
+        if sd.is_null() || (*sd).size == 0 {
+            return Ok(vec![]);
+        }
+        let mut pkt = av_packet_alloc();
+        (*pkt).data = (*sd).data;
+        (*pkt).size = (*sd).size as _;
+        (*pkt).pts = (*frame).pts;
+        (*pkt).time_base = (*frame).time_base;
+        let ret = self.decode_packet(pkt);
+        // the data belongs to the frame
+        (*pkt).data = ptr::null_mut();
+        (*pkt).size = 0;
+        av_packet_free(&mut pkt);
+        ret
+    }
+
+    /// Decode a packet of a subtitle stream
+    pub unsafe fn decode_packet(&mut self, pkt: *const AVPacket) -> Result<Vec<Cue>> {
+        let mut sub: AVSubtitle = zeroed();
+        let mut got = 0;
+        let ret = avcodec_decode_subtitle2(self.ctx, &mut sub, &mut got, pkt);
+        if ret < 0 {
+            bail!("Failed to decode captions: {}", ret);
+        }
+        if got == 0 {
+            return Ok(vec![]);
+        }
+        let base = if sub.pts != AV_NOPTS_VALUE {
+            sub.pts as f64 / AV_TIME_BASE as f64
+        } else {
+            (*pkt).pts as f64 * av_q2d((*self.ctx).pkt_timebase)
+        };
+        let start = base + sub.start_display_time as f64 / 1000.0;
+        let end =
+            if sub.end_display_time > sub.start_display_time && sub.end_display_time != u32::MAX {
+                base + sub.end_display_time as f64 / 1000.0
+            } else {
+                start + MAX_CUE_SECS
+            };
+        let mut text = Vec::new();
+        for i in 0..sub.num_rects as usize {
+            let rect = *sub.rects.add(i);
+            match (*rect).type_ {
+                SUBTITLE_ASS if !(*rect).ass.is_null() => {
+                    text.push(ass_text(&CStr::from_ptr((*rect).ass).to_string_lossy()))
+                }
+                SUBTITLE_TEXT if !(*rect).text.is_null() => {
+                    text.push(CStr::from_ptr((*rect).text).to_string_lossy().to_string())
+                }
+                _ => {}
+            }
+        }
+        avsubtitle_free(&mut sub);
+        // captions without text clear the screen
+        Ok(vec![Cue {
+            start,
+            end,
+            text: text.join("\n").trim().to_string(),
+        }])
+    }
+}
+
+impl Drop for CaptionDecoder {
+    fn drop(&mut self) {
+        unsafe {
+            avcodec_free_context(&mut self.ctx);
+        }
+    }
+}
+
+/// Text of an ASS dialogue line from a subtitle decoder
+/// (`ReadOrder,Layer,Style,Name,MarginL,MarginR,MarginV,Effect,Text`) without the style
+/// overrides
+fn ass_text(line: &str) -> String {
+    let text = line.splitn(9, ',').nth(8).unwrap_or(line);
+    let mut ret = String::with_capacity(text.len());
+    let mut in_override = false;
+    for c in text.chars() {
+        match c {
+            '{' => in_override = true,
+            '}' if in_override => in_override = false,
+            _ if !in_override => ret.push(c),
+            _ => {}
+        }
+    }
+    ret.replace("\\N", "\n")
+        .replace("\\n", "\n")
+        .replace("\\h", " ")
+}
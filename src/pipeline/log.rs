@@ -0,0 +1,130 @@
+use chrono::{SecondsFormat, Utc};
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::{
+    av_log_format_line, av_log_get_level, va_list, AV_LOG_DEBUG, AV_LOG_ERROR, AV_LOG_INFO,
+    AV_LOG_WARNING,
+};
+use log::warn;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// File name of the ffmpeg log written to the output dir of each pipeline
+pub const PIPELINE_LOG: &str = "pipeline.log";
+
+thread_local! {
+    /// Pipeline running on this thread, ffmpeg logs on this thread are attributed to it
+    static PIPELINE: RefCell<Option<PipelineLog>> = const { RefCell::new(None) };
+    /// Partial line logged without a trailing newline
+    static PENDING: RefCell<(String, c_int)> = const { RefCell::new((String::new(), 1)) };
+}
+
+/// Log context of the pipeline running on the current thread
+pub struct PipelineLog {
+    /// Stream id, or the client address until the overseer started the stream
+    name: String,
+    file: Option<File>,
+}
+
+impl PipelineLog {
+    /// Attribute ffmpeg logs on this thread to the client at [addr]
+    pub fn attach_client(addr: &str) {
+        PIPELINE.with_borrow_mut(|p| {
+            *p = Some(PipelineLog {
+                name: addr.to_string(),
+                file: None,
+            })
+        });
+    }
+
+    /// Attribute ffmpeg logs on this thread to pipeline [id], also writing them to the
+    /// pipeline log in its output dir
+    pub fn attach(id: &Uuid, out_dir: &str) {
+        let dir = PathBuf::from(out_dir).join(id.to_string());
+        let file = create_dir_all(&dir).and_then(|_| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dir.join(PIPELINE_LOG))
+        });
+        let file = match file {
+            Ok(f) => Some(f),
+            Err(e) => {
+                warn!("Failed to open pipeline log for {}: {}", id, e);
+                None
+            }
+        };
+        PIPELINE.with_borrow_mut(|p| {
+            *p = Some(PipelineLog {
+                name: id.to_string(),
+                file,
+            })
+        });
+    }
+
+    /// Stop attributing ffmpeg logs on this thread
+    pub fn detach() {
+        PIPELINE.with_borrow_mut(|p| *p = None);
+    }
+}
+
+/// ffmpeg log callback, lines are logged with the pipeline running on the calling thread
+/// and appended to its pipeline log
+pub unsafe extern "C" fn av_log_pipeline(
+    ptr: *mut c_void,
+    level: c_int,
+    fmt: *const c_char,
+    args: va_list,
+) {
+    if level > av_log_get_level() {
+        return;
+    }
+    let mut buf = [0 as c_char; 1024];
+    let line = PENDING.with_borrow_mut(|(pending, print_prefix)| {
+        av_log_format_line(
+            ptr,
+            level,
+            fmt,
+            args,
+            buf.as_mut_ptr(),
+            buf.len() as c_int,
+            print_prefix,
+        );
+        pending.push_str(&CStr::from_ptr(buf.as_ptr()).to_string_lossy());
+        // ffmpeg builds some lines with multiple calls
+        if pending.ends_with('\n') {
+            Some(std::mem::take(pending))
+        } else {
+            None
+        }
+    });
+    let line = match line {
+        Some(l) => l,
+        None => return,
+    };
+    let line = line.trim();
+    if line.is_empty() {
+        return;
+    }
+
+    let log_level = match level {
+        l if l <= AV_LOG_ERROR => log::Level::Error,
+        l if l <= AV_LOG_WARNING => log::Level::Warn,
+        l if l <= AV_LOG_INFO => log::Level::Info,
+        l if l <= AV_LOG_DEBUG => log::Level::Debug,
+        _ => log::Level::Trace,
+    };
+    PIPELINE.with_borrow_mut(|p| match p {
+        Some(p) => {
+            log::log!(target: "ffmpeg", log_level, "[{}] {}", p.name, line);
+            if let Some(f) = &mut p.file {
+                let ts = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+                // a full disk shouldn't break the pipeline, the watchdog deals with that
+                let _ = writeln!(f, "{} {} {}", ts, log_level, line);
+            }
+        }
+        None => log::log!(target: "ffmpeg", log_level, "{}", line),
+    });
+}
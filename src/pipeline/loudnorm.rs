@@ -0,0 +1,229 @@
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVSampleFormat::{AV_SAMPLE_FMT_FLT, AV_SAMPLE_FMT_FLTP};
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::{AVCodecContext, AVFrame};
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts::PI;
+use std::slice;
+use uuid::Uuid;
+
+/// Loudness is measured over 100ms blocks
+const BLOCK_SECS: f64 = 0.1;
+
+/// Blocks in the short-term loudness window (3s, EBU Tech 3341)
+const SHORT_TERM_BLOCKS: usize = 30;
+
+/// Short-term loudness below this is silence and keeps the current gain (LUFS)
+const SILENCE_GATE: f64 = -70.0;
+
+/// Max gain applied in either direction (dB), quiet streams are not boosted into noise
+const MAX_GAIN: f64 = 12.0;
+
+/// Part of the distance to the wanted gain moved per block, smooths gain changes over
+/// a couple of seconds
+const GAIN_SMOOTHING: f64 = 0.05;
+
+/// Max sample peak after normalizing (-1 dBFS)
+const PEAK_LIMIT: f32 = 0.891;
+
+/// Normalizes the loudness of transcoded audio to a target loudness (EBU R128)
+///
+/// Loudness is measured over the short-term window of the decoded audio (K-weighted
+/// per ITU-R BS.1770) and each audio variant is slowly moved towards the target, like
+/// the dynamic mode of ffmpeg's `loudnorm` filter but without the lookahead so no latency
+/// is added. Peaks are limited so boosting quiet audio doesn't clip
+#[derive(Default)]
+pub struct LoudNorm {
+    /// Target loudness in LUFS, not normalized if not set
+    target: Option<f32>,
+    /// Loudness meter per audio variant
+    meters: HashMap<Uuid, Meter>,
+}
+
+impl LoudNorm {
+    pub fn new(target: Option<f32>) -> Self {
+        Self {
+            target,
+            ..Default::default()
+        }
+    }
+
+    /// Normalize audio [frame] of [variant]
+    ///
+    /// [frame] must be in the sample format of [enc], only float formats are normalized
+    pub unsafe fn process(
+        &mut self,
+        frame: *mut AVFrame,
+        variant: &Uuid,
+        enc: *const AVCodecContext,
+    ) {
+        let target = match self.target {
+            Some(t) if !frame.is_null() => t as f64,
+            _ => return,
+        };
+        let n = (*frame).nb_samples as usize;
+        let channels = (*enc).ch_layout.nb_channels as usize;
+        let meter = self
+            .meters
+            .entry(*variant)
+            .or_insert_with(|| Meter::new((*enc).sample_rate as f64, channels));
+        match (*enc).sample_fmt {
+            AV_SAMPLE_FMT_FLTP => {
+                let mut data: Vec<&mut [f32]> = (0..channels)
+                    .map(|c| {
+                        slice::from_raw_parts_mut(*(*frame).extended_data.add(c) as *mut f32, n)
+                    })
+                    .collect();
+                let (from, to) = meter.measure(target, n, |c, i| data[c][i]);
+                let peak = data
+                    .iter()
+                    .flat_map(|d| d.iter())
+                    .fold(0f32, |p, s| p.max(s.abs()));
+                let gain = gain_ramp(from, to, n, peak);
+                for d in data.iter_mut() {
+                    for (i, s) in d.iter_mut().enumerate() {
+                        *s *= gain(i);
+                    }
+                }
+            }
+            AV_SAMPLE_FMT_FLT => {
+                let data =
+                    slice::from_raw_parts_mut(*(*frame).extended_data as *mut f32, n * channels);
+                let (from, to) = meter.measure(target, n, |c, i| data[i * channels + c]);
+                let peak = data.iter().fold(0f32, |p, s| p.max(s.abs()));
+                let gain = gain_ramp(from, to, n, peak);
+                for (i, s) in data.iter_mut().enumerate() {
+                    *s *= gain(i / channels);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Short-term loudness and gain of one audio variant
+struct Meter {
+    /// K-weighting filters per channel
+    filters: Vec<[Biquad; 2]>,
+    block_size: usize,
+    /// Sum of squares and samples of the current block
+    block: (f64, usize),
+    /// Mean square of the last blocks
+    blocks: VecDeque<f64>,
+    /// Current gain in dB
+    gain: f64,
+}
+
+impl Meter {
+    fn new(rate: f64, channels: usize) -> Self {
+        Self {
+            filters: (0..channels)
+                .map(|_| [Biquad::shelf(rate), Biquad::high_pass(rate)])
+                .collect(),
+            block_size: (rate * BLOCK_SECS).max(1.0) as usize,
+            block: (0.0, 0),
+            blocks: VecDeque::with_capacity(SHORT_TERM_BLOCKS),
+            gain: 0.0,
+        }
+    }
+
+    /// Measure [n] samples read with [sample] (channel, index) and move the gain towards
+    /// [target], returns the linear gain at the start and end of the samples
+    fn measure(
+        &mut self,
+        target: f64,
+        n: usize,
+        sample: impl Fn(usize, usize) -> f32,
+    ) -> (f32, f32) {
+        let from = self.gain;
+        for i in 0..n {
+            for (c, [shelf, high_pass]) in self.filters.iter_mut().enumerate() {
+                let s = high_pass.process(shelf.process(sample(c, i) as f64));
+                self.block.0 += s * s;
+            }
+            self.block.1 += 1;
+            if self.block.1 == self.block_size {
+                if self.blocks.len() == SHORT_TERM_BLOCKS {
+                    self.blocks.pop_front();
+                }
+                self.blocks.push_back(self.block.0 / self.block.1 as f64);
+                self.block = (0.0, 0);
+                self.update_gain(target);
+            }
+        }
+        (db_to_linear(from), db_to_linear(self.gain))
+    }
+
+    fn update_gain(&mut self, target: f64) {
+        let ms = self.blocks.iter().sum::<f64>() / self.blocks.len() as f64;
+        let loudness = -0.691 + 10.0 * ms.log10();
+        if !loudness.is_finite() || loudness < SILENCE_GATE {
+            return;
+        }
+        let wanted = (target - loudness).clamp(-MAX_GAIN, MAX_GAIN);
+        self.gain += (wanted - self.gain) * GAIN_SMOOTHING;
+    }
+}
+
+/// Gain per sample index ramping [from] -> [to] over [n] samples, lowered so samples
+/// peaking at [peak] stay below [PEAK_LIMIT]
+fn gain_ramp(from: f32, to: f32, n: usize, peak: f32) -> impl Fn(usize) -> f32 {
+    // audio which already peaks above the limit is not turned down further
+    let limit = if peak > 0.0 {
+        (PEAK_LIMIT / peak).max(1.0)
+    } else {
+        f32::MAX
+    };
+    move |i: usize| (from + (to - from) * i as f32 / n.max(1) as f32).min(limit)
+}
+
+fn db_to_linear(db: f64) -> f32 {
+    10f64.powf(db / 20.0) as f32
+}
+
+/// Second order IIR filter of the K-weighting (ITU-R BS.1770)
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    /// High shelf modelling the acoustic effect of the head
+    fn shelf(rate: f64) -> Self {
+        let f0 = 1681.9744509555;
+        let g = 3.9998438539735;
+        let q = 0.70717523695542;
+        let k = (PI * f0 / rate).tan();
+        let vh = 10f64.powf(g / 20.0);
+        let vb = vh.powf(0.49966677415454);
+        let a0 = 1.0 + k / q + k * k;
+        Self {
+            b: [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    /// RLB high pass
+    fn high_pass(rate: f64) -> Self {
+        let f0 = 38.135470876024;
+        let q = 0.50032703732388;
+        let k = (PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Self {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
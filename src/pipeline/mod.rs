@@ -1,11 +1,24 @@
 use std::fmt::{Display, Formatter};
 
 use crate::egress::EgressConfig;
-use crate::variant::VariantStream;
+use crate::mux::SegmentType;
+use crate::variant::{StreamMapping, VariantStream};
+use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 use uuid::Uuid;
 
+pub mod builder;
+pub mod captions;
+pub mod log;
+pub mod loudnorm;
 pub mod runner;
+pub mod settle;
+pub mod slate;
+#[cfg(feature = "stt")]
+pub mod stt;
+pub mod timing;
+pub mod watermark;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum EgressType {
@@ -39,6 +52,39 @@ impl Display for EgressType {
     }
 }
 
+/// Changes to a running pipeline requested by the overseer
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PipelineControl {
+    /// Replace the input with a placeholder frame and silence
+    ///
+    /// The slate replaces decoded input frames, so it's only shown while a source is
+    /// connected. Nothing is produced during the [PipelineConfig::failover_window], the
+    /// demuxer is blocked on the ingest until a backup takes over
+    pub slate: bool,
+    /// Image shown while [slate] is set, black if not set
+    pub slate_image: Option<PathBuf>,
+    /// Audio looped while [slate] is set, silence if not set
+    pub slate_audio: Option<PathBuf>,
+    /// Record the stream to disk
+    pub recording: bool,
+    /// Segments kept in the HLS playlists, the default window if not set
+    pub playlist_window: Option<usize>,
+    /// Ad break in progress, signalled in the HLS playlists for downstream ad insertion
+    pub ad_break: Option<AdBreak>,
+    /// Stop the pipeline and disconnect the ingest, i.e. the stream was ended
+    pub stop: bool,
+}
+
+/// Ad break marked in the HLS playlists with `EXT-X-DATERANGE` and `EXT-X-CUE-OUT` /
+/// `EXT-X-CUE-IN`, the break ends after [duration] unless it's ended early
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AdBreak {
+    /// Unique within the stream, used as the `EXT-X-DATERANGE` ID
+    pub id: u64,
+    /// Planned length in seconds
+    pub duration: u32,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct PipelineConfig {
     pub id: Uuid,
@@ -46,6 +92,84 @@ pub struct PipelineConfig {
     pub variants: Vec<VariantStream>,
     /// Output muxers
     pub egress: Vec<EgressType>,
+    /// Seconds at the start of the stream in which audio is faded in and out of order
+    /// packets are dropped, see [settle::StartSettle]
+    #[serde(default = "default_settle_window")]
+    pub settle_window: f32,
+    /// Remux finished recordings into a faststart MP4 per video variant, reported with
+    /// [crate::overseer::Overseer::on_recording_complete]
+    #[serde(default)]
+    pub recording_mp4: bool,
+    /// Seconds the stream is kept after its ingest dropped, waiting for a backup ingest
+    /// to take over (see [crate::ingress::failover]), 0 ends the stream right away. The
+    /// playlists don't advance while waiting, the slate isn't shown in the gap
+    #[serde(default)]
+    pub failover_window: u64,
+    /// Seconds after which the wall clock of the `EXT-X-PROGRAM-DATE-TIME` tags is anchored
+    /// to the ingest receive time again, 0 anchors once at the start
+    #[serde(default)]
+    pub program_date_time_anchor: u64,
+    /// Target loudness in LUFS transcoded audio is normalized to (see
+    /// [loudnorm::LoudNorm]), not normalized if not set
+    #[serde(default)]
+    pub loudnorm: Option<f32>,
+    /// Segment type of the HLS egress, picked from the codecs of its variants if not set
+    /// (see [Self::hls_segment_type])
+    #[serde(default)]
+    pub segment_type: Option<SegmentType>,
+    /// Image overlaid on the transcoded video variants
+    #[serde(default)]
+    pub watermark: Option<watermark::Watermark>,
+}
+
+impl PipelineConfig {
+    /// Segment type of HLS egress [c], players only support opus, AV1 and HEVC in fMP4
+    /// segments
+    pub fn hls_segment_type(&self, c: &EgressConfig) -> SegmentType {
+        if let Some(t) = self.segment_type {
+            return t;
+        }
+        if self.fmp4_variant(c).is_some() {
+            SegmentType::FMP4
+        } else {
+            SegmentType::MPEGTS
+        }
+    }
+
+    /// Reject configs the egress can't produce
+    pub fn validate(&self) -> Result<()> {
+        if self.segment_type != Some(SegmentType::MPEGTS) {
+            return Ok(());
+        }
+        for e in &self.egress {
+            if let EgressType::HLS(c) = e {
+                if let Some(v) = self.fmp4_variant(c) {
+                    bail!(
+                        "{} of HLS egress {} needs fMP4 segments, not MPEG-TS",
+                        v,
+                        c.name
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// A variant of egress [c] which can only be served in fMP4 segments
+    fn fmp4_variant(&self, c: &EgressConfig) -> Option<&VariantStream> {
+        self.variants.iter().find(|v| {
+            c.variants.contains(&v.id())
+                && match v {
+                    VariantStream::Audio(a) => a.codec == "libopus",
+                    VariantStream::Video(v) => v.needs_fmp4(),
+                    _ => false,
+                }
+        })
+    }
+}
+
+fn default_settle_window() -> f32 {
+    settle::DEFAULT_SETTLE_WINDOW
 }
 
 impl Display for PipelineConfig {
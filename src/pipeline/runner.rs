@@ -1,26 +1,38 @@
 use std::collections::{HashMap, HashSet};
-use std::io::Read;
 use std::mem::transmute;
 use std::ops::Sub;
 use std::path::PathBuf;
 use std::ptr;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use crate::background::disk_low;
 use crate::egress::hls::HlsEgress;
-use crate::egress::recorder::RecorderEgress;
-use crate::egress::{Egress, EgressResult};
-use crate::ingress::ConnectionInfo;
-use crate::mux::SegmentType;
+use crate::egress::recorder::{RecorderEgress, RecordingRemux};
+use crate::egress::{Egress, EgressResult, WallClock};
+use crate::ingress::{ConnectionInfo, Rejection, SharedReader, PARAM_REALTIME};
+use crate::mux::SEGMENT_LENGTH;
 use crate::overseer::{IngressInfo, IngressStream, IngressStreamType, Overseer};
-use crate::pipeline::{EgressType, PipelineConfig};
+use crate::pipeline::captions::CaptionDecoder;
+use crate::pipeline::log::PipelineLog;
+use crate::pipeline::loudnorm::LoudNorm;
+use crate::pipeline::settle::StartSettle;
+use crate::pipeline::slate::Slate;
+#[cfg(feature = "stt")]
+use crate::pipeline::stt::{SpeechToText, STT_LAG_SEGMENTS};
+use crate::pipeline::timing::{EncodeLatency, SegmentTiming};
+use crate::pipeline::watermark::WatermarkOverlay;
+use crate::pipeline::{EgressType, PipelineConfig, PipelineControl};
+use crate::variant::ladder::check_hls_ladder;
 use crate::variant::{StreamMapping, VariantStream};
 use anyhow::{bail, Result};
+use chrono::Utc;
 use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVCodecID::AV_CODEC_ID_WEBP;
 use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVPictureType::AV_PICTURE_TYPE_NONE;
 use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVPixelFormat::AV_PIX_FMT_YUV420P;
 use ffmpeg_rs_raw::ffmpeg_sys_the_third::{
-    av_frame_free, av_get_sample_fmt, av_packet_free, av_q2d, av_rescale_q, AVMediaType,
+    av_frame_clone, av_frame_free, av_frame_make_writable, av_get_sample_fmt, av_packet_free,
+    av_q2d, av_rescale_q, AVMediaType, AVPacket, AVStream, AV_NOPTS_VALUE,
 };
 use ffmpeg_rs_raw::{
     cstr, get_frame_from_hw, AudioFifo, Decoder, Demuxer, DemuxerInfo, Encoder, Resample, Scaler,
@@ -29,12 +41,14 @@ use ffmpeg_rs_raw::{
 use itertools::Itertools;
 use log::{error, info, warn};
 use tokio::runtime::Handle;
+use tokio::sync::mpsc::UnboundedSender;
 use uuid::Uuid;
 
 /// Pipeline runner is the main entry process for stream transcoding
 ///
 /// Each client connection spawns a new [PipelineRunner] and it should be run in its own thread
-/// using [crate::ingress::spawn_pipeline]
+/// using [crate::ingress::spawn_pipeline], or built with
+/// [crate::pipeline::builder::PipelineBuilder] to run without a listener
 pub struct PipelineRunner {
     /// Async runtime handle
     handle: Handle,
@@ -48,6 +62,9 @@ pub struct PipelineRunner {
     /// Singleton demuxer for this input
     demuxer: Demuxer,
 
+    /// Ingest connection read by [demuxer], polled for link statistics
+    ingress: SharedReader,
+
     /// Singleton decoder for all stream
     decoder: Decoder,
 
@@ -66,12 +83,56 @@ pub struct PipelineRunner {
     /// All configured egress'
     egress: Vec<Box<dyn Egress>>,
 
+    /// Recorder egress, kept separately so it can be started/stopped while running
+    recorder: Option<RecorderEgress>,
+
+    /// Current runtime changes requested by the overseer
+    control: PipelineControl,
+
+    /// Recording was stopped because the disk is almost full
+    recording_paused: bool,
+
+    /// Fade-in and packet filtering at the start of the stream
+    settle: StartSettle,
+
+    /// Loudness normalization of transcoded audio
+    loudnorm: LoudNorm,
+
+    /// Time frames spend in the encoders, reported with the segment timings
+    encode_latency: EncodeLatency,
+
+    /// Watermark blended into the transcoded video variants
+    watermark: WatermarkOverlay,
+
+    /// Caption decoders by input stream index, [None] if the captions can't be decoded
+    captions: HashMap<usize, Option<CaptionDecoder>>,
+
+    /// Captions generated from the audio of an input stream (index)
+    #[cfg(feature = "stt")]
+    stt: Option<(usize, SpeechToText)>,
+
+    /// Reason the overseer refused to start the stream
+    rejection: Option<Rejection>,
+
+    /// Placeholder replacing the input while [PipelineControl::slate] is set
+    slate: Option<Slate>,
+
     /// Info about the input stream
     info: Option<IngressInfo>,
 
+    /// Wall clock time and timestamp (seconds) of the first packet, inputs which are read
+    /// faster than realtime (files) are paced against it
+    pace_origin: Option<(Instant, f64)>,
+
+    /// Last time the wall clock of the egress was anchored to a received packet
+    wall_clock_anchored: Option<Instant>,
+
     /// Overseer managing this pipeline
     overseer: Arc<dyn Overseer>,
 
+    /// Application receiving the egress results, see [crate::pipeline::builder::PipelineBuilder]
+    egress_results: Option<UnboundedSender<EgressResult>>,
+
     fps_counter_start: Instant,
     fps_last_frame_ctr: u64,
 
@@ -86,7 +147,7 @@ impl PipelineRunner {
         out_dir: String,
         overseer: Arc<dyn Overseer>,
         connection: ConnectionInfo,
-        recv: Box<dyn Read + Send>,
+        recv: SharedReader,
     ) -> Result<Self> {
         Ok(Self {
             handle,
@@ -94,7 +155,8 @@ impl PipelineRunner {
             overseer,
             connection,
             config: Default::default(),
-            demuxer: Demuxer::new_custom_io(recv, None)?,
+            demuxer: Demuxer::new_custom_io(Box::new(recv.clone()), None)?,
+            ingress: recv,
             decoder: Decoder::new(),
             scalers: Default::default(),
             resampler: Default::default(),
@@ -102,12 +164,42 @@ impl PipelineRunner {
             copy_stream: Default::default(),
             fps_counter_start: Instant::now(),
             egress: Vec::new(),
+            recorder: None,
+            control: Default::default(),
+            recording_paused: false,
+            settle: Default::default(),
+            loudnorm: Default::default(),
+            encode_latency: Default::default(),
+            watermark: Default::default(),
+            captions: Default::default(),
+            #[cfg(feature = "stt")]
+            stt: None,
+            rejection: None,
+            slate: None,
             frame_ctr: 0,
             fps_last_frame_ctr: 0,
             info: None,
+            pace_origin: None,
+            wall_clock_anchored: None,
+            egress_results: None,
         })
     }
 
+    /// Send every egress result to [tx] after the overseer handled it
+    pub fn set_egress_results(&mut self, tx: UnboundedSender<EgressResult>) {
+        self.egress_results = Some(tx);
+    }
+
+    /// Why the overseer refused to start the stream, if it did
+    pub fn rejection(&self) -> Option<&Rejection> {
+        self.rejection.as_ref()
+    }
+
+    /// Id of the pipeline, set once the overseer started the stream
+    pub fn pipeline_id(&self) -> Option<Uuid> {
+        self.config.as_ref().map(|c| c.id)
+    }
+
     /// EOF, cleanup
     pub unsafe fn flush(&mut self) -> Result<()> {
         for (var, enc) in &mut self.encoders {
@@ -115,12 +207,18 @@ impl PipelineRunner {
                 for eg in self.egress.iter_mut() {
                     eg.process_pkt(pkt, var)?;
                 }
+                if let Some(rec) = &mut self.recorder {
+                    rec.process_pkt(pkt, var)?;
+                }
                 av_packet_free(&mut pkt);
             }
         }
         for eg in self.egress.iter_mut() {
             eg.reset()?;
         }
+        if let Some(rec) = self.recorder.take() {
+            self.finish_recorder(rec)?;
+        }
 
         if let Some(config) = &self.config {
             self.handle.block_on(async {
@@ -132,6 +230,50 @@ impl PipelineRunner {
         Ok(())
     }
 
+    /// Caption decoder of the input [stream] if a subtitle variant takes its captions
+    unsafe fn caption_decoder<'a>(
+        captions: &'a mut HashMap<usize, Option<CaptionDecoder>>,
+        config: &PipelineConfig,
+        stream: *mut AVStream,
+    ) -> Option<&'a mut CaptionDecoder> {
+        let idx = (*stream).index as usize;
+        if !config
+            .variants
+            .iter()
+            .any(|v| matches!(v, VariantStream::Subtitle(_)) && v.src_index() == idx)
+        {
+            return None;
+        }
+        captions
+            .entry(idx)
+            .or_insert_with(|| match CaptionDecoder::new(stream) {
+                Ok(d) => Some(d),
+                Err(e) => {
+                    warn!("Captions of stream {} are not decoded: {}", idx, e);
+                    None
+                }
+            })
+            .as_mut()
+    }
+
+    /// Wait until [pkt] is due so the input plays at realtime speed
+    unsafe fn pace(&mut self, pkt: *mut AVPacket, stream: *mut AVStream) {
+        let ts = if (*pkt).pts != AV_NOPTS_VALUE {
+            (*pkt).pts
+        } else {
+            (*pkt).dts
+        };
+        if ts == AV_NOPTS_VALUE {
+            return;
+        }
+        let t = ts as f64 * av_q2d((*stream).time_base);
+        let (start, origin) = *self.pace_origin.get_or_insert((Instant::now(), t));
+        let due = Duration::from_secs_f64((t - origin).max(0.0));
+        if let Some(wait) = due.checked_sub(start.elapsed()) {
+            std::thread::sleep(wait);
+        }
+    }
+
     /// Main processor, should be called in a loop
     /// Returns false when stream data ended (EOF)
     pub unsafe fn run(&mut self) -> Result<bool> {
@@ -148,6 +290,38 @@ impl PipelineRunner {
         if pkt.is_null() {
             return Ok(false);
         }
+        if self.connection.params.contains_key(PARAM_REALTIME) {
+            self.pace(pkt, stream);
+        }
+        if self.settle.drop_packet(pkt, stream) {
+            av_packet_free(&mut pkt);
+            return Ok(true);
+        }
+        if (*pkt).pts != AV_NOPTS_VALUE {
+            let interval = config.program_date_time_anchor;
+            let due = self
+                .wall_clock_anchored
+                .is_none_or(|t| interval > 0 && t.elapsed() >= Duration::from_secs(interval));
+            if due {
+                let clock = WallClock {
+                    pts: (*pkt).pts as f64 * av_q2d((*stream).time_base),
+                    received: Utc::now(),
+                };
+                self.egress.iter_mut().for_each(|e| e.set_wall_clock(clock));
+                self.wall_clock_anchored = Some(Instant::now());
+            }
+        }
+
+        if (*(*stream).codecpar).codec_type == AVMediaType::AVMEDIA_TYPE_SUBTITLE {
+            if let Some(dec) = Self::caption_decoder(&mut self.captions, config, stream) {
+                match dec.decode_packet(pkt) {
+                    Ok(cues) => self.egress.iter_mut().for_each(|e| e.add_cues(&cues)),
+                    Err(e) => warn!("{}", e),
+                }
+            }
+            av_packet_free(&mut pkt);
+            return Ok(true);
+        }
 
         // TODO: For copy streams, skip decoder
         let frames = match self.decoder.decode_pkt(pkt) {
@@ -165,6 +339,28 @@ impl PipelineRunner {
             (*frame).time_base = (*stream).time_base;
 
             let p = (*stream).codecpar;
+            if (*p).codec_type == AVMediaType::AVMEDIA_TYPE_VIDEO {
+                if let Some(dec) = Self::caption_decoder(&mut self.captions, config, stream) {
+                    match dec.decode_frame(frame) {
+                        Ok(cues) if !cues.is_empty() => {
+                            self.egress.iter_mut().for_each(|e| e.add_cues(&cues))
+                        }
+                        Ok(_) => {}
+                        Err(e) => warn!("{}", e),
+                    }
+                }
+            }
+            if let Some(slate) = &mut self.slate {
+                frame = slate.replace_frame(frame, (*p).codec_type)?;
+            }
+            #[cfg(feature = "stt")]
+            if let Some((idx, stt)) = &mut self.stt {
+                if *idx == (*stream).index as usize {
+                    if let Err(e) = stt.process_frame(frame) {
+                        warn!("Failed to buffer audio for speech to text: {}", e);
+                    }
+                }
+            }
             if (*p).codec_type == AVMediaType::AVMEDIA_TYPE_VIDEO {
                 if (self.frame_ctr % 1800) == 0 {
                     let dst_pic = PathBuf::from(&self.out_dir)
@@ -246,14 +442,39 @@ impl PipelineRunner {
                     }
                     _ => frame,
                 };
+                if new_frame && matches!(var, VariantStream::Audio(_)) {
+                    self.loudnorm.process(frame, &var.id(), enc.codec_context());
+                    self.settle.fade_in(frame, &var.id(), enc.codec_context());
+                }
+                if self.watermark.enabled()
+                    && !frame.is_null()
+                    && matches!(var, VariantStream::Video(_))
+                {
+                    if !new_frame {
+                        // the decoded frame is shared by all variants which aren't scaled
+                        frame = av_frame_clone(frame);
+                        new_frame = true;
+                    }
+                    if av_frame_make_writable(frame) < 0 {
+                        bail!("Failed to make frame writable for the watermark");
+                    }
+                    self.watermark.apply(frame);
+                }
 
+                if !frame.is_null() {
+                    self.encode_latency.frame_sent(&var.id(), (*frame).pts);
+                }
                 let packets = enc.encode_frame(frame)?;
                 // pass new packets to egress
                 for mut pkt in packets {
+                    self.encode_latency.packet_received(&var.id(), (*pkt).pts);
                     for eg in self.egress.iter_mut() {
                         let er = eg.process_pkt(pkt, &var.id())?;
                         egress_results.push(er);
                     }
+                    if let Some(rec) = &mut self.recorder {
+                        rec.process_pkt(pkt, &var.id())?;
+                    }
                     av_packet_free(&mut pkt);
                 }
 
@@ -267,10 +488,37 @@ impl PipelineRunner {
 
         av_packet_free(&mut pkt);
 
+        #[cfg(feature = "stt")]
+        if let Some((_, stt)) = &mut self.stt {
+            let cues = stt.poll();
+            if !cues.is_empty() {
+                self.egress.iter_mut().for_each(|e| e.add_cues(&cues));
+            }
+        }
+
+        let timings: Vec<SegmentTiming> = egress_results
+            .iter()
+            .filter_map(|er| match er {
+                EgressResult::NewSegment(seg) => Some(SegmentTiming::new(
+                    seg,
+                    self.encode_latency.take(&seg.variant),
+                )),
+                _ => None,
+            })
+            .collect();
+
         // egress results
-        self.handle.block_on(async {
+        let (new_segment, control) = self.handle.block_on(async {
+            let mut new_segment = false;
             for er in egress_results {
+                if let Some(tx) = &self.egress_results {
+                    if !matches!(er, EgressResult::None) {
+                        // the application may have stopped listening
+                        let _ = tx.send(er.clone());
+                    }
+                }
                 if let EgressResult::NewSegment(seg) = er {
+                    new_segment = true;
                     if let Err(e) = self
                         .overseer
                         .on_segment(&config.id, &seg.variant, seg.idx, seg.duration, &seg.path)
@@ -280,8 +528,25 @@ impl PipelineRunner {
                     }
                 }
             }
-            Ok(())
+            for t in &timings {
+                self.overseer.on_segment_timing(&config.id, t).await?;
+            }
+            let control = if new_segment {
+                if let Some(stats) = self.ingress.stats() {
+                    self.overseer.on_endpoint_stats(&config.id, &stats).await?;
+                }
+                self.overseer.pipeline_control(&config.id).await?
+            } else {
+                None
+            };
+            Ok((new_segment, control))
         })?;
+        if let Some(control) = control {
+            self.apply_control(control)?;
+        }
+        if new_segment {
+            self.check_disk()?;
+        }
         let elapsed = Instant::now().sub(self.fps_counter_start).as_secs_f32();
         if elapsed >= 2f32 {
             let n_frames = self.frame_ctr - self.fps_last_frame_ctr;
@@ -292,6 +557,142 @@ impl PipelineRunner {
         Ok(true)
     }
 
+    /// Apply runtime changes requested by the overseer
+    unsafe fn apply_control(&mut self, control: PipelineControl) -> Result<()> {
+        if control.stop {
+            bail!("Pipeline stopped by the overseer");
+        }
+        if control == self.control {
+            return Ok(());
+        }
+        let id = match &self.config {
+            Some(cfg) => cfg.id,
+            None => bail!("Cannot control pipeline without config"),
+        };
+
+        if control.recording && self.recorder.is_none() && !self.recording_paused {
+            self.start_recorder()?;
+        } else if !control.recording {
+            if let Some(rec) = self.recorder.take() {
+                self.finish_recorder(rec)?;
+                info!("Recording stopped for {}", id);
+            }
+        }
+        if !control.slate {
+            self.slate = None;
+        } else if !self.control.slate
+            || control.slate_image != self.control.slate_image
+            || control.slate_audio != self.control.slate_audio
+        {
+            self.slate = Some(Slate::new(
+                control.slate_image.clone(),
+                control.slate_audio.clone(),
+            ));
+        }
+        if control.slate != self.control.slate {
+            info!(
+                "Slate {} for {}",
+                if control.slate { "on" } else { "off" },
+                id
+            );
+        }
+        if control.playlist_window != self.control.playlist_window {
+            for e in self.egress.iter_mut() {
+                e.set_playlist_window(control.playlist_window);
+            }
+            info!(
+                "Playlist window of {} set to {:?} segments",
+                id, control.playlist_window
+            );
+        }
+        if control.ad_break != self.control.ad_break {
+            for e in self.egress.iter_mut() {
+                e.set_ad_break(control.ad_break.as_ref());
+            }
+            match &control.ad_break {
+                Some(b) => info!("Ad break {} of {}s started for {}", b.id, b.duration, id),
+                None => info!("Ad break ended for {}", id),
+            }
+        }
+        self.control = control;
+        Ok(())
+    }
+
+    unsafe fn start_recorder(&mut self) -> Result<()> {
+        let cfg = if let Some(ref cfg) = self.config {
+            cfg
+        } else {
+            bail!("Cannot record pipeline without config");
+        };
+        // record the variants of the recorder egress if the stream started with one
+        let recorded = cfg.egress.iter().find_map(|e| match e {
+            EgressType::Recorder(c) => Some(&c.variants),
+            _ => None,
+        });
+        let encoders = self.encoders.iter().filter_map(|(k, v)| {
+            if recorded.is_some_and(|r| !r.contains(k)) {
+                return None;
+            }
+            let var = cfg.variants.iter().find(|x| x.id() == *k)?;
+            Some((var, v))
+        });
+        self.recorder = Some(RecorderEgress::new(
+            &cfg.id,
+            &self.out_dir,
+            encoders,
+            cfg.recording_mp4,
+        )?);
+        info!("Recording started for {}", cfg.id);
+        Ok(())
+    }
+
+    /// Close a recording, MP4 recordings are remuxed on another thread and reported to
+    /// the overseer once written so the stream keeps running meanwhile
+    unsafe fn finish_recorder(&mut self, mut rec: RecorderEgress) -> Result<()> {
+        rec.reset()?;
+        let (id, remux) = match (&self.config, rec.remux()) {
+            (Some(cfg), Some(r)) => (cfg.id, r),
+            _ => return Ok(()),
+        };
+        let overseer = self.overseer.clone();
+        let handle = self.handle.clone();
+        std::thread::Builder::new()
+            .name("recording-remux".to_string())
+            .spawn(move || complete_recording(handle, overseer, id, remux))?;
+        Ok(())
+    }
+
+    /// Pause recording while the disk is almost full so the HLS output keeps working,
+    /// recording resumes in a new part once space is available again
+    unsafe fn check_disk(&mut self) -> Result<()> {
+        let low = disk_low();
+        if low == self.recording_paused || (low && self.recorder.is_none()) {
+            return Ok(());
+        }
+        let id = match &self.config {
+            Some(cfg) => cfg.id,
+            None => return Ok(()),
+        };
+        if low {
+            if let Some(rec) = self.recorder.take() {
+                self.finish_recorder(rec)?;
+            }
+            warn!("Disk space low, recording paused for {}", id);
+        } else {
+            info!("Disk space available, recording resumed for {}", id);
+            if self.control.recording {
+                self.start_recorder()?;
+            }
+        }
+        self.recording_paused = low;
+        self.handle.block_on(async {
+            if let Err(e) = self.overseer.on_recording_paused(&id, low).await {
+                warn!("Failed to notify recording paused: {}", e);
+            }
+        });
+        Ok(())
+    }
+
     unsafe fn setup(&mut self) -> Result<()> {
         if self.info.is_some() {
             return Ok(());
@@ -323,9 +724,32 @@ impl PipelineRunner {
                 .collect(),
         };
 
-        let cfg = self
+        let mut cfg = match self
             .handle
-            .block_on(async { self.overseer.start_stream(&self.connection, &i_info).await })?;
+            .block_on(async { self.overseer.start_stream(&self.connection, &i_info).await })
+        {
+            Ok(cfg) => cfg,
+            Err(e) => {
+                self.rejection = Some(Rejection::from_error(&e));
+                return Err(e);
+            }
+        };
+        check_hls_ladder(&mut cfg.variants, SEGMENT_LENGTH);
+        cfg.validate()?;
+        self.control.recording = cfg
+            .egress
+            .iter()
+            .any(|e| matches!(e, EgressType::Recorder(_)));
+        PipelineLog::attach(&cfg.id, &self.out_dir);
+        self.settle = StartSettle::new(cfg.settle_window);
+        self.loudnorm = LoudNorm::new(cfg.loudnorm);
+        self.watermark = WatermarkOverlay::new(cfg.watermark.clone());
+        if cfg.failover_window > 0 {
+            self.ingress.enable_failover(
+                &self.connection.key,
+                Duration::from_secs(cfg.failover_window),
+            );
+        }
         self.config = Some(cfg);
         self.info = Some(i_info);
 
@@ -341,7 +765,13 @@ impl PipelineRunner {
         };
 
         // src stream indexes
-        let inputs: HashSet<usize> = cfg.variants.iter().map(|e| e.src_index()).collect();
+        // captions have their own decoders
+        let inputs: HashSet<usize> = cfg
+            .variants
+            .iter()
+            .filter(|v| !matches!(v, VariantStream::Subtitle(_)))
+            .map(|e| e.src_index())
+            .collect();
 
         // enable hardware decoding
         self.decoder.enable_hw_decoder_any();
@@ -377,6 +807,24 @@ impl PipelineRunner {
 
         // TODO: Setup copy streams
 
+        // subtitle variants of an audio stream are captions generated by speech to text
+        let stt_src = cfg.variants.iter().find_map(|v| match v {
+            VariantStream::Subtitle(_) => demux_info
+                .streams
+                .iter()
+                .find(|s| s.index == v.src_index() && matches!(s.stream_type, StreamType::Audio))
+                .map(|s| s.index),
+            _ => None,
+        });
+        #[cfg(feature = "stt")]
+        if let Some(idx) = stt_src {
+            self.stt = Some((idx, SpeechToText::new(self.handle.clone())?));
+        }
+        #[cfg(not(feature = "stt"))]
+        if stt_src.is_some() {
+            warn!("Captions can't be generated without the stt feature");
+        }
+
         // Setup egress
         for e in &cfg.egress {
             let c = e.config();
@@ -390,13 +838,41 @@ impl PipelineRunner {
             });
             match e {
                 EgressType::HLS(_) => {
-                    let hls =
-                        HlsEgress::new(&cfg.id, &self.out_dir, 2.0, encoders, SegmentType::MPEGTS)?;
+                    let segment_type = cfg.hls_segment_type(c);
+                    let mut hls = HlsEgress::new(
+                        &cfg.id,
+                        &self.out_dir,
+                        SEGMENT_LENGTH,
+                        encoders,
+                        segment_type,
+                    )?;
+                    let subtitles = cfg.variants.iter().find(|v| {
+                        matches!(v, VariantStream::Subtitle(_)) && c.variants.contains(&v.id())
+                    });
+                    if let Some(s) = subtitles {
+                        let language = self
+                            .info
+                            .as_ref()
+                            .and_then(|i| i.streams.iter().find(|x| x.index == s.src_index()))
+                            .map(|x| x.language.clone())
+                            .filter(|l| !l.is_empty() && l != "und");
+                        // generated captions are written once they're transcribed
+                        #[cfg(feature = "stt")]
+                        let lag = if stt_src == Some(s.src_index()) {
+                            STT_LAG_SEGMENTS
+                        } else {
+                            0
+                        };
+                        #[cfg(not(feature = "stt"))]
+                        let lag = 0;
+                        hls.add_subtitles(language, lag)?;
+                    }
                     self.egress.push(Box::new(hls));
                 }
                 EgressType::Recorder(_) => {
-                    let rec = RecorderEgress::new(&cfg.id, &self.out_dir, encoders)?;
-                    self.egress.push(Box::new(rec));
+                    let rec =
+                        RecorderEgress::new(&cfg.id, &self.out_dir, encoders, cfg.recording_mp4)?;
+                    self.recorder = Some(rec);
                 }
                 _ => warn!("{} is not implemented", e),
             }
@@ -404,3 +880,19 @@ impl PipelineRunner {
         Ok(())
     }
 }
+
+fn complete_recording(
+    handle: Handle,
+    overseer: Arc<dyn Overseer>,
+    id: Uuid,
+    remux: RecordingRemux,
+) {
+    match remux.run() {
+        Ok(files) => {
+            if let Err(e) = handle.block_on(overseer.on_recording_complete(&id, &files)) {
+                warn!("Failed to report recording of {}: {}", id, e);
+            }
+        }
+        Err(e) => warn!("Failed to remux recording of {}: {}", id, e),
+    }
+}
@@ -0,0 +1,114 @@
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVSampleFormat::{AV_SAMPLE_FMT_FLT, AV_SAMPLE_FMT_FLTP};
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::{
+    av_q2d, AVCodecContext, AVFrame, AVPacket, AVStream, AV_NOPTS_VALUE,
+};
+use log::info;
+use std::collections::HashMap;
+use std::slice;
+use uuid::Uuid;
+
+/// Default length of the settle window in seconds
+pub const DEFAULT_SETTLE_WINDOW: f32 = 2.0;
+
+/// Smooths the start of a stream, timestamps from encoders often jump around and audio
+/// pops until the encoder has settled
+///
+/// During the first [window] seconds of each input stream packets which go back in time
+/// are dropped, and audio is faded in over the same window
+#[derive(Default)]
+pub struct StartSettle {
+    /// Length of the window in seconds, 0 disables settling
+    window: f32,
+    /// (first, last) timestamp in seconds of each input stream
+    streams: HashMap<i32, (f64, f64)>,
+    /// Samples faded in per audio variant
+    audio_samples: HashMap<Uuid, u64>,
+    /// Packets dropped during the window
+    dropped: u64,
+}
+
+impl StartSettle {
+    pub fn new(window: f32) -> Self {
+        Self {
+            window: window.max(0.0),
+            ..Default::default()
+        }
+    }
+
+    /// True if [pkt] of [stream] is out of order within the settle window and should be
+    /// dropped
+    pub unsafe fn drop_packet(&mut self, pkt: *const AVPacket, stream: *const AVStream) -> bool {
+        if self.window == 0.0 {
+            return false;
+        }
+        let ts = if (*pkt).dts != AV_NOPTS_VALUE {
+            (*pkt).dts
+        } else {
+            (*pkt).pts
+        };
+        if ts == AV_NOPTS_VALUE {
+            return false;
+        }
+        let t = ts as f64 * av_q2d((*stream).time_base);
+        let (start, last) = self.streams.entry((*stream).index).or_insert((t, f64::MIN));
+        if t - *start >= self.window as f64 {
+            if self.dropped > 0 {
+                info!(
+                    "Dropped {} out of order packets while the stream settled",
+                    self.dropped
+                );
+                self.dropped = 0;
+            }
+            return false;
+        }
+        if t <= *last {
+            self.dropped += 1;
+            return true;
+        }
+        *last = t;
+        false
+    }
+
+    /// Fade in audio [frame] of [variant] during the settle window
+    ///
+    /// [frame] must be in the sample format of [enc], only float formats are faded
+    pub unsafe fn fade_in(
+        &mut self,
+        frame: *mut AVFrame,
+        variant: &Uuid,
+        enc: *const AVCodecContext,
+    ) {
+        if self.window == 0.0 || frame.is_null() {
+            return;
+        }
+        let rate = (*enc).sample_rate as f64;
+        let fade_samples = (self.window as f64 * rate) as u64;
+        let done = self.audio_samples.entry(*variant).or_insert(0);
+        if *done >= fade_samples {
+            return;
+        }
+        let n = (*frame).nb_samples as usize;
+        let channels = (*enc).ch_layout.nb_channels as usize;
+        let gain = |i: usize| ((*done + i as u64) as f32 / fade_samples as f32).min(1.0);
+        match (*enc).sample_fmt {
+            AV_SAMPLE_FMT_FLTP => {
+                for c in 0..channels {
+                    let data =
+                        slice::from_raw_parts_mut(*(*frame).extended_data.add(c) as *mut f32, n);
+                    for (i, s) in data.iter_mut().enumerate() {
+                        *s *= gain(i);
+                    }
+                }
+            }
+            AV_SAMPLE_FMT_FLT => {
+                let data =
+                    slice::from_raw_parts_mut(*(*frame).extended_data as *mut f32, n * channels);
+                for (i, s) in data.iter_mut().enumerate() {
+                    *s *= gain(i / channels);
+                }
+            }
+            _ => {}
+        }
+        *done += n as u64;
+    }
+}
@@ -0,0 +1,294 @@
+use anyhow::{bail, Result};
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVPixelFormat::AV_PIX_FMT_RGBA;
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::{
+    av_frame_alloc, av_frame_clone, av_frame_copy_props, av_frame_free, av_frame_get_buffer,
+    av_frame_make_writable, av_get_bytes_per_sample, av_packet_free, av_sample_fmt_is_planar,
+    av_samples_set_silence, AVFrame, AVMediaType,
+};
+use ffmpeg_rs_raw::{Decoder, Demuxer, Resample, Scaler, StreamType};
+use log::warn;
+use std::fs::File;
+use std::mem::transmute;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+/// Placeholder shown instead of the input while a pipeline is in slate mode
+///
+/// Video is replaced with [image] (or black) and audio with a loop of [audio] (or silence),
+/// both are converted to match the input so the encoders see no change
+pub struct Slate {
+    image: Option<PathBuf>,
+    audio: Option<PathBuf>,
+    /// Image frame matching the size/format of the input video
+    video_frame: Option<*mut AVFrame>,
+    /// Decoded audio loop matching the format of the input audio
+    audio_loop: Option<AudioLoop>,
+}
+
+/// Decoded audio samples, one buffer per plane (a single buffer for packed formats)
+struct AudioLoop {
+    format: i32,
+    sample_rate: i32,
+    channels: i32,
+    planes: Vec<Vec<u8>>,
+    /// Bytes per sample in each plane
+    sample_size: usize,
+    /// Read position in samples
+    pos: usize,
+}
+
+impl Slate {
+    pub fn new(image: Option<PathBuf>, audio: Option<PathBuf>) -> Self {
+        Self {
+            image,
+            audio,
+            video_frame: None,
+            audio_loop: None,
+        }
+    }
+
+    /// Replace the content of a decoded input [frame], the returned frame replaces [frame]
+    pub unsafe fn replace_frame(
+        &mut self,
+        frame: *mut AVFrame,
+        media_type: AVMediaType,
+    ) -> Result<*mut AVFrame> {
+        match media_type {
+            AVMediaType::AVMEDIA_TYPE_VIDEO => self.replace_video(frame),
+            AVMediaType::AVMEDIA_TYPE_AUDIO => {
+                self.replace_audio(frame);
+                Ok(frame)
+            }
+            _ => Ok(frame),
+        }
+    }
+
+    unsafe fn replace_video(&mut self, mut frame: *mut AVFrame) -> Result<*mut AVFrame> {
+        let src = match self.video_frame {
+            Some(s)
+                if (*s).width == (*frame).width
+                    && (*s).height == (*frame).height
+                    && (*s).format == (*frame).format =>
+            {
+                s
+            }
+            _ => {
+                if let Some(mut s) = self.video_frame.take() {
+                    av_frame_free(&mut s);
+                }
+                let s = self.load_image(frame)?;
+                self.video_frame = Some(s);
+                s
+            }
+        };
+        let out = av_frame_clone(src);
+        av_frame_copy_props(out, frame);
+        (*out).time_base = (*frame).time_base;
+        av_frame_free(&mut frame);
+        Ok(out)
+    }
+
+    unsafe fn replace_audio(&mut self, frame: *mut AVFrame) {
+        if av_frame_make_writable(frame) < 0 {
+            return;
+        }
+        let matches = |l: &AudioLoop| {
+            l.format == (*frame).format
+                && l.sample_rate == (*frame).sample_rate
+                && l.channels == (*frame).ch_layout.nb_channels
+        };
+        if let Some(path) = self.audio.clone() {
+            if !self.audio_loop.as_ref().is_some_and(matches) {
+                self.audio_loop = match AudioLoop::load(&path, frame) {
+                    Ok(l) => Some(l),
+                    Err(e) => {
+                        warn!("Failed to load slate audio {}: {}", path.display(), e);
+                        self.audio = None;
+                        None
+                    }
+                };
+            }
+        }
+        match &mut self.audio_loop {
+            Some(l) => l.fill(frame),
+            None => {
+                av_samples_set_silence(
+                    (*frame).extended_data,
+                    0,
+                    (*frame).nb_samples,
+                    (*frame).ch_layout.nb_channels,
+                    transmute((*frame).format),
+                );
+            }
+        }
+    }
+
+    /// Slate image converted to the size and pixel format of [frame], black if no image is
+    /// set or it can't be decoded
+    unsafe fn load_image(&mut self, frame: *const AVFrame) -> Result<*mut AVFrame> {
+        let mut src = match self.image.clone() {
+            Some(path) => match decode_first_frame(&path, AVMediaType::AVMEDIA_TYPE_VIDEO) {
+                Ok(f) => f,
+                Err(e) => {
+                    warn!("Failed to load slate image {}: {}", path.display(), e);
+                    self.image = None;
+                    black_frame(frame)
+                }
+            },
+            None => black_frame(frame),
+        };
+        let mut sw = Scaler::new();
+        let out = sw.process_frame(
+            src,
+            (*frame).width as _,
+            (*frame).height as _,
+            transmute((*frame).format),
+        );
+        av_frame_free(&mut src);
+        out
+    }
+}
+
+impl Drop for Slate {
+    fn drop(&mut self) {
+        if let Some(mut f) = self.video_frame.take() {
+            unsafe { av_frame_free(&mut f) };
+        }
+    }
+}
+
+impl AudioLoop {
+    /// Decode the whole file at [path], resampled to the format of [frame]
+    unsafe fn load(path: &Path, frame: *const AVFrame) -> Result<Self> {
+        let channels = (*frame).ch_layout.nb_channels;
+        let planar = av_sample_fmt_is_planar(transmute((*frame).format)) == 1;
+        let bps = av_get_bytes_per_sample(transmute((*frame).format)) as usize;
+        let mut ret = Self {
+            format: (*frame).format,
+            sample_rate: (*frame).sample_rate,
+            channels,
+            planes: vec![vec![]; if planar { channels as usize } else { 1 }],
+            sample_size: if planar { bps } else { bps * channels as usize },
+            pos: 0,
+        };
+
+        let mut resample = Resample::new(
+            transmute((*frame).format),
+            (*frame).sample_rate as _,
+            channels as _,
+        );
+        for_each_frame(path, AVMediaType::AVMEDIA_TYPE_AUDIO, |f| {
+            let mut resampled = resample.process_frame(f)?;
+            let len = (*resampled).nb_samples as usize * ret.sample_size;
+            for (i, plane) in ret.planes.iter_mut().enumerate() {
+                let data = *(*resampled).extended_data.add(i);
+                plane.extend_from_slice(std::slice::from_raw_parts(data, len));
+            }
+            av_frame_free(&mut resampled);
+            Ok(true)
+        })?;
+        if ret.samples() == 0 {
+            bail!("No audio samples");
+        }
+        Ok(ret)
+    }
+
+    fn samples(&self) -> usize {
+        self.planes[0].len() / self.sample_size
+    }
+
+    /// Overwrite the samples of [frame] with the next samples of the loop
+    unsafe fn fill(&mut self, frame: *mut AVFrame) {
+        let total = self.samples();
+        let mut written = 0;
+        while written < (*frame).nb_samples as usize {
+            let n = ((*frame).nb_samples as usize - written).min(total - self.pos);
+            for (i, plane) in self.planes.iter().enumerate() {
+                let dst = (*(*frame).extended_data.add(i)).add(written * self.sample_size);
+                let src = plane.as_ptr().add(self.pos * self.sample_size);
+                ptr::copy_nonoverlapping(src, dst, n * self.sample_size);
+            }
+            written += n;
+            self.pos = (self.pos + n) % total;
+        }
+    }
+}
+
+/// Black RGBA frame with the same size as [frame]
+unsafe fn black_frame(frame: *const AVFrame) -> *mut AVFrame {
+    let rgba = av_frame_alloc();
+    (*rgba).width = (*frame).width;
+    (*rgba).height = (*frame).height;
+    (*rgba).format = AV_PIX_FMT_RGBA as _;
+    av_frame_get_buffer(rgba, 0);
+    ptr::write_bytes(
+        (*rgba).data[0],
+        0,
+        (*rgba).linesize[0] as usize * (*rgba).height as usize,
+    );
+    rgba
+}
+
+/// Decode the first frame of the first [media_type] stream in a file
+pub(crate) unsafe fn decode_first_frame(
+    path: &Path,
+    media_type: AVMediaType,
+) -> Result<*mut AVFrame> {
+    let mut ret = None;
+    for_each_frame(path, media_type, |f| {
+        ret = Some(av_frame_clone(f));
+        Ok(false)
+    })?;
+    match ret {
+        Some(f) => Ok(f),
+        None => bail!("No frames decoded"),
+    }
+}
+
+/// Decode all frames of the first [media_type] stream in a file until [f] returns false
+///
+/// Frames passed to [f] are freed after it returns
+unsafe fn for_each_frame(
+    path: &Path,
+    media_type: AVMediaType,
+    mut f: impl FnMut(*mut AVFrame) -> Result<bool>,
+) -> Result<()> {
+    let mut demuxer = Demuxer::new_custom_io(Box::new(File::open(path)?), None)?;
+    let info = demuxer.probe_input()?;
+    let stream = info.streams.iter().find(|s| {
+        let t = match s.stream_type {
+            StreamType::Video => AVMediaType::AVMEDIA_TYPE_VIDEO,
+            StreamType::Audio => AVMediaType::AVMEDIA_TYPE_AUDIO,
+            StreamType::Subtitle => AVMediaType::AVMEDIA_TYPE_SUBTITLE,
+        };
+        t == media_type
+    });
+    let stream = match stream {
+        Some(s) => s,
+        None => bail!("No {:?} stream found", media_type),
+    };
+    let mut decoder = Decoder::new();
+    decoder.setup_decoder(stream, None)?;
+    loop {
+        let (mut pkt, pkt_stream) = demuxer.get_packet()?;
+        if pkt.is_null() {
+            return Ok(());
+        }
+        if (*pkt_stream).index as usize != stream.index {
+            av_packet_free(&mut pkt);
+            continue;
+        }
+        let frames = decoder.decode_pkt(pkt);
+        av_packet_free(&mut pkt);
+        let mut more = true;
+        for mut frame in frames? {
+            if more {
+                more = f(frame)?;
+            }
+            av_frame_free(&mut frame);
+        }
+        if !more {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use crate::mux::{Cue, SEGMENT_LENGTH};
+use crate::settings::SpeechToTextSettings;
+use anyhow::{bail, Result};
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVSampleFormat::AV_SAMPLE_FMT_S16;
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::{av_frame_free, av_q2d, AVFrame, AV_NOPTS_VALUE};
+use ffmpeg_rs_raw::Resample;
+use log::warn;
+use serde::Deserialize;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, OnceLock};
+use tokio::runtime::Handle;
+use uuid::Uuid;
+
+/// Sample rate of the mono audio sent for transcription
+const SAMPLE_RATE: usize = 16_000;
+
+/// Seconds of audio per transcription, chunks end on segment boundaries
+const CHUNK_SECS: f64 = SEGMENT_LENGTH as f64 * 2.0;
+
+/// Subtitle segments of generated captions are written this many segments after the
+/// video, giving the transcription of a chunk time to come back
+pub const STT_LAG_SEGMENTS: usize = 3;
+
+/// Transcriptions running per stream, chunks are skipped while the endpoint is behind
+const MAX_IN_FLIGHT: usize = 2;
+
+static CONFIG: OnceLock<SpeechToTextSettings> = OnceLock::new();
+
+/// Set the transcription endpoint, captions are only generated once it's configured
+pub fn configure(settings: SpeechToTextSettings) {
+    let _ = CONFIG.set(settings);
+}
+
+pub fn enabled() -> bool {
+    CONFIG.get().is_some()
+}
+
+/// Generates captions of a stream by sending its audio to a speech to text endpoint
+///
+/// The endpoint takes OpenAI style transcription requests (`/v1/audio/transcriptions`),
+/// which whisper.cpp's `whisper-server` and most hosted services accept. Requests run on
+/// the runtime of the pipeline, their cues are collected with [Self::poll]
+pub struct SpeechToText {
+    handle: Handle,
+    client: reqwest::Client,
+    config: &'static SpeechToTextSettings,
+    resample: Resample,
+    /// Samples of the current chunk
+    pcm: Vec<i16>,
+    /// (index, start in seconds) of the current chunk
+    chunk: Option<(u64, f64)>,
+    in_flight: Arc<AtomicUsize>,
+    tx: Sender<Vec<Cue>>,
+    rx: Receiver<Vec<Cue>>,
+}
+
+impl SpeechToText {
+    pub fn new(handle: Handle) -> Result<Self> {
+        let config = match CONFIG.get() {
+            Some(c) => c,
+            None => bail!("Speech to text is not configured"),
+        };
+        let (tx, rx) = channel();
+        Ok(Self {
+            handle,
+            client: reqwest::Client::new(),
+            config,
+            resample: Resample::new(AV_SAMPLE_FMT_S16, SAMPLE_RATE as _, 1),
+            pcm: Vec::new(),
+            chunk: None,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            tx,
+            rx,
+        })
+    }
+
+    /// Buffer a decoded audio [frame], a finished chunk is sent for transcription
+    pub unsafe fn process_frame(&mut self, frame: *mut AVFrame) -> Result<()> {
+        if (*frame).pts == AV_NOPTS_VALUE {
+            return Ok(());
+        }
+        let t = (*frame).pts as f64 * av_q2d((*frame).time_base);
+        let idx = (t / CHUNK_SECS).floor().max(0.0) as u64;
+        if self.chunk.is_some_and(|(i, _)| i != idx) {
+            self.flush();
+        }
+        self.chunk.get_or_insert((idx, t));
+
+        let mut out = self.resample.process_frame(frame)?;
+        let n = (*out).nb_samples as usize;
+        self.pcm
+            .extend_from_slice(slice::from_raw_parts((*out).data[0] as *const i16, n));
+        av_frame_free(&mut out);
+        Ok(())
+    }
+
+    /// Cues of the transcriptions which finished since the last call
+    pub fn poll(&mut self) -> Vec<Cue> {
+        self.rx.try_iter().flatten().collect()
+    }
+
+    fn flush(&mut self) {
+        let start = match self.chunk.take() {
+            Some((_, s)) => s,
+            None => return,
+        };
+        let pcm = std::mem::take(&mut self.pcm);
+        if pcm.is_empty() {
+            return;
+        }
+        let duration = pcm.len() as f64 / SAMPLE_RATE as f64;
+        if self.in_flight.load(Ordering::Relaxed) >= MAX_IN_FLIGHT {
+            warn!(
+                "Speech to text is behind, skipped {:.1}s of audio at {:.1}s",
+                duration, start
+            );
+            return;
+        }
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let client = self.client.clone();
+        let config = self.config;
+        let tx = self.tx.clone();
+        let in_flight = self.in_flight.clone();
+        self.handle.spawn(async move {
+            match transcribe(&client, config, &pcm).await {
+                Ok(t) => {
+                    let _ = tx.send(t.to_cues(start, duration));
+                }
+                Err(e) => warn!("Speech to text failed: {}", e),
+            }
+            in_flight.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+/// `verbose_json` response of a transcription, only `text` if the endpoint doesn't
+/// return segments
+#[derive(Deserialize)]
+struct Transcription {
+    text: String,
+    #[serde(default)]
+    segments: Vec<TranscriptionSegment>,
+}
+
+#[derive(Deserialize)]
+struct TranscriptionSegment {
+    /// Seconds from the start of the chunk
+    start: f64,
+    end: f64,
+    text: String,
+}
+
+impl Transcription {
+    /// Cues of the chunk starting at [start] lasting [duration] seconds
+    fn to_cues(&self, start: f64, duration: f64) -> Vec<Cue> {
+        let segments = if self.segments.is_empty() {
+            vec![(0.0, duration, self.text.as_str())]
+        } else {
+            self.segments
+                .iter()
+                .map(|s| (s.start, s.end.min(duration), s.text.as_str()))
+                .collect()
+        };
+        segments
+            .into_iter()
+            .map(|(s, e, text)| (s, e, text.trim()))
+            // whisper marks silence and noise as `[BLANK_AUDIO]`, `(music)` etc.
+            .filter(|(s, e, text)| {
+                e > s
+                    && !text.is_empty()
+                    && !(text.starts_with('[') && text.ends_with(']'))
+                    && !(text.starts_with('(') && text.ends_with(')'))
+            })
+            .map(|(s, e, text)| Cue {
+                start: start + s,
+                end: start + e,
+                text: text.to_string(),
+            })
+            .collect()
+    }
+}
+
+async fn transcribe(
+    client: &reqwest::Client,
+    config: &SpeechToTextSettings,
+    pcm: &[i16],
+) -> Result<Transcription> {
+    let boundary = format!("zap-stream-{}", Uuid::new_v4().simple());
+    let mut body = Vec::new();
+    let mut field = |name: &str, value: &str| {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                boundary, name, value
+            )
+            .as_bytes(),
+        );
+    };
+    field("model", config.model.as_deref().unwrap_or("whisper-1"));
+    field("response_format", "verbose_json");
+    if let Some(l) = &config.language {
+        field("language", l);
+    }
+    body.extend_from_slice(
+        format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"chunk.wav\"\r\nContent-Type: audio/wav\r\n\r\n",
+            boundary
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(&wav(pcm));
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    let mut req = client
+        .post(config.url.as_str())
+        .header(
+            "content-type",
+            format!("multipart/form-data; boundary={}", boundary),
+        )
+        .body(body);
+    if let Some(key) = &config.api_key {
+        req = req.bearer_auth(key);
+    }
+    let rsp = req.send().await?;
+    if !rsp.status().is_success() {
+        bail!("{} returned {}", config.url, rsp.status());
+    }
+    Ok(rsp.json().await?)
+}
+
+/// 16-bit mono WAV file of [pcm]
+fn wav(pcm: &[i16]) -> Vec<u8> {
+    let data_len = (pcm.len() * 2) as u32;
+    let mut ret = Vec::with_capacity(44 + data_len as usize);
+    ret.extend_from_slice(b"RIFF");
+    ret.extend_from_slice(&(36 + data_len).to_le_bytes());
+    ret.extend_from_slice(b"WAVEfmt ");
+    ret.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, 1 channel
+    ret.extend_from_slice(&1u16.to_le_bytes());
+    ret.extend_from_slice(&1u16.to_le_bytes());
+    ret.extend_from_slice(&(SAMPLE_RATE as u32).to_le_bytes());
+    ret.extend_from_slice(&(SAMPLE_RATE as u32 * 2).to_le_bytes());
+    ret.extend_from_slice(&2u16.to_le_bytes());
+    ret.extend_from_slice(&16u16.to_le_bytes());
+    ret.extend_from_slice(b"data");
+    ret.extend_from_slice(&data_len.to_le_bytes());
+    for s in pcm {
+        ret.extend_from_slice(&s.to_le_bytes());
+    }
+    ret
+}
@@ -0,0 +1,94 @@
+use crate::egress::NewSegment;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Frames waiting in an encoder which are tracked, frames the encoder drops are forgotten
+/// once this many newer frames were sent
+const MAX_PENDING_FRAMES: usize = 512;
+
+/// Timing of a segment written by a pipeline, reported with
+/// [crate::overseer::Overseer::on_segment_timing] to diagnose timing bugs in production
+#[derive(Debug, Clone, Serialize)]
+pub struct SegmentTiming {
+    /// Video variant (or audio of audio-only groups) of the segment
+    pub variant: Uuid,
+    pub idx: u64,
+    /// Duration in seconds, the difference of [Self::pts_end] and [Self::pts_start]
+    pub duration: f32,
+    /// Presentation time of the first packet in seconds
+    pub pts_start: f32,
+    /// Presentation time of the first packet of the next segment in seconds
+    pub pts_end: f32,
+    /// Average time frames of the segment spent in the encoder in ms, including its
+    /// lookahead, [None] for copied streams
+    pub encode_latency_ms: Option<f32>,
+    /// Time the segment was finished
+    pub written: DateTime<Utc>,
+}
+
+impl SegmentTiming {
+    pub fn new(seg: &NewSegment, encode_latency: Option<Duration>) -> Self {
+        Self {
+            variant: seg.variant,
+            idx: seg.idx,
+            duration: seg.duration,
+            pts_start: seg.pts_start,
+            pts_end: seg.pts_start + seg.duration,
+            encode_latency_ms: encode_latency.map(|d| d.as_secs_f32() * 1000.0),
+            written: Utc::now(),
+        }
+    }
+}
+
+/// Measures the time between a frame being sent to the encoder of a variant and its
+/// packet coming out, matched by the presentation timestamp
+#[derive(Default)]
+pub struct EncodeLatency {
+    variants: HashMap<Uuid, VariantLatency>,
+}
+
+#[derive(Default)]
+struct VariantLatency {
+    /// (pts, sent) of frames waiting in the encoder
+    pending: VecDeque<(i64, Instant)>,
+    /// Total latency and packets since the last [EncodeLatency::take]
+    total: Duration,
+    packets: u32,
+}
+
+impl EncodeLatency {
+    /// A frame with [pts] was sent to the encoder of [variant]
+    pub fn frame_sent(&mut self, variant: &Uuid, pts: i64) {
+        let v = self.variants.entry(*variant).or_default();
+        if v.pending.len() >= MAX_PENDING_FRAMES {
+            v.pending.pop_front();
+        }
+        v.pending.push_back((pts, Instant::now()));
+    }
+
+    /// A packet with [pts] came out of the encoder of [variant]
+    pub fn packet_received(&mut self, variant: &Uuid, pts: i64) {
+        let Some(v) = self.variants.get_mut(variant) else {
+            return;
+        };
+        // packets leave in decode order, B-frames come out before earlier frames
+        if let Some(i) = v.pending.iter().position(|(p, _)| *p == pts) {
+            if let Some((_, sent)) = v.pending.remove(i) {
+                v.total += sent.elapsed();
+                v.packets += 1;
+            }
+        }
+    }
+
+    /// Average latency of [variant] since the last call, [None] if no packet was encoded
+    pub fn take(&mut self, variant: &Uuid) -> Option<Duration> {
+        let v = self.variants.get_mut(variant)?;
+        let ret = (v.packets > 0).then(|| v.total / v.packets);
+        v.total = Duration::ZERO;
+        v.packets = 0;
+        ret
+    }
+}
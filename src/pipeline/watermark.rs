@@ -0,0 +1,200 @@
+use crate::pipeline::slate::decode_first_frame;
+use anyhow::{bail, Result};
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVPixelFormat::{AV_PIX_FMT_YUV420P, AV_PIX_FMT_YUVA420P};
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::{av_frame_free, AVFrame, AVMediaType};
+use ffmpeg_rs_raw::Scaler;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Width of the watermark relative to the width of the video
+const WATERMARK_WIDTH: f32 = 0.15;
+
+/// Distance of the watermark from the edges of the video relative to its height
+const WATERMARK_MARGIN: f32 = 0.03;
+
+/// Corner of the video the watermark is placed in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WatermarkPosition {
+    TopLeft,
+    #[default]
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Display for WatermarkPosition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WatermarkPosition::TopLeft => write!(f, "top-left"),
+            WatermarkPosition::TopRight => write!(f, "top-right"),
+            WatermarkPosition::BottomLeft => write!(f, "bottom-left"),
+            WatermarkPosition::BottomRight => write!(f, "bottom-right"),
+        }
+    }
+}
+
+impl FromStr for WatermarkPosition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "top-left" => Ok(WatermarkPosition::TopLeft),
+            "top-right" => Ok(WatermarkPosition::TopRight),
+            "bottom-left" => Ok(WatermarkPosition::BottomLeft),
+            "bottom-right" => Ok(WatermarkPosition::BottomRight),
+            _ => bail!("Unknown watermark position: {}", s),
+        }
+    }
+}
+
+/// Image overlaid on the transcoded video variants of a stream, copied variants are
+/// passed through untouched
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Watermark {
+    /// Image file (PNG with alpha, or anything ffmpeg decodes)
+    pub image: PathBuf,
+    #[serde(default)]
+    pub position: WatermarkPosition,
+    /// 0 (invisible) to 1 (the image alpha only)
+    pub opacity: f32,
+}
+
+/// Blends a [Watermark] into decoded video frames
+///
+/// The image is scaled to [WATERMARK_WIDTH] of each variant so it looks the same on every
+/// rung of the ladder
+#[derive(Default)]
+pub struct WatermarkOverlay {
+    config: Option<Watermark>,
+    /// Decoded image, loaded with the first frame
+    image: Option<*mut AVFrame>,
+    /// Image converted to YUVA 4:2:0 for each video size
+    scaled: HashMap<(i32, i32), *mut AVFrame>,
+}
+
+impl WatermarkOverlay {
+    pub fn new(config: Option<Watermark>) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.is_some()
+    }
+
+    /// Blend the watermark into the writable video [frame], the watermark is turned off
+    /// if it can't be loaded
+    pub unsafe fn apply(&mut self, frame: *mut AVFrame) {
+        let Some(cfg) = self.config.clone() else {
+            return;
+        };
+        if (*frame).format != AV_PIX_FMT_YUV420P as i32 {
+            warn!("Watermark is only supported on yuv420p video, turned off");
+            self.config = None;
+            return;
+        }
+        let size = ((*frame).width, (*frame).height);
+        let overlay = match self.scaled.get(&size) {
+            Some(o) => *o,
+            None => match self.scale(&cfg, size) {
+                Ok(o) => {
+                    self.scaled.insert(size, o);
+                    o
+                }
+                Err(e) => {
+                    warn!("Failed to load watermark {}: {}", cfg.image.display(), e);
+                    self.config = None;
+                    return;
+                }
+            },
+        };
+        blend(frame, overlay, cfg.position, cfg.opacity);
+    }
+
+    /// Watermark image for video of [size]
+    unsafe fn scale(&mut self, cfg: &Watermark, size: (i32, i32)) -> Result<*mut AVFrame> {
+        let image = match self.image {
+            Some(i) => i,
+            None => {
+                let i = decode_first_frame(&cfg.image, AVMediaType::AVMEDIA_TYPE_VIDEO)?;
+                self.image = Some(i);
+                i
+            }
+        };
+        if (*image).width <= 0 || (*image).height <= 0 {
+            bail!("Empty image");
+        }
+        // even dimensions keep the chroma planes aligned with the luma
+        let width = ((size.0 as f32 * WATERMARK_WIDTH) as i32 & !1).max(2);
+        let height = ((width * (*image).height / (*image).width) & !1).max(2);
+        let mut sw = Scaler::new();
+        sw.process_frame(image, width as _, height as _, AV_PIX_FMT_YUVA420P)
+    }
+}
+
+impl Drop for WatermarkOverlay {
+    fn drop(&mut self) {
+        if let Some(mut f) = self.image.take() {
+            unsafe { av_frame_free(&mut f) };
+        }
+        for (_, mut f) in self.scaled.drain() {
+            unsafe { av_frame_free(&mut f) };
+        }
+    }
+}
+
+/// Check the image at [path] can be decoded
+pub fn probe_image(path: &Path) -> Result<()> {
+    let mut frame = unsafe { decode_first_frame(path, AVMediaType::AVMEDIA_TYPE_VIDEO)? };
+    unsafe { av_frame_free(&mut frame) };
+    Ok(())
+}
+
+/// Alpha blend the YUVA 4:2:0 [overlay] into a corner of the YUV 4:2:0 [frame]
+unsafe fn blend(
+    frame: *mut AVFrame,
+    overlay: *const AVFrame,
+    position: WatermarkPosition,
+    opacity: f32,
+) {
+    let (fw, fh) = ((*frame).width as usize, (*frame).height as usize);
+    let (ow, oh) = ((*overlay).width as usize, (*overlay).height as usize);
+    let margin = (fh as f32 * WATERMARK_MARGIN) as usize & !1;
+    if ow + margin * 2 > fw || oh + margin * 2 > fh {
+        return;
+    }
+    let x = match position {
+        WatermarkPosition::TopLeft | WatermarkPosition::BottomLeft => margin,
+        _ => (fw - ow - margin) & !1,
+    };
+    let y = match position {
+        WatermarkPosition::TopLeft | WatermarkPosition::TopRight => margin,
+        _ => (fh - oh - margin) & !1,
+    };
+    let opacity = (opacity.clamp(0.0, 1.0) * 255.0) as u32;
+    let alpha = (*overlay).data[3];
+    let alpha_stride = (*overlay).linesize[3] as usize;
+    for plane in 0..3 {
+        // chroma planes are half size, sampling the alpha of the top left luma pixel
+        let sub = if plane == 0 { 0 } else { 1 };
+        let dst_stride = (*frame).linesize[plane] as usize;
+        let src_stride = (*overlay).linesize[plane] as usize;
+        for row in 0..oh >> sub {
+            let dst = (*frame).data[plane].add(((y >> sub) + row) * dst_stride + (x >> sub));
+            let src = (*overlay).data[plane].add(row * src_stride);
+            let a_row = alpha.add((row << sub) * alpha_stride);
+            for col in 0..ow >> sub {
+                let a = *a_row.add(col << sub) as u32 * opacity / 255;
+                let d = dst.add(col);
+                *d = ((*src.add(col) as u32 * a + *d as u32 * (255 - a)) / 255) as u8;
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use crate::mux::INIT_SEGMENT;
+use crate::settings::ReplicationSettings;
+use anyhow::{bail, Result};
+use log::{info, warn};
+use m3u8_rs::Playlist;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+use url::Url;
+
+/// Max batches of files waiting to be pushed to a peer, further segments are dropped until
+/// the peer catches up
+const PEER_QUEUE_SIZE: usize = 256;
+
+/// Pushes HLS output to peer nodes so viewers can be served from the nearest region
+///
+/// Peers accept files with `PUT /api/v1/replica/{path}` (see [crate::http::HttpServer]),
+/// authenticated with the shared [ReplicationSettings::token]. Each peer has its own queue
+/// which is pushed in order, so a peer never receives a playlist before its segments.
+#[derive(Clone)]
+pub struct Replicator {
+    queues: Vec<(Url, mpsc::Sender<Vec<PathBuf>>)>,
+}
+
+impl Replicator {
+    pub fn new(out_dir: &str, settings: &ReplicationSettings) -> Result<Self> {
+        let client = reqwest::Client::new();
+        let mut queues = vec![];
+        for p in &settings.peers {
+            let peer: Url = p.parse()?;
+            let (tx, rx) = mpsc::channel(PEER_QUEUE_SIZE);
+            let push = PeerPush {
+                out_dir: PathBuf::from(out_dir),
+                peer: peer.clone(),
+                token: settings.token.clone(),
+                client: client.clone(),
+                init_segments: HashMap::new(),
+            };
+            tokio::spawn(push.run(rx));
+            queues.push((peer, tx));
+        }
+        Ok(Self { queues })
+    }
+
+    /// Push a finished segment followed by its variant and master playlists to all peers,
+    /// fMP4 segments are preceded by the init segment of their variant when it changed
+    ///
+    /// Playlists are pushed after the segment so peers never reference missing files
+    pub fn push_segment(&self, segment: &Path) {
+        let mut files = vec![segment.to_path_buf()];
+        if let Some(variant_dir) = segment.parent() {
+            files.push(variant_dir.join("live.m3u8"));
+            if let Some(stream_dir) = variant_dir.parent() {
+                files.push(stream_dir.join("live.m3u8"));
+            }
+        }
+        for (peer, tx) in &self.queues {
+            if let Err(mpsc::error::TrySendError::Full(_)) = tx.try_send(files.clone()) {
+                warn!(
+                    "Replication to {} is falling behind, dropped {}",
+                    peer,
+                    segment.display()
+                );
+            }
+        }
+    }
+}
+
+/// Pushes the queued files of one peer in order
+struct PeerPush {
+    out_dir: PathBuf,
+    peer: Url,
+    token: String,
+    client: reqwest::Client,
+    /// Init segments already on the peer, by their modification time
+    init_segments: HashMap<PathBuf, SystemTime>,
+}
+
+impl PeerPush {
+    async fn run(mut self, mut rx: mpsc::Receiver<Vec<PathBuf>>) {
+        while let Some(files) = rx.recv().await {
+            if let Err(e) = self.push_init_segment(&files[0]).await {
+                warn!("Failed to replicate init segment to {}: {}", self.peer, e);
+                continue;
+            }
+            for f in files {
+                if let Err(e) = self.push_file(&f).await {
+                    warn!(
+                        "Failed to replicate {} to {}: {}",
+                        f.display(),
+                        self.peer,
+                        e
+                    );
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Push the init segment of the variant of [segment] (fMP4 only) if the peer doesn't
+    /// have this version of it yet
+    async fn push_init_segment(&mut self, segment: &Path) -> Result<()> {
+        let init = match segment.parent() {
+            Some(dir) => dir.join(INIT_SEGMENT),
+            None => return Ok(()),
+        };
+        let modified = match tokio::fs::metadata(&init).await {
+            Ok(m) => m.modified()?,
+            Err(_) => return Ok(()),
+        };
+        if self.init_segments.get(&init) == Some(&modified) {
+            return Ok(());
+        }
+        self.push_file(&init).await?;
+        self.init_segments.insert(init, modified);
+        Ok(())
+    }
+
+    async fn push_file(&self, file: &Path) -> Result<()> {
+        let rel = file.strip_prefix(&self.out_dir)?;
+        let url = self
+            .peer
+            .join(&format!("/api/v1/replica/{}", rel.to_string_lossy()))?;
+        let data = tokio::fs::read(file).await?;
+        self.client
+            .put(url)
+            .bearer_auth(&self.token)
+            .body(data)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Write a file pushed by an origin node into [out_dir]
+///
+/// When a media playlist is received any segments in the same directory which it no longer
+/// references are removed
+pub async fn accept_replica(out_dir: &Path, path: &str, data: &[u8]) -> Result<()> {
+    let rel = Path::new(path);
+    if rel.components().any(|c| !matches!(c, Component::Normal(_))) {
+        bail!("Invalid replica path");
+    }
+    let dst = out_dir.join(rel);
+    let dir = match dst.parent() {
+        Some(d) => d,
+        None => bail!("Invalid replica path"),
+    };
+    tokio::fs::create_dir_all(dir).await?;
+
+    // write to a temp file first so readers never see a partial file
+    let tmp = dst.with_extension("tmp");
+    tokio::fs::write(&tmp, data).await?;
+    tokio::fs::rename(&tmp, &dst).await?;
+
+    if dst.extension().is_some_and(|e| e == "m3u8") {
+        if let Ok((_, Playlist::MediaPlaylist(pl))) = m3u8_rs::parse_playlist(data) {
+            let mut entries = tokio::fs::read_dir(dir).await?;
+            while let Some(e) = entries.next_entry().await? {
+                let name = e.file_name().to_string_lossy().to_string();
+                let is_segment = name.ends_with(".ts") || name.ends_with(".m4s");
+                if is_segment && !pl.segments.iter().any(|s| s.uri == name) {
+                    info!("Removing expired replica segment {}", e.path().display());
+                    tokio::fs::remove_file(e.path()).await?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
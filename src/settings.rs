@@ -1,12 +1,20 @@
+use crate::http::IpRange;
+use crate::variant::video::VideoEncoder;
 use serde::{Deserialize, Serialize};
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use url::Url;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Settings {
     /// List of listen endpoints
     ///
     /// - srt://localhost:3333
+    /// - rist://[::]:5004?key=<stream-key> (feature `rist`)
     /// - tcp://localhost:3334
     /// - rtmp://localhost:1935
+    /// - unix:///run/zap-stream/ingest.sock?key=<stream-key> (MPEG-TS, unix only)
     pub endpoints: Vec<String>,
 
     /// Where to store output (static files)
@@ -18,12 +26,74 @@ pub struct Settings {
     /// Binding address for http server serving files from [output_dir]
     pub listen_http: String,
 
+    /// Binding address for https server (HTTP/1.1 + HTTP/2), requires [tls]
+    pub listen_https: Option<String>,
+
+    /// Binding address (UDP) for HTTP/3 playlist/segment delivery, requires [tls] or [acme]
+    pub listen_http3: Option<String>,
+
+    /// Binding address for the gRPC pipeline control API (feature `grpc`), see
+    /// [crate::control]
+    pub listen_grpc: Option<String>,
+
+    /// Reverse proxies in front of the http servers (IPs or CIDR ranges), the client IP of
+    /// requests from them is taken from `X-Forwarded-For`. Without any the connection address is
+    /// the client IP
+    pub trusted_proxies: Option<Vec<String>>,
+
     /// Overseer service see [crate::overseer::Overseer] for more info
     pub overseer: OverseerConfig,
+
+    /// TLS certificate used by `rtmps://` endpoints and the https/http3 servers
+    pub tls: Option<TlsSettings>,
+
+    /// Automatic certificate for [public_url] used by the https and http3 servers instead of
+    /// [tls], requires the `acme` feature
+    pub acme: Option<AcmeSettings>,
+
+    /// Replicate HLS output to (or accept it from) other nodes
+    pub replication: Option<ReplicationSettings>,
+
+    /// Verify segments against their checksum before serving them, corrupt segments are
+    /// restored from blossom when possible
+    pub verify_segments: Option<bool>,
+
+    /// Min free space (MB) in [output_dir], below this DVR windows are shrunk and
+    /// recordings paused until space is available again (default 1024)
+    pub min_free_space: Option<u64>,
+
+    /// Seconds at the start of each stream in which audio is faded in and out of order
+    /// packets are dropped while encoder timestamps settle, 0 disables (default 2)
+    pub settle_window: Option<f32>,
+
+    /// Encoder of the transcoded video variants, `auto` uses the Raspberry Pi hardware
+    /// encoder when available (default x264)
+    pub video_encoder: Option<VideoEncoder>,
+
+    /// Seconds a stream is kept after its ingest dropped, a backup ingest connected with
+    /// `<key>?backup=1` (or the primary reconnecting) takes over within it, 0 ends streams
+    /// right away (default 10)
+    pub failover_window: Option<u64>,
+
+    /// Seconds after which the `EXT-X-PROGRAM-DATE-TIME` of HLS playlists is anchored to the
+    /// ingest receive time again, correcting drift of the encoder clock. 0 anchors once when
+    /// the stream starts (default 0)
+    pub program_date_time_anchor: Option<u64>,
+
+    /// Run without side effects outside this instance (zap-stream overseer): lightning
+    /// calls are simulated, nostr events are logged instead of published and stream charges
+    /// are written to a shadow ledger instead of user balances (default false)
+    pub dry_run: Option<bool>,
+
+    /// Generate captions of streams without any by transcribing their audio (feature `stt`)
+    pub speech_to_text: Option<SpeechToTextSettings>,
+
+    /// Master playlist variants hidden from some viewer devices, by `User-Agent`
+    pub playlist_rules: Option<Vec<PlaylistRule>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub enum OverseerConfig {
     /// Static output
     Local,
@@ -36,22 +106,805 @@ pub enum OverseerConfig {
     ZapStream {
         /// MYSQL database connection string
         database: String,
+        /// Read-only MYSQL replica connection string, used for history, analytics and admin
+        /// list queries
+        database_replica: Option<String>,
         /// LND node connection details
         lnd: LndSettings,
         /// Relays to publish events to
         relays: Vec<String>,
         /// Nsec to sign nostr events
+        #[serde(default)]
         nsec: String,
+        /// File to read [nsec] from instead of storing it in the config
+        nsec_file: Option<String>,
+        /// Additional nsecs the platform can sign with, i.e. a new key to migrate to or the
+        /// previous key after migrating
+        ///
+        /// [nsec] signs new events until another key is activated with
+        /// `POST /api/v1/admin/keys/{pubkey}/activate`. Keep old keys listed, existing
+        /// stream events can only be updated and deleted with the key which signed them.
+        extra_nsec: Option<Vec<String>>,
         /// Blossom servers
         blossom: Option<Vec<String>>,
         /// Cost (milli-sats) / second / variant
         cost: i64,
+        /// Admin balance credits (milli-sats) at or above this amount must be approved
+        /// by a second admin
+        approval_threshold: Option<i64>,
+        /// Automatic banning of IPs which repeatedly fail to connect
+        ip_ban: Option<IpBanSettings>,
+        /// Ingest nodes recommended to streamers by `/api/v1/ingest/best`
+        ingest_routing: Option<IngestRoutingSettings>,
+        /// Seconds without a new segment before a live stream is ended (default 120)
+        ///
+        /// This is also the window in which an encoder can reconnect after a restart and
+        /// resume its stream
+        stream_timeout: Option<u64>,
+        /// Seconds a live stream is kept after its encoder disconnected, an encoder which
+        /// reconnects in time resumes the stream and its event instead of starting a new one
+        ///
+        /// Replaces [stream_timeout] as the resume window of streams without a pipeline.
+        /// Streams end as soon as the encoder disconnects if not set
+        reconnect_window: Option<u64>,
+        /// Record with a separate CRF encoder (0-51, lower is better) capped at the live
+        /// bitrate instead of reusing the live CBR variants, this costs an extra encode
+        /// per stream but produces much smaller recordings
+        recording_crf: Option<u8>,
+        /// Remux finished recordings into a faststart MP4 per variant, attached to the
+        /// stream and published as a NIP-71 video event for public streams (default false)
+        recording_mp4: Option<bool>,
+        /// Codec of the transcoded audio variant (default aac)
+        audio_codec: Option<AudioCodec>,
+        /// External payment processors accepted for topups, settled payments are reported
+        /// to `/api/v1/webhook/payments/{provider}`
+        payments: Option<PaymentSettings>,
+        /// Source of the exchange rates used to quote fiat topups (default coinbase)
+        exchange_rates: Option<RateSource>,
+        /// Alert admins when a user is debited faster than a threshold
+        burn_alert: Option<BurnAlertSettings>,
+        /// Cost (milli-sats) / GB of stream files served to viewers by this node, billed to
+        /// the streamer (default 0)
+        egress_cost: Option<i64>,
+        /// Peer-assisted delivery, players exchange segments over WebRTC with other viewers
+        /// of the same stream
+        p2p: Option<P2pSettings>,
+        /// Archive the chat of streams when they end, replayed with the recording from
+        /// `/api/v1/stream/{id}/chat` (default false)
+        chat_archive: Option<bool>,
+        /// Merge the live streams of trusted peer instances into `/api/v1/streams`
+        federation: Option<FederationSettings>,
     },
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub enum RateSource {
+    /// Coinbase public exchange rates API
+    #[default]
+    Coinbase,
+    /// URL returning a JSON object of the price of 1 BTC by currency code
+    /// (`{"USD": 97000.5, "EUR": 93000.1}`)
+    Custom { url: String },
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PaymentSettings {
+    pub bitvora: Option<BitvoraSettings>,
+    pub strike: Option<StrikeSettings>,
+    pub opennode: Option<OpenNodeSettings>,
+    pub nwc: Option<NwcSettings>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BitvoraSettings {
+    /// Secret of the webhook, used to verify the signature of webhook requests
+    pub webhook_secret: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StrikeSettings {
+    /// API key used to look up invoices reported by webhooks
+    pub api_key: String,
+    /// Secret of the webhook subscription, used to verify the signature of webhook requests
+    pub webhook_secret: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OpenNodeSettings {
+    /// API key, OpenNode signs webhooks with it and charges are looked up with it
+    pub api_key: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NwcSettings {
+    /// Connection string (`nostr+walletconnect://..`) of the wallet receiving topups, invoices
+    /// reported by webhooks are looked up with it
+    pub uri: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioCodec {
+    /// AAC 192kbps
+    #[default]
+    Aac,
+    /// Opus 128kbps, HLS is served with fMP4 segments as players don't support opus in
+    /// MPEG-TS
+    Opus,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct LndSettings {
     pub address: String,
     pub cert: String,
+    #[serde(alias = "macaroon_file")]
     pub macaroon: String,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsSettings {
+    /// PEM certificate chain, reloaded automatically when the file changes
+    pub cert: PathBuf,
+    /// PEM private key
+    pub key: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AcmeSettings {
+    /// Contact email for the ACME account
+    pub email: Option<String>,
+    /// Directory to store the account key and certificates
+    pub cache_dir: PathBuf,
+    /// Use the Let's Encrypt production directory, otherwise staging
+    pub production: bool,
+}
+
+/// Variants of master playlists hidden from viewers with a matching `User-Agent`, e.g. 4K
+/// for phones or HEVC for browsers which can't play it. All matching rules apply and the
+/// lowest bandwidth variant is kept if a rule would hide every variant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlaylistRule {
+    /// Case-insensitive substrings of the `User-Agent`, the rule matches any of them
+    pub user_agent: Vec<String>,
+    /// Hide variants taller than this
+    pub max_height: Option<u64>,
+    /// Hide variants with a higher `BANDWIDTH` (bits per second)
+    pub max_bandwidth: Option<u64>,
+    /// Hide variants with any codec starting with one of these (e.g. `hvc1`, `av01`)
+    #[serde(default)]
+    pub exclude_codecs: Vec<String>,
+}
+
+impl PlaylistRule {
+    /// The rule applies to viewers with the `User-Agent` [user_agent]
+    pub fn matches(&self, user_agent: &str) -> bool {
+        let ua = user_agent.to_lowercase();
+        self.user_agent
+            .iter()
+            .any(|m| !m.is_empty() && ua.contains(&m.to_lowercase()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpeechToTextSettings {
+    /// OpenAI compatible transcription endpoint, e.g. whisper.cpp
+    /// `http://localhost:8080/v1/audio/transcriptions` (`whisper-server --inference-path`)
+    pub url: String,
+    /// Bearer token sent to [url]
+    pub api_key: Option<String>,
+    /// Model name sent with each request (default `whisper-1`)
+    pub model: Option<String>,
+    /// Spoken language (ISO 639-1), detected by the endpoint if not set
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReplicationSettings {
+    /// Base URLs of peer nodes to push segments and playlists to, empty on edge nodes
+    pub peers: Vec<String>,
+    /// Shared secret used to authenticate pushes between nodes
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IngestRoutingSettings {
+    /// MaxMind GeoIP2/GeoLite2 City database used to locate clients
+    pub geoip_db: Option<String>,
+    /// Ingest nodes in this deployment
+    pub nodes: Vec<IngestNode>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IngestNode {
+    /// Display name / region of the node
+    pub name: String,
+    /// Ingest URLs streamers should use for this node
+    pub endpoints: Vec<String>,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// URL of the node's `/api/v1/capacity` endpoint
+    pub capacity_url: String,
+    /// Number of concurrent streams the node can handle
+    pub max_streams: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IpBanSettings {
+    /// Number of failed connection attempts before an IP is banned
+    #[serde(default = "default_ban_max_failures")]
+    pub max_failures: u32,
+    /// Window (seconds) in which failed attempts are counted
+    #[serde(default = "default_ban_window")]
+    pub window: u64,
+    /// How long (seconds) an IP is banned for
+    #[serde(default = "default_ban_duration")]
+    pub ban_duration: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BurnAlertSettings {
+    /// Debit rate (sats / minute) across all of a user's streams which raises an alert
+    pub max_rate: u64,
+    /// Window (seconds) the rate is averaged over
+    #[serde(default = "default_burn_window")]
+    pub window: u64,
+    /// Min seconds between alerts for the same user
+    #[serde(default = "default_burn_cooldown")]
+    pub cooldown: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct P2pSettings {
+    /// ICE servers (`stun:` / `turn:` URLs) handed to players
+    pub ice_servers: Vec<String>,
+    /// Max peers connected to the signaling of one stream
+    #[serde(default = "default_p2p_max_peers")]
+    pub max_peers: usize,
+}
+
+fn default_p2p_max_peers() -> usize {
+    500
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FederationSettings {
+    /// Base URLs of peer zap-stream instances, their public stream directory is fetched
+    /// from `{url}/api/v1/streams`
+    pub peers: Vec<String>,
+    /// Shared secret peers present to agree on mirroring streams, see
+    /// `POST /api/v1/admin/mirrors`
+    pub token: Option<String>,
+    /// Seconds between refreshes of the peer directories
+    #[serde(default = "default_federation_refresh")]
+    pub refresh_interval: u64,
+}
+
+fn default_federation_refresh() -> u64 {
+    60
+}
+
+fn default_burn_window() -> u64 {
+    300
+}
+
+fn default_burn_cooldown() -> u64 {
+    3600
+}
+
+fn default_ban_max_failures() -> u32 {
+    5
+}
+
+fn default_ban_window() -> u64 {
+    60
+}
+
+fn default_ban_duration() -> u64 {
+    3600
+}
+
+impl Default for IpBanSettings {
+    fn default() -> Self {
+        Self {
+            max_failures: default_ban_max_failures(),
+            window: default_ban_window(),
+            ban_duration: default_ban_duration(),
+        }
+    }
+}
+
+/// Max sane value for [OverseerConfig::ZapStream::cost], 1 sat / second / variant
+const MAX_COST: i64 = 1_000;
+
+/// Max CRF of x264/x265
+const MAX_CRF: u8 = 51;
+
+/// Prefix for file paths which should be loaded from the systemd credentials directory
+const CREDENTIAL_PREFIX: &str = "credential:";
+
+impl Settings {
+    /// Resolve secrets which are not stored in the config directly
+    ///
+    /// - `${VAR}` in secret values is replaced by the environment variable `VAR`
+    /// - `*_file` settings are read from disk, paths starting with `credential:` are
+    ///   loaded from the systemd credentials directory (`$CREDENTIALS_DIRECTORY`)
+    pub fn resolve_secrets(&mut self) -> anyhow::Result<()> {
+        if let Some(r) = &mut self.replication {
+            r.token = expand_env(&r.token)?;
+        }
+        if let Some(k) = self
+            .speech_to_text
+            .as_mut()
+            .and_then(|s| s.api_key.as_mut())
+        {
+            *k = expand_env(k)?;
+        }
+        if let Some(tls) = &mut self.tls {
+            tls.cert = resolve_path(&expand_env(&tls.cert.to_string_lossy())?)?;
+            tls.key = resolve_path(&expand_env(&tls.key.to_string_lossy())?)?;
+        }
+        match &mut self.overseer {
+            OverseerConfig::Webhook { url } => {
+                *url = expand_env(url)?;
+            }
+            OverseerConfig::ZapStream {
+                database,
+                database_replica,
+                lnd,
+                nsec,
+                nsec_file,
+                extra_nsec,
+                payments,
+                ..
+            } => {
+                *database = expand_env(database)?;
+                if let Some(p) = payments {
+                    if let Some(b) = &mut p.bitvora {
+                        b.webhook_secret = expand_env(&b.webhook_secret)?;
+                    }
+                    if let Some(s) = &mut p.strike {
+                        s.api_key = expand_env(&s.api_key)?;
+                        s.webhook_secret = expand_env(&s.webhook_secret)?;
+                    }
+                    if let Some(o) = &mut p.opennode {
+                        o.api_key = expand_env(&o.api_key)?;
+                    }
+                    if let Some(n) = &mut p.nwc {
+                        n.uri = expand_env(&n.uri)?;
+                    }
+                }
+                if let Some(r) = database_replica {
+                    *r = expand_env(r)?;
+                }
+                lnd.cert = resolve_path(&expand_env(&lnd.cert)?)?
+                    .to_string_lossy()
+                    .to_string();
+                lnd.macaroon = resolve_path(&expand_env(&lnd.macaroon)?)?
+                    .to_string_lossy()
+                    .to_string();
+                *nsec = expand_env(nsec)?;
+                if let Some(f) = nsec_file {
+                    let path = resolve_path(&expand_env(f)?)?;
+                    *nsec = std::fs::read_to_string(&path)
+                        .map_err(|e| {
+                            anyhow::anyhow!("Failed to read nsec_file {}: {}", path.display(), e)
+                        })?
+                        .trim()
+                        .to_string();
+                }
+                for k in extra_nsec.iter_mut().flatten() {
+                    *k = expand_env(k)?;
+                }
+            }
+            OverseerConfig::Local => {}
+        }
+        Ok(())
+    }
+
+    /// Deserialize the settings from [config], unknown fields are rejected and errors name the
+    /// path of the offending field
+    pub fn from_config(config: config::Config) -> anyhow::Result<Self> {
+        serde_path_to_error::deserialize(config)
+            .map_err(|e| anyhow::anyhow!("{}: {}", e.path(), e.inner()))
+    }
+
+    /// Check the config for problems which would otherwise only surface later during startup
+    ///
+    /// All problems are reported at once, prefixed with the path of the offending field
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut errors = vec![];
+
+        for (i, e) in self.endpoints.iter().enumerate() {
+            let path = format!("endpoints[{}]", i);
+            match Url::parse(e) {
+                Ok(u) => match u.scheme() {
+                    "srt" | "rist" | "rtmp" | "rtmps" | "tcp" => {
+                        if u.host().is_none() || u.port().is_none() {
+                            errors.push(format!("{}: '{}' must include a host and port", path, e));
+                        }
+                        if u.scheme() == "rist" && !u.query_pairs().any(|(k, _)| k == "key") {
+                            errors.push(format!("{}: rist requires a ?key=<stream-key>", path));
+                        }
+                        if u.scheme() == "rtmps" && self.tls.is_none() {
+                            errors.push(format!("{}: rtmps requires tls settings", path));
+                        }
+                    }
+                    "unix" => {
+                        if !cfg!(unix) {
+                            errors.push(format!("{}: unix sockets are not supported here", path));
+                        }
+                        if u.path().is_empty() || !u.query_pairs().any(|(k, _)| k == "key") {
+                            errors.push(format!(
+                                "{}: unix requires a socket path and ?key=<stream-key>",
+                                path
+                            ));
+                        }
+                    }
+                    "file" | "test-pattern" => {}
+                    s => errors.push(format!("{}: unknown scheme '{}'", path, s)),
+                },
+                Err(err) => errors.push(format!("{}: '{}' is not a valid URL: {}", path, e, err)),
+            }
+        }
+
+        match Url::parse(&self.public_url) {
+            Ok(u) if u.scheme() == "http" || u.scheme() == "https" => {}
+            Ok(u) => errors.push(format!(
+                "public_url: scheme must be http or https, not '{}'",
+                u.scheme()
+            )),
+            Err(e) => errors.push(format!("public_url: not a valid URL: {}", e)),
+        }
+
+        Self::check_addr(&mut errors, "listen_http", Some(&self.listen_http));
+        Self::check_addr(&mut errors, "listen_https", self.listen_https.as_ref());
+        Self::check_addr(&mut errors, "listen_http3", self.listen_http3.as_ref());
+        Self::check_addr(&mut errors, "listen_grpc", self.listen_grpc.as_ref());
+        for (i, p) in self.trusted_proxies.iter().flatten().enumerate() {
+            if let Err(e) = p.parse::<IpRange>() {
+                errors.push(format!(
+                    "trusted_proxies[{}]: '{}' is not an IP or CIDR range: {}",
+                    i, p, e
+                ));
+            }
+        }
+        if self.listen_grpc.is_some() && !cfg!(feature = "grpc") {
+            errors.push("listen_grpc: requires the grpc feature".to_string());
+        }
+        if self.listen_http3.is_some() && self.tls.is_none() && self.acme.is_none() {
+            errors.push("listen_http3: requires tls or acme settings".to_string());
+        }
+        if self.listen_https.is_some() && self.tls.is_none() && self.acme.is_none() {
+            errors.push("listen_https: requires tls or acme settings".to_string());
+        }
+        if let Some(tls) = &self.tls {
+            Self::check_file(&mut errors, "tls.cert", &tls.cert);
+            Self::check_file(&mut errors, "tls.key", &tls.key);
+        }
+        if let Some(acme) = &self.acme {
+            Self::check_dir(&mut errors, "acme.cache_dir", &acme.cache_dir);
+        }
+        Self::check_dir(&mut errors, "output_dir", Path::new(&self.output_dir));
+        if let Some(r) = &self.replication {
+            if r.token.is_empty() {
+                errors.push("replication.token: must not be empty".to_string());
+            }
+            for (i, p) in r.peers.iter().enumerate() {
+                match Url::parse(p) {
+                    Ok(u) if u.scheme() == "http" || u.scheme() == "https" => {}
+                    _ => errors.push(format!(
+                        "replication.peers[{}]: '{}' is not a http(s) URL",
+                        i, p
+                    )),
+                }
+            }
+        }
+        if let Some(s) = &self.speech_to_text {
+            if !cfg!(feature = "stt") {
+                errors.push("speech_to_text: requires the stt feature".to_string());
+            }
+            match Url::parse(&s.url) {
+                Ok(u) if u.scheme() == "http" || u.scheme() == "https" => {}
+                _ => errors.push(format!(
+                    "speech_to_text.url: '{}' is not a http(s) URL",
+                    s.url
+                )),
+            }
+        }
+
+        match &self.overseer {
+            OverseerConfig::Webhook { url } => {
+                if let Err(e) = Url::parse(url) {
+                    errors.push(format!("overseer.webhook.url: not a valid URL: {}", e));
+                }
+            }
+            OverseerConfig::ZapStream {
+                lnd,
+                nsec,
+                extra_nsec,
+                relays,
+                blossom,
+                cost,
+                approval_threshold,
+                ip_ban,
+                ingest_routing,
+                recording_crf,
+                payments,
+                exchange_rates,
+                burn_alert,
+                egress_cost,
+                p2p,
+                federation,
+                ..
+            } => {
+                if nsec.is_empty() {
+                    errors.push("overseer.zap-stream.nsec: nsec or nsec_file is required".into());
+                }
+                for (i, k) in extra_nsec.iter().flatten().enumerate() {
+                    if k.is_empty() || k == nsec {
+                        errors.push(format!(
+                            "overseer.zap-stream.extra_nsec[{}]: must be a different, non-empty nsec",
+                            i
+                        ));
+                    }
+                }
+                if relays.is_empty() {
+                    errors.push("overseer.zap-stream.relays: at least 1 relay is required".into());
+                }
+                for (i, r) in relays.iter().enumerate() {
+                    match Url::parse(r) {
+                        Ok(u) if u.scheme() == "ws" || u.scheme() == "wss" => {}
+                        _ => errors.push(format!(
+                            "overseer.zap-stream.relays[{}]: '{}' is not a ws:// or wss:// URL",
+                            i, r
+                        )),
+                    }
+                }
+                for (i, b) in blossom.iter().flatten().enumerate() {
+                    match Url::parse(b) {
+                        Ok(u) if u.scheme() == "http" || u.scheme() == "https" => {}
+                        _ => errors.push(format!(
+                            "overseer.zap-stream.blossom[{}]: '{}' is not a http(s) URL",
+                            i, b
+                        )),
+                    }
+                }
+                if !(0..=MAX_COST).contains(cost) {
+                    errors.push(format!(
+                        "overseer.zap-stream.cost: {} is outside the range 0..={}",
+                        cost, MAX_COST
+                    ));
+                }
+                if approval_threshold.is_some_and(|t| t <= 0) {
+                    errors.push("overseer.zap-stream.approval_threshold: must be positive".into());
+                }
+                if let Some(r) = ingest_routing {
+                    if let Some(db) = &r.geoip_db {
+                        Self::check_file(
+                            &mut errors,
+                            "overseer.zap-stream.ingest_routing.geoip_db",
+                            Path::new(db),
+                        );
+                    }
+                    for (i, n) in r.nodes.iter().enumerate() {
+                        if Url::parse(&n.capacity_url).is_err() {
+                            errors.push(format!(
+                                "overseer.zap-stream.ingest_routing.nodes[{}].capacity_url: not a valid URL",
+                                i
+                            ));
+                        }
+                    }
+                }
+                if recording_crf.is_some_and(|c| c > MAX_CRF) {
+                    errors.push(format!(
+                        "overseer.zap-stream.recording_crf: must be 0..={}",
+                        MAX_CRF
+                    ));
+                }
+                if let Some(p) = payments {
+                    let secrets = [
+                        (
+                            "bitvora.webhook_secret",
+                            p.bitvora.as_ref().map(|b| &b.webhook_secret),
+                        ),
+                        ("strike.api_key", p.strike.as_ref().map(|s| &s.api_key)),
+                        (
+                            "strike.webhook_secret",
+                            p.strike.as_ref().map(|s| &s.webhook_secret),
+                        ),
+                        ("opennode.api_key", p.opennode.as_ref().map(|o| &o.api_key)),
+                    ];
+                    for (name, secret) in secrets {
+                        if secret.is_some_and(|s| s.is_empty()) {
+                            errors.push(format!(
+                                "overseer.zap-stream.payments.{}: must not be empty",
+                                name
+                            ));
+                        }
+                    }
+                    if let Some(n) = &p.nwc {
+                        if !Url::parse(&n.uri).is_ok_and(|u| u.scheme() == "nostr+walletconnect") {
+                            errors.push(
+                                "overseer.zap-stream.payments.nwc.uri: must be a nostr+walletconnect:// URI"
+                                    .to_string(),
+                            );
+                        }
+                    }
+                }
+                if let Some(RateSource::Custom { url }) = exchange_rates {
+                    match Url::parse(url) {
+                        Ok(u) if u.scheme() == "http" || u.scheme() == "https" => {}
+                        _ => errors.push(format!(
+                            "overseer.zap-stream.exchange_rates.custom.url: '{}' is not a http(s) URL",
+                            url
+                        )),
+                    }
+                }
+                if ip_ban.as_ref().is_some_and(|b| b.max_failures == 0) {
+                    errors.push("overseer.zap-stream.ip_ban.max_failures: must be > 0".into());
+                }
+                if egress_cost.is_some_and(|c| c < 0) {
+                    errors.push("overseer.zap-stream.egress_cost: must be >= 0".into());
+                }
+                if let Some(b) = burn_alert {
+                    if b.max_rate == 0 {
+                        errors.push("overseer.zap-stream.burn_alert.max_rate: must be > 0".into());
+                    }
+                    if b.window == 0 {
+                        errors.push("overseer.zap-stream.burn_alert.window: must be > 0".into());
+                    }
+                }
+                if let Some(p) = p2p {
+                    for (i, s) in p.ice_servers.iter().enumerate() {
+                        if !["stun:", "stuns:", "turn:", "turns:"]
+                            .iter()
+                            .any(|p| s.starts_with(p))
+                        {
+                            errors.push(format!(
+                                "overseer.zap-stream.p2p.ice_servers[{}]: '{}' is not a stun: or turn: URL",
+                                i, s
+                            ));
+                        }
+                    }
+                    if p.max_peers == 0 {
+                        errors.push("overseer.zap-stream.p2p.max_peers: must be > 0".into());
+                    }
+                }
+                if let Some(f) = federation {
+                    for (i, p) in f.peers.iter().enumerate() {
+                        if !Url::parse(p).is_ok_and(|u| u.scheme() == "https") {
+                            errors.push(format!(
+                                "overseer.zap-stream.federation.peers[{}]: not a valid https URL",
+                                i
+                            ));
+                        }
+                    }
+                    if f.refresh_interval == 0 {
+                        errors.push(
+                            "overseer.zap-stream.federation.refresh_interval: must be > 0".into(),
+                        );
+                    }
+                }
+                // lightning is simulated in dry run, staging may not have the node files
+                if !self.dry_run.unwrap_or(false) {
+                    Self::check_file(
+                        &mut errors,
+                        "overseer.zap-stream.lnd.cert",
+                        Path::new(&lnd.cert),
+                    );
+                    Self::check_file(
+                        &mut errors,
+                        "overseer.zap-stream.lnd.macaroon",
+                        Path::new(&lnd.macaroon),
+                    );
+                }
+            }
+            OverseerConfig::Local => {}
+        }
+
+        if !errors.is_empty() {
+            anyhow::bail!(
+                "Invalid config ({} problems):\n  {}",
+                errors.len(),
+                errors.join("\n  ")
+            );
+        }
+        Ok(())
+    }
+
+    fn check_addr(errors: &mut Vec<String>, path: &str, addr: Option<&String>) {
+        if let Some(a) = addr {
+            if let Err(e) = a.to_socket_addrs() {
+                errors.push(format!("{}: '{}' is not a valid host:port: {}", path, a, e));
+            }
+        }
+    }
+
+    fn check_file(errors: &mut Vec<String>, path: &str, file: &Path) {
+        if !file.is_file() {
+            errors.push(format!("{}: file '{}' not found", path, file.display()));
+        }
+    }
+
+    /// Check the directory exists, or its nearest existing parent does so it can be created,
+    /// and is not read-only. Nothing is created or written here
+    fn check_dir(errors: &mut Vec<String>, path: &str, dir: &Path) {
+        let existing = dir
+            .ancestors()
+            .map(|p| {
+                if p.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    p
+                }
+            })
+            .find(|p| p.exists());
+        let Some(existing) = existing else {
+            errors.push(format!("{}: '{}' not found", path, dir.display()));
+            return;
+        };
+        match std::fs::metadata(existing) {
+            Ok(m) if !m.is_dir() => errors.push(format!(
+                "{}: '{}' is not a directory",
+                path,
+                existing.display()
+            )),
+            Ok(m) if m.permissions().readonly() => {
+                errors.push(format!("{}: '{}' is read-only", path, existing.display()))
+            }
+            Ok(_) => {}
+            Err(e) => errors.push(format!("{}: '{}': {}", path, existing.display(), e)),
+        }
+    }
+}
+
+/// Replace `${VAR}` with the value of the environment variable `VAR`
+fn expand_env(value: &str) -> anyhow::Result<String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(e) => start + e,
+            None => anyhow::bail!("Unterminated ${{ in config value"),
+        };
+        let name = &rest[start + 2..end];
+        let var = std::env::var(name)
+            .map_err(|_| anyhow::anyhow!("Environment variable {} is not set", name))?;
+        out.push_str(&rest[..start]);
+        out.push_str(&var);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Map `credential:<name>` to the systemd credentials directory
+fn resolve_path(path: &str) -> anyhow::Result<PathBuf> {
+    match path.strip_prefix(CREDENTIAL_PREFIX) {
+        Some(name) => {
+            let dir = std::env::var("CREDENTIALS_DIRECTORY").map_err(|_| {
+                anyhow::anyhow!("{} requires CREDENTIALS_DIRECTORY to be set", path)
+            })?;
+            Ok(PathBuf::from(dir).join(name))
+        }
+        None => Ok(PathBuf::from(path)),
+    }
+}
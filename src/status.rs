@@ -0,0 +1,127 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use std::fmt::Write;
+
+/// Public summary of the server, served on `/status` (HTML) and `/status.json`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ServerStatus {
+    /// All components are healthy
+    pub healthy: bool,
+    pub components: Vec<ComponentStatus>,
+    /// Number of streams currently live on this server
+    pub active_streams: u64,
+    /// Most recent pipeline incidents, newest first
+    pub incidents: Vec<StatusIncident>,
+}
+
+/// Health of one part of the server (ingest listener, relay, lightning node, database)
+#[derive(Debug, Clone, Serialize)]
+pub struct ComponentStatus {
+    pub name: String,
+    pub healthy: bool,
+    /// Error or state of an unhealthy component
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusIncident {
+    pub error: String,
+    /// The pipeline was restarted automatically
+    pub restarted: bool,
+    pub created: DateTime<Utc>,
+}
+
+impl ComponentStatus {
+    pub fn new(name: &str, result: Result<(), String>) -> Self {
+        Self {
+            name: name.to_string(),
+            healthy: result.is_ok(),
+            detail: result.err(),
+        }
+    }
+}
+
+impl ServerStatus {
+    /// Add components checked outside the overseer (ingest listeners) and update [healthy]
+    pub fn with_components(mut self, components: Vec<ComponentStatus>) -> Self {
+        self.components.extend(components);
+        self.healthy = self.components.iter().all(|c| c.healthy);
+        self
+    }
+
+    /// Render a standalone status page
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        html.push_str("<!doctype html><html><head><meta charset=\"utf-8\"><title>Status</title>");
+        html.push_str(
+            "<style>body{font-family:sans-serif;max-width:48em;margin:2em auto}\
+            td{padding:0.2em 1em}.up{color:#2a2}.down{color:#c22}</style></head><body>",
+        );
+        let _ = write!(
+            html,
+            "<h1 class=\"{}\">{}</h1><p>Live streams: {}</p>",
+            status_class(self.healthy),
+            if self.healthy {
+                "All systems operational"
+            } else {
+                "Some systems are degraded"
+            },
+            self.active_streams
+        );
+
+        html.push_str("<h2>Components</h2><table>");
+        for c in &self.components {
+            let _ = write!(
+                html,
+                "<tr><td>{}</td><td class=\"{}\">{}</td><td>{}</td></tr>",
+                escape_html(&c.name),
+                status_class(c.healthy),
+                if c.healthy { "Up" } else { "Down" },
+                escape_html(c.detail.as_deref().unwrap_or(""))
+            );
+        }
+        html.push_str("</table>");
+
+        html.push_str("<h2>Recent incidents</h2>");
+        if self.incidents.is_empty() {
+            html.push_str("<p>No recent incidents</p>");
+        } else {
+            html.push_str("<table>");
+            for i in &self.incidents {
+                let _ = write!(
+                    html,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    i.created.to_rfc3339_opts(SecondsFormat::Secs, true),
+                    escape_html(&i.error),
+                    if i.restarted { "restarted" } else { "" }
+                );
+            }
+            html.push_str("</table>");
+        }
+        html.push_str("</body></html>");
+        html
+    }
+}
+
+fn status_class(healthy: bool) -> &'static str {
+    if healthy {
+        "up"
+    } else {
+        "down"
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,84 @@
+use crate::settings::TlsSettings;
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use rustls::crypto::ring::sign::any_supported_type;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use tokio_rustls::TlsAcceptor;
+
+/// Certificate resolver which reloads the cert/key from disk when they change,
+/// so renewed certificates are picked up without a restart
+#[derive(Debug)]
+pub struct CertStore {
+    settings: TlsSettings,
+    current: RwLock<(SystemTime, Arc<CertifiedKey>)>,
+}
+
+impl CertStore {
+    pub fn new(settings: &TlsSettings) -> Result<Self> {
+        let modified = Self::modified(settings)?;
+        let key = Self::load(settings)?;
+        Ok(Self {
+            settings: settings.clone(),
+            current: RwLock::new((modified, Arc::new(key))),
+        })
+    }
+
+    /// Create a TLS acceptor using this store with the given ALPN protocols
+    pub fn acceptor(self: Arc<Self>, alpn: &[&[u8]]) -> Result<TlsAcceptor> {
+        Ok(TlsAcceptor::from(self.server_config(alpn)?))
+    }
+
+    /// Create a rustls server config using this store with the given ALPN protocols
+    pub fn server_config(self: Arc<Self>, alpn: &[&[u8]]) -> Result<Arc<ServerConfig>> {
+        let mut config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()?
+                .with_no_client_auth()
+                .with_cert_resolver(self);
+        config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+        Ok(Arc::new(config))
+    }
+
+    fn modified(settings: &TlsSettings) -> Result<SystemTime> {
+        let cert = std::fs::metadata(&settings.cert)?.modified()?;
+        let key = std::fs::metadata(&settings.key)?.modified()?;
+        Ok(cert.max(key))
+    }
+
+    fn load(settings: &TlsSettings) -> Result<CertifiedKey> {
+        let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&settings.cert)?))
+            .collect::<Result<Vec<_>, _>>()?;
+        let key =
+            rustls_pemfile::private_key(&mut BufReader::new(File::open(&settings.key)?))?.ok_or(
+                anyhow!("No private key found in {}", settings.key.display()),
+            )?;
+        info!("Loaded TLS certificate {}", settings.cert.display());
+        Ok(CertifiedKey::new(certs, any_supported_type(&key)?))
+    }
+
+    /// Reload the certificate if the files on disk are newer than the loaded copy
+    fn reload(&self) -> Result<()> {
+        let modified = Self::modified(&self.settings)?;
+        if self.current.read().unwrap().0 >= modified {
+            return Ok(());
+        }
+        let key = Self::load(&self.settings)?;
+        *self.current.write().unwrap() = (modified, Arc::new(key));
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for CertStore {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        if let Err(e) = self.reload() {
+            error!("Failed to reload TLS certificate: {}", e);
+        }
+        Some(self.current.read().unwrap().1.clone())
+    }
+}
@@ -0,0 +1,76 @@
+use crate::variant::VariantStream;
+use log::warn;
+
+/// Max seconds between keyframes (Apple HLS authoring spec 1.10)
+const MAX_KEYFRAME_INTERVAL: f32 = 2.0;
+
+/// Recommended bitrate factor between adjacent rungs (Apple HLS authoring spec 2.3)
+const MIN_RUNG_SPACING: f64 = 1.5;
+const MAX_RUNG_SPACING: f64 = 2.0;
+
+/// Check the transcoded video [variants] against the Apple HLS authoring spec before the
+/// encoders are created
+///
+/// Dimensions and keyframe intervals are fixed so every segment of [segment_length]
+/// seconds starts with a keyframe, bitrate spacing of the ladder can't be fixed without
+/// changing what the operator configured so it's only reported
+pub fn check_hls_ladder(variants: &mut [VariantStream], segment_length: f32) {
+    // keyframes at most every 2s and on every segment boundary
+    let gop_secs = segment_length / (segment_length / MAX_KEYFRAME_INTERVAL).ceil();
+    for v in variants.iter_mut() {
+        let v = match v {
+            VariantStream::Video(v) => v,
+            _ => continue,
+        };
+        // 4:2:0 chroma needs even dimensions
+        if !v.width.is_multiple_of(2) || !v.height.is_multiple_of(2) {
+            warn!(
+                "Variant {}x{} has odd dimensions, using {}x{}",
+                v.width,
+                v.height,
+                v.width & !1,
+                v.height & !1
+            );
+            v.width &= !1;
+            v.height &= !1;
+        }
+        if v.fps > 0.0 {
+            let gop = (v.fps * gop_secs).round().max(1.0) as u16;
+            if v.keyframe_interval == 0
+                || v.keyframe_interval > gop
+                || !gop.is_multiple_of(v.keyframe_interval)
+            {
+                warn!(
+                    "Variant {}p keyframe interval {} does not align with {}s segments, using {}",
+                    v.height, v.keyframe_interval, segment_length, gop
+                );
+                v.keyframe_interval = gop;
+            }
+        }
+    }
+
+    let mut bitrates: Vec<(u16, u64)> = variants
+        .iter()
+        .filter_map(|v| match v {
+            VariantStream::Video(v) => Some((v.height, v.bitrate)),
+            _ => None,
+        })
+        .collect();
+    bitrates.sort_by_key(|(_, b)| *b);
+    for w in bitrates.windows(2) {
+        let ((lo_h, lo), (hi_h, hi)) = (w[0], w[1]);
+        let factor = hi as f64 / lo.max(1) as f64;
+        if !(MIN_RUNG_SPACING..=MAX_RUNG_SPACING).contains(&factor) {
+            warn!(
+                "Variants {}p ({}kbps) and {}p ({}kbps) are {:.2}x apart, HLS players switch best between rungs {}-{}x apart",
+                lo_h,
+                lo / 1000,
+                hi_h,
+                hi / 1000,
+                factor,
+                MIN_RUNG_SPACING,
+                MAX_RUNG_SPACING
+            );
+        }
+    }
+}
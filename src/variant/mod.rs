@@ -7,6 +7,7 @@ use std::fmt::{Display, Formatter};
 use uuid::Uuid;
 
 pub mod audio;
+pub mod ladder;
 pub mod mapping;
 pub mod video;
 
@@ -1,12 +1,95 @@
 use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVColorSpace::AVCOL_SPC_BT709;
+use ffmpeg_rs_raw::ffmpeg_sys_the_third::AVPixelFormat::AV_PIX_FMT_YUV420P;
 use ffmpeg_rs_raw::Encoder;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::intrinsics::transmute;
 use uuid::Uuid;
 
-use crate::variant::{StreamMapping, VariantMapping};
+use crate::variant::{StreamMapping, VariantMapping, VariantStream};
+
+/// V4L2 memory-to-memory H.264 encoder, the hardware encoder of the Raspberry Pi (up to the
+/// Pi 4, the Pi 5 has no hardware video encoder)
+pub const V4L2M2M_H264: &str = "h264_v4l2m2m";
+
+/// AV1 encoders supported by the pipeline, VAAPI (`av1_vaapi`) is not as it needs frames
+/// uploaded to the GPU
+pub const AV1_ENCODERS: [&str; 5] = [
+    "libsvtav1",
+    "libaom-av1",
+    "librav1e",
+    "av1_nvenc",
+    "av1_qsv",
+];
+
+/// HEVC encoders supported by the pipeline, HLS serves HEVC in fMP4 segments only
+pub const HEVC_ENCODERS: [&str; 3] = ["libx265", "hevc_nvenc", "hevc_qsv"];
+
+/// Encoder of the transcoded video variants
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoEncoder {
+    /// libx264 software encoder
+    #[default]
+    X264,
+    /// Hardware encoder of the Raspberry Pi, see [V4L2M2M_H264]
+    V4l2m2m,
+    /// [VideoEncoder::V4l2m2m] when the hardware encoder can be opened, libx264 otherwise
+    Auto,
+}
+
+impl VideoEncoder {
+    /// Resolve [VideoEncoder::Auto] to the encoder available on this machine, call once at
+    /// startup as probing opens the hardware encoder
+    pub fn resolve(self) -> Self {
+        match self {
+            VideoEncoder::Auto if probe_encoder(V4L2M2M_H264) => {
+                info!("Using hardware video encoder {}", V4L2M2M_H264);
+                VideoEncoder::V4l2m2m
+            }
+            VideoEncoder::Auto => VideoEncoder::X264,
+            e => e,
+        }
+    }
+
+    /// Switch the transcoded video [variants] to this encoder
+    pub fn apply(&self, variants: &mut [VariantStream]) {
+        if *self != VideoEncoder::V4l2m2m {
+            return;
+        }
+        for v in variants.iter_mut() {
+            if let VariantStream::Video(v) = v {
+                v.codec = V4L2M2M_H264.to_string();
+                // the Pi encoder supports up to level 4.2 (1080p30)
+                v.level = v.level.min(42);
+            }
+        }
+    }
+}
+
+/// Check [codec] can be opened, hardware encoders are compiled in even when the device is
+/// missing
+fn probe_encoder(codec: &str) -> bool {
+    let enc = unsafe {
+        Encoder::new_with_name(codec).and_then(|e| {
+            e.with_width(640)
+                .with_height(360)
+                .with_pix_fmt(AV_PIX_FMT_YUV420P)
+                .with_bitrate(1_000_000)
+                .with_framerate(30.0)?
+                .open(None)
+        })
+    };
+    match enc {
+        Ok(_) => true,
+        Err(e) => {
+            warn!("Video encoder {} is not available: {}", codec, e);
+            false
+        }
+    }
+}
 
 /// Information related to variant streams for a given egress
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -40,6 +123,128 @@ pub struct VideoVariant {
 
     /// Pixel Format
     pub pixel_format: u32,
+
+    /// Encoder tuning overrides
+    #[serde(default)]
+    pub encoder: EncoderParams,
+}
+
+/// Encoder tuning overrides, unset fields keep the encoder defaults
+///
+/// Names are passed to the encoder as-is, so they depend on the codec
+/// (e.g. `veryfast` for libx264 vs `p4` for NVENC)
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EncoderParams {
+    pub preset: Option<String>,
+    pub tune: Option<String>,
+    /// Profile name, takes precedence over [VideoVariant::profile]
+    pub profile: Option<String>,
+    /// Codec specific options as `key=value:key=value`
+    ///
+    /// Passed as `x264-params` / `x265-params` to libx264 / libx265, other encoders get
+    /// each option set individually
+    pub params: Option<String>,
+    /// Constant rate factor, [VideoVariant::bitrate] becomes the max bitrate instead of
+    /// the target
+    #[serde(default)]
+    pub crf: Option<u8>,
+    /// Tune for glass-to-glass latency over compression: zero-latency tuning, no B-frames,
+    /// intra-refresh instead of full keyframes where the encoder supports it and a VBV of
+    /// about one frame so no frame waits for bits of the next
+    ///
+    /// Segments of intra-refresh streams start on a recovery point, players joining
+    /// mid-segment show a partial picture until the refresh wave passed
+    #[serde(default)]
+    pub low_latency: bool,
+}
+
+impl EncoderParams {
+    /// Add the overrides to the encoder options of [codec]
+    pub fn apply(&self, codec: &str, opt: &mut HashMap<String, String>) {
+        if let Some(preset) = &self.preset {
+            opt.insert("preset".to_string(), preset.clone());
+        }
+        if let Some(tune) = &self.tune {
+            opt.insert("tune".to_string(), tune.clone());
+        }
+        if let Some(profile) = &self.profile {
+            opt.insert("profile".to_string(), profile.clone());
+        }
+        if let Some(crf) = self.crf {
+            opt.insert("crf".to_string(), crf.to_string());
+        }
+        if self.low_latency {
+            Self::apply_low_latency(codec, opt);
+        }
+        if let Some(params) = &self.params {
+            match codec {
+                "libx264" => {
+                    opt.insert("x264-params".to_string(), params.clone());
+                }
+                "libx265" => {
+                    opt.insert("x265-params".to_string(), params.clone());
+                }
+                "libsvtav1" => {
+                    // after the low latency params, later keys win
+                    let v = match opt.remove("svtav1-params") {
+                        Some(ll) => format!("{}:{}", ll, params),
+                        None => params.clone(),
+                    };
+                    opt.insert("svtav1-params".to_string(), v);
+                }
+                _ => {
+                    for (k, v) in params.split(':').filter_map(|p| p.split_once('=')) {
+                        opt.insert(k.to_string(), v.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Low latency options of [codec], an explicit [Self::tune] is kept
+    fn apply_low_latency(codec: &str, opt: &mut HashMap<String, String>) {
+        match codec {
+            "libx264" | "libx265" => {
+                opt.entry("tune".to_string())
+                    .or_insert("zerolatency".to_string());
+                if codec == "libx264" {
+                    opt.insert("intra-refresh".to_string(), "1".to_string());
+                }
+            }
+            "h264_nvenc" | "hevc_nvenc" | "av1_nvenc" => {
+                opt.entry("tune".to_string()).or_insert("ull".to_string());
+                opt.insert("zerolatency".to_string(), "1".to_string());
+                opt.insert("rc-lookahead".to_string(), "0".to_string());
+                opt.insert("intra-refresh".to_string(), "1".to_string());
+            }
+            "h264_qsv" | "hevc_qsv" | "av1_qsv" => {
+                opt.insert("low_delay_brc".to_string(), "1".to_string());
+                opt.insert("look_ahead".to_string(), "0".to_string());
+            }
+            "libsvtav1" => {
+                // low delay prediction structure, svt-av1 has no zero-latency tune
+                opt.insert("svtav1-params".to_string(), "pred-struct=1".to_string());
+            }
+            _ => {}
+        }
+    }
+}
+
+impl VideoVariant {
+    /// Variant is encoded as AV1, HLS serves AV1 in fMP4 segments only
+    pub fn is_av1(&self) -> bool {
+        AV1_ENCODERS.contains(&self.codec.as_str())
+    }
+
+    /// Variant is encoded as HEVC, HLS serves HEVC in fMP4 segments only
+    pub fn is_hevc(&self) -> bool {
+        HEVC_ENCODERS.contains(&self.codec.as_str())
+    }
+
+    /// Variant can't be carried in MPEG-TS HLS segments by players
+    pub fn needs_fmp4(&self) -> bool {
+        self.is_av1() || self.is_hevc()
+    }
 }
 
 impl Display for VideoVariant {
@@ -89,6 +294,12 @@ impl TryInto<Encoder> for &VideoVariant {
                 opt.insert("preset".to_string(), "fast".to_string());
                 //opt.insert("tune".to_string(), "zerolatency".to_string());
             }
+            if self.codec == V4L2M2M_H264 {
+                // fewer buffers than the defaults (16/4), memory is scarce on the Pi
+                opt.insert("num_output_buffers".to_string(), "8".to_string());
+                opt.insert("num_capture_buffers".to_string(), "4".to_string());
+            }
+            self.encoder.apply(&self.codec, &mut opt);
             let enc = Encoder::new_with_name(&self.codec)?
                 .with_bitrate(self.bitrate as _)
                 .with_width(self.width as _)
@@ -100,8 +311,32 @@ impl TryInto<Encoder> for &VideoVariant {
                 .with_options(|ctx| {
                     (*ctx).gop_size = self.keyframe_interval as _;
                     (*ctx).keyint_min = self.keyframe_interval as _;
-                    (*ctx).max_b_frames = 3;
+                    // the V4L2 M2M encoder of the Pi has no B-frames
+                    (*ctx).max_b_frames = if self.codec == V4L2M2M_H264 || self.encoder.low_latency
+                    {
+                        0
+                    } else {
+                        3
+                    };
                     (*ctx).colorspace = AVCOL_SPC_BT709;
+                    if self.is_av1() {
+                        // profile and level are H.264 values, AV1 uses the main profile
+                        // and lets the encoder pick the level
+                        (*ctx).profile = 0;
+                        (*ctx).level = -99;
+                    } else if self.is_hevc() {
+                        // likewise for HEVC, 8-bit 4:2:0 is the main profile
+                        (*ctx).profile = 1;
+                        (*ctx).level = -99;
+                    }
+                    if self.encoder.crf.is_some() {
+                        // cap the bitrate with the VBV, the encoder picks the rate below it
+                        (*ctx).rc_max_rate = self.bitrate as _;
+                        (*ctx).rc_buffer_size = (self.bitrate * 2) as _;
+                    } else if self.encoder.low_latency {
+                        (*ctx).rc_max_rate = self.bitrate as _;
+                        (*ctx).rc_buffer_size = (self.bitrate as f32 / self.fps.max(1.0)) as _;
+                    }
                 })
                 .open(Some(opt))?;
 
@@ -0,0 +1,18 @@
+create table audit_log
+(
+    id          integer unsigned not null auto_increment primary key,
+    -- admin user which performed the action
+    admin_id    integer unsigned not null,
+    -- action type, e.g. view_account
+    action      varchar(50) not null,
+    -- type and id of the object the action was performed on
+    target_type varchar(50) not null,
+    target_id   varchar(50) not null,
+    -- extra details about the action
+    message     text,
+    created     timestamp   not null default current_timestamp,
+
+    constraint fk_audit_log_admin
+        foreign key (admin_id) references user (id)
+);
+create index ix_audit_log_created on audit_log (created);
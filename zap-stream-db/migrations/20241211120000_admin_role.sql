@@ -0,0 +1,3 @@
+-- role of admin users (is_admin = true), 0 = full admin, 1 = moderator
+alter table user
+    add column admin_role tinyint unsigned not null default 0;
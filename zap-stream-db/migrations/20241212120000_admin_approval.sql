@@ -0,0 +1,22 @@
+create table admin_approval
+(
+    id          integer unsigned not null auto_increment primary key,
+    -- 0 = credit balance, 1 = delete user
+    action      tinyint unsigned not null,
+    -- user the action is performed on
+    user_id     integer unsigned not null,
+    -- milli-sats for balance credits
+    amount      bigint,
+    memo        text,
+    -- 0 = pending, 1 = approved, 2 = rejected
+    state       tinyint unsigned not null default 0,
+    proposed_by integer unsigned not null,
+    resolved_by integer unsigned,
+    created     timestamp        not null default current_timestamp,
+    resolved    timestamp,
+
+    constraint fk_admin_approval_proposed_by
+        foreign key (proposed_by) references user (id),
+    constraint fk_admin_approval_resolved_by
+        foreign key (resolved_by) references user (id)
+);
@@ -0,0 +1,18 @@
+create table stream_connection
+(
+    id         integer unsigned not null auto_increment primary key,
+    stream_id  varchar(50)  not null,
+    -- remote address of the encoder
+    ip_addr    varchar(100) not null,
+    -- ingress endpoint the encoder connected to
+    endpoint   varchar(100) not null,
+    -- encoder software, if reported
+    user_agent text,
+    -- protocol specific parameters as json
+    params     text,
+    created    timestamp    not null default current_timestamp,
+
+    constraint fk_stream_connection_stream
+        foreign key (stream_id) references user_stream (id)
+);
+create index ix_stream_connection_ip_addr on stream_connection (ip_addr);
@@ -0,0 +1,3 @@
+-- time the last segment was received, used to detect streams whose pipeline died
+alter table user_stream
+    add column last_segment timestamp;
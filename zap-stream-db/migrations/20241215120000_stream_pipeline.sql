@@ -0,0 +1,3 @@
+-- pipeline config (json) so a stream can be resumed after a restart
+alter table user_stream
+    add column pipeline text;
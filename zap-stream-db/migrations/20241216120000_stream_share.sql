@@ -0,0 +1,22 @@
+-- 0 = public, 1 = unlisted (not published to relays, playback requires a share token)
+alter table user
+    add column visibility tinyint unsigned not null default 0;
+alter table user_stream
+    add column visibility tinyint unsigned not null default 0;
+
+create table stream_share
+(
+    id        integer unsigned not null auto_increment primary key,
+    stream_id varchar(50)      not null,
+    token     varchar(64)      not null,
+    -- token is rejected after this time
+    expires   timestamp,
+    -- max number of playback sessions which can be started with this token
+    max_uses  integer unsigned,
+    uses      integer unsigned not null default 0,
+    created   timestamp        not null default current_timestamp,
+
+    constraint fk_stream_share_stream
+        foreign key (stream_id) references user_stream (id)
+);
+create unique index ix_stream_share_token on stream_share (token);
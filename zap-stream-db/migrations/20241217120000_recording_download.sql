@@ -0,0 +1,21 @@
+create table recording_download
+(
+    id          integer unsigned not null auto_increment primary key,
+    stream_id   varchar(50)      not null,
+    user_id     integer unsigned not null,
+    -- remote address of the client, if known
+    ip_addr     varchar(100),
+    -- file which was downloaded (recording.ts / recording-trim.mp4)
+    file        varchar(100)     not null,
+    -- first byte of the requested range
+    range_start bigint unsigned  not null default 0,
+    -- number of bytes sent
+    length      bigint unsigned  not null,
+    created     timestamp        not null default current_timestamp,
+
+    constraint fk_recording_download_stream
+        foreign key (stream_id) references user_stream (id),
+    constraint fk_recording_download_user
+        foreign key (user_id) references user (id)
+);
+create index ix_recording_download_stream_id on recording_download (stream_id);
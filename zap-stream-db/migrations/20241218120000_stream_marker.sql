@@ -0,0 +1,13 @@
+create table stream_marker
+(
+    id          integer unsigned not null auto_increment primary key,
+    stream_id   varchar(50)      not null,
+    -- seconds from the start of the stream
+    offset_secs float            not null,
+    label       varchar(200)     not null,
+    created     timestamp        not null default current_timestamp,
+
+    constraint fk_stream_marker_stream
+        foreign key (stream_id) references user_stream (id)
+);
+create index ix_stream_marker_stream_id on stream_marker (stream_id);
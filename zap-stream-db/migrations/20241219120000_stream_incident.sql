@@ -0,0 +1,14 @@
+create table stream_incident
+(
+    id        integer unsigned not null auto_increment primary key,
+    stream_id varchar(50)      not null,
+    -- panic message or error which stopped the pipeline
+    error     text             not null,
+    -- an automatic restart was attempted
+    restarted bool             not null default false,
+    created   timestamp        not null default current_timestamp,
+
+    constraint fk_stream_incident_stream
+        foreign key (stream_id) references user_stream (id)
+);
+create index ix_stream_incident_stream_id on stream_incident (stream_id);
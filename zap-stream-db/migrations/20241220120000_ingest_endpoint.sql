@@ -0,0 +1,16 @@
+create table ingest_endpoint
+(
+    id       integer unsigned not null auto_increment primary key,
+    -- listen address of the ingest, matches the connection endpoint
+    endpoint varchar(255)     not null,
+    -- encoder overrides for transcoded video variants, null keeps the default
+    preset   varchar(50),
+    tune     varchar(50),
+    profile  varchar(50),
+    -- codec specific options (e.g. x264-params) as key=value:key=value
+    params   text,
+    created  timestamp        not null default current_timestamp,
+    updated  timestamp        not null default current_timestamp on update current_timestamp,
+
+    constraint uq_ingest_endpoint_endpoint unique (endpoint)
+);
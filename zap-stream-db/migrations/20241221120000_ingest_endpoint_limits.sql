@@ -0,0 +1,11 @@
+-- input restrictions of ingest endpoints, null means unrestricted
+alter table ingest_endpoint
+    add column max_width   integer unsigned,
+    add column max_height  integer unsigned,
+    add column max_fps     float,
+    -- bits per second
+    add column max_bitrate bigint unsigned,
+    -- comma separated ffmpeg codec names (h264,hevc)
+    add column video_codecs varchar(255),
+    -- accept high bit depth (HDR) video
+    add column allow_hdr   bool not null default true;
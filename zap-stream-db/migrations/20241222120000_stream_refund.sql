@@ -0,0 +1,17 @@
+create table stream_refund
+(
+    id        integer unsigned not null auto_increment primary key,
+    stream_id varchar(50)      not null,
+    -- admin which issued the refund
+    admin_id  integer unsigned not null,
+    -- milli-sats credited back to the streamer
+    amount    bigint unsigned  not null,
+    reason    text,
+    created   timestamp        not null default current_timestamp,
+
+    constraint fk_stream_refund_stream
+        foreign key (stream_id) references user_stream (id),
+    constraint fk_stream_refund_admin
+        foreign key (admin_id) references user (id)
+);
+create index ix_stream_refund_stream_id on stream_refund (stream_id);
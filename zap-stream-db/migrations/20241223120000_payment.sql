@@ -0,0 +1,17 @@
+create table payment
+(
+    id          integer unsigned not null auto_increment primary key,
+    user_id     integer unsigned not null,
+    -- payment processor which settled the payment
+    provider    varchar(20)      not null,
+    -- id of the invoice / charge at the processor
+    external_id varchar(200)     not null,
+    -- milli-sats credited to the user
+    amount      bigint unsigned  not null,
+    created     timestamp        not null default current_timestamp,
+
+    constraint fk_payment_user
+        foreign key (user_id) references user (id)
+);
+-- processors retry webhooks, each payment is only credited once
+create unique index ix_payment_provider_external_id on payment (provider, external_id);
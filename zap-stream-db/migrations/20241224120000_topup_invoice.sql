@@ -0,0 +1,12 @@
+create table topup_invoice
+(
+    -- hex payment hash of the lightning invoice
+    payment_hash varchar(64)      not null primary key,
+    user_id      integer unsigned not null,
+    -- milli-sats credited when the invoice is paid
+    amount       bigint unsigned  not null,
+    created      timestamp        not null default current_timestamp,
+
+    constraint fk_topup_invoice_user
+        foreign key (user_id) references user (id)
+);
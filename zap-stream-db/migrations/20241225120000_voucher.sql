@@ -0,0 +1,21 @@
+create table voucher
+(
+    id          integer unsigned not null auto_increment primary key,
+    -- code users redeem, single use
+    code        varchar(32)      not null,
+    -- milli-sats credited when redeemed
+    amount      bigint unsigned  not null,
+    -- admin which created the voucher
+    created_by  integer unsigned not null,
+    memo        text,
+    expires     timestamp        null,
+    redeemed_by integer unsigned,
+    redeemed    timestamp        null,
+    created     timestamp        not null default current_timestamp,
+
+    constraint fk_voucher_created_by
+        foreign key (created_by) references user (id),
+    constraint fk_voucher_redeemed_by
+        foreign key (redeemed_by) references user (id)
+);
+create unique index ix_voucher_code on voucher (code);
@@ -0,0 +1,18 @@
+-- each transfer is recorded as a pair of entries, a debit of the sender and a credit of the recipient
+create table balance_transfer
+(
+    id              integer unsigned not null auto_increment primary key,
+    user_id         integer unsigned not null,
+    -- the other side of the transfer
+    counterparty_id integer unsigned not null,
+    -- milli-sats, negative for the sender
+    amount          bigint           not null,
+    memo            varchar(200),
+    created         timestamp        not null default current_timestamp,
+
+    constraint fk_balance_transfer_user
+        foreign key (user_id) references user (id),
+    constraint fk_balance_transfer_counterparty
+        foreign key (counterparty_id) references user (id)
+);
+create index ix_balance_transfer_user_created on balance_transfer (user_id, created);
@@ -0,0 +1,3 @@
+-- multiplier applied to endpoint rates, 0 for staff streaming for free
+alter table user
+    add column cost_multiplier float not null default 1;
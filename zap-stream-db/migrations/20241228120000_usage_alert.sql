@@ -0,0 +1,19 @@
+create table usage_alert
+(
+    id              integer unsigned not null auto_increment primary key,
+    user_id         integer unsigned not null,
+    -- debit rate in milli-sats / minute which raised the alert
+    rate            bigint unsigned  not null,
+    -- number of live streams of the user at the time
+    streams         integer unsigned not null,
+    -- admin which acknowledged the alert
+    acknowledged_by integer unsigned,
+    acknowledged    timestamp        null,
+    created         timestamp        not null default current_timestamp,
+
+    constraint fk_usage_alert_user
+        foreign key (user_id) references user (id),
+    constraint fk_usage_alert_acknowledged_by
+        foreign key (acknowledged_by) references user (id)
+);
+create index ix_usage_alert_created on usage_alert (created);
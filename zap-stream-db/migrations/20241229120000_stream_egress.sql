@@ -0,0 +1,15 @@
+-- bytes of stream files served to viewers, aggregated per day
+create table stream_egress
+(
+    stream_id varchar(50)      not null,
+    day       date             not null,
+    -- 0 = served by this node, 1 = reported from CDN logs
+    source    tinyint unsigned not null,
+    bytes     bigint unsigned  not null default 0,
+    requests  bigint unsigned  not null default 0,
+
+    primary key (stream_id, day, source),
+    constraint fk_stream_egress_stream
+        foreign key (stream_id) references user_stream (id)
+);
+create index ix_stream_egress_day on stream_egress (day);
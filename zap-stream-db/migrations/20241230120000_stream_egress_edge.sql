@@ -0,0 +1,6 @@
+-- CDN edges report separately, each edge replaces only its own rows
+alter table stream_egress
+    add column edge       varchar(50)     not null default '' after source,
+    add column unique_ips bigint unsigned not null default 0,
+    drop primary key,
+    add primary key (stream_id, day, source, edge);
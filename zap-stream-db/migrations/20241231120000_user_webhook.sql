@@ -0,0 +1,4 @@
+-- signed callbacks sent when the user's streams are created, updated or ended
+alter table user
+    add column webhook_url    text,
+    add column webhook_secret varchar(64);
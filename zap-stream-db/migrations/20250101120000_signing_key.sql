@@ -0,0 +1,7 @@
+-- activations of the platform keys, new events are signed with the key of the latest row
+create table signing_key
+(
+    id      integer unsigned not null auto_increment primary key,
+    pubkey  binary(32)       not null,
+    created timestamp        not null default current_timestamp
+);
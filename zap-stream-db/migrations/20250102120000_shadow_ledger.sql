@@ -0,0 +1,15 @@
+-- stream charges recorded instead of debiting user balances when running in dry run mode
+create table shadow_ledger
+(
+    id        integer unsigned not null auto_increment primary key,
+    user_id   integer unsigned not null,
+    stream_id varchar(50)      not null,
+    -- what was charged (stream, egress)
+    kind      varchar(20)      not null,
+    amount    bigint           not null,
+    created   timestamp        not null default current_timestamp,
+
+    constraint fk_shadow_ledger_user
+        foreign key (user_id) references user (id)
+);
+create index ix_shadow_ledger_user on shadow_ledger (user_id);
@@ -0,0 +1,12 @@
+-- short /s/{code} links redirecting to the watch page of a stream
+create table stream_link
+(
+    code      varchar(16)      not null primary key,
+    stream_id varchar(50)      not null,
+    clicks    integer unsigned not null default 0,
+    created   timestamp        not null default current_timestamp,
+
+    constraint fk_stream_link_stream
+        foreign key (stream_id) references user_stream (id)
+);
+create unique index ix_stream_link_stream on stream_link (stream_id);
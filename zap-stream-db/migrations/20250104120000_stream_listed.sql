@@ -0,0 +1,6 @@
+-- streams which are not listed are left out of the public stream directory, they are
+-- still published to relays
+alter table user
+    add column listed bool not null default true;
+alter table user_stream
+    add column listed bool not null default true;
@@ -0,0 +1,4 @@
+-- viewer stats of ended streams, published in their final stream event
+alter table user_stream
+    add column peak_viewers integer unsigned,
+    add column avg_viewers  float;
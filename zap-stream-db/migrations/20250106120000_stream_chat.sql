@@ -0,0 +1,16 @@
+-- kind 1311 chat messages of ended streams, replayed alongside the recording
+create table stream_chat
+(
+    id          integer unsigned not null auto_increment primary key,
+    stream_id   varchar(50)      not null,
+    event_id    binary(32)       not null,
+    -- seconds from the start of the stream
+    offset_secs float            not null,
+    -- signed nostr event (json)
+    event       text             not null,
+
+    constraint fk_stream_chat_stream
+        foreign key (stream_id) references user_stream (id)
+);
+create unique index ix_stream_chat_event on stream_chat (event_id);
+create index ix_stream_chat_offset on stream_chat (stream_id, offset_secs);
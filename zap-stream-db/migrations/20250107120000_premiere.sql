@@ -0,0 +1,20 @@
+-- imported recordings scheduled to be streamed as a live stream
+create table premiere
+(
+    id        integer unsigned not null auto_increment primary key,
+    user_id   integer unsigned not null,
+    -- imported recording (ended user_stream) to play
+    vod_id    varchar(50)      not null,
+    starts    timestamp        not null,
+    -- 0 = scheduled, 1 = started, 2 = cancelled
+    state     tinyint unsigned not null default 0,
+    -- live stream the premiere was played as
+    stream_id varchar(50),
+    created   timestamp        not null default current_timestamp,
+
+    constraint fk_premiere_user
+        foreign key (user_id) references user (id),
+    constraint fk_premiere_vod
+        foreign key (vod_id) references user_stream (id)
+);
+create index ix_premiere_starts on premiere (state, starts);
@@ -0,0 +1,3 @@
+-- faststart MP4 of the recording, relative to the stream dir
+alter table user_stream
+    add column recording varchar(100);
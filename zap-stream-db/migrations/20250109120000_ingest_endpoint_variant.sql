@@ -0,0 +1,17 @@
+-- transcode ladder of an ingest endpoint, replaces the default transcoded variant when set
+create table ingest_endpoint_variant
+(
+    id          integer unsigned not null auto_increment primary key,
+    endpoint_id integer unsigned not null,
+    width       integer unsigned not null,
+    height      integer unsigned not null,
+    -- null keeps the frame rate of the input
+    fps         float,
+    -- bits per second
+    bitrate     bigint unsigned  not null,
+    -- ffmpeg encoder name, null uses the configured video encoder
+    codec       varchar(50),
+
+    constraint fk_ingest_endpoint_variant_endpoint
+        foreign key (endpoint_id) references ingest_endpoint (id) on delete cascade
+);
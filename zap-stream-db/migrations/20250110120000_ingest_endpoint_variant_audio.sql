@@ -0,0 +1,3 @@
+-- audio-only rung, bitrate is the audio bitrate and the dimensions are unused
+alter table ingest_endpoint_variant
+    add column audio_only bool not null default false;
@@ -0,0 +1,6 @@
+-- target loudness in LUFS the endpoint normalizes audio to, off if null
+alter table ingest_endpoint
+    add column loudnorm float;
+-- user opted out of loudness normalization
+alter table user
+    add column loudnorm bool not null default true;
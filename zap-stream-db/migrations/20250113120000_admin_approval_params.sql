@@ -0,0 +1,3 @@
+-- parameters of the action as JSON, e.g. the stream of a refund (action 2)
+alter table admin_approval
+    add column params text;
@@ -0,0 +1,3 @@
+-- actions not performed on a user (3 = create vouchers) have no user
+alter table admin_approval
+    modify column user_id integer unsigned;
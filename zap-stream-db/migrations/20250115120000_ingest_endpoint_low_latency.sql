@@ -0,0 +1,3 @@
+-- transcode streams of the endpoint with low latency encoder options
+alter table ingest_endpoint
+    add column low_latency bool not null default false;
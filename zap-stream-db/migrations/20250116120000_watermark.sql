@@ -0,0 +1,5 @@
+-- placement of the watermark overlaid on transcoded video of the endpoint, the images are
+-- uploaded with the admin API
+alter table ingest_endpoint
+    add column watermark_position varchar(16),
+    add column watermark_opacity float;
@@ -0,0 +1,25 @@
+-- audit and financial records keep the ids of deleted users and streams, without the foreign
+-- keys deleting a user leaves them in place
+alter table audit_log
+    drop foreign key fk_audit_log_admin;
+alter table admin_approval
+    drop foreign key fk_admin_approval_proposed_by,
+    drop foreign key fk_admin_approval_resolved_by;
+alter table recording_download
+    drop foreign key fk_recording_download_stream,
+    drop foreign key fk_recording_download_user;
+alter table stream_refund
+    drop foreign key fk_stream_refund_stream,
+    drop foreign key fk_stream_refund_admin;
+alter table payment
+    drop foreign key fk_payment_user;
+alter table topup_invoice
+    drop foreign key fk_topup_invoice_user;
+alter table voucher
+    drop foreign key fk_voucher_created_by,
+    drop foreign key fk_voucher_redeemed_by;
+alter table balance_transfer
+    drop foreign key fk_balance_transfer_user,
+    drop foreign key fk_balance_transfer_counterparty;
+alter table shadow_ledger
+    drop foreign key fk_shadow_ledger_user;
@@ -0,0 +1,4 @@
+-- set with every state transition and cleared once the stream event of the new state was
+-- published, streams left set are published again by the stream check
+alter table user_stream
+    add column event_pending bool not null default false;
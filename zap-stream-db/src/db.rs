@@ -1,16 +1,71 @@
-use crate::{User, UserStream};
-use anyhow::Result;
-use sqlx::{Executor, MySqlPool, Row};
+use crate::{
+    AdminApproval, ApprovalAction, ApprovalState, AuditLog, AuditLogFilter, BalanceTransfer,
+    EgressSource, IngestEndpoint, IngestEndpointVariant, Payment, Premiere, PremiereState,
+    RecordingDownload, SigningKey, StreamChatMessage, StreamConnection, StreamEgress,
+    StreamEgressTotal, StreamIncident, StreamLink, StreamMarker, StreamRefund, StreamShare,
+    StreamVisibility, TopupInvoice, UsageAlert, User, UserStream, UserStreamState, Voucher,
+};
+use anyhow::{bail, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::{Executor, MySqlPool, QueryBuilder, Row};
 use uuid::Uuid;
 
+#[derive(Clone)]
 pub struct ZapStreamDb {
     db: MySqlPool,
+    /// Read-only replica used for read-heavy list queries
+    replica: Option<MySqlPool>,
+    /// Record stream charges in the shadow ledger instead of debiting user balances
+    shadow_ledger: bool,
 }
 
 impl ZapStreamDb {
     pub async fn new(db: &str) -> Result<Self> {
         let db = MySqlPool::connect(db).await?;
-        Ok(ZapStreamDb { db })
+        Ok(ZapStreamDb {
+            db,
+            replica: None,
+            shadow_ledger: false,
+        })
+    }
+
+    /// Use a read-only replica for list queries, writes always go to the primary
+    pub async fn with_replica(mut self, replica: &str) -> Result<Self> {
+        self.replica = Some(MySqlPool::connect(replica).await?);
+        Ok(self)
+    }
+
+    /// Write stream charges to the shadow ledger and leave user balances untouched (dry run)
+    pub fn with_shadow_ledger(mut self) -> Self {
+        self.shadow_ledger = true;
+        self
+    }
+
+    /// Record a charge of [amount] in the shadow ledger
+    async fn insert_shadow_charge(
+        tx: &mut sqlx::Transaction<'_, sqlx::MySql>,
+        user_id: u64,
+        stream_id: &str,
+        kind: &str,
+        amount: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            "insert into shadow_ledger (user_id, stream_id, kind, amount) values (?, ?, ?, ?)",
+        )
+        .bind(user_id)
+        .bind(stream_id)
+        .bind(kind)
+        .bind(amount)
+        .execute(&mut **tx)
+        .await?;
+        Ok(())
+    }
+
+    /// Pool to use for history, analytics and admin list queries, the replica if configured.
+    /// Reads which decide access, routing or state changes must use the primary, the replica
+    /// may lag behind
+    fn read_pool(&self) -> &MySqlPool {
+        self.replica.as_ref().unwrap_or(&self.db)
     }
 
     pub async fn migrate(&self) -> Result<()> {
@@ -33,6 +88,20 @@ impl ZapStreamDb {
             .map(|r| r.try_get(0).unwrap()))
     }
 
+    /// Find user by nostr pubkey
+    pub async fn find_user_pubkey(&self, pubkey: &[u8]) -> Result<Option<User>> {
+        Ok(sqlx::query_as("select * from user where pubkey = ?")
+            .bind(pubkey)
+            .fetch_optional(&self.db)
+            .await?)
+    }
+
+    /// Check the database connection
+    pub async fn ping(&self) -> Result<()> {
+        sqlx::query("select 1").execute(&self.db).await?;
+        Ok(())
+    }
+
     /// Get user by id
     pub async fn get_user(&self, uid: u64) -> Result<User> {
         Ok(sqlx::query_as("select * from user where id = ?")
@@ -42,6 +111,14 @@ impl ZapStreamDb {
             .map_err(anyhow::Error::new)?)
     }
 
+    /// Find user by id, [None] if the user was deleted
+    pub async fn find_user(&self, uid: u64) -> Result<Option<User>> {
+        Ok(sqlx::query_as("select * from user where id = ?")
+            .bind(uid)
+            .fetch_optional(&self.db)
+            .await?)
+    }
+
     pub async fn upsert_user(&self, pubkey: &[u8; 32]) -> Result<u64> {
         let res = sqlx::query("insert ignore into user(pubkey) values(?) returning id")
             .bind(pubkey.as_slice())
@@ -59,20 +136,46 @@ impl ZapStreamDb {
     }
 
     pub async fn insert_stream(&self, user_stream: &UserStream) -> Result<()> {
-        sqlx::query("insert into user_stream (id, user_id, state, starts) values (?, ?, ?, ?)")
-            .bind(&user_stream.id)
-            .bind(&user_stream.user_id)
-            .bind(&user_stream.state)
-            .bind(&user_stream.starts)
-            .execute(&self.db)
-            .await?;
+        sqlx::query(
+            "insert into user_stream (id, user_id, state, starts, visibility, listed) values (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&user_stream.id)
+        .bind(&user_stream.user_id)
+        .bind(&user_stream.state)
+        .bind(&user_stream.starts)
+        .bind(&user_stream.visibility)
+        .bind(&user_stream.listed)
+        .execute(&self.db)
+        .await?;
 
         Ok(())
     }
 
+    /// Insert an imported recording, stored as an ended stream
+    pub async fn insert_vod(&self, user_stream: &UserStream) -> Result<()> {
+        sqlx::query(
+            "insert into user_stream (id, user_id, state, starts, ends, title, summary, duration, visibility, listed, event, recording) values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&user_stream.id)
+        .bind(&user_stream.user_id)
+        .bind(&user_stream.state)
+        .bind(&user_stream.starts)
+        .bind(&user_stream.ends)
+        .bind(&user_stream.title)
+        .bind(&user_stream.summary)
+        .bind(&user_stream.duration)
+        .bind(&user_stream.visibility)
+        .bind(&user_stream.listed)
+        .bind(&user_stream.event)
+        .bind(&user_stream.recording)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
     pub async fn update_stream(&self, user_stream: &UserStream) -> Result<()> {
         sqlx::query(
-            "update user_stream set state = ?, starts = ?, ends = ?, title = ?, summary = ?, image = ?, thumb = ?, tags = ?, content_warning = ?, goal = ?, pinned = ?, fee = ?, event = ? where id = ?",
+            "update user_stream set state = ?, starts = ?, ends = ?, title = ?, summary = ?, image = ?, thumb = ?, tags = ?, content_warning = ?, goal = ?, pinned = ?, fee = ?, event = ?, pipeline = ? where id = ?",
         )
             .bind(&user_stream.state)
             .bind(&user_stream.starts)
@@ -87,6 +190,7 @@ impl ZapStreamDb {
             .bind(&user_stream.pinned)
             .bind(&user_stream.fee)
             .bind(&user_stream.event)
+            .bind(&user_stream.pipeline)
             .bind(&user_stream.id)
             .execute(&self.db)
             .await
@@ -94,6 +198,56 @@ impl ZapStreamDb {
         Ok(())
     }
 
+    /// Move a stream from [from] to [to], returns false if the stream was not in state [from]
+    ///
+    /// This is a compare-and-set so concurrent callers cannot both perform the same transition.
+    /// When ending a stream [ends] defaults to now
+    pub async fn transition_stream(
+        &self,
+        id: &str,
+        from: UserStreamState,
+        to: UserStreamState,
+        ends: Option<DateTime<Utc>>,
+    ) -> Result<bool> {
+        if !from.can_transition_to(to) {
+            bail!("Invalid stream state transition {} -> {}", from, to);
+        }
+        let ends = if to == UserStreamState::Ended {
+            Some(ends.unwrap_or(Utc::now()))
+        } else {
+            None
+        };
+        let res = sqlx::query(
+            "update user_stream set state = ?, ends = coalesce(?, ends), event_pending = true where id = ? and state = ?",
+        )
+        .bind(to)
+        .bind(ends)
+        .bind(id)
+        .bind(from)
+        .execute(&self.db)
+        .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Store the published stream [event] of the current state
+    pub async fn set_stream_event(&self, id: &str, event: &str) -> Result<()> {
+        sqlx::query("update user_stream set event = ?, event_pending = false where id = ?")
+            .bind(event)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Streams whose event was not published after their last state transition
+    pub async fn list_event_pending_streams(&self) -> Result<Vec<UserStream>> {
+        Ok(
+            sqlx::query_as("select * from user_stream where event_pending = true")
+                .fetch_all(&self.db)
+                .await?,
+        )
+    }
+
     pub async fn get_stream(&self, id: &Uuid) -> Result<UserStream> {
         Ok(sqlx::query_as("select * from user_stream where id = ?")
             .bind(id.to_string())
@@ -102,6 +256,363 @@ impl ZapStreamDb {
             .map_err(anyhow::Error::new)?)
     }
 
+    /// Set the visibility of new streams for a user
+    pub async fn update_user_cost_multiplier(&self, user_id: u64, multiplier: f32) -> Result<()> {
+        sqlx::query("update user set cost_multiplier = ? where id = ?")
+            .bind(multiplier)
+            .bind(user_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Set or remove (None) the stream webhook of a user
+    pub async fn update_user_webhook(
+        &self,
+        user_id: u64,
+        url: Option<&str>,
+        secret: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query("update user set webhook_url = ?, webhook_secret = ? where id = ?")
+            .bind(url)
+            .bind(secret)
+            .bind(user_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn update_user_visibility(
+        &self,
+        user_id: u64,
+        visibility: StreamVisibility,
+    ) -> Result<()> {
+        sqlx::query("update user set visibility = ? where id = ?")
+            .bind(visibility)
+            .bind(user_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Set if new streams of a user are shown in the public stream directory
+    pub async fn update_user_listed(&self, user_id: u64, listed: bool) -> Result<()> {
+        sqlx::query("update user set listed = ? where id = ?")
+            .bind(listed)
+            .bind(user_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Set if audio of the user's streams is loudness normalized
+    pub async fn update_user_loudnorm(&self, user_id: u64, loudnorm: bool) -> Result<()> {
+        sqlx::query("update user set loudnorm = ? where id = ?")
+            .bind(loudnorm)
+            .bind(user_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Set the viewer stats of an ended stream
+    pub async fn update_stream_viewers(&self, stream_id: &str, peak: u32, avg: f32) -> Result<()> {
+        sqlx::query("update user_stream set peak_viewers = ?, avg_viewers = ? where id = ?")
+            .bind(peak)
+            .bind(avg)
+            .bind(stream_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Attach the MP4 [file] of a recording to a stream
+    pub async fn update_stream_recording(&self, stream_id: &str, file: &str) -> Result<()> {
+        sqlx::query("update user_stream set recording = ? where id = ?")
+            .bind(file)
+            .bind(stream_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Show or hide a stream in the public stream directory
+    pub async fn update_stream_listed(&self, stream_id: &str, listed: bool) -> Result<()> {
+        sqlx::query("update user_stream set listed = ? where id = ?")
+            .bind(listed)
+            .bind(stream_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Set if new streams of a user are recorded
+    pub async fn update_user_recording(&self, user_id: u64, recording: bool) -> Result<()> {
+        sqlx::query("update user set recording = ? where id = ?")
+            .bind(recording)
+            .bind(user_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Create a share token for a stream
+    pub async fn insert_stream_share(&self, share: &StreamShare) -> Result<()> {
+        sqlx::query(
+            "insert into stream_share (stream_id, token, expires, max_uses) values (?, ?, ?, ?)",
+        )
+        .bind(&share.stream_id)
+        .bind(&share.token)
+        .bind(&share.expires)
+        .bind(&share.max_uses)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Use a share token to start playback of a stream, returns false if the token is
+    /// invalid, expired or has no uses left
+    pub async fn redeem_stream_share(&self, stream_id: &str, token: &str) -> Result<bool> {
+        let res = sqlx::query(
+            "update stream_share set uses = uses + 1 where stream_id = ? and token = ? and (expires is null or expires > current_timestamp) and (max_uses is null or uses < max_uses)",
+        )
+        .bind(stream_id)
+        .bind(token)
+        .execute(&self.db)
+        .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Check a share token is valid for a stream without using it
+    pub async fn check_stream_share(&self, stream_id: &str, token: &str) -> Result<bool> {
+        Ok(sqlx::query(
+            "select id from stream_share where stream_id = ? and token = ? and (expires is null or expires > current_timestamp)",
+        )
+        .bind(stream_id)
+        .bind(token)
+        .fetch_optional(&self.db)
+        .await?
+        .is_some())
+    }
+
+    /// Archive chat messages of a stream, messages which are already archived are skipped
+    pub async fn insert_stream_chat(&self, messages: &[StreamChatMessage]) -> Result<()> {
+        for m in messages {
+            sqlx::query(
+                "insert ignore into stream_chat (stream_id, event_id, offset_secs, event) values (?, ?, ?, ?)",
+            )
+            .bind(&m.stream_id)
+            .bind(&m.event_id)
+            .bind(m.offset_secs)
+            .bind(&m.event)
+            .execute(&self.db)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Archived chat messages of a stream between [from] and [to] seconds from its start
+    pub async fn list_stream_chat(
+        &self,
+        stream_id: &str,
+        from: f32,
+        to: f32,
+        limit: u64,
+    ) -> Result<Vec<StreamChatMessage>> {
+        Ok(sqlx::query_as(
+            "select * from stream_chat where stream_id = ? and offset_secs >= ? and offset_secs < ? order by offset_secs, id limit ?",
+        )
+        .bind(stream_id)
+        .bind(from)
+        .bind(to)
+        .bind(limit)
+        .fetch_all(self.read_pool())
+        .await?)
+    }
+
+    pub async fn insert_premiere(&self, premiere: &Premiere) -> Result<u64> {
+        Ok(
+            sqlx::query("insert into premiere (user_id, vod_id, starts) values (?, ?, ?)")
+                .bind(premiere.user_id)
+                .bind(&premiere.vod_id)
+                .bind(premiere.starts)
+                .execute(&self.db)
+                .await?
+                .last_insert_id(),
+        )
+    }
+
+    pub async fn get_premiere(&self, id: u64) -> Result<Option<Premiere>> {
+        Ok(sqlx::query_as("select * from premiere where id = ?")
+            .bind(id)
+            .fetch_optional(&self.db)
+            .await?)
+    }
+
+    /// Scheduled premieres of a user, soonest first
+    pub async fn list_user_premieres(&self, user_id: u64) -> Result<Vec<Premiere>> {
+        Ok(
+            sqlx::query_as(
+                "select * from premiere where user_id = ? and state = 0 order by starts",
+            )
+            .bind(user_id)
+            .fetch_all(self.read_pool())
+            .await?,
+        )
+    }
+
+    /// Scheduled premieres whose start time has passed
+    pub async fn list_due_premieres(&self) -> Result<Vec<Premiere>> {
+        Ok(sqlx::query_as(
+            "select * from premiere where state = 0 and starts <= current_timestamp order by starts",
+        )
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    /// Move a scheduled premiere to [state], returns false if it was not scheduled
+    ///
+    /// This is a compare-and-set so a premiere is only started once by all nodes
+    pub async fn transition_premiere(&self, id: u64, state: PremiereState) -> Result<bool> {
+        let res = sqlx::query("update premiere set state = ? where id = ? and state = 0")
+            .bind(state)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Set the live stream a premiere is played as
+    pub async fn update_premiere_stream(&self, id: u64, stream_id: &str) -> Result<()> {
+        sqlx::query("update premiere set stream_id = ? where id = ?")
+            .bind(stream_id)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Short link of a stream
+    pub async fn get_stream_link(&self, stream_id: &str) -> Result<Option<StreamLink>> {
+        Ok(
+            sqlx::query_as("select * from stream_link where stream_id = ?")
+                .bind(stream_id)
+                .fetch_optional(&self.db)
+                .await?,
+        )
+    }
+
+    /// Create the short link of a stream, returns false if the code is taken or the stream
+    /// already has a link
+    pub async fn insert_stream_link(&self, stream_id: &str, code: &str) -> Result<bool> {
+        let res = sqlx::query("insert ignore into stream_link (code, stream_id) values (?, ?)")
+            .bind(code)
+            .bind(stream_id)
+            .execute(&self.db)
+            .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Count a click on a short link, returns the id of the linked stream
+    pub async fn click_stream_link(&self, code: &str) -> Result<Option<String>> {
+        let res = sqlx::query("update stream_link set clicks = clicks + 1 where code = ?")
+            .bind(code)
+            .execute(&self.db)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Ok(None);
+        }
+        Ok(
+            sqlx::query("select stream_id from stream_link where code = ?")
+                .bind(code)
+                .fetch_optional(&self.db)
+                .await?
+                .map(|r| r.get(0)),
+        )
+    }
+
+    /// Get a stream by id, if it exists
+    pub async fn find_stream(&self, id: &Uuid) -> Result<Option<UserStream>> {
+        Ok(sqlx::query_as("select * from user_stream where id = ?")
+            .bind(id.to_string())
+            .fetch_optional(&self.db)
+            .await?)
+    }
+
+    /// Record a download of a stream recording
+    pub async fn insert_recording_download(&self, download: &RecordingDownload) -> Result<()> {
+        sqlx::query(
+            "insert into recording_download (stream_id, user_id, ip_addr, file, range_start, length) values (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&download.stream_id)
+        .bind(download.user_id)
+        .bind(&download.ip_addr)
+        .bind(&download.file)
+        .bind(download.range_start)
+        .bind(download.length)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Add a marker to a stream
+    pub async fn insert_stream_marker(&self, marker: &StreamMarker) -> Result<u64> {
+        let res = sqlx::query(
+            "insert into stream_marker (stream_id, offset_secs, label) values (?, ?, ?)",
+        )
+        .bind(&marker.stream_id)
+        .bind(marker.offset_secs)
+        .bind(&marker.label)
+        .execute(&self.db)
+        .await?;
+        Ok(res.last_insert_id())
+    }
+
+    /// List all markers of a stream in stream order
+    pub async fn list_stream_markers(&self, stream_id: &str) -> Result<Vec<StreamMarker>> {
+        Ok(
+            sqlx::query_as("select * from stream_marker where stream_id = ? order by offset_secs")
+                .bind(stream_id)
+                .fetch_all(self.read_pool())
+                .await?,
+        )
+    }
+
+    /// Record the encoder connection details for a stream
+    pub async fn insert_stream_connection(&self, conn: &StreamConnection) -> Result<()> {
+        sqlx::query(
+            "insert into stream_connection (stream_id, ip_addr, endpoint, user_agent, params) values (?, ?, ?, ?, ?)",
+        )
+        .bind(&conn.stream_id)
+        .bind(&conn.ip_addr)
+        .bind(&conn.endpoint)
+        .bind(&conn.user_agent)
+        .bind(&conn.params)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// List all encoder connections for a stream
+    pub async fn list_stream_connections(&self, stream_id: &str) -> Result<Vec<StreamConnection>> {
+        Ok(
+            sqlx::query_as("select * from stream_connection where stream_id = ? order by created")
+                .bind(stream_id)
+                .fetch_all(self.read_pool())
+                .await?,
+        )
+    }
+
+    /// Get the most recent live stream for a user
+    pub async fn get_user_live_stream(&self, user_id: u64) -> Result<Option<UserStream>> {
+        Ok(sqlx::query_as(
+            "select * from user_stream where user_id = ? and state = 2 order by starts desc limit 1",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?)
+    }
+
     /// Get the list of active streams
     pub async fn list_live_streams(&self) -> Result<Vec<UserStream>> {
         Ok(sqlx::query_as("select * from user_stream where state = 2")
@@ -109,6 +620,940 @@ impl ZapStreamDb {
             .await?)
     }
 
+    /// Live streams shown in the public stream directory, newest first
+    pub async fn list_listed_streams(&self) -> Result<Vec<UserStream>> {
+        Ok(sqlx::query_as(
+            "select * from user_stream where state = 2 and visibility = 0 and listed = true order by starts desc",
+        )
+        .fetch_all(self.read_pool())
+        .await?)
+    }
+
+    /// List users newest first
+    ///
+    /// [after] is a keyset cursor (created, id) of the last row from the previous page,
+    /// when not set [offset] is used instead
+    pub async fn list_users(
+        &self,
+        after: Option<(DateTime<Utc>, u64)>,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<User>> {
+        let q = if let Some((created, id)) = after {
+            sqlx::query_as(
+                "select * from user where (created, id) < (?, ?) order by created desc, id desc limit ?",
+            )
+            .bind(created)
+            .bind(id)
+            .bind(limit)
+        } else {
+            sqlx::query_as("select * from user order by created desc, id desc limit ? offset ?")
+                .bind(limit)
+                .bind(offset)
+        };
+        Ok(q.fetch_all(self.read_pool()).await?)
+    }
+
+    /// List streams newest first, optionally only for a single user
+    ///
+    /// [after] is a keyset cursor (starts, id) of the last row from the previous page,
+    /// when not set [offset] is used instead
+    pub async fn list_streams(
+        &self,
+        user_id: Option<u64>,
+        after: Option<(DateTime<Utc>, String)>,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<UserStream>> {
+        let mut q = QueryBuilder::new("select * from user_stream where 1 = 1");
+        if let Some(uid) = user_id {
+            q.push(" and user_id = ").push_bind(uid);
+        }
+        let keyset = after.is_some();
+        if let Some((starts, id)) = after {
+            q.push(" and (starts, id) < (")
+                .push_bind(starts)
+                .push(", ")
+                .push_bind(id)
+                .push(")");
+        }
+        q.push(" order by starts desc, id desc limit ")
+            .push_bind(limit);
+        if !keyset {
+            q.push(" offset ").push_bind(offset);
+        }
+        Ok(q.build_query_as().fetch_all(self.read_pool()).await?)
+    }
+
+    /// Ended public streams of a user newest first, see [list_streams] for paging
+    pub async fn list_past_streams(
+        &self,
+        user_id: u64,
+        after: Option<(DateTime<Utc>, String)>,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<UserStream>> {
+        let mut q = QueryBuilder::new(
+            "select * from user_stream where state = 3 and visibility = 0 and user_id = ",
+        );
+        q.push_bind(user_id);
+        let keyset = after.is_some();
+        if let Some((starts, id)) = after {
+            q.push(" and (starts, id) < (")
+                .push_bind(starts)
+                .push(", ")
+                .push_bind(id)
+                .push(")");
+        }
+        q.push(" order by starts desc, id desc limit ")
+            .push_bind(limit);
+        if !keyset {
+            q.push(" offset ").push_bind(offset);
+        }
+        Ok(q.build_query_as().fetch_all(self.read_pool()).await?)
+    }
+
+    /// All streams of a user which started after [since], oldest first
+    pub async fn list_user_streams_since(
+        &self,
+        uid: u64,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<UserStream>> {
+        Ok(sqlx::query_as(
+            "select * from user_stream where user_id = ? and starts >= ? order by starts asc",
+        )
+        .bind(uid)
+        .bind(since)
+        .fetch_all(self.read_pool())
+        .await?)
+    }
+
+    /// Refund part of the cost of a stream to its owner, fails if the stream's refunds would
+    /// exceed its cost
+    pub async fn insert_stream_refund(&self, refund: &StreamRefund) -> Result<u64> {
+        let mut tx = self.db.begin().await?;
+
+        // lock the stream so concurrent refunds can't exceed the cost
+        let stream: UserStream =
+            sqlx::query_as("select * from user_stream where id = ? for update")
+                .bind(&refund.stream_id)
+                .fetch_one(&mut *tx)
+                .await?;
+        let refunded: i64 = sqlx::query(
+            "select cast(coalesce(sum(amount), 0) as signed) from stream_refund where stream_id = ?",
+        )
+        .bind(&refund.stream_id)
+        .fetch_one(&mut *tx)
+        .await?
+        .try_get(0)?;
+        if refunded as u64 + refund.amount > stream.cost {
+            bail!(
+                "Refund exceeds stream cost, {} of {} msats already refunded",
+                refunded,
+                stream.cost
+            );
+        }
+
+        let res = sqlx::query(
+            "insert into stream_refund (stream_id, admin_id, amount, reason) values (?, ?, ?, ?)",
+        )
+        .bind(&refund.stream_id)
+        .bind(refund.admin_id)
+        .bind(refund.amount)
+        .bind(&refund.reason)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("update user set balance = balance + ? where id = ?")
+            .bind(refund.amount)
+            .bind(stream.user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(res.last_insert_id())
+    }
+
+    /// Refunds issued for a stream, oldest first
+    pub async fn list_stream_refunds(&self, stream_id: &str) -> Result<Vec<StreamRefund>> {
+        Ok(
+            sqlx::query_as("select * from stream_refund where stream_id = ? order by created asc")
+                .bind(stream_id)
+                .fetch_all(self.read_pool())
+                .await?,
+        )
+    }
+
+    /// Record a pipeline failure of a stream
+    pub async fn insert_stream_incident(&self, incident: &StreamIncident) -> Result<()> {
+        sqlx::query("insert into stream_incident (stream_id, error, restarted) values (?, ?, ?)")
+            .bind(&incident.stream_id)
+            .bind(&incident.error)
+            .bind(incident.restarted)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// List pipeline failures, newest first, optionally only for one stream
+    ///
+    /// [after] is a keyset cursor (created, id) of the last row from the previous page,
+    /// when not set [offset] is used instead
+    pub async fn list_stream_incidents(
+        &self,
+        stream_id: Option<&str>,
+        after: Option<(DateTime<Utc>, u64)>,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<StreamIncident>> {
+        let mut q = QueryBuilder::new("select * from stream_incident where 1 = 1");
+        if let Some(id) = stream_id {
+            q.push(" and stream_id = ").push_bind(id);
+        }
+        let keyset = after.is_some();
+        if let Some((created, id)) = after {
+            q.push(" and (created, id) < (")
+                .push_bind(created)
+                .push(", ")
+                .push_bind(id)
+                .push(")");
+        }
+        q.push(" order by created desc, id desc limit ")
+            .push_bind(limit);
+        if !keyset {
+            q.push(" offset ").push_bind(offset);
+        }
+        Ok(q.build_query_as().fetch_all(self.read_pool()).await?)
+    }
+
+    /// Encoder settings and input limits of the ingest at [endpoint], if any
+    pub async fn get_ingest_endpoint(&self, endpoint: &str) -> Result<Option<IngestEndpoint>> {
+        Ok(
+            sqlx::query_as("select * from ingest_endpoint where endpoint = ?")
+                .bind(endpoint)
+                .fetch_optional(&self.db)
+                .await?,
+        )
+    }
+
+    pub async fn get_ingest_endpoint_by_id(&self, id: u64) -> Result<Option<IngestEndpoint>> {
+        Ok(sqlx::query_as("select * from ingest_endpoint where id = ?")
+            .bind(id)
+            .fetch_optional(&self.db)
+            .await?)
+    }
+
+    /// List encoder settings of all ingest endpoints
+    pub async fn list_ingest_endpoints(&self) -> Result<Vec<IngestEndpoint>> {
+        Ok(
+            sqlx::query_as("select * from ingest_endpoint order by endpoint")
+                .fetch_all(&self.db)
+                .await?,
+        )
+    }
+
+    /// Create or replace the encoder settings and input limits of an ingest endpoint
+    pub async fn upsert_ingest_endpoint(&self, endpoint: &IngestEndpoint) -> Result<()> {
+        sqlx::query(
+            "insert into ingest_endpoint (endpoint, preset, tune, profile, params, max_width, max_height, max_fps, max_bitrate, video_codecs, allow_hdr, loudnorm, low_latency, watermark_position, watermark_opacity) \
+            values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) \
+            on duplicate key update preset = values(preset), tune = values(tune), profile = values(profile), params = values(params), \
+            max_width = values(max_width), max_height = values(max_height), max_fps = values(max_fps), max_bitrate = values(max_bitrate), \
+            video_codecs = values(video_codecs), allow_hdr = values(allow_hdr), loudnorm = values(loudnorm), \
+            low_latency = values(low_latency), watermark_position = values(watermark_position), \
+            watermark_opacity = values(watermark_opacity)",
+        )
+        .bind(&endpoint.endpoint)
+        .bind(&endpoint.preset)
+        .bind(&endpoint.tune)
+        .bind(&endpoint.profile)
+        .bind(&endpoint.params)
+        .bind(endpoint.max_width)
+        .bind(endpoint.max_height)
+        .bind(endpoint.max_fps)
+        .bind(endpoint.max_bitrate)
+        .bind(&endpoint.video_codecs)
+        .bind(endpoint.allow_hdr)
+        .bind(endpoint.loudnorm)
+        .bind(endpoint.low_latency)
+        .bind(&endpoint.watermark_position)
+        .bind(endpoint.watermark_opacity)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Transcode ladder of the ingest endpoint [endpoint_id], highest rung first
+    pub async fn list_ingest_endpoint_variants(
+        &self,
+        endpoint_id: u64,
+    ) -> Result<Vec<IngestEndpointVariant>> {
+        Ok(sqlx::query_as(
+            "select * from ingest_endpoint_variant where endpoint_id = ? order by height desc, bitrate desc, id",
+        )
+        .bind(endpoint_id)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    /// Replace the transcode ladder of the ingest endpoint [endpoint_id], an empty ladder
+    /// restores the default variants
+    pub async fn set_ingest_endpoint_variants(
+        &self,
+        endpoint_id: u64,
+        variants: &[IngestEndpointVariant],
+    ) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query("delete from ingest_endpoint_variant where endpoint_id = ?")
+            .bind(endpoint_id)
+            .execute(&mut *tx)
+            .await?;
+        for v in variants {
+            sqlx::query(
+                "insert into ingest_endpoint_variant (endpoint_id, width, height, fps, bitrate, codec, audio_only) values (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(endpoint_id)
+            .bind(v.width)
+            .bind(v.height)
+            .bind(v.fps)
+            .bind(v.bitrate)
+            .bind(&v.codec)
+            .bind(v.audio_only)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Remove the encoder settings of an ingest endpoint, returns false if there were none
+    pub async fn delete_ingest_endpoint(&self, endpoint: &str) -> Result<bool> {
+        let res = sqlx::query("delete from ingest_endpoint where endpoint = ?")
+            .bind(endpoint)
+            .execute(&self.db)
+            .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Record an admin action in the audit log
+    pub async fn insert_audit_log(
+        &self,
+        admin_id: u64,
+        action: &str,
+        target_type: &str,
+        target_id: &str,
+        message: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(
+            "insert into audit_log (admin_id, action, target_type, target_id, message) values (?, ?, ?, ?, ?)",
+        )
+        .bind(admin_id)
+        .bind(action)
+        .bind(target_type)
+        .bind(target_id)
+        .bind(message)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// List audit log entries newest first
+    ///
+    /// [after] is a keyset cursor (created, id) of the last row from the previous page,
+    /// when not set [offset] is used instead
+    pub async fn list_audit_log(
+        &self,
+        filter: &AuditLogFilter,
+        after: Option<(DateTime<Utc>, u64)>,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<AuditLog>> {
+        let mut q = QueryBuilder::new("select * from audit_log where 1 = 1");
+        if let Some(admin_id) = filter.admin_id {
+            q.push(" and admin_id = ").push_bind(admin_id);
+        }
+        if let Some(action) = &filter.action {
+            q.push(" and action = ").push_bind(action);
+        }
+        if let Some(target_type) = &filter.target_type {
+            q.push(" and target_type = ").push_bind(target_type);
+        }
+        if let Some(target_id) = &filter.target_id {
+            q.push(" and target_id = ").push_bind(target_id);
+        }
+        if let Some(search) = &filter.search {
+            // match the search text literally, without its wildcards
+            let search = search
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            q.push(" and message like ")
+                .push_bind(format!("%{}%", search));
+        }
+        if let Some(from) = filter.from {
+            q.push(" and created >= ").push_bind(from);
+        }
+        if let Some(to) = filter.to {
+            q.push(" and created < ").push_bind(to);
+        }
+        let keyset = after.is_some();
+        if let Some((created, id)) = after {
+            q.push(" and (created, id) < (")
+                .push_bind(created)
+                .push(", ")
+                .push_bind(id)
+                .push(")");
+        }
+        q.push(" order by created desc, id desc limit ")
+            .push_bind(limit);
+        if !keyset {
+            q.push(" offset ").push_bind(offset);
+        }
+        Ok(q.build_query_as().fetch_all(self.read_pool()).await?)
+    }
+
+    /// Add (or remove with a negative [amount]) milli-sats to a users balance
+    pub async fn add_balance(&self, user_id: u64, amount: i64) -> Result<()> {
+        sqlx::query("update user set balance = balance + ? where id = ?")
+            .bind(amount)
+            .bind(user_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Record a settled payment and credit it to the user
+    ///
+    /// Returns false if the payment was already credited, processors retry webhooks so the
+    /// same payment can be reported more than once
+    pub async fn credit_payment(&self, payment: &Payment) -> Result<bool> {
+        let mut tx = self.db.begin().await?;
+        let res = sqlx::query(
+            "insert into payment (user_id, provider, external_id, amount) values (?, ?, ?, ?)",
+        )
+        .bind(payment.user_id)
+        .bind(&payment.provider)
+        .bind(&payment.external_id)
+        .bind(payment.amount)
+        .execute(&mut *tx)
+        .await;
+        match res {
+            Ok(_) => {}
+            // the unique (provider, external_id) index rejects payments credited before
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+        sqlx::query("update user set balance = balance + ? where id = ?")
+            .bind(payment.amount)
+            .bind(payment.user_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Store a topup invoice so it can be credited once paid
+    pub async fn insert_topup_invoice(&self, invoice: &TopupInvoice) -> Result<()> {
+        sqlx::query("insert into topup_invoice (payment_hash, user_id, amount) values (?, ?, ?)")
+            .bind(&invoice.payment_hash)
+            .bind(invoice.user_id)
+            .bind(invoice.amount)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Topup invoice by hex payment hash
+    pub async fn get_topup_invoice(&self, payment_hash: &str) -> Result<Option<TopupInvoice>> {
+        Ok(
+            sqlx::query_as("select * from topup_invoice where payment_hash = ?")
+                .bind(payment_hash)
+                .fetch_optional(&self.db)
+                .await?,
+        )
+    }
+
+    /// Create a voucher and return its id
+    pub async fn insert_voucher(&self, voucher: &Voucher) -> Result<u64> {
+        let res = sqlx::query(
+            "insert into voucher (code, amount, created_by, memo, expires) values (?, ?, ?, ?, ?)",
+        )
+        .bind(&voucher.code)
+        .bind(voucher.amount)
+        .bind(voucher.created_by)
+        .bind(&voucher.memo)
+        .bind(voucher.expires)
+        .execute(&self.db)
+        .await?;
+        Ok(res.last_insert_id())
+    }
+
+    /// List vouchers, newest first
+    pub async fn list_vouchers(
+        &self,
+        after: Option<(DateTime<Utc>, u64)>,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<Voucher>> {
+        let mut q = QueryBuilder::new("select * from voucher where 1 = 1");
+        let keyset = after.is_some();
+        if let Some((created, id)) = after {
+            q.push(" and (created, id) < (")
+                .push_bind(created)
+                .push(", ")
+                .push_bind(id)
+                .push(")");
+        }
+        q.push(" order by created desc, id desc limit ")
+            .push_bind(limit);
+        if !keyset {
+            q.push(" offset ").push_bind(offset);
+        }
+        Ok(q.build_query_as().fetch_all(self.read_pool()).await?)
+    }
+
+    /// Delete a voucher which has not been redeemed, returns false if there is no such voucher
+    pub async fn delete_voucher(&self, id: u64) -> Result<bool> {
+        let res = sqlx::query("delete from voucher where id = ? and redeemed is null")
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Redeem voucher [code] for [user_id], crediting its amount to the user's balance
+    pub async fn redeem_voucher(&self, code: &str, user_id: u64) -> Result<Voucher> {
+        let mut tx = self.db.begin().await?;
+        let mut voucher: Voucher =
+            match sqlx::query_as("select * from voucher where code = ? for update")
+                .bind(code)
+                .fetch_optional(&mut *tx)
+                .await?
+            {
+                Some(v) => v,
+                None => bail!("Invalid voucher code"),
+            };
+        if voucher.redeemed.is_some() {
+            bail!("Voucher has already been redeemed");
+        }
+        if voucher.expires.is_some_and(|e| e < Utc::now()) {
+            bail!("Voucher has expired");
+        }
+
+        sqlx::query(
+            "update voucher set redeemed_by = ?, redeemed = current_timestamp where id = ?",
+        )
+        .bind(user_id)
+        .bind(voucher.id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "insert into payment (user_id, provider, external_id, amount) values (?, 'voucher', ?, ?)",
+        )
+        .bind(user_id)
+        .bind(voucher.id.to_string())
+        .bind(voucher.amount)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("update user set balance = balance + ? where id = ?")
+            .bind(voucher.amount)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        voucher.redeemed_by = Some(user_id);
+        voucher.redeemed = Some(Utc::now());
+        Ok(voucher)
+    }
+
+    /// Move [amount] milli-sats from [from_user] to [to_user], returns the sender's entry
+    pub async fn transfer_balance(
+        &self,
+        from_user: u64,
+        to_user: u64,
+        amount: u64,
+        memo: Option<&str>,
+    ) -> Result<BalanceTransfer> {
+        if from_user == to_user {
+            bail!("Cannot transfer to yourself");
+        }
+        let amount = i64::try_from(amount)?;
+        let mut tx = self.db.begin().await?;
+
+        // lock both users in one statement so opposite transfers can't deadlock
+        let balances: Vec<(u64, i64)> =
+            sqlx::query_as("select id, balance from user where id in (?, ?) for update")
+                .bind(from_user)
+                .bind(to_user)
+                .fetch_all(&mut *tx)
+                .await?;
+        let balance = match balances.iter().find(|(id, _)| *id == from_user) {
+            Some((_, b)) => *b,
+            None => bail!("User {} not found", from_user),
+        };
+        if !balances.iter().any(|(id, _)| *id == to_user) {
+            bail!("User {} not found", to_user);
+        }
+        if balance < amount {
+            bail!("Insufficient balance");
+        }
+
+        sqlx::query("update user set balance = balance - ? where id = ?")
+            .bind(amount)
+            .bind(from_user)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("update user set balance = balance + ? where id = ?")
+            .bind(amount)
+            .bind(to_user)
+            .execute(&mut *tx)
+            .await?;
+        let res = sqlx::query(
+            "insert into balance_transfer (user_id, counterparty_id, amount, memo) values (?, ?, ?, ?)",
+        )
+        .bind(from_user)
+        .bind(to_user)
+        .bind(-amount)
+        .bind(memo)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "insert into balance_transfer (user_id, counterparty_id, amount, memo) values (?, ?, ?, ?)",
+        )
+        .bind(to_user)
+        .bind(from_user)
+        .bind(amount)
+        .bind(memo)
+        .execute(&mut *tx)
+        .await?;
+        let entry: BalanceTransfer = sqlx::query_as("select * from balance_transfer where id = ?")
+            .bind(res.last_insert_id())
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(entry)
+    }
+
+    /// Transfers sent and received by a user, newest first
+    pub async fn list_balance_transfers(
+        &self,
+        user_id: u64,
+        after: Option<(DateTime<Utc>, u64)>,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<BalanceTransfer>> {
+        let mut q = QueryBuilder::new("select * from balance_transfer where user_id = ");
+        q.push_bind(user_id);
+        let keyset = after.is_some();
+        if let Some((created, id)) = after {
+            q.push(" and (created, id) < (")
+                .push_bind(created)
+                .push(", ")
+                .push_bind(id)
+                .push(")");
+        }
+        q.push(" order by created desc, id desc limit ")
+            .push_bind(limit);
+        if !keyset {
+            q.push(" offset ").push_bind(offset);
+        }
+        Ok(q.build_query_as().fetch_all(self.read_pool()).await?)
+    }
+
+    /// Add origin egress of a stream and charge [cost] milli-sats to its owner
+    pub async fn add_stream_egress(
+        &self,
+        egress: &StreamEgress,
+        user_id: u64,
+        cost: i64,
+    ) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query(
+            "insert into stream_egress (stream_id, day, source, bytes, requests) values (?, ?, ?, ?, ?) \
+            on duplicate key update bytes = bytes + values(bytes), requests = requests + values(requests)",
+        )
+        .bind(&egress.stream_id)
+        .bind(egress.day)
+        .bind(egress.source)
+        .bind(egress.bytes)
+        .bind(egress.requests)
+        .execute(&mut *tx)
+        .await?;
+        if cost > 0 {
+            sqlx::query("update user_stream set cost = cost + ? where id = ?")
+                .bind(cost)
+                .bind(&egress.stream_id)
+                .execute(&mut *tx)
+                .await?;
+            if self.shadow_ledger {
+                Self::insert_shadow_charge(&mut tx, user_id, &egress.stream_id, "egress", cost)
+                    .await?;
+            } else {
+                sqlx::query("update user set balance = balance - ? where id = ?")
+                    .bind(cost)
+                    .bind(user_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Set the CDN egress of a stream for a day, reports replace earlier reports of the same
+    /// edge and day
+    pub async fn set_cdn_egress(&self, egress: &StreamEgress) -> Result<()> {
+        sqlx::query(
+            "insert into stream_egress (stream_id, day, source, edge, bytes, requests, unique_ips) values (?, ?, ?, ?, ?, ?, ?) \
+            on duplicate key update bytes = values(bytes), requests = values(requests), unique_ips = values(unique_ips)",
+        )
+        .bind(&egress.stream_id)
+        .bind(egress.day)
+        .bind(EgressSource::Cdn)
+        .bind(&egress.edge)
+        .bind(egress.bytes)
+        .bind(egress.requests)
+        .bind(egress.unique_ips)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Daily egress of a stream, oldest first
+    pub async fn list_stream_egress(&self, stream_id: &str) -> Result<Vec<StreamEgress>> {
+        Ok(sqlx::query_as(
+            "select * from stream_egress where stream_id = ? order by day asc, source asc, edge asc",
+        )
+        .bind(stream_id)
+        .fetch_all(self.read_pool())
+        .await?)
+    }
+
+    /// Streams with the most egress between [from] and [to] (inclusive)
+    pub async fn top_stream_egress(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+        limit: u64,
+    ) -> Result<Vec<StreamEgressTotal>> {
+        Ok(sqlx::query_as(
+            "select e.stream_id, s.user_id, \
+            cast(sum(if(e.source = 0, e.bytes, 0)) as unsigned) as origin_bytes, \
+            cast(sum(if(e.source = 1, e.bytes, 0)) as unsigned) as cdn_bytes, \
+            cast(sum(e.requests) as unsigned) as requests, \
+            cast(sum(e.unique_ips) as unsigned) as unique_ips \
+            from stream_egress e join user_stream s on s.id = e.stream_id \
+            where e.day between ? and ? \
+            group by e.stream_id, s.user_id \
+            order by sum(e.bytes) desc limit ?",
+        )
+        .bind(from)
+        .bind(to)
+        .bind(limit)
+        .fetch_all(self.read_pool())
+        .await?)
+    }
+
+    pub async fn insert_usage_alert(&self, alert: &UsageAlert) -> Result<u64> {
+        let res = sqlx::query("insert into usage_alert (user_id, rate, streams) values (?, ?, ?)")
+            .bind(alert.user_id)
+            .bind(alert.rate)
+            .bind(alert.streams)
+            .execute(&self.db)
+            .await?;
+        Ok(res.last_insert_id())
+    }
+
+    /// List usage alerts newest first, only alerts not acknowledged yet if [open]
+    pub async fn list_usage_alerts(
+        &self,
+        open: bool,
+        after: Option<(DateTime<Utc>, u64)>,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<UsageAlert>> {
+        let mut q = QueryBuilder::new("select * from usage_alert where 1 = 1");
+        if open {
+            q.push(" and acknowledged is null");
+        }
+        let keyset = after.is_some();
+        if let Some((created, id)) = after {
+            q.push(" and (created, id) < (")
+                .push_bind(created)
+                .push(", ")
+                .push_bind(id)
+                .push(")");
+        }
+        q.push(" order by created desc, id desc limit ")
+            .push_bind(limit);
+        if !keyset {
+            q.push(" offset ").push_bind(offset);
+        }
+        Ok(q.build_query_as().fetch_all(self.read_pool()).await?)
+    }
+
+    /// Acknowledge a usage alert, returns false if it doesn't exist or was already acknowledged
+    pub async fn acknowledge_usage_alert(&self, id: u64, admin_id: u64) -> Result<bool> {
+        let res = sqlx::query(
+            "update usage_alert set acknowledged_by = ?, acknowledged = current_timestamp where id = ? and acknowledged is null",
+        )
+        .bind(admin_id)
+        .bind(id)
+        .execute(&self.db)
+        .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Pubkey of the most recently activated signing key
+    pub async fn get_active_signing_key(&self) -> Result<Option<Vec<u8>>> {
+        Ok(
+            sqlx::query("select pubkey from signing_key order by id desc limit 1")
+                .fetch_optional(&self.db)
+                .await?
+                .map(|r| r.get(0)),
+        )
+    }
+
+    /// Make [pubkey] the key new events are signed with
+    pub async fn activate_signing_key(&self, pubkey: &[u8; 32]) -> Result<()> {
+        sqlx::query("insert into signing_key (pubkey) values (?)")
+            .bind(pubkey.as_slice())
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Signing key activations, newest first
+    pub async fn list_signing_keys(&self, limit: u64) -> Result<Vec<SigningKey>> {
+        Ok(
+            sqlx::query_as("select * from signing_key order by id desc limit ?")
+                .bind(limit)
+                .fetch_all(&self.db)
+                .await?,
+        )
+    }
+
+    /// Delete a user and all of their streams
+    ///
+    /// Audit and financial records (audit log, approvals, payments, transfers, refunds,
+    /// vouchers, downloads) are kept with the ids of the deleted user and streams
+    pub async fn delete_user(&self, user_id: u64) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query("delete from usage_alert where user_id = ?")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("update usage_alert set acknowledged_by = null where acknowledged_by = ?")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("delete from premiere where user_id = ? or vod_id in (select id from user_stream where user_id = ?)")
+            .bind(user_id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        for table in [
+            "stream_connection",
+            "stream_share",
+            "stream_marker",
+            "stream_incident",
+            "stream_link",
+            "stream_chat",
+            "stream_egress",
+        ] {
+            sqlx::query(&format!(
+                "delete from {} where stream_id in (select id from user_stream where user_id = ?)",
+                table
+            ))
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        sqlx::query("delete from user_stream where user_id = ?")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("delete from user where id = ?")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Create a new pending approval and return its id
+    pub async fn insert_approval(
+        &self,
+        action: ApprovalAction,
+        user_id: Option<u64>,
+        amount: Option<i64>,
+        memo: Option<&str>,
+        params: Option<&str>,
+        proposed_by: u64,
+    ) -> Result<u64> {
+        let res = sqlx::query(
+            "insert into admin_approval (action, user_id, amount, memo, params, proposed_by) values (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(action)
+        .bind(user_id)
+        .bind(amount)
+        .bind(memo)
+        .bind(params)
+        .bind(proposed_by)
+        .execute(&self.db)
+        .await?;
+        Ok(res.last_insert_id())
+    }
+
+    pub async fn get_approval(&self, id: u64) -> Result<AdminApproval> {
+        Ok(sqlx::query_as("select * from admin_approval where id = ?")
+            .bind(id)
+            .fetch_one(&self.db)
+            .await?)
+    }
+
+    /// List approvals waiting for a second admin
+    pub async fn list_pending_approvals(&self) -> Result<Vec<AdminApproval>> {
+        Ok(
+            sqlx::query_as("select * from admin_approval where state = ? order by created")
+                .bind(ApprovalState::Pending)
+                .fetch_all(&self.db)
+                .await?,
+        )
+    }
+
+    /// Mark a pending approval as resolved, returns false if it was not pending
+    pub async fn resolve_approval(
+        &self,
+        id: u64,
+        state: ApprovalState,
+        resolved_by: u64,
+    ) -> Result<bool> {
+        let res = sqlx::query(
+            "update admin_approval set state = ?, resolved_by = ?, resolved = current_timestamp where id = ? and state = ?",
+        )
+        .bind(state)
+        .bind(resolved_by)
+        .bind(id)
+        .bind(ApprovalState::Pending)
+        .execute(&self.db)
+        .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Put an approved approval back to pending, used when its action failed
+    pub async fn reopen_approval(&self, id: u64) -> Result<()> {
+        sqlx::query(
+            "update admin_approval set state = ?, resolved_by = null, resolved = null where id = ? and state = ?",
+        )
+        .bind(ApprovalState::Pending)
+        .bind(id)
+        .bind(ApprovalState::Approved)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
     /// Add [duration] & [cost] to a stream and return the new user balance
     pub async fn tick_stream(
         &self,
@@ -119,24 +1564,33 @@ impl ZapStreamDb {
     ) -> Result<i64> {
         let mut tx = self.db.begin().await?;
 
-        sqlx::query("update user_stream set duration = duration + ?, cost = cost + ? where id = ?")
+        sqlx::query("update user_stream set duration = duration + ?, cost = cost + ?, last_segment = current_timestamp where id = ?")
             .bind(&duration)
             .bind(&cost)
             .bind(stream_id.to_string())
             .execute(&mut *tx)
             .await?;
 
-        sqlx::query("update user set balance = balance - ? where id = ?")
-            .bind(&cost)
-            .bind(&user_id)
-            .execute(&mut *tx)
-            .await?;
+        if self.shadow_ledger {
+            Self::insert_shadow_charge(&mut tx, user_id, &stream_id.to_string(), "stream", cost)
+                .await?;
+        } else {
+            sqlx::query("update user set balance = balance - ? where id = ?")
+                .bind(&cost)
+                .bind(&user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
 
-        let balance: i64 = sqlx::query("select balance from user where id = ?")
-            .bind(&user_id)
-            .fetch_one(&mut *tx)
-            .await?
-            .try_get(0)?;
+        // in dry run the balance is what it would be had the shadow charges been debited
+        let balance: i64 = sqlx::query(
+            "select cast(balance - coalesce((select sum(amount) from shadow_ledger where user_id = ?), 0) as signed) from user where id = ?",
+        )
+        .bind(&user_id)
+        .bind(&user_id)
+        .fetch_one(&mut *tx)
+        .await?
+        .try_get(0)?;
 
         tx.commit().await?;
 
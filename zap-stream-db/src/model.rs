@@ -1,4 +1,4 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use sqlx::{FromRow, Type};
 use std::fmt::{Display, Formatter};
 use uuid::Uuid;
@@ -19,13 +19,65 @@ pub struct User {
     pub stream_key: String,
     /// If the user is an admin
     pub is_admin: bool,
+    /// Role of the admin, only used when [is_admin] is set
+    pub admin_role: AdminRole,
     /// If the user is blocked from streaming
     pub is_blocked: bool,
     /// Streams are recorded
     pub recording: bool,
+    /// Visibility of new streams
+    pub visibility: StreamVisibility,
+    /// New streams are shown in the public stream directory
+    pub listed: bool,
+    /// Multiplier applied to endpoint rates, 0 streams for free
+    pub cost_multiplier: f32,
+    /// URL notified when the user's streams are created, updated or ended
+    pub webhook_url: Option<String>,
+    /// Key of the HMAC signature of webhook requests
+    pub webhook_secret: Option<String>,
+    /// Audio is loudness normalized on endpoints which have a target loudness
+    pub loudnorm: bool,
 }
 
-#[derive(Default, Debug, Clone, Type)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[repr(u8)]
+pub enum AdminRole {
+    /// Full access to all admin functions
+    #[default]
+    Admin = 0,
+    /// Can moderate streams but cannot change balances or instance config
+    Moderator = 1,
+}
+
+impl Display for AdminRole {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdminRole::Admin => write!(f, "admin"),
+            AdminRole::Moderator => write!(f, "moderator"),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[repr(u8)]
+pub enum StreamVisibility {
+    /// Stream event is published to relays
+    #[default]
+    Public = 0,
+    /// No stream event is published, playback requires a share token
+    Unlisted = 1,
+}
+
+impl Display for StreamVisibility {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamVisibility::Public => write!(f, "public"),
+            StreamVisibility::Unlisted => write!(f, "unlisted"),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Type)]
 #[repr(u8)]
 pub enum UserStreamState {
     #[default]
@@ -35,6 +87,22 @@ pub enum UserStreamState {
     Ended = 3,
 }
 
+impl UserStreamState {
+    /// Check if a stream in this state is allowed to move to [next]
+    ///
+    /// Planned -> Live -> Ended, streams can also be ended before going live
+    pub fn can_transition_to(&self, next: UserStreamState) -> bool {
+        matches!(
+            (self, next),
+            (UserStreamState::Unknown, UserStreamState::Planned)
+                | (UserStreamState::Unknown, UserStreamState::Live)
+                | (UserStreamState::Planned, UserStreamState::Live)
+                | (UserStreamState::Planned, UserStreamState::Ended)
+                | (UserStreamState::Live, UserStreamState::Ended)
+        )
+    }
+}
+
 impl Display for UserStreamState {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -65,4 +133,417 @@ pub struct UserStream {
     pub duration: f32,
     pub fee: Option<u32>,
     pub event: Option<String>,
+    /// [event] is outdated, publishing it after the last state transition failed
+    pub event_pending: bool,
+    pub last_segment: Option<DateTime<Utc>>,
+    /// Pipeline config (json) used to resume the stream after a restart
+    pub pipeline: Option<String>,
+    pub visibility: StreamVisibility,
+    /// Stream is shown in the public stream directory, it is published to relays either way
+    pub listed: bool,
+    /// Most concurrent viewers, set when the stream ends
+    pub peak_viewers: Option<u32>,
+    /// Average concurrent viewers, set when the stream ends
+    pub avg_viewers: Option<f32>,
+    /// MP4 of the (latest part of the) recording in the stream dir, set once it was remuxed
+    pub recording: Option<String>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct AuditLog {
+    pub id: u64,
+    /// Admin user which performed the action
+    pub admin_id: u64,
+    /// Action type
+    pub action: String,
+    /// Type of the object the action was performed on (user, stream ...)
+    pub target_type: String,
+    /// ID of the object the action was performed on
+    pub target_id: String,
+    /// Extra details
+    pub message: Option<String>,
+    pub created: DateTime<Utc>,
+}
+
+/// Filters for listing the audit log
+#[derive(Debug, Clone, Default)]
+pub struct AuditLogFilter {
+    pub admin_id: Option<u64>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    /// Free text search in the message
+    pub search: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[repr(u8)]
+pub enum ApprovalAction {
+    CreditBalance = 0,
+    DeleteUser = 1,
+    /// Refund of a failed stream, the stream is in [AdminApproval::params]
+    RefundStream = 2,
+    /// Batch of vouchers, the request is in [AdminApproval::params]
+    CreateVouchers = 3,
+}
+
+impl Display for ApprovalAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApprovalAction::CreditBalance => write!(f, "credit_balance"),
+            ApprovalAction::DeleteUser => write!(f, "delete_user"),
+            ApprovalAction::RefundStream => write!(f, "refund_stream"),
+            ApprovalAction::CreateVouchers => write!(f, "create_vouchers"),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[repr(u8)]
+pub enum ApprovalState {
+    #[default]
+    Pending = 0,
+    Approved = 1,
+    Rejected = 2,
+}
+
+impl Display for ApprovalState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApprovalState::Pending => write!(f, "pending"),
+            ApprovalState::Approved => write!(f, "approved"),
+            ApprovalState::Rejected => write!(f, "rejected"),
+        }
+    }
+}
+
+/// A sensitive admin action which must be approved by a second admin
+#[derive(Debug, Clone, FromRow)]
+pub struct AdminApproval {
+    pub id: u64,
+    pub action: ApprovalAction,
+    /// User the action is performed on
+    pub user_id: Option<u64>,
+    /// Amount in milli-sats for balance credits, refunds and vouchers (in total)
+    pub amount: Option<i64>,
+    pub memo: Option<String>,
+    /// Parameters of the action as JSON
+    pub params: Option<String>,
+    pub state: ApprovalState,
+    /// Admin which proposed the action
+    pub proposed_by: u64,
+    /// Admin which approved/rejected the action
+    pub resolved_by: Option<u64>,
+    pub created: DateTime<Utc>,
+    pub resolved: Option<DateTime<Utc>>,
+}
+
+/// Connection details of the encoder for a stream
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct StreamConnection {
+    pub id: u64,
+    pub stream_id: String,
+    /// Remote address of the encoder
+    pub ip_addr: String,
+    /// Ingress endpoint the encoder connected to
+    pub endpoint: String,
+    /// Encoder software, if reported
+    pub user_agent: Option<String>,
+    /// Protocol specific parameters as JSON
+    pub params: Option<String>,
+    pub created: DateTime<Utc>,
+}
+
+/// Token granting playback access to an unlisted stream
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct StreamShare {
+    pub id: u64,
+    pub stream_id: String,
+    pub token: String,
+    /// Token is rejected after this time
+    pub expires: Option<DateTime<Utc>>,
+    /// Max number of playback sessions which can be started with this token
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+    pub created: DateTime<Utc>,
+}
+
+/// Short link redirecting to the watch page of a stream
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct StreamLink {
+    pub code: String,
+    pub stream_id: String,
+    pub clicks: u32,
+    pub created: DateTime<Utc>,
+}
+
+/// Imported recording scheduled to be streamed live
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct Premiere {
+    pub id: u64,
+    pub user_id: u64,
+    /// Imported recording to play
+    pub vod_id: String,
+    pub starts: DateTime<Utc>,
+    pub state: PremiereState,
+    /// Live stream the premiere was played as, set once it started
+    pub stream_id: Option<String>,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[repr(u8)]
+pub enum PremiereState {
+    #[default]
+    Scheduled = 0,
+    Started = 1,
+    Cancelled = 2,
+}
+
+impl Display for PremiereState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PremiereState::Scheduled => write!(f, "scheduled"),
+            PremiereState::Started => write!(f, "started"),
+            PremiereState::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+/// Archived chat message of a stream
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct StreamChatMessage {
+    pub id: u64,
+    pub stream_id: String,
+    pub event_id: Vec<u8>,
+    /// Seconds from the start of the stream
+    pub offset_secs: f32,
+    /// Signed nostr event (json)
+    pub event: String,
+}
+
+/// Download of a stream recording by its owner
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct RecordingDownload {
+    pub id: u64,
+    pub stream_id: String,
+    pub user_id: u64,
+    /// Remote address of the client, if known
+    pub ip_addr: Option<String>,
+    /// File which was downloaded
+    pub file: String,
+    /// First byte of the requested range
+    pub range_start: u64,
+    /// Number of bytes sent
+    pub length: u64,
+    pub created: DateTime<Utc>,
+}
+
+/// Labelled timestamp inserted by the streamer while live
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct StreamMarker {
+    pub id: u64,
+    pub stream_id: String,
+    /// Seconds from the start of the stream
+    pub offset_secs: f32,
+    pub label: String,
+    pub created: DateTime<Utc>,
+}
+
+/// Pipeline failure of a stream, recorded when a pipeline thread crashed
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct StreamIncident {
+    pub id: u64,
+    pub stream_id: String,
+    /// Panic message or error which stopped the pipeline
+    pub error: String,
+    /// An automatic restart was attempted
+    pub restarted: bool,
+    pub created: DateTime<Utc>,
+}
+
+/// Credit returned to a streamer for a stream which failed due to a server error,
+/// compensates part of the stream's cost
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct StreamRefund {
+    pub id: u64,
+    pub stream_id: String,
+    /// Admin which issued the refund
+    pub admin_id: u64,
+    /// Amount in milli-sats
+    pub amount: u64,
+    pub reason: Option<String>,
+    pub created: DateTime<Utc>,
+}
+
+/// Topup settled by an external payment processor
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct Payment {
+    pub id: u64,
+    pub user_id: u64,
+    /// Payment processor which settled the payment
+    pub provider: String,
+    /// Id of the invoice / charge at the processor
+    pub external_id: String,
+    /// Amount in milli-sats
+    pub amount: u64,
+    pub created: DateTime<Utc>,
+}
+
+/// Lightning invoice created for a topup, credited to the user once it is paid
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct TopupInvoice {
+    /// Hex payment hash of the invoice
+    pub payment_hash: String,
+    pub user_id: u64,
+    /// Amount in milli-sats
+    pub amount: u64,
+    pub created: DateTime<Utc>,
+}
+
+/// One side of a balance transfer between two users
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct BalanceTransfer {
+    pub id: u64,
+    pub user_id: u64,
+    /// The other user of the transfer
+    pub counterparty_id: u64,
+    /// Amount in milli-sats, negative for the sender
+    pub amount: i64,
+    pub memo: Option<String>,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[repr(u8)]
+pub enum EgressSource {
+    /// Served by this node
+    #[default]
+    Origin = 0,
+    /// Served by an external CDN, reported from its logs
+    Cdn = 1,
+}
+
+/// Bytes of a stream's files served to viewers on one day (UTC)
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct StreamEgress {
+    pub stream_id: String,
+    pub day: NaiveDate,
+    pub source: EgressSource,
+    /// Name of the CDN edge which reported the egress, empty for [EgressSource::Origin]
+    pub edge: String,
+    pub bytes: u64,
+    pub requests: u64,
+    /// Estimated number of distinct viewer IPs, only reported by CDNs
+    pub unique_ips: u64,
+}
+
+/// Egress of a stream summed over a date range
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct StreamEgressTotal {
+    pub stream_id: String,
+    pub user_id: u64,
+    /// Bytes served by this node
+    pub origin_bytes: u64,
+    /// Bytes served by CDNs
+    pub cdn_bytes: u64,
+    pub requests: u64,
+    /// Sum of the daily unique IP estimates of the CDN edges
+    pub unique_ips: u64,
+}
+
+/// Raised when a user is debited faster than the configured burn rate
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct UsageAlert {
+    pub id: u64,
+    pub user_id: u64,
+    /// Debit rate in milli-sats / minute
+    pub rate: u64,
+    /// Number of live streams of the user when the alert was raised
+    pub streams: u32,
+    /// Admin which acknowledged the alert
+    pub acknowledged_by: Option<u64>,
+    pub acknowledged: Option<DateTime<Utc>>,
+    pub created: DateTime<Utc>,
+}
+
+/// Activation of a platform signing key
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct SigningKey {
+    pub id: u64,
+    pub pubkey: Vec<u8>,
+    pub created: DateTime<Utc>,
+}
+
+/// Single-use code which credits its amount to the user redeeming it
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct Voucher {
+    pub id: u64,
+    pub code: String,
+    /// Amount in milli-sats
+    pub amount: u64,
+    /// Admin which created the voucher
+    pub created_by: u64,
+    pub memo: Option<String>,
+    pub expires: Option<DateTime<Utc>>,
+    pub redeemed_by: Option<u64>,
+    pub redeemed: Option<DateTime<Utc>>,
+    pub created: DateTime<Utc>,
+}
+
+/// Settings of an ingest endpoint, the encoder settings are applied to the transcoded video
+/// variants of streams received on it and input exceeding the limits is rejected
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct IngestEndpoint {
+    pub id: u64,
+    /// Listen address of the ingest, matches the connection endpoint
+    pub endpoint: String,
+    pub preset: Option<String>,
+    pub tune: Option<String>,
+    pub profile: Option<String>,
+    /// Codec specific options as `key=value:key=value`
+    pub params: Option<String>,
+    /// Max input video width
+    pub max_width: Option<u32>,
+    /// Max input video height
+    pub max_height: Option<u32>,
+    /// Max input frame rate
+    pub max_fps: Option<f32>,
+    /// Max input bitrate in bits per second
+    pub max_bitrate: Option<u64>,
+    /// Accepted input video codecs, comma separated ffmpeg codec names
+    pub video_codecs: Option<String>,
+    /// Accept high bit depth (HDR) input video
+    pub allow_hdr: bool,
+    /// Target loudness in LUFS audio is normalized to, not normalized if not set
+    pub loudnorm: Option<f32>,
+    /// Transcode with low latency encoder options (zero-latency tune, intra-refresh, small
+    /// VBV) for interactive streams, at the cost of compression
+    pub low_latency: bool,
+    /// Corner of the watermark (`top-left`, `top-right`, `bottom-left`, `bottom-right`),
+    /// top right if not set
+    pub watermark_position: Option<String>,
+    /// Opacity of the watermark from 0 to 1, opaque if not set
+    pub watermark_opacity: Option<f32>,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+/// Rung of the transcode ladder of an ingest endpoint
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct IngestEndpointVariant {
+    pub id: u64,
+    pub endpoint_id: u64,
+    pub width: u32,
+    pub height: u32,
+    /// Frame rate, the input frame rate if not set
+    pub fps: Option<f32>,
+    /// Bits per second
+    pub bitrate: u64,
+    /// ffmpeg encoder name, the configured video encoder if not set
+    pub codec: Option<String>,
+    /// Audio-only rung, [bitrate] is the audio bitrate and the dimensions are unused
+    pub audio_only: bool,
 }
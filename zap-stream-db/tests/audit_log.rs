@@ -0,0 +1,53 @@
+//! Audit log queries, run against the MySQL database in `DATABASE_URL` (migrated by the
+//! tests), skipped if it's not set
+
+use uuid::Uuid;
+use zap_stream_db::{AuditLogFilter, ZapStreamDb};
+
+async fn db() -> Option<ZapStreamDb> {
+    let url = match std::env::var("DATABASE_URL") {
+        Ok(u) => u,
+        Err(_) => {
+            eprintln!("DATABASE_URL not set, skipping");
+            return None;
+        }
+    };
+    let db = ZapStreamDb::new(&url).await.unwrap();
+    db.migrate().await.unwrap();
+    Some(db)
+}
+
+#[tokio::test]
+async fn search_matches_wildcards_literally() {
+    let Some(db) = db().await else { return };
+    let mut pubkey = [0u8; 32];
+    pubkey[..16].copy_from_slice(Uuid::new_v4().as_bytes());
+    let admin = db.upsert_user(&pubkey).await.unwrap();
+    for message in ["50% off", "500 off", "a_b", "axb", "c\\d"] {
+        db.insert_audit_log(admin, "test", "user", "1", Some(message))
+            .await
+            .unwrap();
+    }
+
+    for (search, expect) in [
+        ("0%", vec!["50% off"]),
+        ("a_b", vec!["a_b"]),
+        ("c\\d", vec!["c\\d"]),
+        ("off", vec!["500 off", "50% off"]),
+    ] {
+        let filter = AuditLogFilter {
+            admin_id: Some(admin),
+            search: Some(search.to_string()),
+            ..Default::default()
+        };
+        let mut found: Vec<String> = db
+            .list_audit_log(&filter, None, 0, 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|e| e.message)
+            .collect();
+        found.sort();
+        assert_eq!(found, expect, "{}", search);
+    }
+}
@@ -0,0 +1,117 @@
+//! Balance changing queries, run against the MySQL database in `DATABASE_URL` (migrated
+//! by the tests), skipped if it's not set
+
+use chrono::Utc;
+use uuid::Uuid;
+use zap_stream_db::{Payment, StreamRefund, UserStream, Voucher, ZapStreamDb};
+
+async fn db() -> Option<ZapStreamDb> {
+    let url = match std::env::var("DATABASE_URL") {
+        Ok(u) => u,
+        Err(_) => {
+            eprintln!("DATABASE_URL not set, skipping");
+            return None;
+        }
+    };
+    let db = ZapStreamDb::new(&url).await.unwrap();
+    db.migrate().await.unwrap();
+    Some(db)
+}
+
+/// New user with a random pubkey
+async fn user(db: &ZapStreamDb) -> u64 {
+    let mut pubkey = [0u8; 32];
+    pubkey[..16].copy_from_slice(Uuid::new_v4().as_bytes());
+    pubkey[16..].copy_from_slice(Uuid::new_v4().as_bytes());
+    db.upsert_user(&pubkey).await.unwrap()
+}
+
+async fn balance(db: &ZapStreamDb, user_id: u64) -> i64 {
+    db.get_user(user_id).await.unwrap().balance
+}
+
+#[tokio::test]
+async fn refunds_are_capped_at_the_stream_cost() {
+    let Some(db) = db().await else { return };
+    let admin = user(&db).await;
+    let owner = user(&db).await;
+    let id = Uuid::new_v4();
+    db.insert_stream(&UserStream {
+        id: id.to_string(),
+        user_id: owner,
+        starts: Utc::now(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    db.tick_stream(&id, owner, 60.0, 10_000).await.unwrap();
+    let before = balance(&db, owner).await;
+
+    let refund = |amount| StreamRefund {
+        stream_id: id.to_string(),
+        admin_id: admin,
+        amount,
+        ..Default::default()
+    };
+    db.insert_stream_refund(&refund(6_000)).await.unwrap();
+    assert!(db.insert_stream_refund(&refund(5_000)).await.is_err());
+    db.insert_stream_refund(&refund(4_000)).await.unwrap();
+    assert!(db.insert_stream_refund(&refund(1)).await.is_err());
+
+    assert_eq!(balance(&db, owner).await, before + 10_000);
+    assert_eq!(
+        db.list_stream_refunds(&id.to_string()).await.unwrap().len(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn transfers_to_yourself_are_rejected() {
+    let Some(db) = db().await else { return };
+    let u = user(&db).await;
+    db.add_balance(u, 5_000).await.unwrap();
+
+    assert!(db.transfer_balance(u, u, 1_000, None).await.is_err());
+    assert_eq!(balance(&db, u).await, 5_000);
+}
+
+#[tokio::test]
+async fn vouchers_are_redeemed_once() {
+    let Some(db) = db().await else { return };
+    let admin = user(&db).await;
+    let (a, b) = (user(&db).await, user(&db).await);
+    let code = Uuid::new_v4().simple().to_string();
+    db.insert_voucher(&Voucher {
+        code: code.clone(),
+        amount: 21_000,
+        created_by: admin,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    db.redeem_voucher(&code, a).await.unwrap();
+    assert!(db.redeem_voucher(&code, a).await.is_err());
+    assert!(db.redeem_voucher(&code, b).await.is_err());
+
+    assert_eq!(balance(&db, a).await, 21_000);
+    assert_eq!(balance(&db, b).await, 0);
+}
+
+#[tokio::test]
+async fn payments_are_credited_once() {
+    let Some(db) = db().await else { return };
+    let u = user(&db).await;
+    let payment = Payment {
+        user_id: u,
+        provider: "test".to_string(),
+        external_id: Uuid::new_v4().to_string(),
+        amount: 50_000,
+        ..Default::default()
+    };
+
+    assert!(db.credit_payment(&payment).await.unwrap());
+    assert!(!db.credit_payment(&payment).await.unwrap());
+
+    assert_eq!(balance(&db, u).await, 50_000);
+}
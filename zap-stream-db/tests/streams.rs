@@ -0,0 +1,87 @@
+//! Stream state transitions, the queries run against the MySQL database in `DATABASE_URL`
+//! (migrated by the tests) and are skipped if it's not set
+
+use chrono::Utc;
+use uuid::Uuid;
+use zap_stream_db::{UserStream, UserStreamState, ZapStreamDb};
+
+async fn db() -> Option<ZapStreamDb> {
+    let url = match std::env::var("DATABASE_URL") {
+        Ok(u) => u,
+        Err(_) => {
+            eprintln!("DATABASE_URL not set, skipping");
+            return None;
+        }
+    };
+    let db = ZapStreamDb::new(&url).await.unwrap();
+    db.migrate().await.unwrap();
+    Some(db)
+}
+
+#[test]
+fn streams_only_move_forward() {
+    use UserStreamState::*;
+    let allowed = [
+        (Unknown, Planned),
+        (Unknown, Live),
+        (Planned, Live),
+        (Planned, Ended),
+        (Live, Ended),
+    ];
+    for from in [Unknown, Planned, Live, Ended] {
+        for to in [Unknown, Planned, Live, Ended] {
+            assert_eq!(
+                from.can_transition_to(to),
+                allowed.contains(&(from, to)),
+                "{} -> {}",
+                from,
+                to
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn transitions_are_pending_until_published() {
+    let Some(db) = db().await else { return };
+    let mut pubkey = [0u8; 32];
+    pubkey[..16].copy_from_slice(Uuid::new_v4().as_bytes());
+    let user = db.upsert_user(&pubkey).await.unwrap();
+    let id = Uuid::new_v4();
+    db.insert_stream(&UserStream {
+        id: id.to_string(),
+        user_id: user,
+        starts: Utc::now(),
+        state: UserStreamState::Live,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    assert!(db
+        .transition_stream(
+            &id.to_string(),
+            UserStreamState::Live,
+            UserStreamState::Ended,
+            None
+        )
+        .await
+        .unwrap());
+    // only one caller wins the transition
+    assert!(!db
+        .transition_stream(
+            &id.to_string(),
+            UserStreamState::Live,
+            UserStreamState::Ended,
+            None
+        )
+        .await
+        .unwrap());
+    let pending = db.list_event_pending_streams().await.unwrap();
+    assert!(pending.iter().any(|s| s.id == id.to_string()));
+
+    db.set_stream_event(&id.to_string(), "{}").await.unwrap();
+    let stream = db.get_stream(&id).await.unwrap();
+    assert!(!stream.event_pending);
+    assert_eq!(stream.event.as_deref(), Some("{}"));
+}
@@ -0,0 +1,124 @@
+//! User management queries, run against the MySQL database in `DATABASE_URL` (migrated by
+//! the tests), skipped if it's not set
+
+use chrono::Utc;
+use uuid::Uuid;
+use zap_stream_db::{
+    AuditLogFilter, Payment, RecordingDownload, StreamConnection, StreamIncident, StreamMarker,
+    StreamRefund, UserStream, ZapStreamDb,
+};
+
+async fn db() -> Option<ZapStreamDb> {
+    let url = match std::env::var("DATABASE_URL") {
+        Ok(u) => u,
+        Err(_) => {
+            eprintln!("DATABASE_URL not set, skipping");
+            return None;
+        }
+    };
+    let db = ZapStreamDb::new(&url).await.unwrap();
+    db.migrate().await.unwrap();
+    Some(db)
+}
+
+/// New user with a random pubkey
+async fn user(db: &ZapStreamDb) -> u64 {
+    let mut pubkey = [0u8; 32];
+    pubkey[..16].copy_from_slice(Uuid::new_v4().as_bytes());
+    pubkey[16..].copy_from_slice(Uuid::new_v4().as_bytes());
+    db.upsert_user(&pubkey).await.unwrap()
+}
+
+#[tokio::test]
+async fn deleting_a_user_who_streamed_keeps_the_ledger() {
+    let Some(db) = db().await else { return };
+    let admin = user(&db).await;
+    let streamer = user(&db).await;
+    let friend = user(&db).await;
+
+    let id = Uuid::new_v4();
+    let stream_id = id.to_string();
+    db.insert_stream(&UserStream {
+        id: stream_id.clone(),
+        user_id: streamer,
+        starts: Utc::now(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    db.insert_stream_connection(&StreamConnection {
+        stream_id: stream_id.clone(),
+        ip_addr: "127.0.0.1".to_string(),
+        endpoint: "rtmp".to_string(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    db.insert_stream_marker(&StreamMarker {
+        stream_id: stream_id.clone(),
+        label: "start".to_string(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    db.insert_stream_incident(&StreamIncident {
+        stream_id: stream_id.clone(),
+        error: "test".to_string(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    db.tick_stream(&id, streamer, 60.0, 10_000).await.unwrap();
+    db.insert_stream_refund(&StreamRefund {
+        stream_id: stream_id.clone(),
+        admin_id: admin,
+        amount: 1_000,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    db.insert_recording_download(&RecordingDownload {
+        stream_id: stream_id.clone(),
+        user_id: streamer,
+        file: "recording.ts".to_string(),
+        length: 1,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    db.credit_payment(&Payment {
+        user_id: streamer,
+        provider: "test".to_string(),
+        external_id: Uuid::new_v4().to_string(),
+        amount: 50_000,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    db.transfer_balance(streamer, friend, 5_000, None)
+        .await
+        .unwrap();
+    db.insert_audit_log(admin, "view_account", "user", &streamer.to_string(), None)
+        .await
+        .unwrap();
+
+    db.delete_user(streamer).await.unwrap();
+    assert!(db.find_user(streamer).await.unwrap().is_none());
+
+    // the other side of transfers, refunds and the audit log are untouched
+    let transfers = db
+        .list_balance_transfers(friend, None, 0, 10)
+        .await
+        .unwrap();
+    assert_eq!(transfers.len(), 1);
+    assert_eq!(transfers[0].counterparty_id, streamer);
+    assert_eq!(db.list_stream_refunds(&stream_id).await.unwrap().len(), 1);
+    let filter = AuditLogFilter {
+        admin_id: Some(admin),
+        ..Default::default()
+    };
+    assert_eq!(
+        db.list_audit_log(&filter, None, 0, 10).await.unwrap().len(),
+        1
+    );
+}